@@ -0,0 +1,135 @@
+use cdasim::{equilibrium, Agent, Call, Cda, Market, Shading, Strategy, Style};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::HashMap;
+
+const SIZES: [usize; 5] = [10, 100, 1_000, 10_000, 100_000];
+
+/// Half buyers and half sellers, all shading by 0.2
+fn agents(num: usize) -> Vec<Agent<'static>> {
+    (0..num)
+        .map(|ind| Agent::new(ind % 2 == 0, "0.2", Style::Standard, Shading::Fixed(0.2)))
+        .collect()
+}
+
+/// Agents with resampled values and shaded bids, ready to trade
+fn shaded(num: usize, rng: &mut StdRng) -> Vec<Agent<'static>> {
+    let mut agents = agents(num);
+    for agent in agents.iter_mut() {
+        agent.resample(rng);
+        agent.shade();
+    }
+    agents
+}
+
+fn bench_market(crit: &mut Criterion, name: &str, market: &impl Market) {
+    let mut group = crit.benchmark_group(name);
+    group.sample_size(10);
+    let mut rng = StdRng::seed_from_u64(0);
+    for num in SIZES {
+        let mut agents = shaded(num, &mut rng);
+        group.bench_with_input(BenchmarkId::from_parameter(num), &num, |bench, _| {
+            bench.iter(|| market.simulate(&mut agents, &mut rng))
+        });
+    }
+    group.finish();
+}
+
+fn bench_cda(crit: &mut Criterion) {
+    bench_market(crit, "cda", &Cda);
+}
+
+fn bench_call(crit: &mut Criterion) {
+    bench_market(crit, "call", &Call);
+}
+
+fn bench_equilibrium(crit: &mut Criterion) {
+    let mut group = crit.benchmark_group("equilibrium");
+    group.sample_size(10);
+    let mut rng = StdRng::seed_from_u64(0);
+    for num in SIZES {
+        let mut agents = shaded(num, &mut rng);
+        group.bench_with_input(BenchmarkId::new("select", num), &num, |bench, _| {
+            bench.iter(|| equilibrium::compute(&mut agents))
+        });
+        group.bench_with_input(BenchmarkId::new("sort", num), &num, |bench, _| {
+            bench.iter(|| equilibrium::compute_sorted(&mut agents))
+        });
+    }
+    group.finish();
+}
+
+fn bench_run_sim(crit: &mut Criterion) {
+    let mut group = crit.benchmark_group("run_sim");
+    group.sample_size(10);
+    let mut rng = StdRng::seed_from_u64(0);
+    for num in SIZES {
+        let mut agents = agents(num);
+        group.bench_with_input(BenchmarkId::from_parameter(num), &num, |bench, _| {
+            bench.iter(|| cdasim::run_sim(&mut agents, &Cda, &mut rng))
+        });
+    }
+    group.finish();
+}
+
+/// Strategy names as a batch of tiny specs repeats them
+const NAMES: [&str; 6] = [
+    "0.2",
+    "0.2_Shift",
+    "U(0,0.5)_Standard_e0.1",
+    "0.3_Correct",
+    "0.4_Roth_d0.5",
+    "0_i1_s0.3",
+];
+
+fn bench_strategies(crit: &mut Criterion) {
+    let mut group = crit.benchmark_group("strategies");
+    let presets = HashMap::new();
+    group.bench_function("parse", |bench| {
+        bench.iter(|| {
+            for name in NAMES {
+                let _ = name.parse::<Strategy>();
+            }
+        })
+    });
+    group.bench_function("resolve", |bench| {
+        bench.iter(|| {
+            for name in NAMES {
+                let _ = cdasim::resolve(name, &presets);
+            }
+        })
+    });
+    group.finish();
+}
+
+/// Tiny specs like those of a huge batch
+const SPECS: [&str; 3] = [
+    r#"{"assignment":{"buyers":{"0.2":3,"0.2_Shift":2},"sellers":{"0.2":5}},"configuration":{}}"#,
+    r#"{"assignment":{"buyers":{"U(0,0.5)_Standard_e0.1":5},"sellers":{"0.4":5}},"configuration":{"cda":false}}"#,
+    r#"{"assignment":{"buyers":{"0.2":1},"sellers":{"0.3_Correct":1}},"configuration":{"cda":true}}"#,
+];
+
+/// Spec parsing with serde_json, or simd-json with that feature, to compare against a saved
+/// baseline of the other
+fn bench_specs(crit: &mut Criterion) {
+    let presets = HashMap::new();
+    crit.bench_function("specs", |bench| {
+        bench.iter(|| {
+            for spec in SPECS {
+                cdasim::parse_spec(spec, true, &presets).unwrap();
+            }
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_cda,
+    bench_call,
+    bench_equilibrium,
+    bench_run_sim,
+    bench_strategies,
+    bench_specs
+);
+criterion_main!(benches);
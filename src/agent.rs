@@ -1,6 +1,12 @@
+use crate::bidding::{BiddingStrategy, MarketState, Outcome};
+use crate::learner::Policy;
+use crate::stats;
+use crate::strategy::{Latency, Shading, Strategy};
+use rand::Rng;
 use serde::ser::{SerializeMap, Serializer};
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
+use std::sync::Arc;
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Style {
@@ -8,36 +14,485 @@ pub enum Style {
     Exponential,
     Shift,
     Correct,
+    Roth,
+    Bandit,
+    External,
+    Bne,
+    Market,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Agent<'a> {
     pub buyer: bool,
+    /// If the agent is one side of a two-sided trader, see [`Agent::pair`]
+    pub trader: bool,
+    /// The number of units in the agent's lot, and which of them it is, see [`Agent::lot`]
+    units: (usize, usize),
+    /// The name of a role other than buyers, sellers, or traders, that the agent plays
+    role: Option<&'a str>,
     strat: &'a str,
-    style: Style,
+    bidder: Box<dyn BiddingStrategy>,
+    dist: Shading,
     shading: f64,
+    min_price: f64,
+    max_price: f64,
+    low: f64,
+    high: f64,
+    scale: (f64, f64),
+    cost: f64,
+    entry_cost: f64,
+    latency: Option<Latency>,
+    requote: Option<(f64, u64)>,
+    display: Option<u64>,
+    /// If the agent sends market orders to a continuous market instead of limit orders
+    market: bool,
+    /// The trade price at or below which a trader's seller sells its unit with a market order
+    stop: Option<f64>,
+    stopped: bool,
+    /// The probability the agent's player takes part in each observation, if it's the fractional
+    /// player of a count
+    presence: Option<f64>,
+    present: bool,
+    control: f64,
+    noise: f64,
+    error: f64,
+    pool: Option<Arc<[f64]>>,
+    replace: bool,
+    /// The quantile the next value is drawn at instead of a random one, see [`Agent::stratify`]
+    quantile: Option<f64>,
+    /// The standard deviation of values around the agent's persistent type, if it has one
+    type_noise: Option<f64>,
+    /// The persistent part of the agent's values, see [`Agent::draw_type`]
+    private_type: Option<f64>,
     pub value: f64,
     pub bid: f64,
     pub utility: f64,
     pub traded: bool,
+    /// The price the agent traded at in the last simulation
+    pub price: Option<f64>,
     pub ce_traded: bool,
+    pub clipped: bool,
+    pub rejected: bool,
+    pub blocked: bool,
+    pub absent: bool,
+    /// The cost of the time the agent's order waited in a timed session
+    pub waiting: f64,
+    /// How long the agent's last order took to reach the market
+    pub delay: f64,
+    withdrawn: bool,
 }
 
 impl<'a> Agent<'a> {
-    pub fn new(buyer: bool, strat: &'a str, style: Style, shading: f64) -> Agent<'a> {
+    pub fn new(buyer: bool, strat: &'a str, style: Style, dist: Shading) -> Agent<'a> {
+        let mut agent = Agent::with_bidder(buyer, strat, style.bidder(None), dist);
+        agent.market = style == Style::Market;
+        agent
+    }
+
+    /// Create an agent with an arbitrary bidding strategy
+    pub fn with_bidder(
+        buyer: bool,
+        strat: &'a str,
+        bidder: Box<dyn BiddingStrategy>,
+        dist: Shading,
+    ) -> Agent<'a> {
         Agent {
             buyer,
+            trader: false,
+            units: (1, 0),
+            role: None,
             strat,
-            style,
-            shading,
+            bidder,
+            dist,
+            shading: dist.quantile(0.5),
+            min_price: f64::NEG_INFINITY,
+            max_price: f64::INFINITY,
+            low: 0.0,
+            high: 1.0,
+            scale: (0.0, 1.0),
+            cost: 0.0,
+            entry_cost: 0.0,
+            latency: None,
+            requote: None,
+            display: None,
+            market: false,
+            stop: None,
+            stopped: false,
+            presence: None,
+            present: true,
+            control: f64::INFINITY,
+            noise: 0.0,
+            error: 0.0,
+            pool: None,
+            replace: true,
+            quantile: None,
+            type_noise: None,
+            private_type: None,
             value: 0.0,
             bid: 0.0,
             utility: 0.0,
             traded: false,
+            price: None,
             ce_traded: false,
+            clipped: false,
+            rejected: false,
+            blocked: false,
+            absent: false,
+            waiting: 0.0,
+            delay: 0.0,
+            withdrawn: false,
+        }
+    }
+
+    /// Create an agent with all of the parameters of a resolved strategy
+    ///
+    /// Strategies without a shading bid truthfully.
+    pub fn from_strategy(buyer: bool, strat: &'a str, params: &Strategy) -> Agent<'a> {
+        let style = params.style.unwrap_or(Style::Standard);
+        let shading = params.shading.unwrap_or(Shading::Fixed(0.0));
+        let mut agent = Agent::with_bidder(buyer, strat, style.bidder(params.epsilon), shading);
+        agent.min_price = params.min_price.unwrap_or(f64::NEG_INFINITY);
+        agent.max_price = params.max_price.unwrap_or(f64::INFINITY);
+        agent.entry_cost = params.entry_cost.unwrap_or(0.0);
+        agent.latency = params.latency;
+        agent.requote = params
+            .requote
+            .map(|delta| (delta, params.requote_after.unwrap_or(1)));
+        agent.display = params.display;
+        agent.market = style == Style::Market;
+        agent.stop = params.stop;
+        agent
+    }
+
+    pub fn strategy(&self) -> &'a str {
+        self.strat
+    }
+
+    /// The built in style the agent bids with, unless it learns or bids some other way
+    pub fn style(&self) -> Option<Style> {
+        self.bidder.style()
+    }
+
+    /// Replace how this agent bids, e.g. to connect an External agent to its process
+    pub fn set_bidder(&mut self, bidder: Box<dyn BiddingStrategy>) {
+        self.bidder = bidder;
+    }
+
+    /// The bounds that values are drawn uniformly between
+    pub fn support(&self) -> (f64, f64) {
+        (self.low, self.high)
+    }
+
+    /// Set the bounds values are drawn between, which are also the agent's price scale
+    pub fn set_support(&mut self, low: f64, high: f64) {
+        self.low = low;
+        self.high = high;
+        self.scale = (low, high);
+    }
+
+    /// The range of prices the agent's bidding strategy sees as [0, 1]
+    pub fn scale(&self) -> (f64, f64) {
+        self.scale
+    }
+
+    /// Set the range of prices the agent's bidding strategy sees as [0, 1]
+    ///
+    /// When roles have different supports this should cover all of them, so that every agent
+    /// bids on the same scale, e.g. shading toward the lowest value any seller might have.
+    pub fn set_scale(&mut self, low: f64, high: f64) {
+        self.scale = (low, high);
+    }
+
+    /// Draw values from an empirical pool instead of uniformly from the support
+    ///
+    /// Values are always drawn with replacement when resampling, agents that shouldn't share values
+    /// are redrawn together by [`crate::values::deal`].
+    pub fn set_pool(&mut self, pool: Arc<[f64]>, replace: bool) {
+        self.pool = Some(pool);
+        self.replace = replace;
+    }
+
+    pub fn pool(&self) -> Option<&Arc<[f64]>> {
+        self.pool.as_ref()
+    }
+
+    pub fn replaces(&self) -> bool {
+        self.replace
+    }
+
+    /// Set the cost the agent pays for every trade it makes
+    pub fn set_cost(&mut self, cost: f64) {
+        self.cost = cost;
+    }
+
+    /// The agent's value net of its trade and entry costs, what it's effectively willing to pay or
+    /// accept
+    pub fn net_value(&self) -> f64 {
+        self.value - self.sign() * (self.cost + self.entry_cost)
+    }
+
+    /// If the agent pays a cost to trade
+    pub fn costly(&self) -> bool {
+        self.cost > 0.0
+    }
+
+    /// If the agent's prices are bounded, and so may be clipped
+    pub fn bounded(&self) -> bool {
+        self.min_price.is_finite() || self.max_price.is_finite()
+    }
+
+    /// If the market has a price control for the agent, and so may block its order
+    pub fn controlled(&self) -> bool {
+        self.control.is_finite()
+    }
+
+    /// If the agent's signal of its value is noisy, and so it may trade at a loss
+    pub fn noisy(&self) -> bool {
+        self.noise > 0.0
+    }
+
+    /// If the agent has an entry cost, and so decides whether to enter the market
+    pub fn chooses_entry(&self) -> bool {
+        self.entry_cost > 0.0
+    }
+
+    /// Decide whether to enter, only if trading at the expected price beats the entry cost
+    pub fn enter(&mut self, price: Option<f64>) {
+        let net_signal = self.signal() - self.sign() * (self.cost + self.entry_cost);
+        self.absent = self.chooses_entry()
+            && price.is_none_or(|price| self.sign() * (net_signal - price) <= 0.0);
+    }
+
+    /// If the agent entered and submitted an order, whether or not it could trade
+    pub fn submitted(&self) -> bool {
+        !self.absent && !self.rejected && !self.blocked
+    }
+
+    /// Charge the entry cost to agents that entered without trading, since traders paid it as
+    /// part of their net value, and any cost of waiting
+    pub fn settle(&mut self) {
+        if self.submitted() && !self.traded {
+            self.utility = -self.entry_cost;
+        }
+        self.utility -= self.waiting;
+    }
+
+    /// Set the market's price control for the agent's role, the maximum bid for buyers and the
+    /// minimum ask for sellers
+    pub fn set_price_control(&mut self, limit: f64) {
+        self.control = self.sign() * limit;
+    }
+
+    /// Set the standard deviation of the noise in the agent's signal of its value
+    pub fn set_noise(&mut self, noise: f64) {
+        self.noise = noise;
+    }
+
+    /// The agent's noisy signal of its value, what it bids and learns with
+    pub fn signal(&self) -> f64 {
+        self.value + self.error
+    }
+
+    /// Draw the agent's signal of its current value
+    pub fn observe(&mut self, rng: &mut impl Rng) {
+        // noiseless agents don't draw, so their random streams are unchanged
+        self.error = if self.noise > 0.0 {
+            self.noise * stats::standard_normal(rng)
+        } else {
+            0.0
+        };
+    }
+
+    /// If the agent has an order in the market
+    pub fn has_order(&self) -> bool {
+        self.submitted() && !self.withdrawn
+    }
+
+    /// The index of the other side of this agent's trader, if it's one side of a trader
+    ///
+    /// A two-sided trader is endowed with one unit and is a pair of adjacent agents, a seller
+    /// offering the unit followed by a buyer bidding for a second one. Once either side trades, the
+    /// other withdraws its order.
+    pub fn pair(&self, index: usize) -> Option<usize> {
+        self.trader
+            .then(|| if self.buyer { index - 1 } else { index + 1 })
+    }
+
+    /// Make the agent part of a player that only takes part in an observation with `prob`
+    pub fn set_presence(&mut self, prob: f64) {
+        self.presence = Some(prob);
+    }
+
+    /// The probability the agent takes part in each observation, if it may not
+    pub fn presence(&self) -> Option<f64> {
+        self.presence
+    }
+
+    /// If the agent took part in the last observation, which only fractional players may not
+    pub fn present(&self) -> bool {
+        self.present
+    }
+
+    /// Decide whether the agent takes part in the next observation, forgetting the last one if not
+    pub fn attend(&mut self, present: bool) {
+        self.present = present;
+        if !present {
+            self.reset();
+            self.absent = true;
+            self.ce_traded = false;
+        }
+    }
+
+    /// Make the agent one unit of a multi-unit player's lot of `units`
+    pub fn set_unit(&mut self, units: usize, unit: usize) {
+        self.units = (units, unit);
+    }
+
+    /// The number of units in the agent's lot
+    pub fn units(&self) -> usize {
+        self.units.0
+    }
+
+    /// If the agent is the first unit of its lot, which stands for the player
+    pub fn leads(&self) -> bool {
+        self.units.1 == 0
+    }
+
+    /// The indices of the agents in this agent's lot
+    ///
+    /// A player that trades several units is a lot of adjacent agents on the same side, each
+    /// with an order for one unit, whose values form a diminishing value schedule, so the first
+    /// unit is the one the player values most. The orders of a lot reach the market together, and
+    /// each trades on its own, so the player's order can partially fill against several
+    /// counterparties. Every other agent is a lot of one.
+    pub fn lot(&self, index: usize) -> std::ops::Range<usize> {
+        let (units, unit) = self.units;
+        index - unit..index - unit + units
+    }
+
+    /// The distribution of the delay before the agent's orders reach the market, if they have one
+    pub fn latency(&self) -> Option<Latency> {
+        self.latency
+    }
+
+    /// How many orders reach the market before the agent requotes an untraded standing order
+    pub fn requote_after(&self) -> Option<u64> {
+        self.requote.map(|(_, events)| events)
+    }
+
+    /// How many units of the agent's lot are shown in a continuous market's book at once, if only
+    /// some of them are
+    pub fn display(&self) -> Option<usize> {
+        self.display.map(|display| display as usize)
+    }
+
+    /// If the agent's orders to a continuous market are market orders, which trade at the best
+    /// price in the book whatever it is, and are cancelled if there's nothing to trade with
+    pub fn market(&self) -> bool {
+        self.market || self.stopped
+    }
+
+    /// If a trade at `price` triggers the agent's stop loss, so it sells its unit at any price
+    ///
+    /// Only the seller of a two-sided trader, which holds a unit, has a stop, and only until it
+    /// trades or withdraws.
+    pub fn triggers(&self, price: f64) -> bool {
+        self.trader
+            && !self.buyer
+            && !self.stopped
+            && self.has_order()
+            && self.stop.is_some_and(|stop| price <= stop)
+    }
+
+    pub fn stop(&self) -> Option<f64> {
+        self.stop
+    }
+
+    /// Send market orders for the rest of the simulation
+    pub fn trigger(&mut self) {
+        self.stopped = true;
+    }
+
+    /// Improve the agent's order by its requote delta, up to its signal and price bounds,
+    /// returning whether it changed
+    pub fn improve(&mut self) -> bool {
+        let Some((delta, _)) = self.requote else {
+            return false;
+        };
+        let bound = if self.buyer {
+            self.max_price
+        } else {
+            -self.min_price
+        };
+        let limit = (self.sign() * self.signal()).min(bound).min(self.control);
+        let bid = (self.bid + delta).min(limit);
+        let improved = bid > self.bid;
+        if improved {
+            self.bid = bid;
+        }
+        improved
+    }
+
+    /// Draw how long the agent's order takes to reach the market
+    ///
+    /// Agents without a latency don't draw, so their random streams are unchanged.
+    pub fn send(&mut self, rng: &mut impl Rng) -> f64 {
+        self.delay = self.latency.map_or(0.0, |latency| latency.sample(rng));
+        self.delay
+    }
+
+    /// Cancel the agent's order for the rest of the simulation
+    pub fn withdraw(&mut self) {
+        self.withdrawn = true;
+    }
+
+    /// Play a role other than the one of the agent's side
+    pub fn set_role(&mut self, role: &'a str) {
+        self.role = Some(role);
+    }
+
+    /// The role the agent's payoffs are reported for
+    pub fn role(&self) -> &'a str {
+        if let Some(role) = self.role {
+            role
+        } else if self.trader {
+            "traders"
+        } else if self.buyer {
+            "buyers"
+        } else {
+            "sellers"
         }
     }
 
+    /// Map a price to where it falls in the value support
+    fn normalize(&self, price: f64) -> f64 {
+        let (low, high) = self.scale;
+        (price - low) / (high - low)
+    }
+
+    /// The price at a fraction of the agent's price scale
+    pub fn denormalize(&self, frac: f64) -> f64 {
+        let (low, high) = self.scale;
+        low + frac * (high - low)
+    }
+
+    /// How far the agent's bid reaches past a price toward the other side of the market, as a
+    /// fraction of its price scale
+    pub fn reach(&self, price: f64) -> f64 {
+        let (low, high) = self.scale;
+        (self.bid - self.sign() * price) / (high - low)
+    }
+
+    /// The shading the agent drew for the current observation
+    pub fn shading(&self) -> f64 {
+        self.shading
+    }
+
+    pub fn set_shading(&mut self, dist: Shading) {
+        self.dist = dist;
+        self.shading = dist.quantile(0.5);
+    }
+
     pub fn sign(&self) -> f64 {
         if self.buyer {
             1.0
@@ -47,34 +502,126 @@ impl<'a> Agent<'a> {
     }
 
     pub fn transact(&mut self, price: f64) {
-        self.utility = (self.value - price) * self.sign();
+        self.utility = (self.net_value() - price) * self.sign();
         self.traded = true;
+        self.price = Some(price);
     }
 
     fn reset(&mut self) {
         self.utility = 0.0;
         self.traded = false;
+        self.price = None;
+        self.waiting = 0.0;
+        self.delay = 0.0;
+        self.withdrawn = false;
+        self.stopped = false;
     }
 
-    pub fn resample(&mut self) {
-        self.value = rand::random();
+    pub fn resample(&mut self, rng: &mut impl Rng) {
+        self.value = match (self.private_type, &self.pool, self.quantile.take()) {
+            (Some(private), _, _) => match self.type_noise {
+                Some(noise) if noise > 0.0 => private + noise * stats::standard_normal(rng),
+                _ => private,
+            },
+            (None, Some(pool), None) => pool[rng.gen_range(0..pool.len())],
+            (None, Some(pool), Some(quantile)) => {
+                pool[((quantile * pool.len() as f64) as usize).min(pool.len() - 1)]
+            }
+            (None, None, quantile) => {
+                let quantile = quantile.unwrap_or_else(|| rng.gen());
+                self.low + quantile * (self.high - self.low)
+            }
+        };
+        let shading = self.dist.sample(rng);
+        self.shading = self.bidder.choose(shading, rng);
         self.bid = self.value * self.sign();
+        self.error = 0.0;
+        self.absent = false;
+        self.rejected = false;
+        self.blocked = false;
         self.reset();
     }
 
+    /// Give the agent a persistent type, its values are that type plus normal noise with this
+    /// standard deviation, once it's drawn
+    pub fn set_types(&mut self, noise: f64) {
+        self.type_noise = Some(noise);
+    }
+
+    /// Draw the agent's type uniformly from its support, if it has types
+    pub fn draw_type(&mut self, rng: &mut impl Rng) {
+        if self.type_noise.is_some() {
+            self.private_type = Some(self.low + rng.gen::<f64>() * (self.high - self.low));
+        }
+    }
+
+    /// Draw the next value at this quantile of its distribution instead of at random
+    pub fn stratify(&mut self, quantile: f64) {
+        self.quantile = Some(quantile);
+    }
+
+    /// What the agent has learned, if it learns
+    pub fn policy(&self) -> Option<Policy> {
+        self.bidder.policy()
+    }
+
+    /// Update any learned state or history with the payoff of the last simulation
+    pub fn learn(&mut self) {
+        self.bidder.learn(&Outcome {
+            value: self.normalize(self.signal()),
+            price: self.normalize(self.bid * self.sign()),
+            traded: self.traded,
+            payoff: self.utility,
+        });
+    }
+
+    /// Set the bid from the bidding strategy, clipped to the agent's price bounds
+    ///
+    /// Bidding strategies act on the agent's signal of its value and prices, normalized to the
+    /// agent's value support. Prices that are NaN, or still infinite after clipping, reject the
+    /// agent's order for this observation, so it doesn't trade. Orders that violate the market's
+    /// price control are blocked, and don't trade either.
     pub fn shade(&mut self) {
-        self.bid = match (self.style, self.buyer) {
-            (Style::Standard, _) | (Style::Correct, true) => {
-                self.value * (self.sign() - self.shading)
-            }
-            (Style::Correct, false) => (self.value - 1.0) * self.shading - self.value,
-            (Style::Exponential, _) => {
-                self.sign() * self.value * (-self.sign() * self.shading).exp()
-            }
-            (Style::Shift, _) => self.sign() * self.value - self.shading,
-        };
+        self.rebid();
         self.reset();
     }
+
+    /// Shift the agent's value by public news, and bid again with it
+    pub fn shift(&mut self, amount: f64) {
+        self.value += amount;
+        self.rebid();
+    }
+
+    /// If the agent bids again with the state of a continuous market when it arrives
+    pub fn watches(&self) -> bool {
+        self.bidder.watches()
+    }
+
+    /// Bid again with the state of the market, whose prices are unnormalized
+    pub fn watch(&mut self, state: MarketState) {
+        let normalized = MarketState {
+            best_bid: state.best_bid.map(|price| self.normalize(price)),
+            best_ask: state.best_ask.map(|price| self.normalize(price)),
+            last_price: state.last_price.map(|price| self.normalize(price)),
+        };
+        self.bidder.watch(&normalized);
+        self.rebid();
+    }
+
+    fn rebid(&mut self) {
+        let value = self.normalize(self.signal());
+        let frac = self.sign() * self.bidder.bid(self.buyer, value, self.shading);
+        let price = self.denormalize(frac);
+        let clipped = price.clamp(self.min_price, self.max_price);
+        self.rejected = !clipped.is_finite();
+        self.clipped = !self.rejected && clipped != price;
+        self.bid = if self.rejected {
+            f64::NEG_INFINITY
+        } else {
+            self.sign() * clipped
+        };
+        self.blocked = !self.rejected && self.bid > self.control;
+    }
 }
 
 impl<'a> Serialize for Agent<'a> {
@@ -83,7 +630,7 @@ impl<'a> Serialize for Agent<'a> {
         S: Serializer,
     {
         let mut map = serializer.serialize_map(Some(3))?;
-        map.serialize_entry("role", if self.buyer { "buyers" } else { "sellers" })?;
+        map.serialize_entry("role", self.role())?;
         map.serialize_entry("strategy", self.strat)?;
         map.serialize_entry("payoff", &self.utility)?;
         map.end()
@@ -99,7 +646,12 @@ impl FromStr for Style {
             "Exponential" => Ok(Style::Exponential),
             "Shift" => Ok(Style::Shift),
             "Correct" => Ok(Style::Correct),
-            _ => Err(format!("unknwon style: \"{}\"", string)),
+            "Roth" => Ok(Style::Roth),
+            "Bandit" => Ok(Style::Bandit),
+            "External" => Ok(Style::External),
+            "Bne" => Ok(Style::Bne),
+            "Market" => Ok(Style::Market),
+            _ => Err(format!("unknown style: \"{}\"", string)),
         }
     }
 }
@@ -117,9 +669,11 @@ mod tests {
                 Style::Exponential,
                 Style::Shift,
                 Style::Correct,
+                Style::Roth,
+                Style::Bandit,
             ] {
                 for shading in (0..11).map(|s| s as f64 / 10.0) {
-                    let mut agent = Agent::new(buyer, &strat, style, shading);
+                    let mut agent = Agent::new(buyer, strat, style, Shading::Fixed(shading));
                     for _ in 0..100 {
                         agent.reset();
                         agent.shade();
@@ -130,6 +684,142 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_price_bounds() {
+        let params: Strategy = "0.5_l0.2_h0.3".parse().unwrap();
+        for (buyer, value, price, clipped) in [
+            (true, 0.8, 0.3, true),
+            (true, 0.5, 0.25, false),
+            (true, 0.2, 0.2, true),
+            (false, 0.1, 0.2, true),
+        ] {
+            let mut agent = Agent::from_strategy(buyer, "", &params);
+            agent.value = value;
+            agent.shade();
+            assert!((agent.bid - agent.sign() * price).abs() < 1e-9);
+            assert_eq!(agent.clipped, clipped);
+        }
+    }
+
+    /// A bidder that always returns the same bid
+    #[derive(Debug, Clone)]
+    struct Constant(f64);
+
+    impl BiddingStrategy for Constant {
+        fn bid(&mut self, _: bool, _: f64, _: f64) -> f64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_rejected() {
+        let bounded: Strategy = "0_h0.9".parse().unwrap();
+        for (bid, buyer, params, rejected) in [
+            (f64::NAN, true, Strategy::new(Shading::Fixed(0.0)), true),
+            (
+                f64::INFINITY,
+                true,
+                Strategy::new(Shading::Fixed(0.0)),
+                true,
+            ),
+            (
+                f64::NEG_INFINITY,
+                false,
+                Strategy::new(Shading::Fixed(0.0)),
+                true,
+            ),
+            (f64::INFINITY, true, bounded, false),
+            (0.5, true, Strategy::new(Shading::Fixed(0.0)), false),
+        ] {
+            let mut agent = Agent::from_strategy(buyer, "", &params);
+            agent.set_bidder(Box::new(Constant(bid)));
+            agent.value = 0.5;
+            agent.shade();
+            assert_eq!(agent.rejected, rejected);
+            assert!(!agent.bid.is_nan());
+        }
+    }
+
+    #[test]
+    fn test_price_control() {
+        for (buyer, value, blocked) in [
+            (true, 0.8, true),
+            (true, 0.4, false),
+            (false, 0.3, true),
+            (false, 0.6, false),
+        ] {
+            let mut agent = Agent::new(buyer, "", Style::Standard, Shading::Fixed(0.0));
+            agent.set_price_control(0.5);
+            agent.value = value;
+            agent.shade();
+            assert_eq!(agent.blocked, blocked);
+            assert_eq!(agent.has_order(), !blocked);
+        }
+    }
+
+    #[test]
+    fn test_support() {
+        let mut rng = rand::thread_rng();
+        let params: Strategy = "0.5_Correct".parse().unwrap();
+        let mut agent = Agent::from_strategy(false, "", &params);
+        agent.set_support(2.0, 4.0);
+        for _ in 0..100 {
+            agent.resample(&mut rng);
+            assert!((2.0..=4.0).contains(&agent.value));
+        }
+        // correct sellers shade halfway to the top of the support
+        agent.value = 3.0;
+        agent.shade();
+        assert!((agent.bid + 3.5).abs() < 1e-9);
+
+        // with buyers' values below, sellers shade toward the top of the whole market
+        agent.set_scale(-2.0, 4.0);
+        agent.shade();
+        assert!((agent.bid + 3.5).abs() < 1e-9);
+        agent.value = -1.0;
+        agent.shade();
+        assert!((agent.bid + 1.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_noise() {
+        let mut rng = rand::thread_rng();
+        let mut agent = Agent::new(true, "", Style::Standard, Shading::Fixed(0.0));
+        agent.value = 0.5;
+        agent.observe(&mut rng);
+        assert_eq!(agent.signal(), 0.5);
+        agent.set_noise(0.1);
+        agent.observe(&mut rng);
+        agent.shade();
+        // truthful agents bid their signal, but are paid by their value
+        assert_eq!(agent.bid, agent.signal());
+        agent.transact(0.5);
+        assert_eq!(agent.utility, 0.0);
+    }
+
+    #[test]
+    fn test_entry() {
+        let params: Strategy = "0_c0.1".parse().unwrap();
+        for (value, price, absent, payoff) in [
+            (0.8, Some(0.5), false, -0.1),
+            (0.55, Some(0.5), true, 0.0),
+            (0.8, None, true, 0.0),
+        ] {
+            let mut agent = Agent::from_strategy(true, "", &params);
+            agent.value = value;
+            agent.enter(price);
+            agent.shade();
+            assert_eq!(agent.absent, absent);
+            assert_eq!(agent.has_order(), !absent);
+            agent.settle();
+            assert_eq!(agent.utility, payoff);
+        }
+        let mut agent = Agent::from_strategy(true, "", &params);
+        agent.value = 0.8;
+        agent.transact(0.5);
+        assert!((agent.utility - 0.2).abs() < 1e-9);
+    }
+
     #[test]
     fn test_inverse_enum() {
         for style in [
@@ -137,6 +827,11 @@ mod tests {
             Style::Exponential,
             Style::Shift,
             Style::Correct,
+            Style::Roth,
+            Style::Bandit,
+            Style::External,
+            Style::Bne,
+            Style::Market,
         ] {
             let string = format!("{:?}", style);
             let copy: Style = string.parse().unwrap();
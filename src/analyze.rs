@@ -0,0 +1,210 @@
+use crate::stats::{self, Test};
+use crate::stream;
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+/// Compare two files of observations
+///
+/// Reads the output of two runs, e.g. of the same profile under two mechanisms, and tests whether
+/// the surplus and the payoff of every strategy differ between them. The payoff of a strategy in an
+/// observation is the mean payoff of the players in its role playing it. The output is a single
+/// line of json with the means, Welch's t-test, a Mann-Whitney U test, and a percentile bootstrap
+/// confidence interval of the difference, second minus first, for every statistic observed in both
+/// files.
+pub struct AnalyzeArgs {
+    /// Observations of the first run, optionally compressed
+    #[clap(value_parser)]
+    first: PathBuf,
+
+    /// Observations of the second run, optionally compressed
+    #[clap(value_parser)]
+    second: PathBuf,
+
+    /// Number of bootstrap resamples
+    #[clap(long, value_parser, default_value_t = 1000)]
+    bootstrap: u64,
+
+    /// Confidence level of the bootstrap intervals
+    #[clap(long, value_parser, default_value_t = 0.95)]
+    confidence: f64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Player {
+    role: String,
+    strategy: String,
+    payoff: f64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Features {
+    surplus: f64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Observation {
+    players: Vec<Player>,
+    features: Features,
+}
+
+/// Samples of every statistic in a file, one per observation it appears in
+#[derive(Debug, Default)]
+struct Samples {
+    surplus: Vec<f64>,
+    payoffs: BTreeMap<(String, String), Vec<f64>>,
+}
+
+#[derive(Serialize, Debug)]
+struct Comparison {
+    first: f64,
+    second: f64,
+    difference: f64,
+    welch: Test,
+    mann_whitney: Test,
+    interval: [f64; 2],
+}
+
+/// The comparisons of each role's strategies
+type Payoffs = BTreeMap<String, BTreeMap<String, Comparison>>;
+
+#[derive(Serialize, Debug)]
+struct Analysis {
+    surplus: Option<Comparison>,
+    payoffs: Payoffs,
+}
+
+pub fn analyze(args: &AnalyzeArgs, out: &mut impl Write) -> io::Result<()> {
+    if args.bootstrap == 0 || !(0.0 < args.confidence && args.confidence < 1.0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "analyze needs at least one bootstrap resample and a confidence in (0, 1)",
+        ));
+    }
+    let first = read_samples(&args.first)?;
+    let mut second = read_samples(&args.second)?;
+    let mut rng = rand::thread_rng();
+    let mut compare = |one: &[f64], two: &[f64]| {
+        // tests need at least two samples from each
+        (one.len() > 1 && two.len() > 1).then(|| {
+            let (first, second) = (stats::mean(one), stats::mean(two));
+            Comparison {
+                first,
+                second,
+                difference: second - first,
+                welch: stats::welch(one, two),
+                mann_whitney: stats::mann_whitney(one, two),
+                interval: stats::bootstrap_diff(
+                    one,
+                    two,
+                    args.bootstrap,
+                    args.confidence,
+                    &mut rng,
+                ),
+            }
+        })
+    };
+
+    let mut analysis = Analysis {
+        surplus: compare(&first.surplus, &second.surplus),
+        payoffs: Payoffs::default(),
+    };
+    for ((role, strat), pays) in first.payoffs {
+        let comparison = match second.payoffs.remove(&(role.clone(), strat.clone())) {
+            Some(other) => compare(&pays, &other),
+            None => None,
+        };
+        let comparisons = analysis.payoffs.entry(role).or_default();
+        if let Some(comparison) = comparison {
+            comparisons.insert(strat, comparison);
+        }
+    }
+    serde_json::to_writer(&mut *out, &analysis)?;
+    writeln!(out)
+}
+
+fn read_samples(path: &Path) -> io::Result<Samples> {
+    let input = stream::decompress(BufReader::new(File::open(path)?))?;
+    parse_samples(input)
+}
+
+fn parse_samples(input: impl BufRead) -> io::Result<Samples> {
+    let mut samples = Samples::default();
+    for line in input.lines() {
+        let obs: Observation = serde_json::from_str(&line?)?;
+        samples.surplus.push(obs.features.surplus);
+        let mut totals: BTreeMap<(String, String), (f64, usize)> = BTreeMap::new();
+        for player in obs.players {
+            let total = totals.entry((player.role, player.strategy)).or_default();
+            total.0 += player.payoff;
+            total.1 += 1;
+        }
+        for (key, (total, count)) in totals {
+            samples
+                .payoffs
+                .entry(key)
+                .or_default()
+                .push(total / count as f64);
+        }
+    }
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AnalyzeArgs;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_roles() {
+        let obs = [
+            r#"{"players":[{"role":"background","strategy":"0","payoff":1.0},{"role":"sellers","strategy":"0","payoff":0.5}],"features":{"surplus":1.5}}"#,
+            r#"{"players":[{"role":"background","strategy":"0","payoff":0.0},{"role":"sellers","strategy":"0","payoff":0.0}],"features":{"surplus":0.0}}"#,
+        ]
+        .join("\n");
+        let path = env::temp_dir().join(format!("cdasim-analyze-{}", std::process::id()));
+        fs::write(&path, obs).unwrap();
+        let args = AnalyzeArgs {
+            first: path.clone(),
+            second: path.clone(),
+            bootstrap: 10,
+            confidence: 0.95,
+        };
+        let mut out = Vec::new();
+        super::analyze(&args, &mut out).unwrap();
+        fs::remove_file(&path).unwrap();
+        let analysis: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        // each role's strategies are compared apart, even with the same names
+        let payoffs = analysis["payoffs"].as_object().unwrap();
+        assert_eq!(
+            payoffs.keys().collect::<Vec<_>>(),
+            ["background", "sellers"]
+        );
+        assert_eq!(payoffs["background"]["0"]["first"], 0.5);
+        assert_eq!(payoffs["sellers"]["0"]["first"], 0.25);
+    }
+
+    #[test]
+    fn test_parse_samples() {
+        let input = [
+            r#"{"players":[{"role":"buyers","strategy":"a","payoff":1.0},{"role":"buyers","strategy":"a","payoff":0.0},{"role":"sellers","strategy":"b","payoff":0.5}],"features":{"surplus":1.5}}"#,
+            r#"{"players":[{"role":"buyers","strategy":"a","payoff":0.0},{"role":"buyers","strategy":"a","payoff":0.0},{"role":"sellers","strategy":"b","payoff":0.0}],"features":{"surplus":0.0}}"#,
+        ]
+        .join("\n");
+        let samples = super::parse_samples(input.as_bytes()).unwrap();
+        assert_eq!(samples.surplus, [1.5, 0.0]);
+        assert_eq!(
+            samples.payoffs[&("buyers".to_owned(), "a".to_owned())],
+            [0.5, 0.0]
+        );
+        assert_eq!(
+            samples.payoffs[&("sellers".to_owned(), "b".to_owned())],
+            [0.5, 0.0]
+        );
+    }
+}
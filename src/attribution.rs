@@ -0,0 +1,157 @@
+use crate::market::{Call, Cda, Market};
+use crate::strategy::{Presets, Shading};
+use crate::Agent;
+use clap::Parser;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+
+#[derive(Parser, Debug)]
+/// Attribute the surplus of each spec to the strategies that produced it
+///
+/// Takes the same spec lines as the default command on stdin. Each sample orders the strategies of
+/// every role at random, then adds them one at a time to a market that starts without them,
+/// crediting each with the change in total surplus. Every market in a sample is simulated with
+/// the same random seed, so the differences reflect the strategies rather than the draws. Each
+/// line of output has an "attribution" map from role to strategy to its mean contribution, a
+/// Shapley value, and the contributions sum to the surplus of the full market less that of the
+/// market without any of them.
+pub struct AttributionArgs {
+    /// Number of random orderings of the strategies to average over
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 1000)]
+    samples: u64,
+
+    /// Replace the agents of strategies not yet added with truthful ones instead of removing them
+    #[clap(long, value_parser)]
+    truthful: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct Analysis<'a> {
+    attribution: BTreeMap<&'a str, BTreeMap<&'a str, f64>>,
+}
+
+pub fn attribution(
+    args: &AttributionArgs,
+    presets: &Presets,
+    strict: bool,
+    ihandle: impl BufRead,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    for (index, line) in ihandle.lines().enumerate() {
+        let _span = tracing::info_span!("spec", line = index).entered();
+        let spec = crate::parse_spec(&line?, strict, presets)?;
+        let agents = crate::build_agents(&spec, presets)?;
+        let attribution = match crate::continuous_market(&spec.configuration, &agents, false)? {
+            Some(market) => shapley(&agents, &market, args),
+            None if spec.configuration.cda.unwrap_or(true) => shapley(&agents, &Cda, args),
+            None => shapley(&agents, &Call, args),
+        };
+        serde_json::to_writer(&mut *out, &Analysis { attribution })?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Estimate the Shapley value of each role's strategies by sampling orderings of them
+fn shapley<'a>(
+    agents: &[Agent<'a>],
+    market: &impl Market,
+    args: &AttributionArgs,
+) -> BTreeMap<&'a str, BTreeMap<&'a str, f64>> {
+    let mut groups: Vec<_> = agents.iter().map(|a| (a.role(), a.strategy())).collect();
+    groups.sort_unstable();
+    groups.dedup();
+    // the group of every agent, so a trader's sides and a player's units stay together
+    let members: Vec<_> = agents
+        .iter()
+        .map(|a| groups.binary_search(&(a.role(), a.strategy())).unwrap())
+        .collect();
+
+    let mut rng = rand::thread_rng();
+    let mut order: Vec<_> = (0..groups.len()).collect();
+    let mut totals = vec![0.0; groups.len()];
+    for _ in 0..args.samples {
+        order.shuffle(&mut rng);
+        let seed = rng.gen();
+        let mut added = vec![false; groups.len()];
+        let mut last = surplus(agents, &members, &added, market, seed, args.truthful);
+        for &group in &order {
+            added[group] = true;
+            let next = surplus(agents, &members, &added, market, seed, args.truthful);
+            totals[group] += next - last;
+            last = next;
+        }
+    }
+
+    let mut attribution: BTreeMap<_, BTreeMap<_, _>> = BTreeMap::new();
+    for ((role, strat), total) in groups.into_iter().zip(totals) {
+        attribution
+            .entry(role)
+            .or_default()
+            .insert(strat, total / args.samples as f64);
+    }
+    attribution
+}
+
+/// The surplus of one simulation with only the agents of added groups, or the rest truthful
+fn surplus(
+    agents: &[Agent<'_>],
+    members: &[usize],
+    added: &[bool],
+    market: &impl Market,
+    seed: u64,
+    truthful: bool,
+) -> f64 {
+    let mut coalition = Vec::with_capacity(agents.len());
+    for (agent, &group) in agents.iter().zip(members) {
+        if added[group] {
+            coalition.push(agent.clone());
+        } else if truthful {
+            let mut agent = agent.clone();
+            agent.set_shading(Shading::Fixed(0.0));
+            coalition.push(agent);
+        }
+    }
+    crate::run_sim(&mut coalition, market, &mut StdRng::seed_from_u64(seed)).surplus
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AttributionArgs;
+    use std::collections::HashMap;
+
+    fn attribute(input: &str, truthful: bool) -> serde_json::Value {
+        let mut out = Vec::new();
+        let args = AttributionArgs {
+            samples: 20,
+            truthful,
+        };
+        super::attribution(&args, &HashMap::new(), false, input.as_bytes(), &mut out).unwrap();
+        serde_json::from_slice(&out).unwrap()
+    }
+
+    #[test]
+    fn test_removed_attribution() {
+        let input = r#"{"assignment":{"buyers":{"0":3,"0.5":1},"sellers":{"0":3}},"configuration":{"cda":false}}"#;
+        let analysis = attribute(input, false);
+        let attribution = &analysis["attribution"];
+        // a side can't trade alone, so both sides are credited with what they trade
+        assert!(attribution["buyers"]["0"].as_f64().unwrap() > 0.0);
+        assert!(attribution["sellers"]["0"].as_f64().unwrap() > 0.0);
+        assert!(attribution["buyers"]["0.5"].is_f64());
+    }
+
+    #[test]
+    fn test_truthful_attribution() {
+        let input = r#"{"assignment":{"buyers":{"0":2},"sellers":{"0":2}},"configuration":{}}"#;
+        let analysis = attribute(input, true);
+        let attribution = &analysis["attribution"];
+        // replacing truthful agents with truthful agents changes nothing
+        assert_eq!(attribution["buyers"]["0"].as_f64().unwrap(), 0.0);
+        assert_eq!(attribution["sellers"]["0"].as_f64().unwrap(), 0.0);
+    }
+}
@@ -0,0 +1,270 @@
+use crate::agent::Style;
+use crate::bne::Bne;
+use crate::learner::{Bandit, Learner, Policy, RothErev};
+use rand::RngCore;
+use serde::Serialize;
+use std::fmt;
+
+/// The result of an agent's participation in one simulation
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Outcome {
+    pub value: f64,
+    pub price: f64,
+    pub traded: bool,
+    pub payoff: f64,
+}
+
+/// What an agent sees of a continuous market when it arrives, before its order reaches it
+///
+/// Prices are normalized like values, and are None if there isn't one yet, like the best ask of
+/// a book without asks, or the last price before anything traded.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct MarketState {
+    pub best_bid: Option<f64>,
+    pub best_ask: Option<f64>,
+    pub last_price: Option<f64>,
+}
+
+/// How an agent turns its value and shading into a bid
+///
+/// Bids are signed so that higher is always more aggressive: buyers bid the price they'd pay, and
+/// sellers bid the negative of the price they'd accept. Values and prices, including those in
+/// [Outcome]s, are normalized so that the market's value support is [0, 1], while payoffs are left
+/// unnormalized. Every built in [Style] is implemented with this trait, and new strategies can be
+/// given to agents with [crate::Agent::with_bidder] without touching [Style], including from
+/// other crates:
+///
+/// ```
+/// use cdasim::{Agent, BiddingStrategy, Call, Market, Shading, Style};
+///
+/// /// Bid halfway between the value and the middle of the support
+/// #[derive(Debug, Clone)]
+/// struct Middle;
+///
+/// impl BiddingStrategy for Middle {
+///     fn bid(&mut self, buyer: bool, value: f64, _shading: f64) -> f64 {
+///         let sign = if buyer { 1.0 } else { -1.0 };
+///         sign * (value + 0.5) / 2.0
+///     }
+/// }
+///
+/// let mut buyer = Agent::with_bidder(true, "middle", Box::new(Middle), Shading::Fixed(0.0));
+/// buyer.value = 0.9;
+/// let mut seller = Agent::new(false, "truthful", Style::Correct, Shading::Fixed(0.0));
+/// seller.value = 0.5;
+/// let mut agents = [buyer, seller];
+/// agents.iter_mut().for_each(Agent::shade);
+/// assert_eq!(agents[0].bid, 0.7);
+/// let price = Call.simulate(&mut agents, &mut rand::thread_rng());
+/// assert_eq!(price, Some(0.6));
+/// ```
+pub trait BiddingStrategy: BoxClone + fmt::Debug + Send {
+    /// The signed bid of an agent
+    fn bid(&mut self, buyer: bool, value: f64, shading: f64) -> f64;
+
+    /// Adjust the shading drawn for a new observation
+    fn choose(&mut self, shading: f64, _rng: &mut dyn RngCore) -> f64 {
+        shading
+    }
+
+    /// Update any state with the outcome of the last simulation
+    fn learn(&mut self, _outcome: &Outcome) {}
+
+    /// If the strategy bids again with the state of a continuous market when its agent arrives
+    fn watches(&self) -> bool {
+        false
+    }
+
+    /// Update any state with the state of the market, before bidding again
+    fn watch(&mut self, _state: &MarketState) {}
+
+    /// What the strategy has learned, if it learns
+    fn policy(&self) -> Option<Policy> {
+        None
+    }
+
+    /// The built in style of the strategy, if its bid depends on nothing but its arguments
+    fn style(&self) -> Option<Style> {
+        None
+    }
+}
+
+/// Cloning for boxed bidding strategies, implemented for every cloneable strategy
+pub trait BoxClone {
+    fn box_clone(&self) -> Box<dyn BiddingStrategy>;
+}
+
+impl<T: BiddingStrategy + Clone + 'static> BoxClone for T {
+    fn box_clone(&self) -> Box<dyn BiddingStrategy> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn BiddingStrategy> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+fn sign(buyer: bool) -> f64 {
+    if buyer {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+impl Style {
+    /// The bidding strategy of a built in style
+    ///
+    /// `epsilon` makes Bandit agents epsilon-greedy. External agents must be connected to a process
+    /// before they can bid, and have their orders rejected until they are. Bne agents bid
+    /// truthfully until their equilibrium is solved. Market agents only bid where markets don't
+    /// take market orders, and bid truthfully there.
+    pub fn bidder(self, epsilon: Option<f64>) -> Box<dyn BiddingStrategy> {
+        match self {
+            Style::Standard => Box::new(Standard),
+            Style::Exponential => Box::new(Exponential),
+            Style::Shift => Box::new(Shift),
+            Style::Correct => Box::new(Correct),
+            Style::Roth => Box::new(Learning(Learner::Roth(RothErev::new()))),
+            Style::Bandit => Box::new(Learning(Learner::Bandit(Bandit::new(epsilon)))),
+            Style::External => Box::new(Unconnected),
+            Style::Bne => Box::new(Bne::truthful()),
+            Style::Market => Box::new(Standard),
+        }
+    }
+}
+
+/// Bid a fraction of value
+#[derive(Debug, Clone, Copy)]
+pub struct Standard;
+
+impl BiddingStrategy for Standard {
+    fn bid(&mut self, buyer: bool, value: f64, shading: f64) -> f64 {
+        value * (sign(buyer) - shading)
+    }
+
+    fn style(&self) -> Option<Style> {
+        Some(Style::Standard)
+    }
+}
+
+/// Scale value exponentially in shading
+#[derive(Debug, Clone, Copy)]
+pub struct Exponential;
+
+impl BiddingStrategy for Exponential {
+    fn bid(&mut self, buyer: bool, value: f64, shading: f64) -> f64 {
+        let sign = sign(buyer);
+        sign * value * (-sign * shading).exp()
+    }
+
+    fn style(&self) -> Option<Style> {
+        Some(Style::Exponential)
+    }
+}
+
+/// Shift value by a constant
+#[derive(Debug, Clone, Copy)]
+pub struct Shift;
+
+impl BiddingStrategy for Shift {
+    fn bid(&mut self, buyer: bool, value: f64, shading: f64) -> f64 {
+        sign(buyer) * value - shading
+    }
+
+    fn style(&self) -> Option<Style> {
+        Some(Style::Shift)
+    }
+}
+
+/// Shade sellers toward the top of the value support instead of toward zero
+#[derive(Debug, Clone, Copy)]
+pub struct Correct;
+
+impl BiddingStrategy for Correct {
+    fn bid(&mut self, buyer: bool, value: f64, shading: f64) -> f64 {
+        if buyer {
+            value * (1.0 - shading)
+        } else {
+            (value - 1.0) * shading - value
+        }
+    }
+
+    fn style(&self) -> Option<Style> {
+        Some(Style::Correct)
+    }
+}
+
+/// Bid like [Standard] with a shading level chosen by a learner
+#[derive(Debug, Clone)]
+pub struct Learning(pub Learner);
+
+impl BiddingStrategy for Learning {
+    fn bid(&mut self, buyer: bool, value: f64, shading: f64) -> f64 {
+        Standard.bid(buyer, value, shading)
+    }
+
+    fn choose(&mut self, shading: f64, mut rng: &mut dyn RngCore) -> f64 {
+        shading * self.0.choose(&mut rng)
+    }
+
+    fn learn(&mut self, outcome: &Outcome) {
+        self.0.update(outcome.payoff)
+    }
+
+    fn policy(&self) -> Option<Policy> {
+        Some(self.0.policy())
+    }
+}
+
+/// An External agent that hasn't been connected to a process, whose orders are always rejected
+#[derive(Debug, Clone, Copy)]
+struct Unconnected;
+
+impl BiddingStrategy for Unconnected {
+    fn bid(&mut self, _: bool, _: f64, _: f64) -> f64 {
+        f64::NAN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BiddingStrategy, Outcome, Standard};
+    use crate::agent::Style;
+
+    #[test]
+    fn test_truthful_at_zero() {
+        for style in [
+            Style::Standard,
+            Style::Exponential,
+            Style::Shift,
+            Style::Correct,
+            Style::Roth,
+            Style::Bandit,
+            Style::Bne,
+            Style::Market,
+        ] {
+            let mut bidder = style.bidder(None);
+            for buyer in [false, true] {
+                let sign = if buyer { 1.0 } else { -1.0 };
+                assert_eq!(bidder.bid(buyer, 0.7, 0.0), sign * 0.7);
+            }
+        }
+    }
+
+    #[test]
+    fn test_box_clone() {
+        let mut bidder: Box<dyn BiddingStrategy> = Style::Bandit.bidder(Some(0.1));
+        let copy = bidder.clone();
+        bidder.learn(&Outcome {
+            value: 0.5,
+            price: 0.4,
+            traded: true,
+            payoff: 0.1,
+        });
+        assert_ne!(bidder.policy(), copy.policy());
+        assert!(Standard.policy().is_none());
+    }
+}
@@ -0,0 +1,207 @@
+use crate::bidding::{BiddingStrategy, Correct};
+use crate::strategy::Shading;
+use crate::{Agent, Market};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::Deserialize;
+use std::sync::Arc;
+
+/// How to numerically solve for equilibrium bidding functions
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BneConfig {
+    /// Number of evenly spaced values the bidding function is solved at
+    #[serde(default = "default_grid")]
+    pub grid: usize,
+    /// Number of evenly spaced shadings considered at each value
+    #[serde(default = "default_grid")]
+    pub shadings: usize,
+    /// Number of simulations used to estimate the payoff of each shading
+    #[serde(default = "default_samples")]
+    pub samples: u64,
+    /// Number of rounds of best response
+    #[serde(default = "default_iterations")]
+    pub iterations: u64,
+}
+
+fn default_grid() -> usize {
+    11
+}
+
+fn default_samples() -> u64 {
+    200
+}
+
+fn default_iterations() -> u64 {
+    10
+}
+
+impl Default for BneConfig {
+    fn default() -> Self {
+        BneConfig {
+            grid: default_grid(),
+            shadings: default_grid(),
+            samples: default_samples(),
+            iterations: default_iterations(),
+        }
+    }
+}
+
+impl BneConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.grid < 2 || self.shadings < 2 || self.samples == 0 {
+            Err("bne needs a grid and shadings of at least 2 and at least one sample".to_owned())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A bidding function solved for the equilibrium of one market
+///
+/// The function is the shading of a [Correct] agent at evenly spaced normalized values, and is
+/// linearly interpolated between them, so buyers shade toward the bottom of the market's support
+/// and sellers toward its top. The agent's own shading is ignored.
+#[derive(Debug, Clone)]
+pub struct Bne {
+    buyers: Arc<[f64]>,
+    sellers: Arc<[f64]>,
+}
+
+impl Bne {
+    /// Truthful bidding, which agents use until an equilibrium is solved for
+    pub fn truthful() -> Self {
+        Bne {
+            buyers: Arc::new([0.0, 0.0]),
+            sellers: Arc::new([0.0, 0.0]),
+        }
+    }
+
+    /// The shading at a normalized value
+    pub fn shading(&self, buyer: bool, value: f64) -> f64 {
+        let table = if buyer { &self.buyers } else { &self.sellers };
+        let pos = value.clamp(0.0, 1.0) * (table.len() - 1) as f64;
+        let low = (pos.floor() as usize).min(table.len() - 2);
+        let frac = pos - low as f64;
+        table[low] * (1.0 - frac) + table[low + 1] * frac
+    }
+}
+
+impl BiddingStrategy for Bne {
+    fn bid(&mut self, buyer: bool, value: f64, _: f64) -> f64 {
+        Correct.bid(buyer, value, self.shading(buyer, value))
+    }
+}
+
+/// Solve for a symmetric equilibrium of every agent in a market bidding with the same function
+///
+/// Each round, a buyer and a seller deviate to the best shading on a grid at each of the grid's
+/// values, estimated against everyone else bidding with the current function, using common random
+/// numbers across shadings. The function then moves halfway to the best responses. Solving is
+/// seeded, so the same market always produces the same function.
+pub fn solve(agents: &[Agent<'_>], market: &impl Market, config: &BneConfig) -> Bne {
+    let mut bne = Bne {
+        buyers: vec![0.0; config.grid].into(),
+        sellers: vec![0.0; config.grid].into(),
+    };
+    let step = |num: usize| 1.0 / (num - 1) as f64;
+    for round in 0..config.iterations {
+        let mut population = agents.to_vec();
+        for agent in population.iter_mut() {
+            agent.set_bidder(Box::new(bne.clone()));
+        }
+        let mut next = [bne.buyers.to_vec(), bne.sellers.to_vec()];
+        for (side, buyer) in [true, false].into_iter().enumerate() {
+            // traders' payoffs depend on both of their orders, so only plain agents deviate
+            let Some(deviator) = population
+                .iter()
+                .position(|a| a.buyer == buyer && !a.trader)
+            else {
+                continue;
+            };
+            let original = population[deviator].clone();
+            for (point, shading) in next[side].iter_mut().enumerate() {
+                let value = point as f64 * step(config.grid);
+                let seed = (round * 2 + side as u64) * config.grid as u64 + point as u64;
+                let best = (0..config.shadings)
+                    .map(|ind| ind as f64 * step(config.shadings))
+                    .map(|candidate| {
+                        population[deviator] = original.clone();
+                        population[deviator].set_bidder(Box::new(Correct));
+                        population[deviator].set_shading(Shading::Fixed(candidate));
+                        let payoff = payoff(&mut population, deviator, value, market, seed, config);
+                        (candidate, payoff)
+                    })
+                    .fold((0.0, f64::NEG_INFINITY), |best, point| {
+                        if point.1 > best.1 {
+                            point
+                        } else {
+                            best
+                        }
+                    })
+                    .0;
+                *shading = (*shading + best) / 2.0;
+            }
+            population[deviator] = original;
+        }
+        let [buyers, sellers] = next;
+        bne = Bne {
+            buyers: buyers.into(),
+            sellers: sellers.into(),
+        };
+    }
+    bne
+}
+
+/// The mean payoff of an agent at a fixed normalized value
+fn payoff(
+    agents: &mut [Agent<'_>],
+    deviator: usize,
+    value: f64,
+    market: &impl Market,
+    seed: u64,
+    config: &BneConfig,
+) -> f64 {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut total = 0.0;
+    for _ in 0..config.samples {
+        agents.iter_mut().for_each(|a| a.resample(&mut rng));
+        agents[deviator].value = agents[deviator].denormalize(value);
+        agents.iter_mut().for_each(Agent::shade);
+        market.simulate(agents, &mut rng);
+        agents.iter_mut().for_each(Agent::settle);
+        total += agents[deviator].utility;
+    }
+    total / config.samples as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bne, BneConfig};
+    use crate::strategy::Shading;
+    use crate::{Agent, Call, Style};
+
+    #[test]
+    fn test_bne() {
+        let truthful = Bne::truthful();
+        assert_eq!(truthful.shading(true, 0.3), 0.0);
+
+        let agents: Vec<_> = [true, false]
+            .into_iter()
+            .map(|buyer| Agent::new(buyer, "", Style::Standard, Shading::Fixed(0.0)))
+            .collect();
+        let config = BneConfig {
+            samples: 50,
+            iterations: 3,
+            ..BneConfig::default()
+        };
+        let bne = super::solve(&agents, &Call, &config);
+        let again = super::solve(&agents, &Call, &config);
+        // a bilateral call market rewards shading, and solving is deterministic
+        assert!(bne.shading(true, 0.8) > 0.0);
+        assert!(bne.shading(false, 0.2) > 0.0);
+        assert_eq!(bne.shading(true, 0.5), again.shading(true, 0.5));
+        assert!(config.validate().is_ok());
+        assert!(BneConfig { grid: 1, ..config }.validate().is_err());
+    }
+}
@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+/// How much of a batch run has been output
+///
+/// Every spec line before `spec` is complete, and the first `obs` observations of line `spec` have
+/// been written.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub spec: usize,
+    pub obs: u64,
+}
+
+impl Progress {
+    /// The first observation of a spec line that still needs to be output, or None if it's done
+    pub fn remaining(&self, spec: usize) -> Option<u64> {
+        if spec < self.spec {
+            None
+        } else if spec == self.spec {
+            Some(self.obs)
+        } else {
+            Some(0)
+        }
+    }
+}
+
+/// Load progress from a checkpoint file, starting from scratch if it doesn't exist
+pub fn load(path: &Path) -> io::Result<Progress> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(serde_json::from_str(&contents)?),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(Progress::default()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Save progress, replacing the checkpoint file atomically so an interrupt can't corrupt it
+pub fn save(path: &Path, progress: Progress) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, serde_json::to_string(&progress)?)?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Progress;
+
+    #[test]
+    fn test_round_trip() {
+        let path = std::env::temp_dir().join(format!("cdasim-progress-{}", std::process::id()));
+        assert_eq!(super::load(&path).unwrap(), Progress::default());
+        let progress = Progress { spec: 3, obs: 7 };
+        super::save(&path, progress).unwrap();
+        assert_eq!(super::load(&path).unwrap(), progress);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(progress.remaining(2), None);
+        assert_eq!(progress.remaining(3), Some(7));
+        assert_eq!(progress.remaining(4), Some(0));
+    }
+}
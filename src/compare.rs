@@ -0,0 +1,267 @@
+use crate::expr::Expr;
+use crate::market::{Call, Cda};
+use crate::strategy::Presets;
+use crate::{stats, Agent, Spec};
+use clap::Parser;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use std::io::{self, BufRead, Write};
+use tracing::warn;
+
+#[derive(Parser, Debug)]
+/// Decide which of two specs has a higher expected feature by simulating them until it's clear
+///
+/// Takes pairs of consecutive spec lines on stdin, e.g. the same profile under two mechanisms.
+/// Both specs of a pair are simulated with the same random seed for every observation, so the
+/// difference of their features doesn't vary with the draws they share. After every observation
+/// past --min-obs, an asymptotic confidence sequence of the mean difference is checked, which
+/// holds at every number of observations at once, so stopping as soon as it excludes zero still
+/// errs with probability at most --alpha. Each pair produces a line with the "first" and "second"
+/// spec indices, the "decision" of which is higher or "undecided" at --max-obs, the
+/// "observations" simulated, and the "difference", second minus first, with the "radius" of its
+/// confidence sequence. Observations where either spec lacks the feature, like a price when no one
+/// trades, don't count toward the test. Episodes of burn in aren't simulated.
+pub struct CompareArgs {
+    /// Feature to compare, including derived features
+    #[clap(long, value_parser, default_value = "surplus")]
+    feature: String,
+
+    /// Probability of deciding for the wrong spec, or either when they're the same
+    #[clap(long, value_parser = probability, default_value_t = 0.05)]
+    alpha: f64,
+
+    /// Observations before the first check, which the confidence sequence is also tightest at
+    #[clap(long, value_parser = clap::value_parser!(u64).range(2..), default_value_t = 100)]
+    min_obs: u64,
+
+    /// Observations to give up at as undecided
+    #[clap(long, value_parser, default_value_t = 100_000)]
+    max_obs: u64,
+
+    /// Make comparisons reproducible by drawing every observation's seed from this
+    #[clap(long, value_parser)]
+    seed: Option<u64>,
+}
+
+/// Which spec of a pair has the higher expected feature
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Decision {
+    First,
+    Second,
+    Undecided,
+}
+
+#[derive(Serialize, Debug)]
+struct Difference {
+    mean: f64,
+    radius: f64,
+}
+
+#[derive(Serialize, Debug)]
+struct Comparison {
+    first: usize,
+    second: usize,
+    decision: Decision,
+    observations: u64,
+    difference: Difference,
+}
+
+/// Running mean and variance of differences with Welford's algorithm
+#[derive(Debug, Default)]
+struct Running {
+    count: u64,
+    mean: f64,
+    squares: f64,
+}
+
+impl Running {
+    fn add(&mut self, sample: f64) {
+        self.count += 1;
+        let delta = sample - self.mean;
+        self.mean += delta / self.count as f64;
+        self.squares += delta * (sample - self.mean);
+    }
+
+    fn variance(&self) -> f64 {
+        self.squares / (self.count as f64 - 1.0)
+    }
+}
+
+pub fn compare(
+    args: &CompareArgs,
+    presets: &Presets,
+    strict: bool,
+    ihandle: impl BufRead,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let lines = ihandle.lines().collect::<io::Result<Vec<_>>>()?;
+    if lines.len() % 2 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("specs are compared in pairs, but there are {}", lines.len()),
+        ));
+    }
+    let mut rng = match args.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    for (pair, lines) in lines.chunks(2).enumerate() {
+        let first = pair * 2;
+        let _span = tracing::info_span!("pair", first).entered();
+        let specs = lines
+            .iter()
+            .map(|line| crate::parse_spec(line, strict, presets))
+            .collect::<io::Result<Vec<_>>>()?;
+        let comparison = sequential(args, presets, &specs, first, &mut rng)?;
+        serde_json::to_writer(&mut *out, &comparison)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Simulate a pair of specs with common seeds until their confidence sequence excludes zero
+fn sequential(
+    args: &CompareArgs,
+    presets: &Presets,
+    specs: &[Spec],
+    first: usize,
+    rng: &mut impl Rng,
+) -> io::Result<Comparison> {
+    let mut simulated = Vec::with_capacity(2);
+    for spec in specs {
+        let agents = crate::build_agents(spec, presets)?;
+        let derived = crate::parse_derived(&spec.configuration)?;
+        simulated.push((spec, agents, derived));
+    }
+    let mut differences = Running::default();
+    let mut radius = f64::INFINITY;
+    let mut decision = Decision::Undecided;
+    let mut observations = 0;
+    while observations < args.max_obs {
+        observations += 1;
+        let seed = rng.gen();
+        let mut values = [None; 2];
+        for ((spec, agents, derived), value) in simulated.iter_mut().zip(&mut values) {
+            *value = observe(spec, agents, derived, &args.feature, seed)?;
+        }
+        let [Some(one), Some(two)] = values else {
+            continue;
+        };
+        differences.add(two - one);
+        if differences.count < args.min_obs {
+            continue;
+        }
+        radius = stats::confidence_sequence(
+            differences.variance(),
+            differences.count as f64,
+            args.alpha,
+            args.min_obs as f64,
+        );
+        if differences.mean - radius > 0.0 {
+            decision = Decision::Second;
+            break;
+        } else if differences.mean + radius < 0.0 {
+            decision = Decision::First;
+            break;
+        }
+    }
+    if differences.count < args.min_obs {
+        warn!(
+            observations,
+            feature = args.feature,
+            "too few observations with the feature to compare"
+        );
+    }
+    Ok(Comparison {
+        first,
+        second: first + 1,
+        decision,
+        observations,
+        difference: Difference {
+            mean: differences.mean,
+            radius,
+        },
+    })
+}
+
+/// A feature of one observation of a spec from its seed, if it has one
+fn observe(
+    spec: &Spec,
+    agents: &mut [Agent<'_>],
+    derived: &[(String, Expr)],
+    feature: &str,
+    seed: u64,
+) -> io::Result<Option<f64>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut features = match crate::continuous_market(&spec.configuration, agents, false)? {
+        Some(market) => crate::run_sim(agents, &market, &mut rng),
+        None if spec.configuration.cda.unwrap_or(true) => crate::run_sim(agents, &Cda, &mut rng),
+        None => crate::run_sim(agents, &Call, &mut rng),
+    };
+    crate::derive(&mut features, derived)?;
+    Ok(serde_json::to_value(&features)?[feature].as_f64())
+}
+
+/// Parse a probability strictly between zero and one
+fn probability(arg: &str) -> Result<f64, String> {
+    match arg.parse() {
+        Ok(prob) if 0.0 < prob && prob < 1.0 => Ok(prob),
+        Ok(prob) => Err(format!("{} isn't between 0 and 1", prob)),
+        Err(err) => Err(format!("{}", err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CompareArgs;
+    use clap::Parser;
+    use std::collections::HashMap;
+
+    fn compare(specs: &[&str], args: &[&str]) -> Vec<serde_json::Value> {
+        let args = CompareArgs::parse_from(["compare", "--seed", "1"].iter().chain(args));
+        let mut out = Vec::new();
+        let input = specs.join("\n");
+        super::compare(&args, &HashMap::new(), false, input.as_bytes(), &mut out).unwrap();
+        serde_json::Deserializer::from_slice(&out)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn test_compare() {
+        let truthful = r#"{"assignment":{"buyers":{"0":3},"sellers":{"0":3}},"configuration":{}}"#;
+        let shaded =
+            r#"{"assignment":{"buyers":{"0.4":3},"sellers":{"0.4":3}},"configuration":{}}"#;
+        let lines = compare(&[shaded, truthful, truthful, shaded], &["--min-obs", "20"]);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["first"], 0);
+        assert_eq!(lines[0]["decision"], "second");
+        assert!(lines[0]["difference"]["mean"].as_f64().unwrap() > 0.0);
+        assert_eq!(lines[1]["second"], 3);
+        assert_eq!(lines[1]["decision"], "first");
+        assert!(lines[1]["observations"].as_u64().unwrap() >= 20);
+
+        // identical specs have identical observations, which never differ
+        let lines = compare(
+            &[truthful, truthful],
+            &["--min-obs", "5", "--max-obs", "30"],
+        );
+        assert_eq!(lines[0]["decision"], "undecided");
+        assert_eq!(lines[0]["observations"], 30);
+        assert_eq!(lines[0]["difference"]["mean"], 0.0);
+
+        let args = CompareArgs::parse_from(["compare"]);
+        let res = super::compare(
+            &args,
+            &HashMap::new(),
+            false,
+            truthful.as_bytes(),
+            &mut vec![],
+        );
+        assert!(res.is_err());
+        assert!(CompareArgs::try_parse_from(["compare", "--alpha", "1"]).is_err());
+    }
+}
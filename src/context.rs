@@ -0,0 +1,48 @@
+use crate::engine::{self, Order};
+use crate::equilibrium::Side;
+use std::cell::Cell;
+
+/// Storage that simulations reuse from one observation to the next
+///
+/// Small markets simulated many times would otherwise spend much of their time allocating and
+/// freeing the same few buffers. Every thread has its own context, see [`with`].
+#[derive(Default)]
+pub struct SimContext {
+    /// The buys and sells of a call market
+    pub orders: (Vec<Order>, Vec<Order>),
+    /// The order agents arrive in a continuous market
+    pub arrivals: Vec<usize>,
+    /// When each agent arrives
+    pub times: Vec<f64>,
+    /// The units of icebergs not yet shown
+    pub hidden: Vec<bool>,
+    pub engine: engine::Buffers,
+    /// The buyers and sellers of a competitive equilibrium
+    pub sides: (Side, Side),
+}
+
+thread_local! {
+    static CONTEXT: Cell<Option<Box<SimContext>>> = const { Cell::new(None) };
+}
+
+/// Run `func` with this thread's context, or a new one while that's in use
+pub fn with<T>(func: impl FnOnce(&mut SimContext) -> T) -> T {
+    let mut context = CONTEXT.take().unwrap_or_default();
+    let res = func(&mut context);
+    CONTEXT.set(Some(context));
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_reuse() {
+        super::with(|ctx| ctx.arrivals.extend([1, 2]));
+        super::with(|ctx| {
+            assert_eq!(ctx.arrivals, [1, 2]);
+            super::with(|nested| assert!(nested.arrivals.is_empty()));
+        });
+        let other = std::thread::spawn(|| super::with(|ctx| ctx.arrivals.len()));
+        assert_eq!(other.join().unwrap(), 0);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::{Agent, Features};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// An observation in the schema egtaonline expects
+///
+/// Only numeric features go in "features", everything else, like the market maker's report, goes in
+/// "extended_features", and features without a value are dropped.
+#[derive(Serialize, Debug)]
+pub struct Observation<'a> {
+    /// The profile that was simulated, role to strategy to count
+    profile: BTreeMap<&'a str, BTreeMap<&'a str, u64>>,
+    features: Map<String, Value>,
+    extended_features: Map<String, Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    players: Option<Vec<Player<'a>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    symmetry_groups: Option<Vec<SymmetryGroup<'a>>>,
+}
+
+#[derive(Serialize, Debug)]
+struct Player<'a> {
+    role: &'a str,
+    strategy: &'a str,
+    payoff: f64,
+    features: Map<String, Value>,
+}
+
+/// Every player of one strategy in one role, with their mean payoff
+#[derive(Serialize, Debug)]
+struct SymmetryGroup<'a> {
+    role: &'a str,
+    strategy: &'a str,
+    count: u64,
+    payoff: f64,
+}
+
+impl<'a> Observation<'a> {
+    /// Convert a simulation, listing every player or aggregating them into symmetry groups
+    pub fn new(agents: &'a [Agent<'a>], features: &Features, aggregate: bool) -> Self {
+        let mut totals: BTreeMap<(&str, &str), (f64, u64)> = BTreeMap::new();
+        for player in crate::players(agents) {
+            let total = totals.entry((player.role, player.strategy)).or_default();
+            total.0 += player.payoff;
+            total.1 += 1;
+        }
+        let mut profile: BTreeMap<_, BTreeMap<_, _>> = BTreeMap::new();
+        for (&(role, strat), &(_, count)) in &totals {
+            profile.entry(role).or_default().insert(strat, count);
+        }
+
+        let value = serde_json::to_value(features).expect("features are always serializable");
+        let mut numeric = Map::new();
+        let mut extended = Map::new();
+        for (name, val) in value.as_object().expect("features are a struct") {
+            match val {
+                Value::Number(_) => numeric.insert(name.clone(), val.clone()),
+                Value::Null => None,
+                _ => extended.insert(name.clone(), val.clone()),
+            };
+        }
+
+        let (players, symmetry_groups) = if aggregate {
+            let groups = totals
+                .into_iter()
+                .map(|((role, strategy), (total, count))| SymmetryGroup {
+                    role,
+                    strategy,
+                    count,
+                    payoff: total / count as f64,
+                })
+                .collect();
+            (None, Some(groups))
+        } else {
+            let players = crate::players(agents)
+                .map(|player| Player {
+                    role: player.role,
+                    strategy: player.strategy,
+                    payoff: player.payoff,
+                    features: Map::new(),
+                })
+                .collect();
+            (Some(players), None)
+        };
+        Observation {
+            profile,
+            features: numeric,
+            extended_features: extended,
+            players,
+            symmetry_groups,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Observation;
+    use crate::strategy::Shading;
+    use crate::{Agent, Call, Style};
+
+    #[test]
+    fn test_egta_observation() {
+        let mut rng = rand::thread_rng();
+        let mut agents: Vec<_> = [true, true, false]
+            .into_iter()
+            .map(|buyer| Agent::new(buyer, "0", Style::Standard, Shading::Fixed(0.0)))
+            .collect();
+        let features = crate::run_sim(&mut agents, &Call, &mut rng);
+        let obs = serde_json::to_value(Observation::new(&agents, &features, false)).unwrap();
+        assert_eq!(obs["profile"]["buyers"]["0"], 2);
+        assert_eq!(obs["players"].as_array().unwrap().len(), 3);
+        assert!(obs["players"][0]["features"].is_object());
+        assert!(obs["features"]["surplus"].is_f64());
+        assert!(obs.get("symmetry_groups").is_none());
+
+        let obs = serde_json::to_value(Observation::new(&agents, &features, true)).unwrap();
+        let groups = obs["symmetry_groups"].as_array().unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0]["count"], 2);
+        assert!(obs.get("players").is_none());
+    }
+}
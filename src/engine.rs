@@ -0,0 +1,604 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use crate::maker::MarketMaker;
+use crate::Agent;
+use serde::{Deserialize, Serialize};
+
+/// An agent's standing order in a book, ordered by its signed bid, then its priority, then by
+/// which reached the book first
+#[derive(Debug, Clone, Copy)]
+pub struct Order {
+    pub bid: f64,
+    pub index: usize,
+    pub priority: f64,
+    /// How many orders reached the book before it
+    pub placed: usize,
+}
+
+impl Order {
+    /// An order without priority, for markets that don't break ties
+    pub fn new(bid: f64, index: usize) -> Self {
+        Order {
+            bid,
+            index,
+            priority: 0.0,
+            placed: 0,
+        }
+    }
+}
+
+impl Ord for Order {
+    fn cmp(&self, other: &Order) -> Ordering {
+        self.bid
+            .partial_cmp(&other.bid)
+            .expect("got nan bids")
+            .then(self.priority.total_cmp(&other.priority))
+            .then(other.placed.cmp(&self.placed))
+    }
+}
+
+impl PartialOrd for Order {
+    fn partial_cmp(&self, other: &Order) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Order {
+    fn eq(&self, other: &Order) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+/// Which of the orders at the best price trades first
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Matching {
+    /// The order that reached the book first
+    #[default]
+    PriceTime,
+    /// A random order, with chances proportional to its size
+    ProRata,
+    /// The largest order, then the one that reached the book first
+    Size,
+}
+
+impl Eq for Order {}
+
+/// Something that happens in a continuous market
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// An agent arrives at the market and decides whether to quote
+    Arrive(usize),
+    /// An agent's order reaches the market, trading or standing in the book
+    Quote(usize),
+    /// An agent's standing order leaves the book
+    Cancel(usize),
+    /// An agent cancels its standing order and quotes an improved one
+    Requote(usize),
+    /// Continuous trading stops, and later orders wait in the book for the closing call
+    Close,
+    /// The market maker sells a unit of its long position to the best bid after its stop
+    Liquidate,
+    /// The market closes and every standing order leaves the book
+    Clear,
+}
+
+impl Action {
+    /// The agent an action is for, if it's for one
+    pub fn agent(self) -> Option<usize> {
+        match self {
+            Action::Arrive(index)
+            | Action::Quote(index)
+            | Action::Cancel(index)
+            | Action::Requote(index) => Some(index),
+            Action::Close | Action::Clear | Action::Liquidate => None,
+        }
+    }
+}
+
+/// An action scheduled at a time, with ties broken by when it was scheduled
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    pub time: f64,
+    pub action: Action,
+    seq: usize,
+}
+
+impl Ord for Event {
+    // reversed so the earliest event is at the top of the queue
+    fn cmp(&self, other: &Event) -> Ordering {
+        other
+            .time
+            .total_cmp(&self.time)
+            .then(other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Event) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Event) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Event {}
+
+/// The total number of orders standing at a price
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Level {
+    pub price: f64,
+    pub depth: usize,
+}
+
+/// A trade of an incoming order with the other side of the book
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Fill {
+    pub price: f64,
+    /// The agent whose standing order traded, or None if it was the market maker's quote
+    pub counterparty: Option<usize>,
+}
+
+/// Transact an agent at a price, withdrawing the other side of its trader if it has one
+fn fill(agents: &mut [Agent<'_>], index: usize, price: f64) -> Option<usize> {
+    agents[index].transact(price);
+    let pair = agents[index].pair(index);
+    if let Some(pair) = pair {
+        agents[pair].withdraw();
+    }
+    pair
+}
+
+/// Transact a buyer and a seller at a price
+pub fn trade(agents: &mut [Agent<'_>], buy: usize, sell: usize, price: f64) {
+    fill(agents, buy, price);
+    fill(agents, sell, price);
+}
+
+/// The storage of an engine, kept after a simulation so the next one needn't allocate it
+#[derive(Default)]
+pub struct Buffers {
+    queue: BinaryHeap<Event>,
+    buys: BinaryHeap<Order>,
+    sells: BinaryHeap<Order>,
+    standing: Vec<bool>,
+    entries: Vec<Option<f64>>,
+    exits: Vec<Option<f64>>,
+    priorities: Vec<f64>,
+}
+
+/// Empty a buffer and fill it with `len` copies of `value`
+fn reset<T: Clone>(buf: &mut Vec<T>, len: usize, value: T) {
+    buf.clear();
+    buf.resize(len, value);
+}
+
+/// A discrete-event engine for continuous double auctions
+///
+/// Events are processed in time order, and each moves the book forward: an arriving agent may
+/// quote, a quote trades with the best standing order on the other side if it crosses it, at its
+/// price, or stands in the book, where orders at the same price rank by their priority and then by
+/// which arrived first, and a cancellation removes a standing order. The market maker's
+/// quotes are always standing, but orders in the book take priority at the same price. When a
+/// trader's order trades, the other side is cancelled at the same time. The engine records when
+/// every order entered and left the book, so callers can express time-dependent behavior by
+/// scheduling further events.
+pub struct Engine<'m> {
+    queue: BinaryHeap<Event>,
+    seq: usize,
+    now: f64,
+    buys: BinaryHeap<Order>,
+    sells: BinaryHeap<Order>,
+    standing: Vec<bool>,
+    entries: Vec<Option<f64>>,
+    exits: Vec<Option<f64>>,
+    priorities: Vec<f64>,
+    placed: usize,
+    stops: usize,
+    maker: Option<&'m mut MarketMaker>,
+}
+
+impl<'m> Engine<'m> {
+    #[cfg(test)]
+    pub fn new(agents: usize, maker: Option<&'m mut MarketMaker>) -> Self {
+        Engine::reusing(Buffers::default(), agents, maker)
+    }
+
+    /// A new engine that keeps its state in the buffers of a previous one
+    pub fn reusing(
+        mut buffers: Buffers,
+        agents: usize,
+        maker: Option<&'m mut MarketMaker>,
+    ) -> Self {
+        buffers.queue.clear();
+        buffers.buys.clear();
+        buffers.sells.clear();
+        reset(&mut buffers.standing, agents, false);
+        reset(&mut buffers.entries, agents, None);
+        reset(&mut buffers.exits, agents, None);
+        reset(&mut buffers.priorities, agents, 0.0);
+        Engine {
+            queue: buffers.queue,
+            seq: 0,
+            now: 0.0,
+            buys: buffers.buys,
+            sells: buffers.sells,
+            standing: buffers.standing,
+            entries: buffers.entries,
+            exits: buffers.exits,
+            priorities: buffers.priorities,
+            placed: 0,
+            stops: 0,
+            maker,
+        }
+    }
+
+    /// The storage of the engine, for the next to reuse
+    pub fn into_buffers(self) -> Buffers {
+        Buffers {
+            queue: self.queue,
+            buys: self.buys,
+            sells: self.sells,
+            standing: self.standing,
+            entries: self.entries,
+            exits: self.exits,
+            priorities: self.priorities,
+        }
+    }
+
+    pub fn schedule(&mut self, time: f64, action: Action) {
+        self.queue.push(Event {
+            time,
+            action,
+            seq: self.seq,
+        });
+        self.seq += 1;
+    }
+
+    /// Pop the next event, advancing the clock to it
+    pub fn next_event(&mut self) -> Option<Event> {
+        let event = self.queue.pop()?;
+        self.now = event.time;
+        Some(event)
+    }
+
+    /// If an agent's order is standing in the book
+    pub fn standing(&self, index: usize) -> bool {
+        self.standing[index]
+    }
+
+    /// When an agent's first order reached the market
+    pub fn entry(&self, index: usize) -> Option<f64> {
+        self.entries[index]
+    }
+
+    /// When an agent's order traded or left the book
+    pub fn exit(&self, index: usize) -> Option<f64> {
+        self.exits[index]
+    }
+
+    /// Drop orders at the top of a book that have left it or whose agents have since withdrawn
+    fn prune(book: &mut BinaryHeap<Order>, agents: &[Agent<'_>], standing: &[bool]) {
+        while book
+            .peek()
+            .is_some_and(|o| !standing[o.index] || !agents[o.index].has_order())
+        {
+            book.pop();
+        }
+    }
+
+    /// Leave the market at the current time after trading, cancelling the other side of a trader
+    fn leave(&mut self, index: usize, pair: Option<usize>) {
+        self.standing[index] = false;
+        self.exits[index].get_or_insert(self.now);
+        if let Some(pair) = pair {
+            self.schedule(self.now, Action::Cancel(pair));
+        }
+    }
+
+    /// Set the priority of an agent's next orders over others at the same price, higher first
+    pub fn prioritize(&mut self, index: usize, priority: f64) {
+        self.priorities[index] = priority;
+    }
+
+    /// An agent's order reaching the market now
+    fn order(&mut self, agents: &[Agent<'_>], index: usize) -> Order {
+        self.entries[index].get_or_insert(self.now);
+        self.exits[index] = None;
+        self.placed += 1;
+        Order {
+            bid: agents[index].bid,
+            index,
+            priority: self.priorities[index],
+            placed: self.placed,
+        }
+    }
+
+    /// Submit an agent's order, returning its trade if it traded
+    ///
+    /// A market order trades with the best order or market maker quote on the other side at any
+    /// price, and is cancelled if there isn't one.
+    pub fn quote(&mut self, agents: &mut [Agent<'_>], index: usize) -> Option<Fill> {
+        let incoming = self.order(agents, index);
+        let market = agents[index].market();
+        let limit = if market { f64::INFINITY } else { incoming.bid };
+        let (price, counterparty) = if agents[index].buyer {
+            Engine::prune(&mut self.sells, agents, &self.standing);
+            let book = self.sells.peek().map(|sell| -sell.bid);
+            match (book, self.maker.as_ref().and_then(|m| m.ask())) {
+                (_, Some(ask)) if ask <= limit && book.is_none_or(|b| ask < b) => {
+                    self.maker.as_mut().unwrap().sell(ask);
+                    (ask, None)
+                }
+                (Some(ask), _) if ask <= limit => (ask, self.sells.pop().map(|s| s.index)),
+                _ if market => return self.unfilled(agents, index),
+                _ => {
+                    self.buys.push(incoming);
+                    self.standing[index] = true;
+                    return None;
+                }
+            }
+        } else {
+            Engine::prune(&mut self.buys, agents, &self.standing);
+            let book = self.buys.peek().map(|buy| buy.bid);
+            match (book, self.maker.as_ref().and_then(|m| m.bid())) {
+                (_, Some(bid)) if -limit <= bid && book.is_none_or(|b| bid > b) => {
+                    self.maker.as_mut().unwrap().buy(bid);
+                    (bid, None)
+                }
+                (Some(bid), _) if -limit <= bid => (bid, self.buys.pop().map(|b| b.index)),
+                _ if market => return self.unfilled(agents, index),
+                _ => {
+                    self.sells.push(incoming);
+                    self.standing[index] = true;
+                    return None;
+                }
+            }
+        };
+        for party in [Some(index), counterparty].into_iter().flatten() {
+            let pair = fill(agents, party, price);
+            self.leave(party, pair);
+        }
+        if let Some(maker) = self.maker.as_mut() {
+            maker.observe(price);
+        }
+        self.trigger(agents, price);
+        Some(Fill {
+            price,
+            counterparty,
+        })
+    }
+
+    /// Trigger every stop at or above a trade's price
+    ///
+    /// Traders whose stop triggers cancel any standing order and quote a market order now, or when
+    /// they arrive if they haven't yet, and a market maker starts liquidating.
+    fn trigger(&mut self, agents: &mut [Agent<'_>], price: f64) {
+        for (index, agent) in agents.iter_mut().enumerate() {
+            if agent.triggers(price) {
+                agent.trigger();
+                self.stops += 1;
+                if self.cancel(index) {
+                    self.schedule(self.now, Action::Quote(index));
+                }
+            }
+        }
+        if let Some(maker) = self.maker.as_mut().filter(|maker| maker.triggers(price)) {
+            maker.trigger();
+            self.stops += 1;
+            self.schedule(self.now, Action::Liquidate);
+        }
+    }
+
+    /// Sell a unit of a stopped market maker's long position to the best bid, continuing until it
+    /// isn't long or there are no bids
+    pub fn liquidate(&mut self, agents: &mut [Agent<'_>]) -> Option<Fill> {
+        Engine::prune(&mut self.buys, agents, &self.standing);
+        let maker = self.maker.as_mut().filter(|maker| maker.long())?;
+        let buy = self.buys.pop()?;
+        maker.sell(buy.bid);
+        maker.observe(buy.bid);
+        if maker.long() {
+            self.schedule(self.now, Action::Liquidate);
+        }
+        let pair = fill(agents, buy.index, buy.bid);
+        self.leave(buy.index, pair);
+        self.trigger(agents, buy.bid);
+        Some(Fill {
+            price: buy.bid,
+            counterparty: Some(buy.index),
+        })
+    }
+
+    /// The number of stops that triggered
+    pub fn stops(&self) -> usize {
+        self.stops
+    }
+
+    /// The best bid and ask someone would trade at now, in the book or quoted by the market maker
+    pub fn best(&mut self, agents: &[Agent<'_>]) -> (Option<f64>, Option<f64>) {
+        Engine::prune(&mut self.buys, agents, &self.standing);
+        Engine::prune(&mut self.sells, agents, &self.standing);
+        let maker = self.maker.as_ref();
+        let bid = self.buys.peek().map(|buy| buy.bid);
+        let ask = self.sells.peek().map(|sell| -sell.bid);
+        (
+            bid.into_iter()
+                .chain(maker.and_then(|m| m.bid()))
+                .reduce(f64::max),
+            ask.into_iter()
+                .chain(maker.and_then(|m| m.ask()))
+                .reduce(f64::min),
+        )
+    }
+
+    /// Cancel a market order that found nothing to trade with
+    fn unfilled(&mut self, agents: &mut [Agent<'_>], index: usize) -> Option<Fill> {
+        agents[index].withdraw();
+        self.exits[index] = Some(self.now);
+        None
+    }
+
+    /// Put an agent's order in the book without trading, even if it crosses the other side
+    pub fn rest(&mut self, agents: &[Agent<'_>], index: usize) {
+        let order = self.order(agents, index);
+        if agents[index].buyer {
+            self.buys.push(order);
+        } else {
+            self.sells.push(order);
+        }
+        self.standing[index] = true;
+    }
+
+    /// The standing buy and sell orders of agents that haven't withdrawn, in no particular order
+    ///
+    /// Orders an agent replaced by requoting are left out.
+    pub fn resting(&self, agents: &[Agent<'_>]) -> (Vec<Order>, Vec<Order>) {
+        let resting = |book: &BinaryHeap<Order>| {
+            book.iter()
+                .filter(|o| {
+                    let agent = &agents[o.index];
+                    self.standing[o.index] && agent.has_order() && agent.bid == o.bid
+                })
+                .copied()
+                .collect()
+        };
+        (resting(&self.buys), resting(&self.sells))
+    }
+
+    /// The price levels of standing bids from highest to lowest, and asks from lowest to highest
+    pub fn levels(&self, agents: &[Agent<'_>]) -> (Vec<Level>, Vec<Level>) {
+        let (buys, sells) = self.resting(agents);
+        let levels = |mut orders: Vec<Order>, sign: f64| {
+            orders.sort_unstable_by(|a, b| b.cmp(a));
+            let mut levels: Vec<Level> = Vec::new();
+            for order in orders {
+                let price = sign * order.bid;
+                match levels.last_mut() {
+                    Some(level) if level.price == price => level.depth += 1,
+                    _ => levels.push(Level { price, depth: 1 }),
+                }
+            }
+            levels
+        };
+        (levels(buys, 1.0), levels(sells, -1.0))
+    }
+
+    /// Remove an agent's standing order from the book, returning whether it was standing
+    pub fn cancel(&mut self, index: usize) -> bool {
+        let standing = std::mem::replace(&mut self.standing[index], false);
+        if standing {
+            self.exits[index] = Some(self.now);
+        }
+        standing
+    }
+
+    /// Remove every standing order from the book
+    pub fn clear(&mut self) {
+        for index in 0..self.standing.len() {
+            self.cancel(index);
+        }
+        self.buys.clear();
+        self.sells.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, Engine};
+    use crate::strategy::Shading;
+    use crate::{Agent, Style};
+
+    fn truthful(buyer: bool, value: f64) -> Agent<'static> {
+        let mut agent = Agent::new(buyer, "", Style::Correct, Shading::Fixed(0.0));
+        agent.value = value;
+        agent.shade();
+        agent
+    }
+
+    #[test]
+    fn test_engine() {
+        let mut agents = [
+            truthful(true, 0.4),
+            truthful(true, 0.7),
+            truthful(false, 0.5),
+        ];
+        let mut engine = Engine::new(agents.len(), None);
+        engine.schedule(0.5, Action::Quote(2));
+        engine.schedule(0.1, Action::Quote(1));
+        engine.schedule(0.1, Action::Quote(0));
+        engine.schedule(0.2, Action::Cancel(1));
+        let mut prices = Vec::new();
+        while let Some(event) = engine.next_event() {
+            match event.action {
+                Action::Quote(index) => {
+                    prices.extend(engine.quote(&mut agents, index).map(|f| f.price))
+                }
+                Action::Cancel(index) => assert!(engine.cancel(index)),
+                _ => unreachable!(),
+            }
+        }
+        // the higher bid was cancelled before the seller arrived, so nothing trades
+        assert!(prices.is_empty());
+        assert_eq!(engine.exit(1), Some(0.2));
+        assert_eq!(engine.entry(2), Some(0.5));
+
+        let (bids, asks) = engine.levels(&agents);
+        assert_eq!(
+            bids,
+            [super::Level {
+                price: 0.4,
+                depth: 1
+            }]
+        );
+        assert_eq!(asks[0].price, 0.5);
+
+        engine.schedule(0.6, Action::Quote(1));
+        engine.schedule(0.8, Action::Clear);
+        while let Some(event) = engine.next_event() {
+            match event.action {
+                Action::Quote(index) => {
+                    let fill = engine.quote(&mut agents, index);
+                    assert!(fill.is_none_or(|f| f.counterparty == Some(2)));
+                    prices.extend(fill.map(|f| f.price));
+                }
+                Action::Clear => engine.clear(),
+                _ => unreachable!(),
+            }
+        }
+        assert_eq!(prices, [0.5]);
+        assert_eq!(engine.levels(&agents), (Vec::new(), Vec::new()));
+        assert_eq!(engine.exit(2), Some(0.6));
+        assert_eq!(engine.exit(0), Some(0.8));
+    }
+
+    #[test]
+    fn test_market_orders() {
+        let market = |buyer| Agent::new(buyer, "", Style::Market, Shading::Fixed(0.0));
+        let mut agents = [
+            truthful(false, 0.3),
+            truthful(false, 0.5),
+            market(true),
+            market(true),
+            market(true),
+        ];
+        let mut engine = Engine::new(agents.len(), None);
+        // market orders walk the book from the best ask, whatever their values
+        for index in 0..agents.len() {
+            engine.quote(&mut agents, index);
+        }
+        assert_eq!(agents[2].price, Some(0.3));
+        assert_eq!(agents[3].price, Some(0.5));
+        assert!(agents[3].utility < 0.0);
+        // the last one finds nothing and is cancelled instead of standing
+        assert_eq!(agents[4].price, None);
+        assert!(!engine.standing(4));
+        assert!(!agents[4].has_order());
+        assert_eq!(engine.levels(&agents), (Vec::new(), Vec::new()));
+    }
+}
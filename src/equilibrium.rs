@@ -0,0 +1,236 @@
+use crate::context;
+use crate::Agent;
+use std::cmp::Ordering;
+use std::mem;
+
+/// The competitive equilibrium of a set of agents' values
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Equilibrium {
+    /// The midpoint of the marginal buyer and seller values, or None if no one can trade
+    pub price: Option<f64>,
+    /// The maximum total surplus
+    pub surplus: f64,
+}
+
+/// Values of one role with the index of their agent, stored contiguously
+#[derive(Default)]
+pub struct Side {
+    buyer: bool,
+    values: Vec<f64>,
+    indices: Vec<usize>,
+    /// Positions into values, ordered by how willing each agent is to trade
+    order: Vec<usize>,
+}
+
+impl Side {
+    fn new(agents: &[Agent<'_>], buyer: bool) -> Self {
+        let mut side = Side::default();
+        side.fill(agents, buyer);
+        side
+    }
+
+    /// Replace the side with the agents of one role
+    fn fill(&mut self, agents: &[Agent<'_>], buyer: bool) {
+        self.buyer = buyer;
+        self.values.clear();
+        self.indices.clear();
+        for (index, agent) in agents.iter().enumerate().filter(|(_, a)| a.buyer == buyer) {
+            self.values.push(agent.net_value());
+            self.indices.push(index);
+        }
+        self.order.clear();
+        self.order.extend(0..self.values.len());
+    }
+
+    fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Compare positions by value, descending for buyers and ascending for sellers
+    fn compare(&self, a: usize, b: usize) -> Ordering {
+        let ord = self.values[a].total_cmp(&self.values[b]);
+        if self.buyer {
+            ord.reverse()
+        } else {
+            ord
+        }
+    }
+
+    fn sort(&mut self) {
+        let mut order = mem::take(&mut self.order);
+        order.sort_unstable_by(|&a, &b| self.compare(a, b));
+        self.order = order;
+    }
+
+    /// Partially order positions [start, end) so that position `nth` has its final rank
+    fn select(&mut self, start: usize, end: usize, nth: usize) {
+        let mut order = mem::take(&mut self.order);
+        order[start..end].select_nth_unstable_by(nth - start, |&a, &b| self.compare(a, b));
+        self.order = order;
+    }
+
+    /// The value of the agent at rank `nth`
+    fn value(&self, nth: usize) -> f64 {
+        self.values[self.order[nth]]
+    }
+
+    /// Mark the first `num` agents as trading, returning the sum of their values
+    fn trade(&self, agents: &mut [Agent<'_>], num: usize) -> f64 {
+        self.order[..num]
+            .iter()
+            .map(|&pos| {
+                agents[self.indices[pos]].ce_traded = true;
+                self.values[pos]
+            })
+            .sum()
+    }
+}
+
+/// The equilibrium once both sides are ordered up to the `matched` marginal pair
+fn finish(agents: &mut [Agent<'_>], buys: &Side, sells: &Side, matched: usize) -> Equilibrium {
+    agents.iter_mut().for_each(|a| a.ce_traded = false);
+    let surplus = buys.trade(agents, matched) - sells.trade(agents, matched);
+    let price = (matched > 0).then(|| (buys.value(matched - 1) + sells.value(matched - 1)) / 2.0);
+    Equilibrium { price, surplus }
+}
+
+/// Compute the competitive equilibrium of agents' values net of trade costs, setting which agents
+/// trade in it
+///
+/// Rather than sorting every value, this binary searches for the number of trades with
+/// selections over a shrinking range, finding the marginal pair in expected linear time.
+pub fn compute(agents: &mut [Agent<'_>]) -> Equilibrium {
+    context::with(|ctx| {
+        let (buys, sells) = &mut ctx.sides;
+        buys.fill(agents, true);
+        sells.fill(agents, false);
+        search(agents, buys, sells)
+    })
+}
+
+/// Binary search for the competitive equilibrium of both sides
+fn search(agents: &mut [Agent<'_>], buys: &mut Side, sells: &mut Side) -> Equilibrium {
+    // the number of trades is in [low, high], every agent ranked before low is in place, and
+    // every agent ranked after high is after the end of its side's search range
+    let mut low = 0;
+    let mut high = buys.len().min(sells.len());
+    let (mut buy_end, mut sell_end) = (buys.len(), sells.len());
+    while low < high {
+        let mid = low + (high - low).div_ceil(2);
+        buys.select(low, buy_end, mid - 1);
+        sells.select(low, sell_end, mid - 1);
+        if sells.value(mid - 1) <= buys.value(mid - 1) {
+            low = mid;
+        } else {
+            high = mid - 1;
+            buy_end = high;
+            sell_end = high;
+        }
+    }
+    finish(agents, buys, sells, low)
+}
+
+/// The most surplus agents could gain by all trading at one price, the equilibrium surplus when
+/// the price clears the market
+///
+/// As many buyers willing to pay the price trade with as many sellers willing to accept it, the
+/// most eager first, so a price off the equilibrium loses the trades its short side can't make.
+pub fn surplus_at(agents: &[Agent<'_>], price: f64) -> f64 {
+    let gains = |buyer: bool| -> Vec<f64> {
+        agents
+            .iter()
+            .filter(|a| a.buyer == buyer)
+            .map(|a| a.sign() * (a.net_value() - price))
+            .filter(|&gain| gain >= 0.0)
+            .collect()
+    };
+    let (mut buys, mut sells) = (gains(true), gains(false));
+    let trades = buys.len().min(sells.len());
+    let best = |gains: &mut Vec<f64>| -> f64 {
+        if gains.len() > trades {
+            gains.select_nth_unstable_by(trades, |a, b| b.total_cmp(a));
+        }
+        gains[..trades].iter().sum()
+    };
+    best(&mut buys) + best(&mut sells)
+}
+
+/// Compute the competitive equilibrium by fully sorting both sides
+pub fn compute_sorted(agents: &mut [Agent<'_>]) -> Equilibrium {
+    let mut buys = Side::new(agents, true);
+    let mut sells = Side::new(agents, false);
+    buys.sort();
+    sells.sort();
+    let matched = (0..buys.len().min(sells.len()))
+        .take_while(|&nth| sells.value(nth) <= buys.value(nth))
+        .count();
+    finish(agents, &buys, &sells, matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::strategy::Shading;
+    use crate::{Agent, Call, Market, Style};
+    use rand::Rng;
+
+    #[test]
+    fn test_matches_call() {
+        let mut rng = rand::thread_rng();
+        for num in [0, 1, 2, 5, 20, 101] {
+            let mut agents: Vec<_> = (0..num)
+                .map(|_| Agent::new(rng.gen(), "", Style::Standard, Shading::Fixed(0.0)))
+                .collect();
+            agents.iter_mut().for_each(|a| a.resample(&mut rng));
+            let equi = super::compute(&mut agents);
+            let sorted = super::compute_sorted(&mut agents);
+            assert_eq!(sorted.price, equi.price);
+            assert!((sorted.surplus - equi.surplus).abs() < 1e-9);
+
+            let price = Call.simulate(&mut agents, &mut rng);
+            let surplus: f64 = agents.iter().map(|a| a.utility).sum();
+            assert_eq!(equi.price, price);
+            assert!((equi.surplus - surplus).abs() < 1e-9);
+            for agent in &agents {
+                assert_eq!(agent.ce_traded, agent.traded);
+            }
+        }
+    }
+
+    #[test]
+    fn test_surplus_at() {
+        let mut rng = rand::thread_rng();
+        for num in [0, 1, 2, 5, 20, 101] {
+            let mut agents: Vec<_> = (0..num)
+                .map(|_| Agent::new(rng.gen(), "", Style::Standard, Shading::Fixed(0.0)))
+                .collect();
+            agents.iter_mut().for_each(|a| a.resample(&mut rng));
+            let equi = super::compute(&mut agents);
+            if let Some(price) = equi.price {
+                assert!((super::surplus_at(&agents, price) - equi.surplus).abs() < 1e-9);
+            }
+            for price in [0.0, 0.25, 0.5, 0.75, 1.0] {
+                let surplus = super::surplus_at(&agents, price);
+                assert!((0.0..=equi.surplus + 1e-9).contains(&surplus));
+            }
+            assert_eq!(super::surplus_at(&agents, 2.0), 0.0);
+        }
+    }
+
+    #[test]
+    fn test_cost() {
+        for (cost, surplus) in [(0.0, 0.1), (0.05, 0.05), (0.2, 0.0)] {
+            let mut agents: Vec<_> = [(true, 0.6), (false, 0.5)]
+                .into_iter()
+                .map(|(buyer, value)| {
+                    let mut agent = Agent::new(buyer, "", Style::Standard, Shading::Fixed(0.0));
+                    agent.value = value;
+                    agent.set_cost(cost / 2.0);
+                    agent
+                })
+                .collect();
+            let equi = super::compute(&mut agents);
+            assert!((equi.surplus - surplus).abs() < 1e-9);
+            assert_eq!(equi.price.is_some(), surplus > 0.0);
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use crate::market::{Call, Cda, Market};
+use crate::strategy::Presets;
+use crate::{Agent, Count, Spec, Style};
+use clap::{Parser, ValueEnum};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+
+#[derive(Parser, Debug)]
+/// Evolve the strategy frequencies of each spec's population
+///
+/// Takes the same spec lines as the default command on stdin, using the assignment as the initial
+/// population. Every generation estimates the mean payoff of each strategy from --samples
+/// simulations, and then updates the number of agents playing each strategy within a role
+/// according to --dynamics. Each line of output is one generation with the frequency and mean
+/// payoff of every strategy.
+pub struct EvolveArgs {
+    /// Number of generations to run
+    #[clap(long, value_parser, default_value_t = 100)]
+    generations: u64,
+
+    /// Number of simulations per generation used to estimate payoffs
+    #[clap(long, value_parser, default_value_t = 100)]
+    samples: u64,
+
+    /// How the population changes between generations
+    #[clap(long, value_enum, default_value_t = Dynamics::Replicator)]
+    dynamics: Dynamics,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dynamics {
+    /// Redraw every agent's strategy in proportion to count times payoff
+    Replicator,
+    /// Replace one random agent per role with the offspring of a fitness proportional parent
+    Moran,
+    /// Every agent copies a random agent's strategy with probability proportional to how much
+    /// better it did
+    Imitation,
+}
+
+/// The strategies and their counts for one role
+struct Population<'a> {
+    names: Vec<&'a str>,
+    /// A configured agent playing each strategy
+    protos: Vec<Agent<'a>>,
+    counts: Vec<usize>,
+}
+
+#[derive(Serialize, Debug)]
+struct Share {
+    frequency: f64,
+    payoff: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+struct Generation<'a> {
+    generation: u64,
+    buyers: BTreeMap<&'a str, Share>,
+    sellers: BTreeMap<&'a str, Share>,
+}
+
+pub fn evolve(
+    args: &EvolveArgs,
+    presets: &Presets,
+    strict: bool,
+    ihandle: impl BufRead,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    for (index, line) in ihandle.lines().enumerate() {
+        let _span = tracing::info_span!("spec", line = index).entered();
+        let spec = crate::parse_spec(&line?, strict, presets)?;
+        if !spec.assignment.traders.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "evolve doesn't support traders",
+            ));
+        } else if !spec.assignment.others.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "evolve only supports buyers and sellers",
+            ));
+        }
+        let mut pops = [
+            population(&spec, &spec.assignment.buyers, true, presets)?,
+            population(&spec, &spec.assignment.sellers, false, presets)?,
+        ];
+        if spec.configuration.cda.unwrap_or(true) {
+            evolve_market(args, &mut pops, &Cda, out)?
+        } else {
+            evolve_market(args, &mut pops, &Call, out)?
+        }
+    }
+    Ok(())
+}
+
+fn population<'a>(
+    spec: &Spec,
+    assignment: &'a BTreeMap<String, Count>,
+    buyer: bool,
+    presets: &Presets,
+) -> io::Result<Population<'a>> {
+    let mut pop = Population {
+        names: Vec::new(),
+        protos: Vec::new(),
+        counts: Vec::new(),
+    };
+    for (name, count) in assignment {
+        if count.fraction() != 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("evolve needs whole counts, not {} of \"{}\"", count.0, name),
+            ));
+        }
+        pop.names.push(name);
+        let role = if buyer { "buyers" } else { "sellers" };
+        let parsed = crate::resolve_strategy(spec, role, name, presets)?;
+        // Bne agents would need their equilibrium solved again for every population
+        if let Some(style @ (Style::External | Style::Bne)) = parsed.style {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "evolve doesn't support {:?} strategies like \"{}\"",
+                    style, name
+                ),
+            ));
+        }
+        let mut proto = Agent::from_strategy(buyer, name, &parsed);
+        crate::configure_agent(spec, &mut proto)?;
+        pop.protos.push(proto);
+        pop.counts.push(count.whole() as usize);
+    }
+    Ok(pop)
+}
+
+fn evolve_market(
+    args: &EvolveArgs,
+    pops: &mut [Population<'_>; 2],
+    market: &impl Market,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let mut rng = rand::thread_rng();
+    for generation in 0..args.generations {
+        let payoffs = mean_payoffs(pops, market, args.samples, &mut rng);
+        let [buyers, sellers] = [0, 1].map(|role| {
+            let pop = &pops[role];
+            let total: usize = pop.counts.iter().sum();
+            pop.names
+                .iter()
+                .zip(&pop.counts)
+                .zip(&payoffs[role])
+                .map(|((&name, &count), &payoff)| {
+                    let frequency = count as f64 / total as f64;
+                    (name, Share { frequency, payoff })
+                })
+                .collect()
+        });
+        serde_json::to_writer(
+            &mut *out,
+            &Generation {
+                generation,
+                buyers,
+                sellers,
+            },
+        )?;
+        writeln!(out)?;
+
+        for (pop, pays) in pops.iter_mut().zip(&payoffs) {
+            if pop.counts.iter().sum::<usize>() == 0 {
+                continue;
+            }
+            let pays: Vec<f64> = pays.iter().map(|p| p.unwrap_or(0.0)).collect();
+            match args.dynamics {
+                Dynamics::Replicator => replicator(&mut pop.counts, &pays, &mut rng),
+                Dynamics::Moran => moran(&mut pop.counts, &pays, &mut rng),
+                Dynamics::Imitation => imitation(&mut pop.counts, &pays, &mut rng),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The mean payoff of every strategy in every role, or None for extinct strategies
+fn mean_payoffs(
+    pops: &[Population<'_>; 2],
+    market: &impl Market,
+    samples: u64,
+    rng: &mut impl Rng,
+) -> [Vec<Option<f64>>; 2] {
+    let mut agents = Vec::new();
+    let mut kinds = Vec::new();
+    for (role, pop) in pops.iter().enumerate() {
+        for (strat, proto) in pop.protos.iter().enumerate() {
+            for _ in 0..pop.counts[strat] {
+                agents.push(proto.clone());
+                kinds.push((role, strat));
+            }
+        }
+    }
+
+    let mut totals = [
+        vec![0.0; pops[0].names.len()],
+        vec![0.0; pops[1].names.len()],
+    ];
+    for _ in 0..samples {
+        crate::run_sim(&mut agents, market, rng);
+        for (agent, &(role, strat)) in agents.iter().zip(&kinds) {
+            totals[role][strat] += agent.utility;
+        }
+    }
+    [0, 1].map(|role| {
+        totals[role]
+            .iter()
+            .zip(&pops[role].counts)
+            .map(|(total, &count)| {
+                if count == 0 || samples == 0 {
+                    None
+                } else {
+                    Some(total / (count as u64 * samples) as f64)
+                }
+            })
+            .collect()
+    })
+}
+
+/// Payoffs shifted to be positive for use as fitness
+fn fitness(payoffs: &[f64]) -> Vec<f64> {
+    let offset = payoffs.iter().copied().fold(0.0, f64::min);
+    payoffs.iter().map(|pay| pay - offset + 1e-6).collect()
+}
+
+fn replicator(counts: &mut [usize], payoffs: &[f64], rng: &mut impl Rng) {
+    let total: usize = counts.iter().sum();
+    let mut shares: Vec<f64> = counts.iter().map(|&c| c as f64 / total as f64).collect();
+    crate::solve::replicate(&mut shares, payoffs);
+    let dist = WeightedIndex::new(&shares).expect("invalid shares");
+    counts.iter_mut().for_each(|c| *c = 0);
+    for _ in 0..total {
+        counts[dist.sample(rng)] += 1;
+    }
+}
+
+fn moran(counts: &mut [usize], payoffs: &[f64], rng: &mut impl Rng) {
+    let weights: Vec<f64> = fitness(payoffs)
+        .iter()
+        .zip(counts.iter())
+        .map(|(fit, &count)| fit * count as f64)
+        .collect();
+    let birth = WeightedIndex::new(&weights)
+        .expect("invalid fitness")
+        .sample(rng);
+    let death = WeightedIndex::new(&*counts)
+        .expect("empty population")
+        .sample(rng);
+    counts[death] -= 1;
+    counts[birth] += 1;
+}
+
+fn imitation(counts: &mut [usize], payoffs: &[f64], rng: &mut impl Rng) {
+    let (low, high) = payoffs
+        .iter()
+        .zip(counts.iter())
+        .filter(|(_, &count)| count > 0)
+        .fold(
+            (f64::INFINITY, f64::NEG_INFINITY),
+            |(low, high), (&pay, _)| (low.min(pay), high.max(pay)),
+        );
+    if high <= low {
+        return;
+    }
+    let models = WeightedIndex::new(&*counts).expect("empty population");
+    let mut next = vec![0; counts.len()];
+    for (strat, &count) in counts.iter().enumerate() {
+        for _ in 0..count {
+            let model = models.sample(rng);
+            let prob = (payoffs[model] - payoffs[strat]) / (high - low);
+            if rng.gen::<f64>() < prob {
+                next[model] += 1;
+            } else {
+                next[strat] += 1;
+            }
+        }
+    }
+    counts.copy_from_slice(&next);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dynamics, EvolveArgs};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_dynamics_preserve_size() {
+        let mut rng = rand::thread_rng();
+        let payoffs = [0.1, 0.4, -0.2];
+        for update in [super::replicator, super::moran, super::imitation] {
+            let mut counts = [3, 4, 5];
+            for _ in 0..20 {
+                update(&mut counts, &payoffs, &mut rng);
+                assert_eq!(counts.iter().sum::<usize>(), 12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_evolve() {
+        for dynamics in [Dynamics::Replicator, Dynamics::Moran, Dynamics::Imitation] {
+            let args = EvolveArgs {
+                generations: 4,
+                samples: 3,
+                dynamics,
+            };
+            let input = r#"{"assignment":{"buyers":{"0.2":3,"0.5":2},"sellers":{"0.3":4}},"configuration":{}}"#;
+            let mut out = Vec::new();
+            super::evolve(&args, &HashMap::new(), false, input.as_bytes(), &mut out).unwrap();
+            let lines: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&out)
+                .into_iter()
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(lines.len(), 4);
+            for line in lines {
+                let total: f64 = line["buyers"]
+                    .as_object()
+                    .unwrap()
+                    .values()
+                    .map(|s| s["frequency"].as_f64().unwrap())
+                    .sum();
+                assert!((total - 1.0).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unsupported_style() {
+        let args = EvolveArgs {
+            generations: 1,
+            samples: 1,
+            dynamics: Dynamics::Replicator,
+        };
+        for input in [
+            r#"{"assignment":{"buyers":{"0.2_External":1},"sellers":{"0":1}},"configuration":{"external":["cat"]}}"#,
+            r#"{"assignment":{"buyers":{"Bne":1},"sellers":{"0":1}},"configuration":{}}"#,
+        ] {
+            let err = super::evolve(
+                &args,
+                &HashMap::new(),
+                false,
+                input.as_bytes(),
+                &mut Vec::new(),
+            )
+            .unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+}
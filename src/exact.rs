@@ -0,0 +1,151 @@
+use crate::expr::Expr;
+use crate::market::Call;
+use crate::strategy::{Presets, Shading};
+use crate::summary::{Means, Report};
+use crate::{Agent, Extra, Spec, Style};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::io;
+
+/// The most players a call market can have to be integrated
+const MAX_PLAYERS: usize = 4;
+
+/// About how many points the values of every player are integrated over together
+const GRID_POINTS: f64 = (1 << 16) as f64;
+
+/// The most points one value is integrated over
+const MAX_NODES: u64 = 1 << 12;
+
+/// The number of points each value of a spec is integrated over, or why it can't be
+///
+/// Only call markets of a few players qualify, where every player has one value drawn uniformly
+/// and bids a fixed shading of it without learning, so nothing but the values is random.
+pub fn nodes(spec: &Spec, agents: &[Agent<'_>], presets: &Presets) -> Result<u64, &'static str> {
+    fixed(spec, agents, presets)?;
+    if agents.len() > MAX_PLAYERS {
+        return Err("there are too many players");
+    }
+    let nodes = GRID_POINTS.powf(1.0 / agents.len() as f64).floor() as u64;
+    Ok(nodes.clamp(1, MAX_NODES))
+}
+
+/// Check that a spec is a call market where nothing but the values is random, or why it isn't
+///
+/// Every player has one value drawn uniformly and bids a fixed shading of it without learning.
+pub fn fixed(spec: &Spec, agents: &[Agent<'_>], presets: &Presets) -> Result<(), &'static str> {
+    let config = &spec.configuration;
+    if config.cda != Some(false) {
+        return Err("the market isn't a call market");
+    } else if !matches!(crate::continuous_market(config, agents, false), Ok(None)) {
+        return Err("the market is continuous");
+    } else if config.values.is_some() || agents.iter().any(|a| a.pool().is_some()) {
+        return Err("values aren't uniform");
+    } else if config.types.is_some() {
+        return Err("values have persistent types");
+    } else if config.noise.is_some_and(|noise| noise > 0.0) {
+        return Err("signals are noisy");
+    } else if config.external.is_some() {
+        return Err("external agents bid");
+    } else if agents.iter().any(|agent| agent.trader) {
+        return Err("traders have two values");
+    } else if agents.iter().any(|agent| agent.units() > 1) {
+        return Err("multi-unit players have value schedules");
+    } else if agents.iter().any(|agent| agent.presence().is_some()) {
+        return Err("the number of players is random");
+    }
+    for (role, counts) in spec.assignment.iter() {
+        for part in counts.keys().flat_map(|name| name.split('|')) {
+            let strat = crate::resolve_strategy(spec, role, part, presets)
+                .map_err(|_| "a strategy is invalid")?;
+            if !matches!(strat.shading, Some(Shading::Fixed(_))) {
+                return Err("shadings are random");
+            } else if !matches!(
+                strat.style,
+                Some(Style::Standard | Style::Exponential | Style::Shift | Style::Correct)
+            ) {
+                return Err("strategies learn or draw their bids");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The mean features and payoffs of a call market over a grid of every player's values
+///
+/// Each value takes the midpoint of each of `nodes` equal parts of its support, and every
+/// combination of them is simulated once, a midpoint rule for the expectation over the values.
+pub fn integrate(
+    agents: &mut [Agent<'_>],
+    nodes: u64,
+    derived: &[(String, Expr)],
+    extras: &[Extra],
+) -> io::Result<Report> {
+    // nothing else is random, but resampling still takes a generator
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut means = Means::default();
+    let mut point = vec![0; agents.len()];
+    loop {
+        for (agent, &node) in agents.iter_mut().zip(&point) {
+            agent.resample(&mut rng);
+            let (low, high) = agent.support();
+            agent.value = low + (node as f64 + 0.5) / nodes as f64 * (high - low);
+        }
+        let mut features = crate::run_drawn(agents, &Call, &mut rng);
+        crate::derive(&mut features, derived)?;
+        features.select(agents, extras);
+        means.add(&features, agents);
+        // count through every point like an odometer
+        let Some(dim) = point.iter().position(|&node| node + 1 < nodes) else {
+            break;
+        };
+        point[..dim].fill(0);
+        point[dim] += 1;
+    }
+    Ok(means.report())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Extra;
+    use std::collections::HashMap;
+
+    fn nodes(line: &str) -> Result<u64, &'static str> {
+        let spec = crate::parse_spec(line, true, &HashMap::new()).unwrap();
+        let agents = crate::build_agents(&spec, &HashMap::new()).unwrap();
+        super::nodes(&spec, &agents, &HashMap::new())
+    }
+
+    #[test]
+    fn test_eligible() {
+        let call = r#"{"assignment":{"buyers":{"0.1":1},"sellers":{"0_Shift":1}},"configuration":{"cda":false}}"#;
+        assert_eq!(nodes(call), Ok(256));
+        let cda = r#"{"assignment":{"buyers":{"0":1},"sellers":{"0":1}},"configuration":{}}"#;
+        assert!(nodes(cda).is_err());
+        let random = r#"{"assignment":{"buyers":{"U(0,1)":1},"sellers":{"0":1}},"configuration":{"cda":false}}"#;
+        assert!(nodes(random).is_err());
+        let learning = r#"{"assignment":{"buyers":{"1_Roth":1},"sellers":{"0":1}},"configuration":{"cda":false}}"#;
+        assert!(nodes(learning).is_err());
+        let large =
+            r#"{"assignment":{"buyers":{"0":3},"sellers":{"0":2}},"configuration":{"cda":false}}"#;
+        assert!(nodes(large).is_err());
+    }
+
+    #[test]
+    fn test_integrate() {
+        let line =
+            r#"{"assignment":{"buyers":{"0":1},"sellers":{"0":1}},"configuration":{"cda":false}}"#;
+        let spec = crate::parse_spec(line, true, &HashMap::new()).unwrap();
+        let mut agents = crate::build_agents(&spec, &HashMap::new()).unwrap();
+        let report = super::integrate(&mut agents, 256, &[], &[Extra::Thin]).unwrap();
+        let report = serde_json::to_value(report).unwrap();
+        assert_eq!(report["observations"], 256 * 256);
+        let mean = |stat: &serde_json::Value| stat["mean"].as_f64().unwrap();
+        // truthful agents trade half the time, for 1/6 surplus split evenly between them
+        let features = &report["features"];
+        assert!((mean(&features["surplus"]) - 1.0 / 6.0).abs() < 1e-4);
+        assert!((mean(&features["no_trade"]) - 0.5).abs() < 1e-2);
+        assert!((mean(&features["ce_price"]) - 0.5).abs() < 1e-4);
+        assert_eq!(features["surplus"]["stderr"], 0.0);
+        assert!((mean(&report["payoffs"]["buyers"]["0"]) - 1.0 / 12.0).abs() < 1e-4);
+    }
+}
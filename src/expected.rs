@@ -0,0 +1,273 @@
+use crate::equilibrium;
+use crate::stats;
+use crate::{Agent, Spec};
+use rand::Rng;
+use serde::Serialize;
+use tracing::{info, warn};
+
+/// How many standard errors a simulated mean may be from its expectation before it's flagged
+const THRESHOLD: f64 = 4.0;
+
+/// How many draws of values estimate the mean equilibrium surplus when it has no closed form
+const CONTROL_DRAWS: u64 = 1 << 16;
+
+/// Closed form expectations of the competitive equilibrium when every value is uniform
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Expected {
+    /// The expected maximum surplus
+    pub surplus: f64,
+    /// The probability that anyone can trade
+    pub trade: f64,
+    /// The expected equilibrium price given that anyone can trade, only known in closed form when
+    /// there are as many buyers as sellers
+    pub price: Option<f64>,
+}
+
+/// ln(n choose k)
+fn ln_choose(n: u64, k: u64) -> f64 {
+    stats::ln_gamma(n as f64 + 1.0)
+        - stats::ln_gamma(k as f64 + 1.0)
+        - stats::ln_gamma((n - k) as f64 + 1.0)
+}
+
+/// ln B(a, b) of the beta function
+fn ln_beta(a: f64, b: f64) -> f64 {
+    stats::ln_gamma(a) + stats::ln_gamma(b) - stats::ln_gamma(a + b)
+}
+
+/// The expected competitive equilibrium of buyers and sellers with values uniform on [low, high]
+///
+/// The surplus is the integral over prices t of the expected number of trades that straddle t,
+/// the smaller of the number of buyers above it and sellers below it. Those are independent
+/// binomials, so the integral is an exact sum of beta functions. By symmetry, equal numbers of
+/// buyers and sellers trade at the middle of the support on average.
+pub fn uniform(buyers: u64, sellers: u64, low: f64, high: f64) -> Expected {
+    let mut surplus = 0.0;
+    for above in 1..=buyers {
+        for below in 1..=sellers {
+            let ln_term = ln_choose(buyers, above)
+                + ln_choose(sellers, below)
+                + ln_beta(
+                    (buyers - above + below) as f64 + 1.0,
+                    (above + sellers - below) as f64 + 1.0,
+                );
+            surplus += above.min(below) as f64 * ln_term.exp();
+        }
+    }
+    let trade = if buyers == 0 || sellers == 0 {
+        0.0
+    } else {
+        // no one trades when every buyer values less than every seller
+        1.0 - (-ln_choose(buyers + sellers, buyers)).exp()
+    };
+    Expected {
+        surplus: surplus * (high - low),
+        trade,
+        price: (buyers == sellers && buyers > 0).then(|| (low + high) / 2.0),
+    }
+}
+
+/// The expectations of a spec, or why it doesn't have any in closed form
+pub fn for_spec(spec: &Spec) -> Result<Expected, &'static str> {
+    let config = &spec.configuration;
+    let count = |map: &std::collections::BTreeMap<String, crate::Count>| {
+        map.values().map(|count| count.0).sum::<f64>()
+    };
+    let whole = |map| {
+        let count = count(map);
+        (count.fract() == 0.0)
+            .then_some(count as u64)
+            .ok_or("the number of players is random")
+    };
+    if count(&spec.assignment.traders) > 0.0 {
+        return Err("traders have two values");
+    } else if spec.assignment.others.values().any(|map| count(map) > 0.0) {
+        return Err("there are roles besides buyers and sellers");
+    } else if config.values.is_some() {
+        return Err("values aren't uniform");
+    } else if config.types.is_some() {
+        return Err("values have persistent types");
+    } else if config.shocks.is_some() {
+        return Err("shocks change values");
+    } else if config.cost.is_some_and(|cost| cost != 0.0) {
+        return Err("costs change net values");
+    }
+    let support = |role| crate::role_support(spec, role).map_err(|_| "the support is invalid");
+    let (low, high) = support("buyers")?;
+    if support("sellers")? != (low, high) {
+        return Err("buyers and sellers have different supports");
+    }
+    // every unit of a multi-unit player is an independent draw, so only the number of units matters
+    let units = |role| {
+        config
+            .units
+            .as_ref()
+            .and_then(|units| units.get(role))
+            .map_or(1, |&units| units as u64)
+    };
+    Ok(uniform(
+        whole(&spec.assignment.buyers)? * units("buyers"),
+        whole(&spec.assignment.sellers)? * units("sellers"),
+        low,
+        high,
+    ))
+}
+
+/// The mean equilibrium surplus of a spec, as a control variate for its surplus
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Control {
+    pub mean: f64,
+    /// The standard error of an estimated mean
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<f64>,
+    /// How many draws of values estimated the mean
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub draws: Option<u64>,
+}
+
+/// The mean equilibrium surplus of a spec's agents, or why it can't be known
+///
+/// The equilibrium only depends on values, so without a closed form its mean is estimated from
+/// draws of values alone, which are much cheaper than trading.
+pub fn control(
+    spec: &Spec,
+    agents: &[Agent<'_>],
+    rng: &mut impl Rng,
+) -> Result<Control, &'static str> {
+    if spec.configuration.shocks.is_some() {
+        return Err("shocks change values while trading");
+    } else if spec.configuration.types.is_some() {
+        // the surplus is correlated within a spec line, so its mean over them doesn't control it
+        return Err("values have persistent types");
+    } else if let (Ok(expected), false) = (for_spec(spec), agents.iter().any(Agent::chooses_entry))
+    {
+        return Ok(Control {
+            mean: expected.surplus,
+            stderr: None,
+            draws: None,
+        });
+    }
+    let mut agents = agents.to_vec();
+    let surplus: Vec<_> = (0..CONTROL_DRAWS)
+        .map(|_| {
+            crate::draw_values(&mut agents, rng);
+            // entry depends on the equilibrium, like when simulating
+            if agents.iter().any(Agent::chooses_entry) {
+                let price = equilibrium::compute(&mut agents).price;
+                agents.iter_mut().for_each(|a| a.enter(price));
+            }
+            equilibrium::compute(&mut agents).surplus
+        })
+        .collect();
+    Ok(Control {
+        mean: stats::mean(&surplus),
+        stderr: Some((stats::variance(&surplus) / surplus.len() as f64).sqrt()),
+        draws: Some(CONTROL_DRAWS),
+    })
+}
+
+/// Samples of a spec's competitive equilibrium to compare to its expectation
+#[derive(Debug)]
+pub struct Check {
+    expected: Expected,
+    surplus: Vec<f64>,
+    trade: Vec<f64>,
+    price: Vec<f64>,
+}
+
+impl Check {
+    pub fn new(expected: Expected) -> Self {
+        Check {
+            expected,
+            surplus: Vec::new(),
+            trade: Vec::new(),
+            price: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, surplus: f64, price: Option<f64>) {
+        self.surplus.push(surplus);
+        self.trade.push(if price.is_some() { 1.0 } else { 0.0 });
+        self.price.extend(price);
+    }
+
+    /// Log how far each simulated mean is from its expectation, warning about any that are
+    /// further than could be explained by chance, returning how many were
+    pub fn report(&self) -> usize {
+        let comparisons = [
+            ("ce_surplus", &self.surplus, Some(self.expected.surplus)),
+            ("trade", &self.trade, Some(self.expected.trade)),
+            ("ce_price", &self.price, self.expected.price),
+        ];
+        let mut deviations = 0;
+        for (feature, samples, expected) in comparisons {
+            let Some(expected) = expected.filter(|_| samples.len() > 1) else {
+                continue;
+            };
+            let simulated = stats::mean(samples);
+            let stderr = (stats::variance(samples) / samples.len() as f64).sqrt();
+            let z = (simulated - expected) / stderr;
+            // constant samples should match exactly
+            if z.abs() > THRESHOLD || (stderr == 0.0 && simulated != expected) {
+                deviations += 1;
+                warn!(
+                    feature,
+                    simulated, expected, z, "simulated mean deviates from closed form"
+                );
+            } else {
+                info!(
+                    feature,
+                    simulated, expected, z, "simulated mean matches closed form"
+                );
+            }
+        }
+        deviations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Check;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_uniform() {
+        // one buyer and seller trade their difference if it's positive, 1/6 on average
+        let one = super::uniform(1, 1, 0.0, 1.0);
+        assert!((one.surplus - 1.0 / 6.0).abs() < 1e-9);
+        assert!((one.trade - 0.5).abs() < 1e-9);
+        assert_eq!(one.price, Some(0.5));
+        assert!((super::uniform(1, 1, 0.0, 2.0).surplus - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(super::uniform(3, 0, 0.0, 1.0).surplus, 0.0);
+        assert_eq!(super::uniform(3, 2, 0.0, 1.0).price, None);
+
+        let mut check = Check::new(one);
+        for _ in 0..100 {
+            check.add(1.0, Some(0.5));
+        }
+        assert_eq!(check.report(), 2);
+    }
+
+    #[test]
+    fn test_control() {
+        let mut rng = rand::thread_rng();
+        let control = |config: &str, rng: &mut _| {
+            let line = format!(
+                r#"{{"assignment":{{"buyers":{{"0":1}},"sellers":{{"0":1}}}},"configuration":{}}}"#,
+                config
+            );
+            let spec = crate::parse_spec(&line, true, &HashMap::new()).unwrap();
+            let agents = crate::build_agents(&spec, &HashMap::new()).unwrap();
+            super::control(&spec, &agents, rng)
+        };
+        let closed = control("{}", &mut rng).unwrap();
+        assert!((closed.mean - 1.0 / 6.0).abs() < 1e-9);
+        assert_eq!(closed.draws, None);
+        // a buyer on [0, 2] gains (2 - s)^2 / 4 on average from a seller at s
+        let sampled = control(r#"{"support":{"buyers":[0,2]}}"#, &mut rng).unwrap();
+        assert!(sampled.draws.is_some());
+        assert!((sampled.mean - 7.0 / 12.0).abs() < 4.0 * sampled.stderr.unwrap());
+        let shocks = r#"{"shocks":[{"at":1,"shift":0.1}]}"#;
+        assert!(control(shocks, &mut rng).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::tournament::{Matrix, Profile};
+use serde::Serialize;
+use std::io::{self, Write};
+
+/// Write a payoff matrix as a Gambit normal form game, with buyers as the first player
+///
+/// Payoffs are listed with the first player's strategy changing fastest, as Gambit expects.
+pub fn nfg(matrix: &Matrix<'_>, out: &mut impl Write) -> io::Result<()> {
+    let names: Vec<_> = matrix.strategies.iter().map(|s| quote(s)).collect();
+    let names = names.join(" ");
+    writeln!(
+        out,
+        "NFG 1 R \"cdasim tournament\" {{ \"buyers\" \"sellers\" }}"
+    )?;
+    writeln!(out)?;
+    writeln!(out, "{{ {{ {} }}", names)?;
+    writeln!(out, "{{ {} }}", names)?;
+    writeln!(out, "}}")?;
+    writeln!(out, "\"\"")?;
+    writeln!(out)?;
+    let num = matrix.strategies.len();
+    let payoffs: Vec<_> = (0..num)
+        .flat_map(|col| (0..num).map(move |row| (row, col)))
+        .flat_map(|(row, col)| [matrix.buyers[row][col], matrix.sellers[row][col]])
+        .map(|payoff| payoff.to_string())
+        .collect();
+    writeln!(out, "{}", payoffs.join(" "))
+}
+
+/// A Gambit string literal
+fn quote(string: &str) -> String {
+    format!("\"{}\"", string.replace('"', "\\\""))
+}
+
+#[derive(Serialize, Debug)]
+struct Game<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    roles: [Role<'a>; 2],
+    profiles: Vec<GameProfile<'a>>,
+}
+
+#[derive(Serialize, Debug)]
+struct Role<'a> {
+    name: &'static str,
+    count: u64,
+    strategies: &'a [String],
+}
+
+/// Each role's list of [strategy, count, payoff] triples
+#[derive(Serialize, Debug)]
+struct GameProfile<'a> {
+    buyers: Vec<(&'a str, u64, f64)>,
+    sellers: Vec<(&'a str, u64, f64)>,
+}
+
+/// Write profiles as a line of gameanalysis game json
+pub fn gameanalysis(
+    strategies: &[String],
+    buyers: u64,
+    sellers: u64,
+    profiles: &[Profile],
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let triples = |profile: &'_ Profile, role| -> Vec<(&str, u64, f64)> {
+        let counts = if role == "buyers" {
+            &profile.buyers
+        } else {
+            &profile.sellers
+        };
+        strategies
+            .iter()
+            .filter_map(|strat| {
+                let count = *counts.get(strat)?;
+                Some((strat.as_str(), count, profile.payoffs[role][strat]))
+            })
+            .collect()
+    };
+    let game = Game {
+        kind: "game.1",
+        roles: [
+            Role {
+                name: "buyers",
+                count: buyers,
+                strategies,
+            },
+            Role {
+                name: "sellers",
+                count: sellers,
+                strategies,
+            },
+        ],
+        profiles: profiles
+            .iter()
+            .map(|profile| GameProfile {
+                buyers: triples(profile, "buyers"),
+                sellers: triples(profile, "sellers"),
+            })
+            .collect(),
+    };
+    serde_json::to_writer(&mut *out, &game)?;
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tournament::Matrix;
+
+    #[test]
+    fn test_nfg() {
+        let strategies = ["a".to_owned(), "b\"".to_owned()];
+        let matrix = Matrix {
+            strategies: &strategies,
+            buyers: vec![vec![1.0, 2.0], vec![3.0, 4.0]],
+            sellers: vec![vec![5.0, 6.0], vec![7.0, 8.0]],
+        };
+        let mut out = Vec::new();
+        super::nfg(&matrix, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(r#"{ { "a" "b\"" }"#));
+        // the buyer's strategy changes fastest
+        assert_eq!(out.lines().last().unwrap(), "1 5 3 7 2 6 4 8");
+    }
+}
@@ -0,0 +1,218 @@
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+
+/// A function an expression can call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Func {
+    Abs,
+    Sqrt,
+    Ln,
+    Min,
+    Max,
+}
+
+impl Func {
+    fn arity(self) -> usize {
+        match self {
+            Func::Abs | Func::Sqrt | Func::Ln => 1,
+            Func::Min | Func::Max => 2,
+        }
+    }
+}
+
+/// An arithmetic expression over named numbers
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Num(f64),
+    Var(String),
+    Neg(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+    Call(Func, Vec<Expr>),
+}
+
+impl Expr {
+    /// Evaluate with the values of variables, which may be missing, erroring on unknown variables
+    ///
+    /// The result is None if any variable it uses is missing, or if it isn't finite, e.g. after
+    /// dividing by zero.
+    pub fn eval(
+        &self,
+        vars: &impl Fn(&str) -> Result<Option<f64>, String>,
+    ) -> Result<Option<f64>, String> {
+        let bin = |a: &Expr, b: &Expr, op: fn(f64, f64) -> f64| -> Result<Option<f64>, String> {
+            Ok(a.eval(vars)?.zip(b.eval(vars)?).map(|(a, b)| op(a, b)))
+        };
+        let res = match self {
+            Expr::Num(num) => Some(*num),
+            Expr::Var(name) => vars(name)?,
+            Expr::Neg(inner) => inner.eval(vars)?.map(|v| -v),
+            Expr::Add(a, b) => bin(a, b, |a, b| a + b)?,
+            Expr::Sub(a, b) => bin(a, b, |a, b| a - b)?,
+            Expr::Mul(a, b) => bin(a, b, |a, b| a * b)?,
+            Expr::Div(a, b) => bin(a, b, |a, b| a / b)?,
+            Expr::Call(func, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(vars))
+                    .collect::<Result<Option<Vec<_>>, _>>()?;
+                args.map(|args| match func {
+                    Func::Abs => args[0].abs(),
+                    Func::Sqrt => args[0].sqrt(),
+                    Func::Ln => args[0].ln(),
+                    Func::Min => args[0].min(args[1]),
+                    Func::Max => args[0].max(args[1]),
+                })
+            }
+        };
+        Ok(res.filter(|v| v.is_finite()))
+    }
+}
+
+/// A recursive descent parser with the usual precedence, where * and / bind tighter than + and -
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_space(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        self.skip_space();
+        self.chars.next_if_eq(&expected).is_some()
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        loop {
+            if self.eat('+') {
+                expr = Expr::Add(Box::new(expr), Box::new(self.product()?));
+            } else if self.eat('-') {
+                expr = Expr::Sub(Box::new(expr), Box::new(self.product()?));
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        loop {
+            if self.eat('*') {
+                expr = Expr::Mul(Box::new(expr), Box::new(self.unary()?));
+            } else if self.eat('/') {
+                expr = Expr::Div(Box::new(expr), Box::new(self.unary()?));
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat('-') {
+            Ok(Expr::Neg(Box::new(self.unary()?)))
+        } else {
+            self.atom()
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        self.skip_space();
+        if self.eat('(') {
+            let expr = self.sum()?;
+            return if self.eat(')') {
+                Ok(expr)
+            } else {
+                Err("missing )".to_owned())
+            };
+        }
+        let mut token = String::new();
+        while let Some(c) = self
+            .chars
+            .next_if(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '.')
+        {
+            token.push(c);
+        }
+        match token.chars().next() {
+            None => match self.chars.peek() {
+                Some(c) => Err(format!("unexpected {}", c)),
+                None => Err("unexpected end".to_owned()),
+            },
+            Some(first) if first.is_ascii_digit() || first == '.' => token
+                .parse()
+                .map(Expr::Num)
+                .map_err(|_| format!("invalid number {}", token)),
+            Some(_) if self.eat('(') => {
+                let func = match token.as_str() {
+                    "abs" => Func::Abs,
+                    "sqrt" => Func::Sqrt,
+                    "ln" => Func::Ln,
+                    "min" => Func::Min,
+                    "max" => Func::Max,
+                    _ => return Err(format!("unknown function {}", token)),
+                };
+                let mut args = vec![self.sum()?];
+                while self.eat(',') {
+                    args.push(self.sum()?);
+                }
+                if !self.eat(')') {
+                    Err("missing )".to_owned())
+                } else if args.len() != func.arity() {
+                    Err(format!("{} takes {} arguments", token, func.arity()))
+                } else {
+                    Ok(Expr::Call(func, args))
+                }
+            }
+            Some(_) => Ok(Expr::Var(token)),
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            chars: text.chars().peekable(),
+        };
+        let expr = parser.sum()?;
+        parser.skip_space();
+        match parser.chars.next() {
+            None => Ok(expr),
+            Some(c) => Err(format!("unexpected {}", c)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Expr;
+
+    fn eval(text: &str) -> Result<Option<f64>, String> {
+        let vars = |name: &str| match name {
+            "surplus" => Ok(Some(3.0)),
+            "ce_surplus" => Ok(Some(4.0)),
+            "ce_price" => Ok(None),
+            _ => Err(format!("unknown {}", name)),
+        };
+        text.parse::<Expr>()?.eval(&vars)
+    }
+
+    #[test]
+    fn test_expr() {
+        assert_eq!(eval("surplus / ce_surplus"), Ok(Some(0.75)));
+        assert_eq!(eval("1 + 2 * 3 - -4 / 2"), Ok(Some(9.0)));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(Some(9.0)));
+        assert_eq!(eval("max(surplus, 2 * 2) - abs(-1)"), Ok(Some(3.0)));
+        assert_eq!(eval("ce_price * 2"), Ok(None));
+        assert_eq!(eval("surplus / 0"), Ok(None));
+        assert!(eval("missing + 1").is_err());
+        for invalid in ["", "1 +", "(1", "1 2", "foo(1)", "min(1)", "1..2"] {
+            assert!(invalid.parse::<Expr>().is_err(), "{}", invalid);
+        }
+    }
+}
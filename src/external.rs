@@ -0,0 +1,228 @@
+use crate::bidding::{BiddingStrategy, MarketState, Outcome};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex, PoisonError};
+use tracing::warn;
+
+/// Number of previous outcomes sent to the external process with every decision
+const HISTORY: usize = 20;
+
+/// The market that external agents are told about
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct MarketInfo {
+    pub cda: bool,
+    pub buyers: usize,
+    pub sellers: usize,
+}
+
+#[derive(Serialize, Debug)]
+struct Request<'a> {
+    agent: usize,
+    role: &'static str,
+    value: f64,
+    shading: f64,
+    market: MarketInfo,
+    state: MarketState,
+    history: &'a VecDeque<Outcome>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Response {
+    price: f64,
+}
+
+/// A child process that makes decisions for external agents
+///
+/// Every decision writes a single line of json to the process's stdin with the agent's index,
+/// role, value, shading, the market info, the market's state, and the agent's most recent
+/// outcomes. The process must respond with a single line of json of the form `{"price": <price>}`,
+/// which is the price the agent bids if it's a buyer or asks if it's a seller. Values and prices
+/// are normalized so the market's value support is [0, 1].
+///
+/// Agents decide once their values are drawn, when the state is empty, and in a continuous market
+/// again when they arrive, with the best bid and ask in the book and the last price traded. If the
+/// process fails to respond, the error is logged, and it's asked nothing else, so every order of
+/// its agents is rejected.
+#[derive(Debug)]
+pub struct ExternalProcess {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+    market: MarketInfo,
+    failed: bool,
+}
+
+impl ExternalProcess {
+    /// Spawn a process from a program and its arguments
+    pub fn spawn(command: &[String], market: MarketInfo) -> io::Result<Self> {
+        let (program, args) = command.split_first().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "external command is empty")
+        })?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Ok(ExternalProcess {
+            child,
+            stdin,
+            stdout,
+            market,
+            failed: false,
+        })
+    }
+
+    fn request(&mut self, request: &Request<'_>) -> io::Result<f64> {
+        let stdin = self.stdin.as_mut().unwrap();
+        serde_json::to_writer(&mut *stdin, request)?;
+        writeln!(stdin)?;
+        stdin.flush()?;
+        let mut line = String::new();
+        if self.stdout.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "external process closed its output",
+            ));
+        }
+        let response: Response = serde_json::from_str(&line)?;
+        Ok(response.price)
+    }
+}
+
+impl Drop for ExternalProcess {
+    fn drop(&mut self) {
+        // closing stdin signals the process to exit
+        drop(self.stdin.take());
+        let _ = self.child.wait();
+    }
+}
+
+/// An agent's connection to an external process
+#[derive(Debug, Clone)]
+pub struct ExternalAgent {
+    process: Arc<Mutex<ExternalProcess>>,
+    id: usize,
+    history: VecDeque<Outcome>,
+    /// The state of the market for the next decision, empty until the agent watches it
+    state: MarketState,
+}
+
+impl ExternalAgent {
+    pub fn new(process: Arc<Mutex<ExternalProcess>>, id: usize) -> Self {
+        ExternalAgent {
+            process,
+            id,
+            history: VecDeque::with_capacity(HISTORY),
+            state: MarketState::default(),
+        }
+    }
+
+    /// Ask the external process for this agent's price, or NaN if it failed
+    pub fn price(&mut self, buyer: bool, value: f64, shading: f64) -> f64 {
+        let mut process = self.process.lock().unwrap_or_else(PoisonError::into_inner);
+        if process.failed {
+            return f64::NAN;
+        }
+        let request = Request {
+            agent: self.id,
+            role: if buyer { "buyers" } else { "sellers" },
+            value,
+            shading,
+            market: process.market,
+            state: mem::take(&mut self.state),
+            history: &self.history,
+        };
+        process.request(&request).unwrap_or_else(|err| {
+            // a process that failed may be out of step with its requests, so it isn't asked again
+            warn!(agent = self.id, %err, "external process failed, rejecting its orders");
+            process.failed = true;
+            f64::NAN
+        })
+    }
+}
+
+impl BiddingStrategy for ExternalAgent {
+    fn bid(&mut self, buyer: bool, value: f64, shading: f64) -> f64 {
+        let sign = if buyer { 1.0 } else { -1.0 };
+        sign * self.price(buyer, value, shading)
+    }
+
+    fn watches(&self) -> bool {
+        true
+    }
+
+    fn watch(&mut self, state: &MarketState) {
+        self.state = *state;
+    }
+
+    fn learn(&mut self, outcome: &Outcome) {
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(*outcome);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{ExternalAgent, ExternalProcess, MarketInfo};
+    use crate::bidding::{BiddingStrategy, MarketState, Outcome};
+    use std::sync::{Arc, Mutex};
+
+    fn agent(script: &str) -> ExternalAgent {
+        let command = ["sh".to_owned(), "-c".to_owned(), script.to_owned()];
+        let market = MarketInfo {
+            cda: true,
+            buyers: 1,
+            sellers: 1,
+        };
+        let process = ExternalProcess::spawn(&command, market).unwrap();
+        ExternalAgent::new(Arc::new(Mutex::new(process)), 0)
+    }
+
+    #[test]
+    fn test_external_price() {
+        let mut agent = agent(r#"while read line; do echo '{"price": 0.25}'; done"#);
+        assert_eq!(agent.bid(true, 0.5, 0.0), 0.25);
+        agent.learn(&Outcome {
+            value: 0.5,
+            price: 0.25,
+            traded: true,
+            payoff: 0.25,
+        });
+        assert_eq!(agent.bid(false, 0.1, 0.0), -0.25);
+    }
+
+    #[test]
+    fn test_external_state() {
+        let script = r#"while read line; do
+            case "$line" in
+                *'"state":{"best_bid":0.4,"best_ask":null,"last_price":0.3}'*) echo '{"price": 0.4}';;
+                *) echo '{"price": 0.1}';;
+            esac
+        done"#;
+        let mut agent = agent(script);
+        assert!(agent.watches());
+        agent.watch(&MarketState {
+            best_bid: Some(0.4),
+            best_ask: None,
+            last_price: Some(0.3),
+        });
+        assert_eq!(agent.bid(true, 0.5, 0.0), 0.4);
+        // the state is only for the decision after watching
+        assert_eq!(agent.bid(true, 0.5, 0.0), 0.1);
+    }
+
+    #[test]
+    fn test_external_failure() {
+        // garbage, and then nothing once the process exits
+        let mut agent = agent("read line; echo garbage");
+        assert!(agent.bid(true, 0.5, 0.0).is_nan());
+        assert!(agent.bid(true, 0.5, 0.0).is_nan());
+    }
+}
@@ -0,0 +1,87 @@
+use serde_json::{Map, Value};
+
+/// Copy the parts of an observation at dotted paths, keeping their nesting
+///
+/// A path's components are object keys, and arrays are selected from element-wise, so
+/// "players.payoff" keeps only the payoff of every player. Paths whose first key isn't at the top
+/// of the observation are looked up in its "features". Paths that don't exist are left out.
+pub fn select(observation: &Value, paths: &[String]) -> Value {
+    let mut selected = Value::Object(Map::new());
+    for path in paths {
+        let mut keys: Vec<_> = path.split('.').collect();
+        if observation.get(keys[0]).is_none() {
+            keys.insert(0, "features");
+        }
+        pick(observation, &keys, &mut selected);
+    }
+    selected
+}
+
+fn pick(source: &Value, keys: &[&str], dest: &mut Value) {
+    let Some((key, rest)) = keys.split_first() else {
+        *dest = source.clone();
+        return;
+    };
+    match source {
+        Value::Object(fields) => {
+            let Some(child) = fields.get(*key) else {
+                return;
+            };
+            if !dest.is_object() {
+                *dest = Value::Object(Map::new());
+            }
+            let dests = dest.as_object_mut().unwrap();
+            let entry = dests.entry(*key).or_insert(Value::Null);
+            pick(child, rest, entry);
+            // nothing was found further down
+            if entry.is_null() && !rest.is_empty() {
+                dests.remove(*key);
+            }
+        }
+        Value::Array(elements) => {
+            if !dest.is_array() {
+                *dest = Value::Array(vec![Value::Null; elements.len()]);
+            }
+            let dests = dest.as_array_mut().unwrap();
+            for (element, dest) in elements.iter().zip(dests) {
+                pick(element, keys, dest);
+            }
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    #[test]
+    fn test_select() {
+        let obs = json!({
+            "players": [
+                {"role": "buyers", "payoff": 0.5},
+                {"role": "sellers", "payoff": 0.1},
+            ],
+            "features": {"surplus": 0.6, "ce_price": null, "clipped": 0},
+        });
+        let paths = [
+            "surplus",
+            "ce_price",
+            "players.payoff",
+            "missing",
+            "players.role",
+            "seed.x",
+        ];
+        let paths: Vec<_> = paths.iter().map(|&path| path.to_owned()).collect();
+        assert_eq!(
+            super::select(&obs, &paths),
+            json!({
+                "features": {"surplus": 0.6, "ce_price": null},
+                "players": [
+                    {"payoff": 0.5, "role": "buyers"},
+                    {"payoff": 0.1, "role": "sellers"},
+                ],
+            })
+        );
+    }
+}
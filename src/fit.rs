@@ -0,0 +1,197 @@
+use crate::stats;
+use crate::strategy::{self, Presets, Shading};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+
+#[derive(Parser, Debug)]
+/// Fit payoffs as a polynomial of shading
+///
+/// Reads observations from stdin, and groups the players of every role by their strategy with its
+/// shading replaced by "*", e.g. "0.2_Shift" and "0.4_Shift" are both "*_Shift". Within each
+/// group, the payoffs of players with a fixed shading are regressed on powers of that shading up
+/// to --degree, which gives a smooth surrogate of the payoff of every shading between the ones
+/// observed. The output is a single line of json with a "fits" map from role to group to the
+/// number of "observations", the distinct shading "levels", the "coefficients" from the constant
+/// up, and "r_squared", where the coefficients are null without more levels than the degree.
+pub struct FitArgs {
+    /// Degree of the polynomial
+    #[clap(long, value_parser, default_value_t = 2)]
+    degree: usize,
+}
+
+#[derive(Deserialize, Debug)]
+struct Player {
+    role: String,
+    strategy: String,
+    payoff: f64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Observation {
+    players: Vec<Player>,
+}
+
+/// The shading and payoff of every player in a group
+#[derive(Debug, Default)]
+struct Points {
+    shadings: Vec<f64>,
+    payoffs: Vec<f64>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Fit {
+    observations: usize,
+    levels: Vec<f64>,
+    coefficients: Option<Vec<f64>>,
+    r_squared: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+struct Analysis {
+    fits: BTreeMap<String, BTreeMap<String, Fit>>,
+}
+
+/// The group of a strategy and its shading, if it has a fixed one
+fn family(name: &str, presets: &Presets) -> Option<(String, f64)> {
+    let strat = strategy::resolve(name, presets).ok()?;
+    let Some(Shading::Fixed(shading)) = strat.shading else {
+        return None;
+    };
+    let rest = strategy::Strategy {
+        shading: None,
+        ..strat
+    };
+    let family = match strat.style {
+        Some(_) => format!("*_{}", rest),
+        None => format!("*{}", rest),
+    };
+    Some((family, shading))
+}
+
+fn fit_family(degree: usize, points: &Points) -> Fit {
+    let Points { shadings, payoffs } = points;
+    let mut levels = shadings.clone();
+    levels.sort_unstable_by(f64::total_cmp);
+    levels.dedup();
+    let coefficients = (levels.len() > degree)
+        .then(|| {
+            let rows: Vec<Vec<f64>> = shadings
+                .iter()
+                .map(|&x| (0..=degree as i32).map(|pow| x.powi(pow)).collect())
+                .collect();
+            stats::least_squares(&rows, payoffs)
+        })
+        .flatten();
+    let r_squared = coefficients.as_ref().and_then(|coefs| {
+        let mean = stats::mean(payoffs);
+        let (mut residual, mut total) = (0.0, 0.0);
+        for (&x, &y) in shadings.iter().zip(payoffs) {
+            let pred = coefs.iter().rev().fold(0.0, |acc, coef| acc * x + coef);
+            residual += (y - pred) * (y - pred);
+            total += (y - mean) * (y - mean);
+        }
+        (total > 0.0).then(|| 1.0 - residual / total)
+    });
+    Fit {
+        observations: payoffs.len(),
+        levels,
+        coefficients,
+        r_squared,
+    }
+}
+
+pub fn fit(
+    args: &FitArgs,
+    presets: &Presets,
+    ihandle: impl BufRead,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let mut samples: BTreeMap<String, BTreeMap<String, Points>> = BTreeMap::new();
+    for line in ihandle.lines() {
+        let obs: Observation = serde_json::from_str(&line?)?;
+        for player in obs.players {
+            // strategies without a fixed shading aren't on the curve
+            let Some((family, shading)) = family(&player.strategy, presets) else {
+                continue;
+            };
+            let points = samples
+                .entry(player.role)
+                .or_default()
+                .entry(family)
+                .or_default();
+            points.shadings.push(shading);
+            points.payoffs.push(player.payoff);
+        }
+    }
+    let fits = samples
+        .into_iter()
+        .map(|(role, families)| {
+            let fits = families
+                .into_iter()
+                .map(|(family, points)| (family, fit_family(args.degree, &points)))
+                .collect();
+            (role, fits)
+        })
+        .collect();
+    serde_json::to_writer(&mut *out, &Analysis { fits })?;
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FitArgs;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_family() {
+        let presets = HashMap::new();
+        assert_eq!(super::family("0.2", &presets), Some(("*".to_owned(), 0.2)));
+        assert_eq!(
+            super::family("0.4_Shift_l0.1", &presets),
+            Some(("*_Shift_l0.1".to_owned(), 0.4))
+        );
+        assert_eq!(super::family("U(0,1)_Shift", &presets), None);
+        assert_eq!(super::family("Roth", &presets), None);
+    }
+
+    #[test]
+    fn test_fit_payoffs() {
+        // buyer payoffs follow 1 + x - 2x^2, and the seller only plays one shading
+        let input: Vec<_> = [0.0, 0.25, 0.5, 0.75, 1.0]
+            .iter()
+            .map(|x| {
+                let payoff = 1.0 + x - 2.0 * x * x;
+                format!(
+                    r#"{{"players":[{{"role":"buyers","strategy":"{}_Shift","payoff":{}}},{{"role":"sellers","strategy":"0.5","payoff":0.1}}]}}"#,
+                    x, payoff
+                )
+            })
+            .collect();
+        let mut out = Vec::new();
+        let args = FitArgs { degree: 2 };
+        super::fit(
+            &args,
+            &HashMap::new(),
+            input.join("\n").as_bytes(),
+            &mut out,
+        )
+        .unwrap();
+        let analysis: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let buyers = &analysis["fits"]["buyers"]["*_Shift"];
+        assert_eq!(buyers["observations"], 5);
+        for (coef, exp) in buyers["coefficients"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .zip([1.0, 1.0, -2.0])
+        {
+            assert!((coef.as_f64().unwrap() - exp).abs() < 1e-9);
+        }
+        assert!((buyers["r_squared"].as_f64().unwrap() - 1.0).abs() < 1e-9);
+        let sellers = &analysis["fits"]["sellers"]["*"];
+        assert_eq!(sellers["levels"], serde_json::json!([0.5]));
+        assert!(sellers["coefficients"].is_null());
+    }
+}
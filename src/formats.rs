@@ -0,0 +1,174 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// How specs are written
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpecFormat {
+    /// A json spec on every line
+    Json,
+    /// A toml table of one spec, or of an array of tables "specs" with many
+    Toml,
+    /// Yaml documents of one spec each, separated by "---" lines
+    Yaml,
+}
+
+impl SpecFormat {
+    /// The format of a file from its extension, json unless it's .toml, .yaml, or .yml
+    pub fn detect(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => SpecFormat::Toml,
+            Some("yaml" | "yml") => SpecFormat::Yaml,
+            _ => SpecFormat::Json,
+        }
+    }
+}
+
+/// The json spec lines of specs in another format
+pub fn spec_lines(format: SpecFormat, text: &str) -> io::Result<Vec<String>> {
+    let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidData, err);
+    let specs = match format {
+        SpecFormat::Json => return Ok(text.lines().map(str::to_owned).collect()),
+        SpecFormat::Toml => {
+            let table: Value = toml::from_str(text)
+                .map_err(|err| invalid(format!("invalid toml specs: {}", err)))?;
+            match table {
+                Value::Object(mut table) if table.len() == 1 && table.contains_key("specs") => {
+                    match table.remove("specs") {
+                        Some(Value::Array(specs)) => specs,
+                        _ => return Err(invalid("toml \"specs\" must be an array".to_owned())),
+                    }
+                }
+                spec => vec![spec],
+            }
+        }
+        SpecFormat::Yaml => serde_yaml_ng::Deserializer::from_str(text)
+            .map(Value::deserialize)
+            .filter(|doc| !matches!(doc, Ok(Value::Null)))
+            .collect::<Result<_, _>>()
+            .map_err(|err| invalid(format!("invalid yaml specs: {}", err)))?,
+    };
+    specs
+        .iter()
+        .map(|spec| Ok(serde_json::to_string(spec)?))
+        .collect()
+}
+
+/// A toml file of many specs
+#[derive(Serialize, Debug)]
+struct TomlSpecs {
+    specs: Vec<Value>,
+}
+
+/// Write json spec lines in another format, that [`spec_lines`] reads back
+///
+/// Toml specs are always an array of tables "specs", and can't have nulls.
+pub fn write_specs(
+    format: SpecFormat,
+    lines: impl Iterator<Item = io::Result<String>>,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidData, err);
+    let mut specs = Vec::new();
+    for (spec_index, line) in lines.enumerate() {
+        let line = line?;
+        if format == SpecFormat::Json {
+            writeln!(out, "{}", line)?;
+            continue;
+        }
+        let spec: Value = serde_json::from_str(&line)
+            .map_err(|err| invalid(format!("invalid spec {}: {}", spec_index, err)))?;
+        specs.push(spec);
+    }
+    match format {
+        SpecFormat::Json => Ok(()),
+        SpecFormat::Toml => {
+            let text = toml::to_string(&TomlSpecs { specs })
+                .map_err(|err| invalid(format!("specs can't be written as toml: {}", err)))?;
+            write!(out, "{}", text)
+        }
+        SpecFormat::Yaml => {
+            for spec in specs {
+                writeln!(out, "---")?;
+                serde_yaml_ng::to_writer(&mut *out, &spec)
+                    .map_err(|err| invalid(format!("specs can't be written as yaml: {}", err)))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpecFormat;
+    use std::io;
+    use std::path::Path;
+
+    #[test]
+    fn test_spec_lines() {
+        let toml = r#"
+[assignment.buyers]
+"0.1" = 2
+[assignment.sellers]
+"0.2" = 2
+[configuration]
+cda = false
+"#;
+        let yaml = "assignment:\n  buyers: {0.1: 2}\n  sellers:\n    0.2: 2\nconfiguration:\n  cda: false\n";
+        let json = r#"{"assignment":{"buyers":{"0.1":2},"sellers":{"0.2":2}},"configuration":{"cda":false}}"#;
+        assert_eq!(super::spec_lines(SpecFormat::Toml, toml).unwrap(), [json]);
+        assert_eq!(super::spec_lines(SpecFormat::Yaml, yaml).unwrap(), [json]);
+        let many = "[[specs]]\nassignment = {}\n[[specs]]\nconfiguration = {}\n";
+        let lines = super::spec_lines(SpecFormat::Toml, many).unwrap();
+        assert_eq!(lines, [r#"{"assignment":{}}"#, r#"{"configuration":{}}"#]);
+        assert!(super::spec_lines(SpecFormat::Toml, "specs = 1").is_err());
+        assert!(super::spec_lines(SpecFormat::Toml, "[oops").is_err());
+        let anchored = "base: &base {cda: false}\nconfiguration: *base\nnote: |\n  two\n  lines\n";
+        assert_eq!(
+            super::spec_lines(SpecFormat::Yaml, anchored).unwrap(),
+            [r#"{"base":{"cda":false},"configuration":{"cda":false},"note":"two\nlines\n"}"#]
+        );
+        assert_eq!(
+            super::spec_lines(SpecFormat::Yaml, "---\n---\n{}").unwrap(),
+            ["{}"]
+        );
+        assert!(super::spec_lines(SpecFormat::Yaml, "a: [1").is_err());
+
+        assert_eq!(
+            SpecFormat::detect(Path::new("a/specs.yml")),
+            SpecFormat::Yaml
+        );
+        assert_eq!(
+            SpecFormat::detect(Path::new("specs.toml")),
+            SpecFormat::Toml
+        );
+        assert_eq!(
+            SpecFormat::detect(Path::new("specs.jsonl")),
+            SpecFormat::Json
+        );
+    }
+
+    #[test]
+    fn test_write_specs() {
+        let lines = [
+            r#"{"assignment":{"buyers":{"0.1":2}},"configuration":{"fee":[1,2.5],"name":"a: b"}}"#,
+            r#"{"assignment":{}}"#,
+        ];
+        for format in [SpecFormat::Json, SpecFormat::Toml, SpecFormat::Yaml] {
+            let mut out = Vec::new();
+            super::write_specs(
+                format,
+                lines.iter().map(|&line| Ok(line.to_owned())),
+                &mut out,
+            )
+            .unwrap();
+            let text = String::from_utf8(out).unwrap();
+            assert_eq!(super::spec_lines(format, &text).unwrap(), lines);
+        }
+        let null = [Ok::<_, io::Error>(r#"{"seed":null}"#.to_owned())];
+        let mut out = Vec::new();
+        assert!(super::write_specs(SpecFormat::Toml, null.into_iter(), &mut out).is_err());
+    }
+}
@@ -0,0 +1,411 @@
+use crate::context;
+use crate::engine::Order;
+use crate::market::{self, Call, Market};
+use crate::strategy::Presets;
+use crate::{Agent, Spec, Style};
+use rand::Rng;
+use std::cell::RefCell;
+use std::sync::{mpsc, OnceLock};
+use tracing::error;
+use wgpu::util::DeviceExt;
+
+/// The kernel that simulates a batch of observations
+const SHADER: &str = include_str!("gpu.wgsl");
+
+/// The most agents a market can have to be simulated on the GPU, as in the kernel
+const MAX_AGENTS: usize = 64;
+
+/// Invocations per workgroup, as in the kernel
+const WORKGROUP: u32 = 64;
+
+/// Observations simulated per launch of the kernel
+const BATCH: u32 = 4096;
+
+/// The device batches run on, and the kernel compiled for it
+struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl Gpu {
+    /// Compile the kernel for the first adapter that can run it, if any
+    async fn new() -> Option<Gpu> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                ..Default::default()
+            })
+            .await?;
+        let capabilities = adapter.get_downlevel_capabilities();
+        if !capabilities
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+        {
+            return None;
+        }
+        let descriptor = wgpu::DeviceDescriptor {
+            label: Some("cdasim"),
+            required_limits: adapter.limits(),
+            ..Default::default()
+        };
+        let (device, queue) = adapter.request_device(&descriptor, None).await.ok()?;
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("call markets"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("call markets"),
+            layout: None,
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        Some(Gpu {
+            device,
+            queue,
+            pipeline,
+        })
+    }
+}
+
+/// The GPU of this process, found the first time it's needed
+fn gpu() -> Option<&'static Gpu> {
+    static GPU: OnceLock<Option<Gpu>> = OnceLock::new();
+    GPU.get_or_init(|| pollster::block_on(Gpu::new())).as_ref()
+}
+
+/// Clear an observation's bids like [Call], starting from the order the kernel ranked them in
+///
+/// The kernel ranks single precision bids, so bids that nearly tie may be out of the order of the
+/// agents' own bids. Sorting them again costs little since they're nearly sorted, and then the
+/// pairs that trade and their price are exactly those of a call market.
+fn clear(agents: &mut [Agent<'_>], words: &[u32]) -> Option<f64> {
+    context::with(|ctx| {
+        let (buys, sells) = &mut ctx.orders;
+        let buyers = agents.iter().filter(|a| a.buyer).count();
+        buys.clear();
+        buys.resize(buyers, Order::new(0.0, 0));
+        sells.clear();
+        sells.resize(agents.len() - buyers, Order::new(0.0, 0));
+        for (index, agent) in agents.iter().enumerate() {
+            let book = if agent.buyer { &mut *buys } else { &mut *sells };
+            book[words[2 * index] as usize] = Order::new(agent.bid, index);
+        }
+        market::call(agents, buys, sells).map(|(price, _)| price)
+    })
+}
+
+/// The little endian bytes of words, as buffers hold them
+fn bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+/// The observations of the current batch, and which one the agents are in
+#[derive(Default)]
+struct State {
+    words: Vec<u32>,
+    /// Observations launched so far
+    launched: u64,
+    /// The next observation in the batch
+    next: usize,
+    /// The observation the agents are in, None if it was drawn on the CPU
+    current: Option<usize>,
+    /// If a batch failed, and every observation since is simulated on the CPU
+    failed: bool,
+}
+
+/// A call market whose observations are simulated on the GPU in batches
+///
+/// The kernel draws every agent's value, shades it, and sorts the bids of each observation in
+/// parallel. Each observation's agents are then given the values it drew, and their bids are
+/// cleared in the order it sorted them, so their features and payoffs are computed as usual.
+/// Observations are drawn from the batch's seed rather than from each observation's, so they
+/// can't be reproduced individually.
+pub struct Batch {
+    gpu: &'static Gpu,
+    agents: usize,
+    seed: u64,
+    params: wgpu::Buffer,
+    output: wgpu::Buffer,
+    staging: wgpu::Buffer,
+    bindings: wgpu::BindGroup,
+    state: RefCell<State>,
+}
+
+impl Batch {
+    /// A batch to simulate a spec's call market with, or why it can't be
+    ///
+    /// Specs qualify if they're call markets where nothing but the values is random, as for
+    /// integrating them, of at most 64 agents without price bounds, price controls, or entry costs.
+    pub fn new(
+        spec: &Spec,
+        agents: &[Agent<'_>],
+        presets: &Presets,
+        seed: u64,
+    ) -> Result<Batch, &'static str> {
+        crate::exact::fixed(spec, agents, presets)?;
+        if agents.is_empty() {
+            return Err("there are no agents");
+        } else if agents.len() > MAX_AGENTS {
+            return Err("there are too many agents");
+        } else if agents.iter().any(|a| a.bounded() || a.controlled()) {
+            return Err("prices are bounded");
+        } else if agents.iter().any(Agent::chooses_entry) {
+            return Err("agents choose whether to enter");
+        }
+        let mut words = Vec::with_capacity(agents.len() * 8);
+        for agent in agents {
+            let style = match agent.style() {
+                Some(Style::Standard) => 0,
+                Some(Style::Exponential) => 1,
+                Some(Style::Shift) => 2,
+                Some(Style::Correct) => 3,
+                _ => return Err("strategies learn or draw their bids"),
+            };
+            let (low, high) = agent.support();
+            let (scale_low, scale_high) = agent.scale();
+            words.extend([u32::from(agent.buyer), style]);
+            words.extend(
+                [agent.shading(), low, high, scale_low, scale_high].map(|x| (x as f32).to_bits()),
+            );
+            words.push(0);
+        }
+        let gpu = gpu().ok_or("there's no GPU")?;
+
+        let device = &gpu.device;
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("params"),
+            size: 32,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // the agents' parameters are the same for every batch
+        let parameters = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("agents"),
+            contents: &bytes(&words),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let size = 4 * u64::from(BATCH) * 2 * agents.len() as u64;
+        let output = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("output"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("staging"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bindings = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("bindings"),
+            layout: &gpu.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: parameters.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: output.as_entire_binding(),
+                },
+            ],
+        });
+        Ok(Batch {
+            gpu,
+            agents: agents.len(),
+            seed,
+            params,
+            output,
+            staging,
+            bindings,
+            state: RefCell::default(),
+        })
+    }
+
+    /// The number of words of each observation
+    fn stride(&self) -> usize {
+        2 * self.agents
+    }
+
+    /// Simulate the next batch of observations, and read them back
+    fn launch(&self, state: &mut State) -> Result<(), wgpu::BufferAsyncError> {
+        let gpu = self.gpu;
+        let params = [
+            self.agents as u32,
+            BATCH,
+            self.seed as u32,
+            (self.seed >> 32) as u32,
+            state.launched as u32,
+            (state.launched >> 32) as u32,
+            0,
+            0,
+        ];
+        gpu.queue.write_buffer(&self.params, 0, &bytes(&params));
+        let mut encoder = gpu
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            pass.set_pipeline(&gpu.pipeline);
+            pass.set_bind_group(0, &self.bindings, &[]);
+            pass.dispatch_workgroups(BATCH.div_ceil(WORKGROUP), 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&self.output, 0, &self.staging, 0, self.output.size());
+        gpu.queue.submit([encoder.finish()]);
+
+        let slice = self.staging.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |res| drop(sender.send(res)));
+        gpu.device.poll(wgpu::Maintain::Wait);
+        receiver.recv().map_err(|_| wgpu::BufferAsyncError)??;
+        let data = slice.get_mapped_range();
+        state.words.clear();
+        state.words.extend(
+            data.chunks_exact(4)
+                .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]])),
+        );
+        drop(data);
+        self.staging.unmap();
+        state.launched += u64::from(BATCH);
+        state.next = 0;
+        Ok(())
+    }
+}
+
+impl Market for Batch {
+    fn draw(&self, agents: &mut [Agent<'_>], rng: &mut impl Rng) -> bool {
+        let mut state = self.state.borrow_mut();
+        state.current = None;
+        if state.failed {
+            return false;
+        } else if state.next * self.stride() >= state.words.len() {
+            if let Err(err) = self.launch(&mut state) {
+                error!(%err, "GPU batch failed, simulating on the CPU");
+                state.failed = true;
+                return false;
+            }
+        }
+        let obs = state.next;
+        let words = &state.words[obs * self.stride()..(obs + 1) * self.stride()];
+        for (agent, quantile) in agents.iter_mut().zip(words[1..].iter().step_by(2)) {
+            agent.resample(rng);
+            let (low, high) = agent.support();
+            agent.value = low + f64::from(f32::from_bits(*quantile)) * (high - low);
+            agent.observe(rng);
+        }
+        state.next += 1;
+        state.current = Some(obs);
+        true
+    }
+
+    fn simulate(&self, agents: &mut [Agent<'_>], rng: &mut impl Rng) -> Option<f64> {
+        let state = self.state.borrow();
+        let Some(obs) = state.current else {
+            return Call.simulate(agents, rng);
+        };
+        // agents whose orders may be rejected aren't ranked like the rest
+        if agents.iter().any(|agent| !agent.has_order()) {
+            return Call.simulate(agents, rng);
+        }
+        clear(
+            agents,
+            &state.words[obs * self.stride()..(obs + 1) * self.stride()],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Batch;
+    use crate::market::{Call, Market};
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+    use std::collections::HashMap;
+
+    fn batch(line: &str) -> Result<Batch, &'static str> {
+        let spec = crate::parse_spec(line, true, &HashMap::new()).unwrap();
+        let agents = crate::build_agents(&spec, &HashMap::new()).unwrap();
+        Batch::new(&spec, &agents, &HashMap::new(), 0)
+    }
+
+    #[test]
+    fn test_eligible() {
+        let cda = r#"{"assignment":{"buyers":{"0":1},"sellers":{"0":1}},"configuration":{}}"#;
+        assert!(batch(cda).is_err());
+        let learning = r#"{"assignment":{"buyers":{"1_Roth":1},"sellers":{"0":1}},"configuration":{"cda":false}}"#;
+        assert!(batch(learning).is_err());
+        let bounded = r#"{"assignment":{"buyers":{"0":1},"sellers":{"0":1}},"configuration":{"cda":false,"ceiling":0.5}}"#;
+        assert!(batch(bounded).is_err());
+        let large = r#"{"assignment":{"buyers":{"0":40},"sellers":{"0":40}},"configuration":{"cda":false}}"#;
+        assert_eq!(batch(large).err(), Some("there are too many agents"));
+    }
+
+    #[test]
+    fn test_clear() {
+        let line = r#"{"assignment":{"buyers":{"0.2":3,"0.1_Exponential":2},"sellers":{"0.3_Correct":2,"0.05_Shift":2}},"configuration":{"cda":false}}"#;
+        let spec = crate::parse_spec(line, true, &HashMap::new()).unwrap();
+        let mut agents = crate::build_agents(&spec, &HashMap::new()).unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            for agent in agents.iter_mut() {
+                agent.resample(&mut rng);
+                agent.shade();
+            }
+            // ranks out of order, as the kernel's may be for bids that nearly tie
+            let mut words = vec![0; 2 * agents.len()];
+            for buyer in [true, false] {
+                let mut ranks: Vec<_> = (0..agents.iter().filter(|a| a.buyer == buyer).count())
+                    .map(|rank| rank as u32)
+                    .collect();
+                ranks.shuffle(&mut rng);
+                let side = agents.iter().enumerate().filter(|(_, a)| a.buyer == buyer);
+                for ((index, _), rank) in side.zip(ranks) {
+                    words[2 * index] = rank;
+                }
+            }
+            let mut expected = agents.clone();
+            let price = super::clear(&mut agents, &words);
+            assert_eq!(price, Call.simulate(&mut expected, &mut rng));
+            for (agent, other) in agents.iter().zip(&expected) {
+                assert_eq!(agent.traded, other.traded);
+                assert_eq!(agent.utility, other.utility);
+            }
+        }
+    }
+
+    #[test]
+    fn test_clears_like_call() {
+        let line = r#"{"assignment":{"buyers":{"0.2":3,"0.1_Exponential":2},"sellers":{"0.3_Correct":2,"0.05_Shift":2}},"configuration":{"cda":false}}"#;
+        let spec = crate::parse_spec(line, true, &HashMap::new()).unwrap();
+        let mut agents = crate::build_agents(&spec, &HashMap::new()).unwrap();
+        let batch = match Batch::new(&spec, &agents, &HashMap::new(), 0) {
+            Ok(batch) => batch,
+            // without an adapter there's nothing to check the kernel against
+            Err("there's no GPU") => return,
+            Err(reason) => panic!("{}", reason),
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        // more than a batch, so the kernel is launched again
+        for _ in 0..5000 {
+            assert!(batch.draw(&mut agents, &mut rng));
+            agents.iter_mut().for_each(|a| a.shade());
+            let mut expected = agents.clone();
+            let price = batch.simulate(&mut agents, &mut rng);
+            assert_eq!(price, Call.simulate(&mut expected, &mut rng));
+            for (agent, other) in agents.iter().zip(&expected) {
+                assert_eq!(agent.traded, other.traded);
+                assert_eq!(agent.utility, other.utility);
+            }
+        }
+    }
+}
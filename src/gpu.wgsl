@@ -0,0 +1,133 @@
+// Simulate a batch of call markets, one observation per invocation
+//
+// Every observation draws each agent's value, shades it into a bid, and sorts the bids of each
+// side. Its output is the rank of each agent's bid among its side, and the quantile its value was
+// drawn at.
+
+// The most agents a market can have, the size of the arrays each side is sorted in
+const MAX_AGENTS: u32 = 64u;
+
+struct Params {
+    agents: u32,
+    observations: u32,
+    seed_lo: u32,
+    seed_hi: u32,
+    offset_lo: u32,
+    offset_hi: u32,
+    pad_lo: u32,
+    pad_hi: u32,
+}
+
+struct Agent {
+    buyer: u32,
+    style: u32,
+    shading: f32,
+    low: f32,
+    high: f32,
+    scale_low: f32,
+    scale_high: f32,
+    pad: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> agents: array<Agent>;
+@group(0) @binding(2) var<storage, read_write> output: array<u32>;
+
+// The pcg hash of a word
+fn pcg(input: u32) -> u32 {
+    let state = input * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// A uniform draw in (0, 1) for an agent in an observation, on a grid of 2^24 points
+fn uniform(obs_lo: u32, obs_hi: u32, agent: u32) -> f32 {
+    let seed = pcg(params.seed_hi ^ pcg(params.seed_lo));
+    let hash = pcg(agent ^ pcg(obs_hi ^ pcg(obs_lo ^ seed)));
+    return (f32(hash >> 8u) + 0.5) / 16777216.0;
+}
+
+// The signed bid of an agent with a value, like the bidding strategy of its style
+fn signed_bid(agent: Agent, value: f32) -> f32 {
+    let sign = select(-1.0, 1.0, agent.buyer != 0u);
+    let width = agent.scale_high - agent.scale_low;
+    let norm = (value - agent.scale_low) / width;
+    var bid: f32;
+    switch agent.style {
+        // Exponential
+        case 1u: {
+            bid = sign * norm * exp(-sign * agent.shading);
+        }
+        // Shift
+        case 2u: {
+            bid = sign * norm - agent.shading;
+        }
+        // Correct
+        case 3u: {
+            if agent.buyer != 0u {
+                bid = norm * (1.0 - agent.shading);
+            } else {
+                bid = (norm - 1.0) * agent.shading - norm;
+            }
+        }
+        // Standard
+        default: {
+            bid = norm * (sign - agent.shading);
+        }
+    }
+    return sign * (agent.scale_low + sign * bid * width);
+}
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let obs = id.x;
+    if obs >= params.observations {
+        return;
+    }
+    // the index of the observation is 64 bits, carried from the low word
+    let obs_lo = params.offset_lo + obs;
+    let obs_hi = params.offset_hi + select(0u, 1u, obs_lo < obs);
+    let base = obs * 2u * params.agents;
+
+    // each side sorted by descending signed bid, earlier agents first among equal bids
+    var buys: array<f32, MAX_AGENTS>;
+    var buyers: array<u32, MAX_AGENTS>;
+    var num_buys = 0u;
+    var sells: array<f32, MAX_AGENTS>;
+    var sellers: array<u32, MAX_AGENTS>;
+    var num_sells = 0u;
+    for (var index = 0u; index < params.agents; index++) {
+        let agent = agents[index];
+        let quantile = uniform(obs_lo, obs_hi, index);
+        let bid = signed_bid(agent, agent.low + quantile * (agent.high - agent.low));
+        output[base + 1u + 2u * index] = bitcast<u32>(quantile);
+        if agent.buyer != 0u {
+            var pos = num_buys;
+            while pos > 0u && buys[pos - 1u] < bid {
+                buys[pos] = buys[pos - 1u];
+                buyers[pos] = buyers[pos - 1u];
+                pos--;
+            }
+            buys[pos] = bid;
+            buyers[pos] = index;
+            num_buys++;
+        } else {
+            var pos = num_sells;
+            while pos > 0u && sells[pos - 1u] < bid {
+                sells[pos] = sells[pos - 1u];
+                sellers[pos] = sellers[pos - 1u];
+                pos--;
+            }
+            sells[pos] = bid;
+            sellers[pos] = index;
+            num_sells++;
+        }
+    }
+
+    for (var rank = 0u; rank < num_buys; rank++) {
+        output[base + 2u * buyers[rank]] = rank;
+    }
+    for (var rank = 0u; rank < num_sells; rank++) {
+        output[base + 2u * sellers[rank]] = rank;
+    }
+}
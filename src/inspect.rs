@@ -0,0 +1,305 @@
+use crate::engine::{Action, Level};
+use crate::market::{Call, Continuous, Step};
+use crate::strategy::Presets;
+use crate::{Agent, Features};
+use clap::Parser;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::io::{self, BufRead, Write};
+
+/// Number of price levels of each side of the book shown after every event
+const DEPTH: usize = 3;
+
+#[derive(Parser, Debug)]
+/// Replay one observation of a spec and describe everything that happened
+///
+/// Reads spec lines on stdin and simulates the one at --line with the seed of one of its
+/// observations, e.g. the "seed" of the default command's --tag-output. It prints every agent's
+/// value and order, every event of a CDA with the best levels of the book after it, and every
+/// agent's trade and payoff. Agents start fresh, so observations that depend on earlier ones,
+/// like those of learning agents or a market maker, or after a burn in, aren't reproduced.
+pub struct InspectArgs {
+    /// Seed of the observation to replay
+    #[clap(long, value_parser)]
+    seed: u64,
+
+    /// Index of the spec line to replay
+    #[clap(long, value_parser, default_value_t = 0)]
+    line: usize,
+
+    /// Animate a CDA in the terminal, redrawing the book ladder, the tape of trades, and the
+    /// cumulative surplus after every event
+    ///
+    /// The arrow keys or + and - double or halve the speed while it plays, space pauses it, and q
+    /// quits.
+    #[cfg(feature = "tui")]
+    #[clap(long, value_parser)]
+    tui: bool,
+
+    /// Events per second of the animation
+    #[cfg(feature = "tui")]
+    #[clap(long, value_parser = speed, default_value_t = 4.0, requires = "tui")]
+    speed: f64,
+}
+
+pub fn inspect(
+    args: &InspectArgs,
+    presets: &Presets,
+    strict: bool,
+    ihandle: impl BufRead,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    writeln!(out, "spec line {} with seed {}", args.line, args.seed)?;
+    replay(
+        args.line,
+        args.seed,
+        presets,
+        strict,
+        ihandle,
+        |agents, features, trace| {
+            #[cfg(feature = "tui")]
+            if args.tui {
+                return match trace {
+                    Some(trace) => crate::tui::play(agents, trace, args.speed, out),
+                    None => Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "only cdas can be animated",
+                    )),
+                };
+            }
+            describe(agents, features, trace, out)
+        },
+    )
+}
+
+/// Simulate one observation of the spec at a line of the input with a seed, then pass its
+/// settled agents, features, and trace if it's a CDA, to `then`
+pub fn replay<T>(
+    line: usize,
+    seed: u64,
+    presets: &Presets,
+    strict: bool,
+    ihandle: impl BufRead,
+    then: impl FnOnce(&[Agent<'_>], &Features, Option<&[Step]>) -> io::Result<T>,
+) -> io::Result<T> {
+    let text = ihandle.lines().nth(line).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("there's no spec line {}", line),
+        )
+    })??;
+    let spec = crate::parse_spec(&text, strict, presets)?;
+    let mut agents = crate::build_agents(&spec, presets)?;
+    let mut rng = StdRng::seed_from_u64(seed);
+    let (features, trace) = match crate::continuous_market(&spec.configuration, &agents, false)? {
+        Some(market) => {
+            let market = market.with_trace();
+            let features = crate::run_sim(&mut agents, &market, &mut rng);
+            (features, Some(market.trace()))
+        }
+        None if spec.configuration.cda.unwrap_or(true) => {
+            // a plain continuous market draws the same random numbers as a cda
+            let market = Continuous::new().with_trace();
+            let features = crate::run_sim(&mut agents, &market, &mut rng);
+            (features, Some(market.trace()))
+        }
+        None => (crate::run_sim(&mut agents, &Call, &mut rng), None),
+    };
+    then(&agents, &features, trace.as_deref())
+}
+
+/// Parse a speed whose pause between events, its inverse, is a duration
+#[cfg(feature = "tui")]
+fn speed(arg: &str) -> Result<f64, String> {
+    match arg.parse::<f64>() {
+        Ok(speed) if std::time::Duration::try_from_secs_f64(1.0 / speed).is_ok() => Ok(speed),
+        Ok(speed) => Err(format!("{} isn't a positive speed", speed)),
+        Err(err) => Err(format!("{}", err)),
+    }
+}
+
+/// The price of an agent's signed bid
+fn price(agent: &Agent<'_>, bid: f64) -> f64 {
+    agent.sign() * bid
+}
+
+fn book(levels: &[Level]) -> String {
+    if levels.is_empty() {
+        return "-".to_owned();
+    }
+    let mut shown: Vec<_> = levels
+        .iter()
+        .take(DEPTH)
+        .map(|level| format!("{:.4}x{}", level.price, level.depth))
+        .collect();
+    if levels.len() > DEPTH {
+        shown.push("...".to_owned());
+    }
+    shown.join(" ")
+}
+
+/// What happened in one event
+pub fn event(agents: &[Agent<'_>], step: &Step) -> String {
+    match step.action {
+        Action::Arrive(index) => format!("agent {} arrives", index),
+        Action::Quote(index) => {
+            let agent = &agents[index];
+            let verb = if agent.buyer { "bids" } else { "asks" };
+            match (step.bid, step.fill) {
+                (None, _) => format!("agent {}'s order is dropped", index),
+                (Some(bid), None) => {
+                    format!(
+                        "agent {} {} {:.4} and stands",
+                        index,
+                        verb,
+                        price(agent, bid)
+                    )
+                }
+                (Some(bid), Some(fill)) => {
+                    let with = fill
+                        .counterparty
+                        .map_or_else(|| "the market maker".to_owned(), |c| format!("agent {}", c));
+                    format!(
+                        "agent {} {} {:.4} and trades with {} at {:.4}",
+                        index,
+                        verb,
+                        price(agent, bid),
+                        with,
+                        fill.price
+                    )
+                }
+            }
+        }
+        Action::Cancel(index) => format!("agent {}'s order is cancelled", index),
+        Action::Requote(index) => format!("agent {} may requote", index),
+        Action::Close => "continuous trading stops".to_owned(),
+        Action::Liquidate => match step.fill {
+            Some(fill) => format!("the market maker liquidates at {:.4}", fill.price),
+            None => "the market maker can't liquidate".to_owned(),
+        },
+        Action::Clear => "the market closes".to_owned(),
+    }
+}
+
+fn describe(
+    agents: &[Agent<'_>],
+    features: &Features,
+    trace: Option<&[Step]>,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    writeln!(out, "\nagents")?;
+    for (index, agent) in agents.iter().enumerate() {
+        let order = if agent.submitted() {
+            let verb = if agent.buyer { "bids" } else { "asks" };
+            format!("{} {:.4}", verb, price(agent, agent.bid))
+        } else {
+            "no order".to_owned()
+        };
+        writeln!(
+            out,
+            "{:>4} {:<8} {:<12} value {:.4}, {}",
+            index,
+            agent.role(),
+            agent.strategy(),
+            agent.value,
+            order
+        )?;
+    }
+
+    match trace {
+        Some(trace) => {
+            writeln!(out, "\nevents")?;
+            for step in trace {
+                writeln!(
+                    out,
+                    "{:>8.4} {:<60} book {} | {}",
+                    step.time,
+                    event(agents, step),
+                    book(&step.bids),
+                    book(&step.asks)
+                )?;
+            }
+        }
+        None => {
+            let price = agents.iter().find_map(|a| a.price);
+            match price {
+                Some(price) => writeln!(out, "\nthe call market clears at {:.4}", price)?,
+                None => writeln!(out, "\nthe call market doesn't clear")?,
+            }
+        }
+    }
+
+    writeln!(out, "\ntrades")?;
+    for (index, agent) in agents.iter().enumerate() {
+        let trade = match agent.price {
+            Some(price) => format!("trades at {:.4}", price),
+            None => "doesn't trade".to_owned(),
+        };
+        // adding zero turns the negative zero payoffs of sellers into zero
+        writeln!(
+            out,
+            "{:>4} {}, payoff {:.4}",
+            index,
+            trade,
+            agent.utility + 0.0
+        )?;
+    }
+
+    let efficiency = features
+        .efficiency
+        .map_or_else(|| "-".to_owned(), |e| format!("{:.4}", e));
+    let ce_price = features
+        .ce_price
+        .map_or_else(|| "-".to_owned(), |p| format!("{:.4}", p));
+    writeln!(
+        out,
+        "\nsurplus {:.4} of a possible {:.4} at price {}, efficiency {}",
+        features.surplus, features.ce_surplus, ce_price, efficiency
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InspectArgs;
+    use clap::Parser;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_inspect() {
+        let args = InspectArgs::parse_from(["inspect", "--seed", "3", "--line", "1"]);
+        let call =
+            r#"{"assignment":{"buyers":{"0":2},"sellers":{"0":2}},"configuration":{"cda":false}}"#;
+        let cda = r#"{"assignment":{"buyers":{"0":2},"sellers":{"0":2}},"configuration":{}}"#;
+        let input = format!("{}\n{}\n", call, cda);
+        let mut out = Vec::new();
+        super::inspect(&args, &HashMap::new(), false, input.as_bytes(), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text.matches("arrives").count(), 4);
+        assert!(text.contains("the market closes"));
+
+        let mut again = Vec::new();
+        super::inspect(&args, &HashMap::new(), false, input.as_bytes(), &mut again).unwrap();
+        assert_eq!(String::from_utf8(again).unwrap(), text);
+
+        let args = InspectArgs::parse_from(["inspect", "--seed", "3", "--line", "0"]);
+        let mut out = Vec::new();
+        super::inspect(&args, &HashMap::new(), false, input.as_bytes(), &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("the call market"));
+
+        let args = InspectArgs::parse_from(["inspect", "--seed", "3", "--line", "2"]);
+        assert!(super::inspect(
+            &args,
+            &HashMap::new(),
+            false,
+            input.as_bytes(),
+            &mut Vec::new()
+        )
+        .is_err());
+
+        #[cfg(feature = "tui")]
+        for speed in ["0", "-1", "1e-320", "nan"] {
+            let args = ["inspect", "--seed", "3", "--tui", "--speed", speed];
+            assert!(InspectArgs::try_parse_from(args).is_err());
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use crate::{Agent, Features};
+use serde::Serialize;
+use std::fmt;
+
+/// Relative tolerance of sums that should be equal
+const TOLERANCE: f64 = 1e-9;
+
+/// An accounting identity that a simulation broke
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "invariant", rename_all = "snake_case")]
+pub enum Violation {
+    /// Buyers paid a different total than sellers received
+    BudgetBalance { paid: f64, received: f64 },
+    /// An agent traded at a price worse than its own bid
+    IndividualRationality { index: usize, bid: f64, price: f64 },
+    /// The surplus of trades plus what was lost to inefficiency isn't the maximum surplus
+    SurplusIdentity {
+        traded: f64,
+        im_surplus: f64,
+        em_surplus: f64,
+        ce_surplus: f64,
+    },
+    /// A different number of buyers traded than sellers
+    MatchedCounts { buyers: usize, sellers: usize },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::BudgetBalance { paid, received } => {
+                write!(f, "buyers paid {} but sellers received {}", paid, received)
+            }
+            Violation::IndividualRationality { index, bid, price } => {
+                write!(f, "agent {} with bid {} traded at {}", index, bid, price)
+            }
+            Violation::SurplusIdentity {
+                traded,
+                im_surplus,
+                em_surplus,
+                ce_surplus,
+            } => write!(
+                f,
+                "traded surplus {} plus im {} and em {} isn't ce surplus {}",
+                traded, im_surplus, em_surplus, ce_surplus
+            ),
+            Violation::MatchedCounts { buyers, sellers } => {
+                write!(f, "{} buyers traded with {} sellers", buyers, sellers)
+            }
+        }
+    }
+}
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() <= TOLERANCE * a.abs().max(b.abs()).max(1.0)
+}
+
+/// Every invariant a settled simulation violates
+///
+/// Trades with a market maker aren't between agents, so when a maker traded only individual
+/// rationality is checked. Individual rationality is relative to the signed bid an agent's order
+/// traded with, and the surplus identity is over net values, so it holds regardless of costs.
+pub fn check(agents: &[Agent<'_>], features: &Features) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    for (index, agent) in agents.iter().enumerate() {
+        // rounding in the market's price shouldn't count
+        let worse = |price: &f64| {
+            let signed = agent.sign() * price;
+            signed > agent.bid && !close(signed, agent.bid)
+        };
+        if let Some(price) = agent.price.filter(worse) {
+            violations.push(Violation::IndividualRationality {
+                index,
+                bid: agent.sign() * agent.bid,
+                price,
+            });
+        }
+    }
+    if features.maker.as_ref().is_some_and(|m| m.trades > 0) {
+        return violations;
+    }
+
+    let traded = || agents.iter().filter(|a| a.traded);
+    let buyers = traded().filter(|a| a.buyer).count();
+    let sellers = traded().count() - buyers;
+    if buyers != sellers {
+        violations.push(Violation::MatchedCounts { buyers, sellers });
+    }
+    let total = |buyer: bool| {
+        traded()
+            .filter(|a| a.buyer == buyer)
+            .filter_map(|a| a.price)
+            .sum::<f64>()
+    };
+    let (paid, received) = (total(true), total(false));
+    if !close(paid, received) {
+        violations.push(Violation::BudgetBalance { paid, received });
+    }
+    // without an equilibrium price the em surplus is just the rest of the ce surplus
+    let split = features
+        .ce_price
+        .and(features.im_surplus.zip(features.em_surplus));
+    if let Some((im_surplus, em_surplus)) = split {
+        let traded: f64 = traded().map(|a| a.sign() * a.net_value()).sum();
+        if !close(traded + im_surplus + em_surplus, features.ce_surplus) {
+            violations.push(Violation::SurplusIdentity {
+                traded,
+                im_surplus,
+                em_surplus,
+                ce_surplus: features.ce_surplus,
+            });
+        }
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Violation;
+    use crate::strategy::Shading;
+    use crate::{Agent, Call, Cda, Style};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_invariants() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let strats = [Style::Standard, Style::Shift, Style::Roth];
+        let mut agents: Vec<_> = (0..7)
+            .map(|i| Agent::new(i % 2 == 0, "", strats[i % 3], Shading::Uniform(0.0, 0.5)))
+            .collect();
+        for _ in 0..50 {
+            let features = crate::run_sim(&mut agents, &Cda, &mut rng);
+            assert_eq!(super::check(&agents, &features), []);
+            let features = crate::run_sim(&mut agents, &Call, &mut rng);
+            assert_eq!(super::check(&agents, &features), []);
+        }
+
+        // a trade at a price worse than the buyer's bid breaks rationality and budget balance
+        let mut features = crate::run_sim(&mut agents, &Call, &mut rng);
+        let buyer = agents.iter().position(|a| a.buyer).unwrap();
+        let price = agents[buyer].bid + 1.0;
+        agents[buyer].transact(price);
+        features.ce_price = None;
+        let violations = super::check(&agents, &features);
+        assert!(matches!(
+            violations[0],
+            Violation::IndividualRationality { index, .. } if index == buyer
+        ));
+        assert!(violations.len() > 1);
+    }
+}
@@ -0,0 +1,204 @@
+use rand::Rng;
+use serde::Serialize;
+
+/// Number of evenly spaced shading levels learners choose between
+pub const ARMS: usize = 11;
+
+/// State of an agent that learns its shading across observations
+///
+/// Learners choose between [ARMS] levels from zero to their maximum shading, and are rewarded with
+/// their payoff after every observation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Learner {
+    Roth(RothErev),
+    Bandit(Bandit),
+}
+
+/// A summary of what a learner has learned
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Policy {
+    /// The fraction of the maximum shading of the currently preferred arm
+    pub level: f64,
+    /// Choice probabilities for Roth-Erev, and mean rewards for bandits
+    pub estimates: Vec<f64>,
+}
+
+impl Learner {
+    /// Choose an arm, returning its fraction of the maximum shading
+    pub fn choose(&mut self, rng: &mut impl Rng) -> f64 {
+        let arm = match self {
+            Learner::Roth(roth) => roth.choose(rng),
+            Learner::Bandit(bandit) => bandit.choose(rng),
+        };
+        level(arm)
+    }
+
+    /// Reinforce the last chosen arm with a reward
+    pub fn update(&mut self, reward: f64) {
+        match self {
+            Learner::Roth(roth) => roth.update(reward),
+            Learner::Bandit(bandit) => bandit.update(reward),
+        }
+    }
+
+    pub fn policy(&self) -> Policy {
+        let estimates: Vec<f64> = match self {
+            Learner::Roth(roth) => {
+                let total: f64 = roth.propensities.iter().sum();
+                roth.propensities.iter().map(|p| p / total).collect()
+            }
+            Learner::Bandit(bandit) => bandit.means.to_vec(),
+        };
+        Policy {
+            level: level(argmax(&estimates)),
+            estimates,
+        }
+    }
+}
+
+fn level(arm: usize) -> f64 {
+    arm as f64 / (ARMS - 1) as f64
+}
+
+/// The first index of the largest value
+fn argmax(values: &[f64]) -> usize {
+    values.iter().enumerate().fold(
+        0,
+        |best, (ind, val)| if *val > values[best] { ind } else { best },
+    )
+}
+
+/// Roth–Erev reinforcement learning
+///
+/// Every arm starts with the same propensity and is chosen with probability proportional to it.
+/// After each observation all propensities decay by the recency rate, the chosen arm is reinforced
+/// by most of the reward, and the remainder is spread over the other arms as experimentation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RothErev {
+    propensities: [f64; ARMS],
+    arm: usize,
+}
+
+const INITIAL_PROPENSITY: f64 = 1.0;
+const RECENCY: f64 = 0.1;
+const EXPERIMENTATION: f64 = 0.2;
+
+impl RothErev {
+    pub fn new() -> Self {
+        RothErev {
+            propensities: [INITIAL_PROPENSITY; ARMS],
+            arm: 0,
+        }
+    }
+
+    fn choose(&mut self, rng: &mut impl Rng) -> usize {
+        let total: f64 = self.propensities.iter().sum();
+        let mut draw = rng.gen::<f64>() * total;
+        self.arm = ARMS - 1;
+        for (arm, prop) in self.propensities.iter().enumerate() {
+            if draw < *prop {
+                self.arm = arm;
+                break;
+            }
+            draw -= prop;
+        }
+        self.arm
+    }
+
+    fn update(&mut self, reward: f64) {
+        let reward = reward.max(0.0);
+        for (arm, prop) in self.propensities.iter_mut().enumerate() {
+            let reinforcement = if arm == self.arm {
+                reward * (1.0 - EXPERIMENTATION)
+            } else {
+                reward * EXPERIMENTATION / (ARMS - 1) as f64
+            };
+            *prop = (1.0 - RECENCY) * *prop + reinforcement;
+        }
+    }
+}
+
+/// A multi-armed bandit using epsilon-greedy exploration if epsilon is set and UCB1 otherwise
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bandit {
+    epsilon: Option<f64>,
+    counts: [u64; ARMS],
+    means: [f64; ARMS],
+    arm: usize,
+}
+
+impl Bandit {
+    pub fn new(epsilon: Option<f64>) -> Self {
+        Bandit {
+            epsilon,
+            counts: [0; ARMS],
+            means: [0.0; ARMS],
+            arm: 0,
+        }
+    }
+
+    fn choose(&mut self, rng: &mut impl Rng) -> usize {
+        self.arm = match self.epsilon {
+            Some(eps) => {
+                // always draw twice so that paired simulations stay aligned
+                let explore = rng.gen::<f64>() < eps;
+                let random = rng.gen_range(0..ARMS);
+                if explore {
+                    random
+                } else {
+                    argmax(&self.means)
+                }
+            }
+            None => match self.counts.iter().position(|&count| count == 0) {
+                Some(untried) => untried,
+                None => {
+                    let total: u64 = self.counts.iter().sum();
+                    let bounds: Vec<f64> = self
+                        .means
+                        .iter()
+                        .zip(&self.counts)
+                        .map(|(mean, &count)| {
+                            mean + (2.0 * (total as f64).ln() / count as f64).sqrt()
+                        })
+                        .collect();
+                    argmax(&bounds)
+                }
+            },
+        };
+        self.arm
+    }
+
+    fn update(&mut self, reward: f64) {
+        self.counts[self.arm] += 1;
+        self.means[self.arm] += (reward - self.means[self.arm]) / self.counts[self.arm] as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bandit, Learner, RothErev, ARMS};
+
+    /// Reward only the middle arm and check that it's learned
+    fn check_learns(mut learner: Learner) {
+        let mut rng = rand::thread_rng();
+        for _ in 0..2000 {
+            let level = learner.choose(&mut rng);
+            assert!((0.0..=1.0).contains(&level));
+            learner.update(if level == 0.5 { 1.0 } else { 0.0 });
+        }
+        let policy = learner.policy();
+        assert_eq!(policy.level, 0.5);
+        assert_eq!(policy.estimates.len(), ARMS);
+    }
+
+    #[test]
+    fn test_roth_erev() {
+        check_learns(Learner::Roth(RothErev::new()));
+    }
+
+    #[test]
+    fn test_bandit() {
+        check_learns(Learner::Bandit(Bandit::new(None)));
+        check_learns(Learner::Bandit(Bandit::new(Some(0.1))));
+    }
+}
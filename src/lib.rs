@@ -0,0 +1,3984 @@
+mod agent;
+mod analyze;
+mod attribution;
+mod bidding;
+mod bne;
+mod checkpoint;
+mod compare;
+mod context;
+mod egta;
+mod engine;
+pub mod equilibrium;
+mod evolve;
+mod exact;
+mod expected;
+mod export;
+mod expr;
+mod external;
+mod fields;
+mod fit;
+mod formats;
+#[cfg(feature = "gpu")]
+mod gpu;
+mod inspect;
+mod invariants;
+mod learner;
+mod maker;
+mod market;
+mod mmap;
+mod monitor;
+mod optimize;
+mod plan;
+mod plot;
+mod profile;
+mod regret;
+mod replay;
+mod sampling;
+mod scale;
+mod sensitivity;
+mod solve;
+mod stats;
+mod strategy;
+mod stream;
+mod summary;
+mod sweep;
+mod template;
+mod tournament;
+#[cfg(feature = "tui")]
+mod tui;
+mod values;
+
+pub use agent::{Agent, Style};
+pub use bidding::{BiddingStrategy, MarketState, Outcome};
+use bne::BneConfig;
+use checkpoint::Progress;
+use clap::{Parser, Subcommand, ValueEnum};
+use engine::Matching;
+use expected::{Check, Control, Expected};
+use expr::Expr;
+use external::{ExternalAgent, ExternalProcess, MarketInfo};
+use formats::SpecFormat;
+use learner::Policy;
+use maker::{MakerConfig, MakerReport, MarketMaker};
+use market::{
+    BookSnapshot, ClosingCall, ClosingReport, Session, SessionReport, Shock, ShockReport,
+    Snapshots, SpreadReport, Stopping,
+};
+pub use market::{Call, Cda, Continuous, Market};
+use profile::Phase;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use sampling::Sobol;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+use strategy::Presets;
+pub use strategy::{resolve, Shading, Strategy};
+use stream::{Compression, Output, Sharded};
+use summary::Summary;
+use template::Template;
+use tracing::level_filters::LevelFilter;
+use tracing::{debug, debug_span, error, info_span, warn};
+use values::Values;
+
+#[derive(Deserialize, Debug, Clone)]
+struct Config {
+    style: Option<Style>,
+    buyer_style: Option<Style>,
+    seller_style: Option<Style>,
+    trader_style: Option<Style>,
+    buyer_shading: Option<Shading>,
+    seller_shading: Option<Shading>,
+    trader_shading: Option<Shading>,
+    cda: Option<bool>,
+    episodes: Option<u64>,
+    burn_in: Option<bool>,
+    external: Option<Vec<String>>,
+    support: Option<Supports>,
+    cost: Option<f64>,
+    floor: Option<f64>,
+    ceiling: Option<f64>,
+    noise: Option<f64>,
+    market_maker: Option<MakerConfig>,
+    shocks: Option<Vec<Shock>>,
+    session: Option<Session>,
+    book_snapshots: Option<Snapshots>,
+    arrival_order: Option<Vec<usize>>,
+    closing_call: Option<ClosingCall>,
+    stopping: Option<Stopping>,
+    matching: Option<Matching>,
+    units: Option<BTreeMap<String, usize>>,
+    bne: Option<BneConfig>,
+    values: Option<Values>,
+    derived: Option<BTreeMap<String, String>>,
+    roles: Option<BTreeMap<String, RoleConfig>>,
+    persistent: Option<bool>,
+    types: Option<Types>,
+    obs: Option<u64>,
+    weight: Option<f64>,
+}
+
+/// Persistent private types that every agent's values are drawn around
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+struct Types {
+    /// The standard deviation of values around their type
+    noise: f64,
+}
+
+/// The side of the market a role's agents trade on
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Side {
+    Buyers,
+    Sellers,
+    Traders,
+}
+
+impl Side {
+    /// The built in role on this side
+    fn role(self) -> &'static str {
+        match self {
+            Side::Buyers => "buyers",
+            Side::Sellers => "sellers",
+            Side::Traders => "traders",
+        }
+    }
+
+    /// Whether each of a player's agents is a buyer, where traders are a seller followed by a
+    /// buyer
+    fn agents(self) -> &'static [bool] {
+        match self {
+            Side::Buyers => &[true],
+            Side::Sellers => &[false],
+            Side::Traders => &[false, true],
+        }
+    }
+}
+
+/// A role besides buyers, sellers, and traders
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+struct RoleConfig {
+    side: Side,
+    /// The bounds its values are drawn between, its side's by default
+    support: Option<[f64; 2]>,
+}
+
+/// The bounds each role's values are drawn between
+#[derive(Deserialize, Debug, Default, Clone)]
+struct Supports {
+    buyers: Option<[f64; 2]>,
+    sellers: Option<[f64; 2]>,
+    traders: Option<[f64; 2]>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+struct Roles {
+    #[serde(default)]
+    buyers: BTreeMap<String, Count>,
+    #[serde(default)]
+    sellers: BTreeMap<String, Count>,
+    #[serde(default)]
+    traders: BTreeMap<String, Count>,
+    /// Roles declared in the configuration's "roles"
+    #[serde(flatten)]
+    others: BTreeMap<String, BTreeMap<String, Count>>,
+}
+
+/// The number of players of a strategy, where a fraction is the probability of one more
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, PartialOrd)]
+#[serde(transparent)]
+struct Count(f64);
+
+impl Count {
+    /// The players always in the market
+    fn whole(self) -> u64 {
+        self.0 as u64
+    }
+
+    /// The probability of one more player in each observation
+    fn fraction(self) -> f64 {
+        self.0.fract()
+    }
+}
+
+/// Whole counts are written as integers, as they're usually given
+impl Serialize for Count {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.fraction() == 0.0 && self.0 < u64::MAX as f64 {
+            serializer.serialize_u64(self.whole())
+        } else {
+            serializer.serialize_f64(self.0)
+        }
+    }
+}
+
+impl From<u64> for Count {
+    fn from(count: u64) -> Self {
+        Count(count as f64)
+    }
+}
+
+impl Roles {
+    /// The assignment with every count multiplied by `factor`
+    fn scaled(&self, factor: f64) -> Roles {
+        let scale = |counts: &BTreeMap<String, Count>| {
+            counts
+                .iter()
+                .map(|(strat, count)| (strat.clone(), Count(count.0 * factor)))
+                .collect()
+        };
+        Roles {
+            buyers: scale(&self.buyers),
+            sellers: scale(&self.sellers),
+            traders: scale(&self.traders),
+            others: self
+                .others
+                .iter()
+                .map(|(role, counts)| (role.clone(), scale(counts)))
+                .collect(),
+        }
+    }
+
+    /// Every role and its assignment, starting with buyers, sellers, and traders
+    fn iter(&self) -> impl Iterator<Item = (&str, &BTreeMap<String, Count>)> {
+        [
+            ("buyers", &self.buyers),
+            ("sellers", &self.sellers),
+            ("traders", &self.traders),
+        ]
+        .into_iter()
+        .chain(self.others.iter().map(|(role, map)| (role.as_str(), map)))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Spec {
+    assignment: Roles,
+    configuration: Config,
+}
+
+impl Spec {
+    /// The configuration of a role other than buyers, sellers, and traders
+    fn custom_role(&self, role: &str) -> Option<&RoleConfig> {
+        match role {
+            "buyers" | "sellers" | "traders" => None,
+            _ => self.configuration.roles.as_ref()?.get(role),
+        }
+    }
+
+    /// The number of units each player in a role trades
+    fn units(&self, role: &str) -> usize {
+        self.configuration
+            .units
+            .as_ref()
+            .and_then(|units| units.get(role))
+            .copied()
+            .unwrap_or(1)
+    }
+
+    /// The side of the market a role trades on
+    fn side(&self, role: &str) -> io::Result<Side> {
+        match role {
+            "buyers" => Ok(Side::Buyers),
+            "sellers" => Ok(Side::Sellers),
+            "traders" => Ok(Side::Traders),
+            _ => self
+                .custom_role(role)
+                .map(|config| config.side)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "role \"{}\" isn't declared in the configuration's \"roles\"",
+                            role
+                        ),
+                    )
+                }),
+        }
+    }
+}
+
+/// Summary statistics of one simulation
+#[derive(Serialize, Debug)]
+pub struct Features {
+    surplus: f64,
+    ce_surplus: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    im_surplus: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    em_surplus: Option<f64>,
+    ce_price: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    efficiency: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    imbalance: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    no_trade: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trade_surplus: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    price_surplus: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clipped: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rejected: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blocked: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    losses: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    participation: Option<f64>,
+    /// Quantiles of player payoffs in each role with players
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    payoffs: BTreeMap<String, Quantiles>,
+    /// How aggressively each strategy in each role bid
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    aggressiveness: BTreeMap<String, BTreeMap<String, Aggressiveness>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    maker: Option<MakerReport>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    shocks: Vec<ShockReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session: Option<SessionReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency: Option<LatencyReport>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    book: Vec<BookSnapshot>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    arrivals: Option<Vec<usize>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    closing: Option<ClosingReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spread: Option<SpreadReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stopped: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stops: Option<usize>,
+    #[serde(flatten)]
+    meta: Option<Meta>,
+    /// How the surplus changed over a persistent spec's observations, in its last one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    learning: Option<Learning>,
+    /// Values of the spec's derived features
+    #[serde(flatten)]
+    derived: BTreeMap<String, Option<f64>>,
+}
+
+impl Features {
+    /// Clear the features of options that aren't enabled, so observations only have the baseline
+    /// features and those of the extras asked for or the configuration the agents have
+    fn select(&mut self, agents: &[Agent<'_>], extras: &[Extra]) {
+        if !extras.contains(&Extra::Thin) {
+            self.imbalance = None;
+            self.no_trade = None;
+            self.trade_surplus = None;
+        }
+        if !extras.contains(&Extra::PriceSurplus) {
+            self.price_surplus = None;
+        }
+        if !extras.contains(&Extra::Quantiles) {
+            self.payoffs.clear();
+        }
+        if !extras.contains(&Extra::Aggressiveness) {
+            self.aggressiveness.clear();
+        }
+        if !agents.iter().any(Agent::costly) {
+            self.efficiency = None;
+        }
+        if !agents.iter().any(Agent::bounded) {
+            self.clipped = None;
+        }
+        if self.rejected == Some(0) {
+            self.rejected = None;
+        }
+        if !agents.iter().any(Agent::controlled) {
+            self.blocked = None;
+        }
+        if !agents.iter().any(Agent::noisy) {
+            self.losses = None;
+        }
+        if !agents.iter().any(Agent::chooses_entry) {
+            self.participation = None;
+        }
+    }
+}
+
+/// Features beyond the baseline that observations only have when asked for
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Extra {
+    /// "imbalance", "no_trade" and "trade_surplus", which describe thin markets
+    Thin,
+    /// "price_surplus", the surplus at the mean trade price
+    PriceSurplus,
+    /// "payoffs", the percentiles of every role's payoffs
+    Quantiles,
+    /// "aggressiveness" of every strategy's bids
+    Aggressiveness,
+}
+
+/// What it took to simulate an observation
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+struct Meta {
+    /// Wall clock time in microseconds
+    sim_micros: u64,
+    /// Number of draws from the random number generator
+    rng_draws: u64,
+    /// Number of events in a CDA
+    #[serde(skip_serializing_if = "Option::is_none")]
+    events: Option<usize>,
+}
+
+/// How the values of a spec's observations were stratified
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+struct Stratification {
+    /// "latin_hypercube" or "sobol"
+    scheme: &'static str,
+    /// The number of observations in each Latin hypercube, starting with the first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    batch: Option<u64>,
+}
+
+impl Stratification {
+    fn new(args: &Args) -> Option<Self> {
+        match (args.stratify, args.sobol) {
+            (Some(batch), _) => Some(Stratification {
+                scheme: "latin_hypercube",
+                batch: Some(batch),
+            }),
+            (None, true) => Some(Stratification {
+                scheme: "sobol",
+                batch: None,
+            }),
+            (None, false) => None,
+        }
+    }
+}
+
+/// The mean surplus of the first and second half of a spec's observations
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+struct Learning {
+    first_half: Option<f64>,
+    second_half: Option<f64>,
+}
+
+impl Learning {
+    /// Split the surpluses of the observations from `start` at half of `num_obs`
+    fn new(surpluses: &[f64], start: u64, num_obs: u64) -> Self {
+        let split = ((num_obs / 2).saturating_sub(start) as usize).min(surpluses.len());
+        let (first, second) = surpluses.split_at(split);
+        let [first_half, second_half] =
+            [first, second].map(|half| (!half.is_empty()).then(|| stats::mean(half)));
+        Learning {
+            first_half,
+            second_half,
+        }
+    }
+}
+
+/// How the latency of agents' orders related to their payoffs in one simulation
+#[derive(Serialize, Debug)]
+struct LatencyReport {
+    mean: f64,
+    /// Correlation between the latency and payoff of agents that submitted orders
+    correlation: Option<f64>,
+}
+
+impl LatencyReport {
+    fn new(agents: &[Agent<'_>]) -> Option<Self> {
+        if agents.iter().all(|a| a.latency().is_none()) {
+            return None;
+        }
+        let (delays, payoffs): (Vec<_>, Vec<_>) = agents
+            .iter()
+            .filter(|a| a.submitted())
+            .map(|a| (a.delay, a.utility))
+            .unzip();
+        (!delays.is_empty()).then(|| LatencyReport {
+            mean: stats::mean(&delays),
+            correlation: stats::correlation(&delays, &payoffs),
+        })
+    }
+}
+
+/// The mean distance of the bids one strategy submitted from reference prices, as fractions of
+/// the bidders' price scales
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+struct Aggressiveness {
+    /// How far bids reached past the competitive equilibrium price, negative if they fell short
+    from_ce: Option<f64>,
+    /// How far bids were shaded from the bidders' own values
+    from_value: f64,
+}
+
+impl Aggressiveness {
+    fn by_strategy(
+        agents: &[Agent<'_>],
+        ce_price: Option<f64>,
+    ) -> BTreeMap<String, BTreeMap<String, Aggressiveness>> {
+        let mut sums: BTreeMap<_, BTreeMap<_, (f64, f64, usize)>> = BTreeMap::new();
+        for agent in agents.iter().filter(|a| a.submitted()) {
+            let (ce, value, count) = sums
+                .entry(agent.role())
+                .or_default()
+                .entry(agent.strategy())
+                .or_default();
+            *ce += ce_price.map_or(0.0, |price| agent.reach(price));
+            *value -= agent.reach(agent.value);
+            *count += 1;
+        }
+        sums.into_iter()
+            .map(|(role, strats)| {
+                let strats = strats
+                    .into_iter()
+                    .map(|(strat, (ce, value, count))| {
+                        let count = count as f64;
+                        let aggressiveness = Aggressiveness {
+                            from_ce: ce_price.map(|_| ce / count),
+                            from_value: value / count,
+                        };
+                        (strat.to_owned(), aggressiveness)
+                    })
+                    .collect();
+                (role.to_owned(), strats)
+            })
+            .collect()
+    }
+}
+
+/// Roles with more players than this estimate payoff quantiles with a sketch instead of sorting
+const SKETCH_PLAYERS: usize = 1000;
+
+/// The 10th, 50th and 90th percentiles of payoffs
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+struct Quantiles {
+    p10: f64,
+    p50: f64,
+    p90: f64,
+}
+
+impl Quantiles {
+    const PROBS: [f64; 3] = [0.1, 0.5, 0.9];
+
+    /// The payoff quantiles of every role's players, where a trader's payoff includes both sides
+    fn by_role(agents: &[Agent<'_>]) -> BTreeMap<String, Quantiles> {
+        let mut payoffs: BTreeMap<_, Vec<f64>> = BTreeMap::new();
+        for player in players(agents) {
+            payoffs.entry(player.role).or_default().push(player.payoff);
+        }
+        payoffs
+            .into_iter()
+            .map(|(role, mut payoffs)| {
+                let [p10, p50, p90] = if payoffs.len() > SKETCH_PLAYERS {
+                    Quantiles::PROBS.map(|prob| {
+                        let mut sketch = stats::P2::new(prob);
+                        payoffs.iter().for_each(|&payoff| sketch.add(payoff));
+                        sketch.quantile().unwrap()
+                    })
+                } else {
+                    payoffs.sort_unstable_by(f64::total_cmp);
+                    Quantiles::PROBS.map(|prob| stats::quantile(&payoffs, prob))
+                };
+                (role.to_owned(), Quantiles { p10, p50, p90 })
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct Observation<'a, 'b: 'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spec_index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    obs_index: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spec_hash: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stratification: Option<Stratification>,
+    #[serde(serialize_with = "serialize_players")]
+    players: &'a [Agent<'b>],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    assignment: Option<Assignment<'a>>,
+    features: Features,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    policies: Option<Vec<Option<Policy>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spec: Option<&'a SpecEcho>,
+}
+
+/// A normalized copy of a spec, so output can be read without the input that produced it
+#[derive(Serialize, Debug)]
+struct SpecEcho {
+    /// The assignment with equivalent strategies merged under their canonical names
+    assignment: Roles,
+    /// Every strategy of the assignment with the defaults of its role filled in
+    strategies: BTreeMap<String, BTreeMap<String, String>>,
+    /// "cda", "call", or "continuous"
+    market: &'static str,
+    configuration: serde_json::Value,
+}
+
+impl SpecEcho {
+    fn new(line: &str, spec: &Spec, market: &'static str, presets: &Presets) -> io::Result<Self> {
+        let mut strategies = BTreeMap::new();
+        for (role, counts) in spec.assignment.iter() {
+            let mut resolved = BTreeMap::new();
+            for name in counts.keys() {
+                let parts = name
+                    .split('|')
+                    .map(|part| Ok(resolve_strategy(spec, role, part, presets)?.to_string()))
+                    .collect::<io::Result<Vec<_>>>()?;
+                resolved.insert(name.clone(), parts.join("|"));
+            }
+            strategies.insert(role.to_owned(), resolved);
+        }
+        let mut raw: serde_json::Value = serde_json::from_str(line)?;
+        Ok(SpecEcho {
+            assignment: spec.assignment.clone(),
+            strategies,
+            market,
+            configuration: raw["configuration"].take(),
+        })
+    }
+}
+
+/// The market a spec is simulated in, one of "cda", "call", or "continuous"
+fn market_name(config: &Config, agents: &[Agent<'_>]) -> io::Result<&'static str> {
+    Ok(if continuous_market(config, agents, false)?.is_some() {
+        "continuous"
+    } else if config.cda.unwrap_or(true) {
+        "cda"
+    } else {
+        "call"
+    })
+}
+
+/// The record written before a spec's observations when echoing specs as headers
+#[derive(Serialize, Debug)]
+struct SpecHeader<'a> {
+    spec_index: usize,
+    spec: &'a SpecEcho,
+}
+
+/// Where the normalized spec goes in the output
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Echo {
+    /// A record with the "spec_index" and "spec" before each spec's observations
+    Header,
+    /// A "spec" in every observation, or every summary
+    Observation,
+}
+
+/// A player in the game, where both sides of a trader, or every unit of a lot, are one player
+#[derive(Serialize, Debug)]
+struct Player<'a> {
+    role: &'a str,
+    strategy: &'a str,
+    payoff: f64,
+    /// The number of units a multi-unit player traded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filled: Option<usize>,
+    /// The prices of the units a multi-unit player traded, best unit first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prices: Option<Vec<f64>>,
+}
+
+/// The players of a simulation, combining the payoffs of each trader's sides and each lot's units
+fn players<'a>(agents: &'a [Agent<'a>]) -> impl Iterator<Item = Player<'a>> + 'a {
+    agents
+        .iter()
+        .enumerate()
+        .filter(|(_, agent)| !(agent.trader && agent.buyer) && agent.leads() && agent.present())
+        .map(|(index, agent)| {
+            let lot = &agents[agent.lot(index)];
+            let prices = (lot.len() > 1).then(|| lot.iter().filter_map(|a| a.price).collect());
+            Player {
+                role: agent.role(),
+                strategy: agent.strategy(),
+                payoff: lot.iter().map(|a| a.utility).sum::<f64>()
+                    + agent.pair(index).map_or(0.0, |pair| agents[pair].utility),
+                filled: prices.as_ref().map(Vec::len),
+                prices,
+            }
+        })
+}
+
+/// The number of players of each strategy in each role, and what every player drew
+#[derive(Serialize, Debug)]
+struct Assignment<'a> {
+    counts: BTreeMap<&'a str, BTreeMap<&'a str, u64>>,
+    draws: Vec<Draw>,
+}
+
+/// A player's random draws in one observation
+#[derive(Serialize, Debug)]
+struct Draw {
+    value: f64,
+    shading: f64,
+    /// A trader's value for a second unit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    second_value: Option<f64>,
+    /// A multi-unit player's values for its further units
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schedule: Option<Vec<f64>>,
+}
+
+impl<'a> Assignment<'a> {
+    fn realized(agents: &'a [Agent<'a>]) -> Self {
+        let mut counts: BTreeMap<_, BTreeMap<_, u64>> = BTreeMap::new();
+        let mut draws = Vec::new();
+        for (index, agent) in agents.iter().enumerate() {
+            if (agent.trader && agent.buyer) || !agent.leads() || !agent.present() {
+                continue;
+            }
+            *counts
+                .entry(agent.role())
+                .or_default()
+                .entry(agent.strategy())
+                .or_default() += 1;
+            draws.push(Draw {
+                value: agent.value,
+                shading: agent.shading(),
+                second_value: agent.pair(index).map(|pair| agents[pair].value),
+                schedule: (agent.units() > 1).then(|| {
+                    agents[agent.lot(index)][1..]
+                        .iter()
+                        .map(|a| a.value)
+                        .collect()
+                }),
+            });
+        }
+        Assignment { counts, draws }
+    }
+}
+
+fn serialize_players<S: serde::Serializer>(
+    agents: &&[Agent<'_>],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(players(agents))
+}
+
+#[derive(Parser)]
+#[clap(version, about, args_conflicts_with_subcommands = true)]
+/// Run an egtaonline style simulation of a simple market
+///
+/// Takes as input to stdin, lines of json simulation spec files. Each spec file must have the
+/// following structure:
+///
+/// {
+///     assignment: {
+///         buyers?: {[strat]: [count]},
+///         sellers?: {[strat]: [count]},
+///         traders?: {[strat]: [count]},
+///         [role]?: {[strat]: [count]}
+///     },
+///     configuraion: {
+///         cda?: true,
+///         style?: "Standard",
+///         buyer_style?: style, seller_style?: style, trader_style?: style,
+///         buyer_shading?: shading, seller_shading?: shading, trader_shading?: shading,
+///         episodes?: 0,
+///         burn_in?: true,
+///         external?: [program, args...],
+///         support?: {buyers?: [low, high], sellers?: [low, high], traders?: [low, high]},
+///         cost?: 0,
+///         floor?: -inf,
+///         ceiling?: inf,
+///         noise?: 0,
+///         values?: path or {path: path, replace?: true} or {buyers?: [values...], ...},
+///         market_maker?: {spread?: 0.1, limit?: 10, price?: 0.5, stop?: price},
+///         shocks?: [{at: [index], shift: [amount]}...],
+///         session?: {patience?: inf, wait_cost?: 0},
+///         book_snapshots?: [event...] or {every: events},
+///         arrival_order?: [index...],
+///         closing_call?: {at: time},
+///         stopping?: {probability: probability},
+///         matching?: "price_time" or "pro_rata" or "size",
+///         units?: {[role]: units},
+///         bne?: {grid?: 11, shadings?: 11, samples?: 200, iterations?: 10},
+///         derived?: {[name]: [expression]},
+///         roles?: {[role]: {side: "buyers" or "sellers" or "traders", support?: [low, high]}},
+///         persistent?: true,
+///         types?: {noise: 0},
+///         obs?: --obs,
+///         weight?: 1
+///     }
+/// }
+///
+/// [count] is an integer for the number of players playing that strategy. [strat] has the form
+/// <shading>[_<style>][_<key><value>]..., where <shading> is a float in [0, 1] representing the
+/// amount of shading, 1 being the highest, or U(<low>,<high>) to have every agent draw its own
+/// shading uniformly each observation, and <style> is one of {Standard, Exponential, Shift,
+/// Correct, Roth, Bandit, External, Bne, Market}. Similarly "style" can be any of those to set a
+/// default for agents, and "buyer_style", "seller_style" and "trader_style" set defaults for each
+/// role that take precedence over it. [strat] may omit the shading if it starts with a style, e.g.
+/// "Shift", in which case it uses its role's "buyer_shading", "seller_shading" or "trader_shading",
+/// a float or U(<low>,<high>). "cda" indicates if the market is a CDA or a call market. The
+/// optional parameters are "l<price>" and "h<price>" to bound the prices an agent bids or asks,
+/// with the number of clipped bids reported as the "clipped" feature, "e<epsilon>" for Bandit
+/// agents, and "c<cost>" for an entry cost. Agents with an entry cost only submit an order if their
+/// signal's surplus at the equilibrium price exceeds it, pay it whenever they submit, and the
+/// fraction of agents that enter is reported as the "participation" feature. "d<latency>" is how
+/// long an agent's orders take to reach a CDA, a float or E(<mean>) for an exponentially
+/// distributed latency, where agents arrive over one unit of time. Faster agents can trade ahead of
+/// slower agents that arrived before them, and orders that reach the market after it closes are
+/// dropped. Observations with latency include a "latency" feature with the mean latency and its
+/// correlation with payoffs. "r<delta>" makes an agent requote in a CDA: once "k<events>" more
+/// orders, 1 by default, reach the market while its order stands untraded, it cancels the order and
+/// quotes one improved by <delta>, never past its value or price bounds, and repeats until it
+/// trades. Market agents send market orders to a CDA, which trade immediately at the best price on
+/// the other side of the book, whatever it is, so they can trade at a loss, and are cancelled if
+/// there's nothing to trade with, while a multi-unit player's market order walks the book one unit
+/// at a time. They ignore their shading, and bid their value in a call market or closing call.
+/// "s<stop>" gives a trader a stop loss in a CDA: once anything trades at or below <stop>, it
+/// cancels the ask for its unit and sells it with a market order instead, or when it arrives if it
+/// hasn't yet, so a falling price can cascade through the stops of traders and the market maker.
+/// Observations of a CDA where anyone has a stop include a "stops" feature with the number of stops
+/// that triggered. Orders whose prices are NaN or infinite after bounding are rejected instead of
+/// traded, and counted by a "rejected" feature when there are any. "support" sets the range each
+/// role's values are drawn uniformly from, [0, 1] by default, and any finite range, including
+/// negative values like sellers' disposal costs. Shading is relative to the market's support, the
+/// smallest range covering the support of every role with agents, so e.g. Standard agents bid a
+/// fraction of the distance between their value and the low end of it, and buyers and sellers
+/// always bid on the same scale. "cost" is the transaction cost of every trade, split evenly
+/// between the buyer and seller. The competitive equilibrium only trades pairs whose gains exceed
+/// the cost, and observations of specs with a cost have an "efficiency", the ratio of surplus to
+/// its surplus, both net of costs. "floor" and "ceiling" are price controls, the minimum price
+/// sellers may ask and the maximum price buyers may bid. Orders that violate them are blocked,
+/// counted by the "blocked" feature, and don't trade. "noise" is the standard deviation of normal
+/// noise added to every agent's value to produce the signal it bids and learns with, while its
+/// payoff still uses its true value, so agents can trade at a loss, counted by the "losses"
+/// feature. "values" is a csv file of empirical values, with lines of <role>,<value>, that the
+/// agents of each role in it draw from instead of uniformly, with replacement unless "replace" is
+/// false, in which case no two agents in a role draw the same line. "values" can instead be a map
+/// from role to a list of fixed values, one for each of the role's agents, who never resample them,
+/// so an induced value experiment can be replicated exactly. Agents are ordered by strategy name
+/// within a role, and each trader takes two consecutive values. Either way, shading is still
+/// relative to the "support", which should cover the values.
+///
+/// "traders" are two-sided: each is endowed with one unit and draws two values, the higher the value
+/// of the unit it holds, and the lower the value of a second unit. It offers to sell its unit and
+/// bids for another, both shaded according to its strategy, and withdraws the other order as soon
+/// as one trades. A trader's payoff is the gain from its trade, and the competitive equilibrium
+/// treats it as a seller and buyer at its two values.
+///
+/// Assignments may have roles besides buyers, sellers, and traders, e.g. the "background" and
+/// "hft" of another game, if "roles" declares which "side" of the market each one trades on, and
+/// optionally the "support" its values are drawn from, which is its side's by default. Players of
+/// a role behave like those of its side, with its side's default style and shading, but are
+/// reported with their own role. "values" only apply to buyers, sellers, and traders.
+///
+/// Either side of an assignment may be empty to study thin markets. "im_surplus" and "em_surplus"
+/// are left out unless there are both buyers and sellers. With --extra-features thin, observations
+/// have an "imbalance", the ratio of buyers to sellers, counting each trader on both sides, and
+/// left out without sellers, "no_trade", 1 if nothing traded and 0 otherwise, so its mean is the
+/// probability of no trade, and "trade_surplus", the surplus only if something traded, so its mean
+/// is the surplus conditional on trade. --extra-features price-surplus adds "price_surplus", the
+/// most surplus there could have been if everyone traded at the mean price of the trades that
+/// happened, left out if nothing traded, which is the "ce_surplus" when that price clears the
+/// market, and less the further the market's prices were from discovering it.
+///
+/// --extra-features quantiles adds "payoffs", the "p10", "p50" and "p90" percentiles of player
+/// payoffs in each role with players, which show the tails that mean payoffs hide. Roles with over
+/// a thousand players estimate them with a streaming sketch. --extra-features aggressiveness adds
+/// "aggressiveness", for each strategy in each role, the mean distance of the bids its agents
+/// submitted from the competitive equilibrium price, "from_ce", positive when they reached past it
+/// and null without one, and from their own values, "from_value", positive when shaded, both as
+/// fractions of the agents' price scales.
+///
+/// "market_maker" adds a dealer to a CDA that always quotes a bid and an ask "spread" apart around
+/// its estimate of the equilibrium price, starting at "price" and moving toward trade prices. It
+/// trades a unit with any agent that crosses its quotes until its inventory reaches +/- "limit",
+/// and keeps its inventory and cash across the observations of a spec. Observations then include a
+/// "maker" feature with its inventory, cash, mark to market "pnl", price estimate, number of trades,
+/// and the "path" of its inventory over the observation. With a "stop", once anything trades at or
+/// below it while the maker is long, the maker stops bidding for the rest of the observation and
+/// sells its position a unit at a time to the best bids in the book.
+///
+/// "shocks" are public news in a CDA. After "at" agents have arrived, the values of every agent yet
+/// to arrive shift by "shift", and they bid with their new values, while orders already in the book
+/// stand. The competitive equilibrium uses values after the shocks. Observations then include a
+/// "shocks" feature with the price of the last trade before and the first trade after each one.
+///
+/// "session" makes a CDA a timed session of unit length. Agents arrive at uniformly random times
+/// and their orders leave the book "patience" after they arrive if they haven't traded. Agents pay
+/// "wait_cost" per unit of time their order stands in the book, until it trades, leaves, or the
+/// session ends. Observations then include a "session" feature with the number of agents that
+/// "expired" at their deadline and the mean time agents spent "waiting" in the book.
+///
+/// "book_snapshots" records the order book of a CDA after a list of numbers of events, or after
+/// every so many events, where arrivals, orders reaching the market, cancellations, and the close
+/// are all events. Observations then include a "book" feature with each snapshot's event count,
+/// time, and the price and depth of every level of standing "bids" and "asks", best first.
+///
+/// "arrival_order" makes agents arrive in a CDA in a fixed order, a list of every agent's index,
+/// instead of a random one, e.g. the "arrivals" of an observation output with --arrivals. The
+/// random order is still drawn and discarded, so an observation's seed with its arrival order
+/// reproduces it exactly. Agents are indexed like players, except that each trader is two agents,
+/// its seller then its buyer.
+///
+/// "closing_call" ends a CDA with a call auction. Continuous trading stops at time "at", in (0, 1],
+/// after which orders that reach the market rest in the book without trading and agents stop
+/// requoting, and when the market closes every order in the book clears at once at a single price.
+/// Observations then include a "closing" feature with the number of units traded "continuous"ly
+/// and in the "closing" call, and the closing call's "price".
+///
+/// "stopping" makes a CDA close early at an uncertain time. After each arrival the market closes
+/// with "probability", in (0, 1], so the number of agents that arrive while it's open is
+/// geometrically distributed, and the rest never trade. Observations then include a "stopped"
+/// feature with the number of agents that arrived before the market closed.
+///
+/// "matching" picks which of the orders at the best price in a CDA's book trades first: the one
+/// that reached the book first for "price_time", the default, a random one with chances
+/// proportional to its size for "pro_rata", or the largest one, then the first, for "size", where
+/// an order's size is the number of units its player trades, see "units".
+///
+/// "units" makes every player of a role of buyers or sellers trade that many units instead of one.
+/// A player draws a value for every unit, and they're sorted into a diminishing value schedule, so
+/// a buyer's first unit is worth the most and a seller's first unit costs the least. Each unit is
+/// shaded and quoted on its own, all at once when the player arrives, so an order can partially
+/// fill against several counterparties, and a player's payoff is the sum of the payoffs of its
+/// units. Players with several units are output with the number of units they "filled" and the
+/// "prices" of each, best unit first, and their draws include the "schedule" of their further
+/// units' values.
+/// A strategy with an "i" parameter shows only that many of a player's units in a CDA's book at
+/// once, like an iceberg order, and reveals another as soon as one of them trades. Observations
+/// of a market with such players include a "spread" feature with the mean spread of the book after
+/// every event, as it's "displayed" and in "total" with the hidden units.
+///
+/// "derived" adds features computed from the others, e.g. `{"eff": "surplus / ce_surplus"}`.
+/// Expressions combine numbers and the names of numeric features with +, -, *, /, parentheses, and
+/// the functions abs, sqrt, ln, min and max. A derived feature is null when a feature it uses is
+/// null or its value isn't finite, and it's summarized like any other.
+///
+/// Roth agents are Roth-Erev reinforcement learners and Bandit agents are multi-armed bandits that
+/// choose their shading between 0 and <shading>, and keep learning across all observations of a
+/// spec unless "persistent" is false, in which case every observation starts from what they
+/// learned during the "episodes". If "persistent" is true, the last observation includes a
+/// "learning" feature with the mean surplus of the "first_half" and "second_half" of the
+/// observations, and so does the summary, to show the effect of learning. Bandits use UCB unless
+/// the strategy has an epsilon parameter, e.g. "1_Bandit_e0.1", in which case they're
+/// epsilon-greedy. "episodes" is the number of learning simulations to run before the observations,
+/// which are discarded if "burn_in" is true, and output as additional observations otherwise. The
+/// final observation of a spec with learning agents includes "policies", the learned policy of each
+/// player in order, or null for players that don't learn.
+///
+/// "obs" is the number of observations of the spec, overriding --obs, and "weight" multiplies it,
+/// rounding to the nearest whole observation, so important profiles can get more samples in the
+/// same run. Summaries of specs with a "weight" report it, so they can be weighted when combined.
+///
+/// "types" makes observations of a spec line panel data, like repeated lab sessions with the same
+/// subjects. Every agent draws a type uniformly from its support once per spec line, before the
+/// "episodes", and its value in every observation is its type plus normal noise with standard
+/// deviation "noise", which may fall outside the support. Types can't be combined with "values",
+/// and values around them aren't stratified by --stratify or --sobol.
+///
+/// External agents delegate their bids to the "external" command, which is started once per spec.
+/// For every decision it's sent a line of json with the agent's index, role, value, shading, the
+/// market's type and size, its "state", and the agent's recent outcomes, and it must respond with a
+/// line of json like {"price": 0.5}, the price the agent bids or asks. Agents decide when their
+/// values are drawn, and in a continuous market again when they arrive, with the state's
+/// "best_bid", "best_ask" and "last_price" set from the book. Values and prices sent to and from
+/// the command are rescaled so the market's support is [0, 1]. If the command fails to respond,
+/// it's logged as a warning and every later order of its agents is rejected.
+///
+/// Bne agents ignore their shading and bid with an equilibrium bidding function solved numerically
+/// for the spec's market, its size, and every role's support, once per spec. It's solved for
+/// everyone in the market bidding with it, by "iterations" rounds of damped best response, where a
+/// buyer and a seller choose among "shadings" evenly spaced shadings in [0, 1] at each of "grid"
+/// evenly spaced values, each estimated with "samples" simulations. Unlike Correct, which is only
+/// an equilibrium for uniform [0, 1] values, it adapts to the configuration, but solving is slow
+/// for large markets.
+///
+/// [strat] may also be a composite of strategies separated by "|", e.g. "0.3_Shift|0.1_Standard",
+/// whose [count] players are split as evenly as possible among them, earlier ones taking any
+/// remainder, so 5 players are 3 of the first and 2 of the second. Its players are all reported
+/// as the composite strategy, so its payoff is that of the whole population.
+///
+/// Strategies are reported in a canonical form, e.g. "0.50" as "0.5", and equivalent strategies
+/// of a role, like "0.5_Standard" and "0.5" when Standard is the default style, are merged into
+/// one with the sum of their counts, named after the first in sorted order, with a warning.
+///
+/// A [count] may be fractional to approximate a role size between two others without separate
+/// specs. "4.5" is 4 players and a fifth that takes part in each observation with probability 0.5,
+/// and only reports a payoff in the observations it does, while a composite's fractional player
+/// plays the part that would take the next remainder.
+///
+/// [strat] may instead be the name of a preset loaded with --strategies. Logs, e.g. warnings about
+/// rejected orders or unknown keys in a spec, are written to stderr and configured with --log-level
+/// and --log-json. Unknown keys are errors with --strict. Input may be gzip or zstd compressed, and
+/// output can be compressed with --compress.
+struct Args {
+    /// Number of observations per spec file to produce, unless it sets its own "obs"
+    #[clap(long, value_parser, default_value_t = 1)]
+    obs: u64,
+
+    /// Flush stdout after every observation
+    #[clap(long, value_parser)]
+    flush: bool,
+
+    /// Simulate this many spec lines at once
+    ///
+    /// Each run of identical lines is simulated by one thread, while another compresses and writes
+    /// the output in input order. Output of later lines waits in bounded buffers, so threads that
+    /// get ahead pause instead of filling memory.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 1, conflicts_with = "checkpoint")]
+    jobs: u64,
+
+    /// Make output reproducible by deriving every observation's random seed from this
+    ///
+    /// The seed of each observation is a hash of this seed, its spec line's index, and its
+    /// observation index, so results don't depend on --jobs, and a run resumed from a checkpoint
+    /// reproduces the run that was interrupted. Agents that call external programs can still vary.
+    #[clap(long, value_parser)]
+    seed: Option<u64>,
+
+    /// Record progress in this file, and resume from it if it exists
+    ///
+    /// After every observation, the index of the current spec line and the number of its
+    /// observations output so far are saved. Rerunning with the same input and checkpoint skips
+    /// everything already output, and appends the rest. Every observation is tagged with its
+    /// "spec_index" and "obs_index" so partial output can be joined back to its input. Learning
+    /// agents in a spec that was interrupted start learning from scratch when resumed.
+    #[clap(long, value_parser, conflicts_with = "out")]
+    checkpoint: Option<PathBuf>,
+
+    /// Tag every observation with where it came from
+    ///
+    /// Adds the "spec_index" and "obs_index", the "seed" that reproduces the observation's random
+    /// draws, and a "spec_hash" of the spec line's text.
+    #[clap(long, value_parser)]
+    tag_output: bool,
+
+    /// Include the realized assignment in every observation
+    ///
+    /// Adds an "assignment" with the "counts" of each strategy in each role, and the "draws" of
+    /// every player in order, its value and the shading it drew. Traders also include the
+    /// "second_value" of a second unit, and multi-unit players the "schedule" of their further
+    /// units' values.
+    #[clap(long, value_parser)]
+    assignment: bool,
+
+    /// Include the order agents arrived in of every CDA observation
+    ///
+    /// Adds an "arrivals" feature with the index of every agent in the order they arrived, which
+    /// an "arrival_order" can fix to rerun the observation.
+    #[clap(long, value_parser)]
+    arrivals: bool,
+
+    /// Echo each spec as it was understood, so the output describes itself
+    ///
+    /// The echo has the "assignment" with equivalent strategies merged, the resolved "strategies"
+    /// of every name in it with their role's default style and shading filled in, the "market"
+    /// that was simulated, one of "cda", "call", or "continuous", and the spec's "configuration".
+    /// Headers are separate lines without "players", which readers of observations must skip.
+    #[clap(long, value_enum, conflicts_with = "egta_format")]
+    echo: Option<Echo>,
+
+    /// Only output these comma separated fields of every observation
+    ///
+    /// Fields are dotted paths, e.g. "players.payoff" for only the payoff of every player, and
+    /// features can be named without their "features." prefix, so `--fields
+    /// surplus,ce_price,players.payoff` outputs `{"features": {"surplus": ..., "ce_price": ...},
+    /// "players": [{"payoff": ...}, ...]}`. Fields an observation doesn't have are left out.
+    #[clap(
+        long,
+        value_parser,
+        value_delimiter = ',',
+        conflicts_with_all = ["summary", "egta_format"]
+    )]
+    fields: Vec<String>,
+
+    /// Add these comma separated extra features to observations and summaries
+    ///
+    /// Observations only have the surplus and competitive equilibrium features, and the features
+    /// of options a spec uses, like "clipped" for price bounds, unless extras are asked for.
+    #[clap(long, value_enum, value_delimiter = ',')]
+    extra_features: Vec<Extra>,
+
+    /// Output one summary per spec instead of every observation
+    ///
+    /// The summary has the mean and standard error of every feature and of every strategy's
+    /// payoff, where a strategy's payoff in an observation is the mean payoff of its players.
+    #[clap(long, value_parser)]
+    summary: bool,
+
+    /// Add 95% percentile bootstrap confidence intervals with this many resamples to summaries
+    #[clap(long, value_parser, requires = "summary")]
+    bootstrap: Option<u64>,
+
+    /// Stratify the uniform values of every agent over batches of this many observations
+    ///
+    /// Each batch draws a Latin hypercube with a coordinate for every agent, so within a batch
+    /// every agent's value falls once in each of as many equal parts of its support. This reduces
+    /// the variance of means of features that depend smoothly on values, like the surplus, and
+    /// the standard errors of summaries overstate it. A last partial batch is only partly
+    /// stratified. Tagged observations and summaries record the "stratification". Values from
+    /// "values" distributions, traders' second values, and further units aren't stratified.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    stratify: Option<u64>,
+
+    /// Draw the uniform values of every agent from a scrambled Sobol sequence over observations
+    ///
+    /// Each observation's values are the next point of a sequence with a coordinate for every
+    /// agent, which fills the space of values more evenly than independent draws, so means of
+    /// features that depend smoothly on values converge faster. The sequence is scrambled by a
+    /// random digital shift, drawn from the --seed if there is one, so every point is still
+    /// uniform. Observations aren't independent, so standard errors of summaries overstate the
+    /// error. Tagged observations and summaries record the "stratification". Values from "values"
+    /// distributions, traders' second values, and further units are drawn independently.
+    ///
+    /// The sequence is generated in the simulator, without a dependency, and its quality is
+    /// limited. Only the first 16 agents' coordinates use Joe and Kuo's tuned direction numbers.
+    /// Later agents' coordinates use further primitive polynomials with pseudorandom initial
+    /// direction numbers, so each one is still stratified, but pairs of them are spread less
+    /// evenly, and with many agents the gain over independent draws shrinks. Coordinates have 32
+    /// bits, and observations after the first 2^32 - 1 draw values independently. The digital
+    /// shift isn't a full Owen scramble, so the variance doesn't fall at its faster rate.
+    #[clap(long, value_parser, conflicts_with = "stratify")]
+    sobol: bool,
+
+    /// Add a "controlled_surplus" to summaries, the mean surplus adjusted by its regression on the
+    /// "ce_surplus"
+    ///
+    /// The equilibrium surplus only depends on values, so its mean has a closed form when every
+    /// value is uniform on one support, and is otherwise estimated from 65536 draws of values
+    /// without trading. The adjusted mean removes the variance the surplus shares with it, so its
+    /// "stderr" is often much smaller than the surplus's own for the same --obs. The "control" has
+    /// the mean used, and its "stderr" and "draws" when estimated. Specs with shocks are logged as
+    /// warnings and not adjusted.
+    #[clap(long, value_parser, requires = "summary")]
+    control_variate: bool,
+
+    /// Keep simulating each spec past its observations until its summary's standard error is at
+    /// most this
+    ///
+    /// The largest "stderr" of the --target-feature, or of every strategy's payoff without one, is
+    /// checked after the spec's observations, and again after as many more as it projects are
+    /// needed from how far it was. Specs stop at --max-obs and are logged as warnings if they
+    /// haven't reached the target. Standard errors of --stratify and --sobol overstate the error,
+    /// so those stop late.
+    #[clap(long, value_parser = positive, requires = "summary")]
+    target_se: Option<f64>,
+
+    /// The feature whose standard error --target-se targets, instead of every payoff's
+    #[clap(long, value_parser, requires = "target_se")]
+    target_feature: Option<String>,
+
+    /// The most observations --target-se extends a spec to
+    #[clap(long, value_parser, default_value_t = 100_000)]
+    max_obs: u64,
+
+    /// Integrate the expectations of small call markets instead of simulating them
+    ///
+    /// Call markets of at most four players, whose values are uniform and who bid a fixed shading
+    /// of them without learning, have the means of their features and payoffs computed with a
+    /// midpoint rule over a grid of every player's values. Each is written as a summary with an
+    /// "exact" count of the points each value took and zero standard errors. Specs that can't be
+    /// integrated are logged as warnings and simulated as usual.
+    #[clap(long, value_parser, conflicts_with_all = ["checkpoint", "egta_format", "fields"])]
+    exact: bool,
+
+    /// Simulate call markets in batches on the GPU
+    ///
+    /// Call markets of at most 64 agents, whose values are uniform and who bid a fixed shading of
+    /// them without learning, have thousands of observations at a time drawn, shaded and sorted
+    /// on the GPU, and their features computed from them as usual. Values are drawn in single
+    /// precision from a stream of the spec's own, so an observation's "seed" doesn't reproduce
+    /// it. Specs that can't be batched, or every spec if there's no GPU, are logged as warnings
+    /// and simulated as usual.
+    #[cfg(feature = "gpu")]
+    #[clap(long, value_parser, conflicts_with_all = ["checkpoint", "exact", "stratify", "sobol"])]
+    gpu: bool,
+
+    /// Write observations in the schema egtaonline expects
+    ///
+    /// Each observation has the "profile" that was simulated, numeric "features", every other
+    /// feature in "extended_features", and "players" with their role, strategy, payoff and empty
+    /// "features". Tags, assignments and policies aren't included.
+    #[clap(long, value_parser, conflicts_with = "summary")]
+    egta_format: bool,
+
+    /// Replace the players of egtaonline observations with "symmetry_groups", the count and mean
+    /// payoff of each strategy in each role
+    #[clap(long, value_parser, requires = "egta_format")]
+    aggregate: bool,
+
+    /// Compare each spec's simulated competitive equilibrium to its closed form expectation
+    ///
+    /// Specs where every value is uniform on the same support, i.e. without traders, "values",
+    /// "shocks", costs, or entry costs, have an exact expected equilibrium surplus and probability
+    /// of trade, and an expected price when there are as many buyers as sellers. Simulated means
+    /// further than four standard errors from them are logged as warnings, and others at "info".
+    #[clap(long, value_parser)]
+    check: bool,
+
+    /// Check accounting invariants after every observation, and fail on the first that breaks one
+    ///
+    /// Buyers must pay what sellers receive, as many buyers must trade as sellers, no agent may
+    /// trade at a price worse than its bid, and the surplus of trades plus the "im_surplus" and
+    /// "em_surplus" must be the "ce_surplus". Trades with a market maker are only checked against
+    /// bids. Every violation is logged as an error with the observation's seed.
+    #[clap(long, value_parser)]
+    paranoid: bool,
+
+    /// Reject spec lines with unknown keys, e.g. a misspelled "configuraion", instead of warning
+    /// about them
+    #[clap(long, value_parser, global = true)]
+    strict: bool,
+
+    /// Load named strategies from a toml (or json with a .json extension) file
+    ///
+    /// Each entry maps a name to a table with a "shading" and an optional "style", e.g. `shift_fast
+    /// = { shading = 0.3, style = "Shift" }`. Assignments can then use the name as a strategy.
+    #[clap(long, value_parser, global = true)]
+    strategies: Option<PathBuf>,
+
+    /// Read spec lines from this file mapped into memory instead of from stdin
+    ///
+    /// Large uncompressed files are split into lines without copying them through a buffer first,
+    /// so many --jobs can be kept busy. The file mustn't change while it's simulated.
+    #[clap(long, value_parser)]
+    mmap: Option<PathBuf>,
+
+    /// The format specs are written in, by default detected from the extension of the --mmap file,
+    /// and otherwise json
+    ///
+    /// Hand written specs with nested configuration are often easier to write in toml or yaml. A
+    /// toml file is one spec, unless its only key is an array of tables "specs", e.g. a
+    /// `[[specs]]` header before each one. A yaml file has a document for every spec, separated
+    /// by "---" lines. Both are converted to json spec lines, so a "spec_hash" is of the converted
+    /// line, and --convert writes specs back in either format.
+    #[clap(long, value_enum)]
+    spec_format: Option<SpecFormat>,
+
+    /// Substitute VALUE for the variable KEY in specs, e.g. `--define N=4`, which can be repeated
+    ///
+    /// Specs are templates where `${KEY}` is replaced by its defined value, or else by the
+    /// environment variable, before they're parsed, so one template can be run with many values
+    /// without generating near identical spec lines. `${KEY:-default}` has a default for when
+    /// it's defined by neither, undefined variables are errors, and `$${` is a literal `${`.
+    /// Values are inserted as is, e.g. `{"buyers": {"${S}_Shift": ${N}}}`, and the "spec_hash"
+    /// is of the substituted spec.
+    #[clap(long, value_parser = template::parse_define)]
+    define: Vec<(String, String)>,
+
+    /// Check every spec and write what simulating it would do instead of simulating it
+    ///
+    /// Each spec line is parsed with templates and defaults resolved, and written as a plan with
+    /// its "spec_index" and "spec_hash", the number of "agents", burn in "episodes" and
+    /// "observations", the "spec" as --echo would write it, and "estimated_seconds" to simulate it
+    /// on one thread, from timing a few throwaway observations, or null with external agents.
+    /// Invalid lines are written with their "error" instead, and the run fails after every line is
+    /// checked if any were.
+    #[clap(long, value_parser, conflicts_with = "checkpoint")]
+    dry_run: bool,
+
+    /// Write the specs in this format instead of simulating them
+    ///
+    /// Specs are read as they would be simulated, in their --spec-format with --define variables
+    /// substituted, but aren't checked. Toml is written as an array of tables "specs", and yaml as
+    /// a document for every spec, so hand written specs can be converted between formats and read
+    /// back with --spec-format.
+    #[clap(long, value_enum, conflicts_with_all = ["checkpoint", "dry_run"])]
+    convert: Option<SpecFormat>,
+
+    /// Draw values from this csv file for specs without their own "values"
+    #[clap(long, value_parser)]
+    values: Option<PathBuf>,
+
+    /// Compress output
+    ///
+    /// Input is decompressed automatically if it's gzip or zstd.
+    #[clap(long, value_enum, default_value_t = Compression::None, global = true)]
+    compress: Compression,
+
+    /// Write output to files named after this pattern instead of stdout
+    ///
+    /// With --shard-size, "{}" in the pattern is replaced by the index of each file, padded to five
+    /// digits, e.g. `--out shard-{}.jsonl --shard-size 100000` writes "shard-00000.jsonl",
+    /// "shard-00001.jsonl", and so on, of 100000 lines each. Compressed output compresses every
+    /// file on its own.
+    #[clap(long, value_parser, global = true)]
+    out: Option<String>,
+
+    /// Start a new output file after this many lines
+    ///
+    /// Every summary is one line, so with --summary, a size of 1 writes a file for every spec.
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "out",
+        global = true
+    )]
+    shard_size: Option<u64>,
+
+    /// Print running aggregates of every observation so far to stderr after every this many
+    ///
+    /// The aggregates are the mean "efficiency" where defined, "surplus", number of agents that
+    /// "traded", and fraction of observations with "no_trade", over every spec simulated so far.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    stats_every: Option<u64>,
+
+    /// Also replace this file with the running aggregates as json whenever they're printed
+    #[clap(long, value_parser, requires = "stats_every")]
+    stats_file: Option<PathBuf>,
+
+    /// Add what it took to simulate every observation to its features
+    ///
+    /// "sim_micros" is the wall clock time of the simulation in microseconds, "rng_draws" the
+    /// number of random numbers it drew, and "events" the number of events of a CDA, e.g. to find
+    /// the specs that take the longest in a large sweep.
+    #[clap(long, value_parser)]
+    profile: bool,
+
+    /// Print the time spent in each phase of the simulations to stderr when done
+    ///
+    /// Phases include parsing each spec line and building its agents, as well as simulating it.
+    #[clap(long, value_parser, global = true)]
+    bench_profile: bool,
+
+    /// The most agents one spec may have, counting both sides of a trader and every unit
+    ///
+    /// Specs with more are errors before any agent is created, so a malformed count like 1e12
+    /// fails quickly instead of exhausting memory.
+    #[clap(long, value_parser, default_value_t = DEFAULT_MAX_AGENTS, global = true)]
+    max_agents: u64,
+
+    /// Most verbose level of logs written to stderr
+    ///
+    /// Spec lines and observations are logged at "info" and "debug", and the phases of each
+    /// simulation at "trace".
+    #[clap(long, value_parser, default_value_t = LevelFilter::WARN, global = true)]
+    log_level: LevelFilter,
+
+    /// Write logs as lines of json instead of text
+    #[clap(long, value_parser, global = true)]
+    log_json: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    Solve(solve::SolveArgs),
+    Optimize(optimize::OptimizeArgs),
+    Regret(regret::RegretArgs),
+    Attribution(attribution::AttributionArgs),
+    Sensitivity(sensitivity::SensitivityArgs),
+    Sweep(sweep::SweepArgs),
+    Fit(fit::FitArgs),
+    Scale(scale::ScaleArgs),
+    Evolve(evolve::EvolveArgs),
+    Analyze(analyze::AnalyzeArgs),
+    Tournament(tournament::TournamentArgs),
+    Inspect(inspect::InspectArgs),
+    Plot(plot::PlotArgs),
+    Replay(replay::ReplayArgs),
+    Compare(compare::CompareArgs),
+}
+
+/// Run the command line interface
+pub fn run() -> io::Result<()> {
+    let args = Args::parse();
+    let logger = tracing_subscriber::fmt()
+        .with_max_level(args.log_level)
+        .with_writer(io::stderr);
+    if args.log_json {
+        logger.json().init();
+    } else {
+        logger.init();
+    }
+    let presets = match &args.strategies {
+        Some(path) => strategy::load_presets(path)?,
+        None => HashMap::new(),
+    };
+
+    if args.bench_profile {
+        profile::enable();
+    }
+    MAX_AGENTS.store(args.max_agents, Ordering::Relaxed);
+    if let Some(every) = args.stats_every {
+        monitor::enable(every, args.stats_file.clone());
+    }
+
+    match &args.out {
+        Some(pattern) => {
+            let mut ohandle = Sharded::new(pattern, args.shard_size, args.compress)?;
+            dispatch(&args, &presets, &mut ohandle)?;
+            ohandle.finish()?;
+        }
+        None => {
+            let stdout = io::stdout();
+            let mut ohandle = Output::new(stdout.lock(), args.compress)?;
+            dispatch(&args, &presets, &mut ohandle)?;
+            ohandle.finish()?.flush()?;
+        }
+    }
+    if args.bench_profile {
+        profile::report(&mut io::stderr())?;
+    }
+    Ok(())
+}
+
+/// Parse a finite positive number
+fn positive(arg: &str) -> Result<f64, String> {
+    match arg.parse() {
+        Ok(num) if f64::is_finite(num) && num > 0.0 => Ok(num),
+        Ok(num) => Err(format!("{} isn't finite and positive", num)),
+        Err(err) => Err(format!("{}", err)),
+    }
+}
+
+/// Run the command of the arguments, writing its output to `ohandle`
+fn dispatch(args: &Args, presets: &Presets, ohandle: &mut impl Write) -> io::Result<()> {
+    // only commands that read specs touch stdin, so the rest don't block on it
+    let input = || stream::decompress(io::stdin().lock());
+    match &args.command {
+        Some(Command::Solve(solve_args)) => solve::solve(solve_args, ohandle),
+        Some(Command::Optimize(opt_args)) => {
+            optimize::optimize(opt_args, presets, args.strict, input()?, ohandle)
+        }
+        Some(Command::Regret(regret_args)) => {
+            regret::regret(regret_args, presets, args.strict, input()?, ohandle)
+        }
+        Some(Command::Attribution(attr_args)) => {
+            attribution::attribution(attr_args, presets, args.strict, input()?, ohandle)
+        }
+        Some(Command::Sensitivity(sens_args)) => {
+            sensitivity::sensitivity(sens_args, presets, args.strict, input()?, ohandle)
+        }
+        Some(Command::Sweep(sweep_args)) => {
+            sweep::sweep(sweep_args, args.strict, input()?, ohandle)
+        }
+        Some(Command::Fit(fit_args)) => fit::fit(fit_args, presets, input()?, ohandle),
+        Some(Command::Scale(scale_args)) => {
+            scale::scale(scale_args, presets, args.strict, input()?, ohandle)
+        }
+        Some(Command::Evolve(evolve_args)) => {
+            evolve::evolve(evolve_args, presets, args.strict, input()?, ohandle)
+        }
+        Some(Command::Analyze(analyze_args)) => analyze::analyze(analyze_args, ohandle),
+        Some(Command::Inspect(inspect_args)) => {
+            inspect::inspect(inspect_args, presets, args.strict, input()?, ohandle)
+        }
+        Some(Command::Plot(plot_args)) => {
+            plot::plot(plot_args, presets, args.strict, input()?, ohandle)
+        }
+        Some(Command::Replay(replay_args)) => {
+            replay::replay(replay_args, presets, args.strict, input()?, ohandle)
+        }
+        Some(Command::Compare(compare_args)) => {
+            compare::compare(compare_args, presets, args.strict, input()?, ohandle)
+        }
+        Some(Command::Tournament(tourn_args)) => {
+            tournament::tournament(tourn_args, presets, args.strict, input()?, ohandle)
+        }
+        None => match (&args.mmap, spec_format(args)) {
+            (Some(path), SpecFormat::Json) => {
+                let map = mmap::Mmap::open(path)?;
+                if stream::compressed(&map) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "compressed specs can't be mapped, pipe them to stdin instead",
+                    ));
+                }
+                let template = Template::new(&args.define);
+                let lines = mmap::lines(&map).map(|line| template.apply_line(line?));
+                simulate_lines(args, presets, args.strict, lines, ohandle)
+            }
+            (None, SpecFormat::Json) => {
+                simulate_specs(args, presets, args.strict, input()?, ohandle)
+            }
+            (path, format) => {
+                let text = match path {
+                    Some(path) => {
+                        let map = mmap::Mmap::open(path)?;
+                        std::str::from_utf8(&map)
+                            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+                            .to_owned()
+                    }
+                    None => io::read_to_string(input()?)?,
+                };
+                let text = Template::new(&args.define).apply(&text)?;
+                let lines = formats::spec_lines(format, &text)?;
+                simulate_lines(
+                    args,
+                    presets,
+                    args.strict,
+                    lines.into_iter().map(Ok),
+                    ohandle,
+                )
+            }
+        },
+    }
+}
+
+/// The format of the specs to simulate
+fn spec_format(args: &Args) -> SpecFormat {
+    args.spec_format
+        .or_else(|| args.mmap.as_deref().map(SpecFormat::detect))
+        .unwrap_or(SpecFormat::Json)
+}
+
+fn simulate_specs(
+    args: &Args,
+    presets: &Presets,
+    strict: bool,
+    ihandle: impl BufRead,
+    ohandle: &mut impl Write,
+) -> io::Result<()> {
+    let template = Template::new(&args.define);
+    let lines = ihandle.lines().map(|line| template.apply_line(line?));
+    simulate_lines(args, presets, strict, lines, ohandle)
+}
+
+fn simulate_lines(
+    args: &Args,
+    presets: &Presets,
+    strict: bool,
+    lines: impl Iterator<Item = io::Result<String>>,
+    ohandle: &mut impl Write,
+) -> io::Result<()> {
+    if let Some(format) = args.convert {
+        return formats::write_specs(format, lines, ohandle);
+    }
+    if args.dry_run {
+        return plan::dry_run(args, presets, strict, lines, ohandle);
+    }
+    let progress = match &args.checkpoint {
+        Some(path) => checkpoint::load(path)?,
+        None => Progress::default(),
+    };
+    let default_values = args
+        .values
+        .as_deref()
+        .map(|path| Values::load(path, true))
+        .transpose()?;
+    let runner = SpecRunner {
+        args,
+        presets,
+        strict,
+        progress,
+        default_values: default_values.as_ref(),
+    };
+    let mut lines = lines.enumerate().peekable();
+    let mut next_group = || -> io::Result<Option<(String, Vec<usize>)>> {
+        while let Some((index, line)) = lines.next() {
+            let line = line?;
+            // runs of identical lines, common when resampling a profile, share their parsed agents
+            let mut indices = vec![index];
+            while let Some((next, Ok(next_line))) = lines.peek() {
+                if *next_line != line {
+                    break;
+                }
+                indices.push(*next);
+                lines.next();
+            }
+            indices.retain(|&index| runner.progress.remaining(index).is_some());
+            if !indices.is_empty() {
+                return Ok(Some((line, indices)));
+            }
+        }
+        Ok(None)
+    };
+    // progress is only recorded once output is durable, so checkpoints write as they go
+    if args.checkpoint.is_some() {
+        while let Some((line, indices)) = next_group()? {
+            runner.run(&line, indices, ohandle)?;
+        }
+        return Ok(());
+    }
+
+    // workers simulate whole groups of lines, and send their output in chunks to this thread,
+    // which writes the groups in input order while the workers keep simulating
+    let jobs = args.jobs as usize;
+    let (work_tx, work_rx) = mpsc::sync_channel::<(String, Vec<usize>, Chunks)>(2 * jobs);
+    let work_rx = Mutex::new(work_rx);
+    thread::scope(|scope| {
+        // workers stop once there's no more work, including when this returns early
+        let work_tx = work_tx;
+        for _ in 0..jobs {
+            let (work_rx, runner) = (&work_rx, &runner);
+            scope.spawn(move || loop {
+                let Ok((line, indices, mut chunks)) = work_rx.lock().unwrap().recv() else {
+                    break;
+                };
+                let res = runner.run(&line, indices, &mut chunks);
+                if let Err(err) = res.and_then(|()| chunks.flush()) {
+                    // the writer only stops early after an error of its own
+                    let _ = chunks.tx.send(Err(err));
+                }
+            });
+        }
+        let mut pending = VecDeque::new();
+        loop {
+            while pending.len() < 2 * jobs {
+                let Some((line, indices)) = next_group()? else {
+                    break;
+                };
+                let (tx, rx) = mpsc::sync_channel(PENDING_CHUNKS);
+                let chunks = Chunks {
+                    tx,
+                    buf: Vec::new(),
+                };
+                work_tx
+                    .send((line, indices, chunks))
+                    .expect("workers stopped early");
+                pending.push_back(rx);
+            }
+            let Some(rx) = pending.pop_front() else {
+                break;
+            };
+            for chunk in rx {
+                ohandle.write_all(&chunk?)?;
+                if args.flush {
+                    ohandle.flush()?;
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Bytes of output a worker collects before sending them to be written
+const CHUNK_BYTES: usize = 1 << 16;
+
+/// Chunks of a group's output that can wait to be written before its worker blocks
+const PENDING_CHUNKS: usize = 16;
+
+/// The output of a group of spec lines, sent to the writer in chunks
+struct Chunks {
+    tx: mpsc::SyncSender<io::Result<Vec<u8>>>,
+    buf: Vec<u8>,
+}
+
+impl Write for Chunks {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        if self.buf.len() >= CHUNK_BYTES {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    /// Send everything so far to the writer, which also flushes it with --flush
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::take(&mut self.buf);
+        self.tx
+            .send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "output stopped early"))
+    }
+}
+
+/// Everything shared by the simulations of every spec line
+struct SpecRunner<'a> {
+    args: &'a Args,
+    presets: &'a Presets,
+    strict: bool,
+    progress: Progress,
+    default_values: Option<&'a Values>,
+}
+
+impl SpecRunner<'_> {
+    /// Simulate a run of identical spec lines at these indices
+    fn run(&self, line: &str, indices: Vec<usize>, out: &mut impl Write) -> io::Result<()> {
+        let mut spec = profile::time(Phase::Parse, || parse_spec(line, self.strict, self.presets))?;
+        if spec.configuration.values.is_none() {
+            spec.configuration.values = self.default_values.cloned();
+        }
+        let template = profile::time(Phase::Build, || build_agents(&spec, self.presets))?;
+        let mut agents = template.clone();
+        let (burn_in, num_obs) = spec_obs(&spec.configuration, self.args)?;
+        let hash = spec_hash(line);
+        let derived = parse_derived(&spec.configuration)?;
+        let echo = match self.args.echo {
+            Some(_) => {
+                let market = market_name(&spec.configuration, &template)?;
+                Some(SpecEcho::new(line, &spec, market, self.presets)?)
+            }
+            None => None,
+        };
+        let expected = if !self.args.check {
+            None
+        } else if template.iter().any(Agent::chooses_entry) {
+            warn!(reason = "entry costs change net values", "can't check spec");
+            None
+        } else {
+            expected::for_spec(&spec)
+                .map_err(|reason| warn!(reason, "can't check spec"))
+                .ok()
+        };
+        let exact = if !self.args.exact {
+            None
+        } else {
+            exact::nodes(&spec, &template, self.presets)
+                .map_err(|reason| warn!(reason, "can't integrate spec, simulating it"))
+                .ok()
+        };
+        if let Some(nodes) = exact {
+            let report = exact::integrate(&mut agents, nodes, &derived, &self.args.extra_features)?;
+            for index in indices {
+                let tag = SpecTag {
+                    index,
+                    hash: &hash,
+                    expected,
+                    derived: &derived,
+                    persistent: spec.configuration.persistent,
+                    types: spec.configuration.types.is_some(),
+                    weight: spec.configuration.weight,
+                    echo: echo.as_ref(),
+                    control: None,
+                };
+                output_exact(report.clone(), nodes, out, self.args, tag)?;
+            }
+            return Ok(());
+        }
+        let control = if !self.args.control_variate {
+            None
+        } else {
+            // estimates use a stream beyond any observation's and the burn in's
+            let mut rng = match (self.args.seed, indices.first()) {
+                (Some(seed), Some(&index)) => {
+                    StdRng::seed_from_u64(derive_seed(seed, index, u64::MAX - 1))
+                }
+                _ => StdRng::from_entropy(),
+            };
+            expected::control(&spec, &template, &mut rng)
+                .map_err(|reason| warn!(reason, "can't control spec"))
+                .ok()
+        };
+        for (rep, index) in indices.into_iter().enumerate() {
+            let _span = info_span!("spec", line = index).entered();
+            if rep == 0 {
+                debug!(?spec, "parsed spec");
+            } else {
+                agents.clone_from(&template);
+            }
+            let start = self.progress.remaining(index).unwrap_or(0);
+            let obs = start.min(num_obs)..num_obs;
+            let tag = SpecTag {
+                index,
+                hash: &hash,
+                expected,
+                derived: &derived,
+                persistent: spec.configuration.persistent,
+                types: spec.configuration.types.is_some(),
+                weight: spec.configuration.weight,
+                echo: echo.as_ref(),
+                control,
+            };
+            match continuous_market(&spec.configuration, &agents, self.args.arrivals)? {
+                Some(market) => {
+                    output_sim(&mut agents, &market, out, self.args, tag, burn_in, obs)?
+                }
+                // only a continuous market counts its events
+                None if spec.configuration.cda.unwrap_or(true) && self.args.profile => {
+                    let market = Continuous::new();
+                    output_sim(&mut agents, &market, out, self.args, tag, burn_in, obs)?
+                }
+                None if spec.configuration.cda.unwrap_or(true) => {
+                    output_sim(&mut agents, &Cda, out, self.args, tag, burn_in, obs)?
+                }
+                None => {
+                    #[cfg(feature = "gpu")]
+                    if let Some(batch) = self.batch(&spec, &agents, index) {
+                        output_sim(&mut agents, &batch, out, self.args, tag, burn_in, obs)?;
+                        continue;
+                    }
+                    output_sim(&mut agents, &Call, out, self.args, tag, burn_in, obs)?
+                }
+            };
+        }
+        Ok(())
+    }
+
+    /// A batch to simulate the call market of the spec at an index on the GPU with, if --gpu
+    /// asks for one and the spec can be batched
+    #[cfg(feature = "gpu")]
+    fn batch(&self, spec: &Spec, agents: &[Agent<'_>], index: usize) -> Option<gpu::Batch> {
+        if !self.args.gpu {
+            return None;
+        }
+        // batches draw from a stream of their own, beyond any observation's
+        let seed = match self.args.seed {
+            Some(seed) => derive_seed(seed, index, u64::MAX / 2 + 1),
+            None => StdRng::from_entropy().gen(),
+        };
+        gpu::Batch::new(spec, agents, self.presets, seed)
+            .map_err(|reason| warn!(reason, "can't simulate spec on the GPU, simulating it"))
+            .ok()
+    }
+}
+
+/// The number of burn in episodes and observations of a spec
+fn spec_obs(config: &Config, args: &Args) -> io::Result<(u64, u64)> {
+    let obs = config.obs.unwrap_or(args.obs);
+    let obs = match config.weight {
+        None => obs,
+        Some(weight) if weight.is_finite() && weight >= 0.0 => (obs as f64 * weight).round() as u64,
+        Some(weight) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid weight {}, must be finite and nonnegative", weight),
+            ))
+        }
+    };
+    let episodes = config.episodes.unwrap_or(0);
+    Ok(if config.burn_in.unwrap_or(true) {
+        (episodes, obs)
+    } else {
+        (0, episodes + obs)
+    })
+}
+
+/// Parse the expressions of a spec's derived features
+fn parse_derived(config: &Config) -> io::Result<Vec<(String, Expr)>> {
+    let Some(derived) = &config.derived else {
+        return Ok(Vec::new());
+    };
+    derived
+        .iter()
+        .map(|(name, text)| {
+            let expr = text.parse().map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid derived feature {} \"{}\": {}", name, text, err),
+                )
+            })?;
+            Ok((name.clone(), expr))
+        })
+        .collect()
+}
+
+/// Evaluate a spec's derived features from the rest of an observation's
+fn derive(features: &mut Features, derived: &[(String, Expr)]) -> io::Result<()> {
+    if derived.is_empty() {
+        return Ok(());
+    }
+    let value = serde_json::to_value(&*features)?;
+    let vars = |name: &str| match value.get(name) {
+        Some(serde_json::Value::Null) => Ok(None),
+        Some(val) => val
+            .as_f64()
+            .map(Some)
+            .ok_or_else(|| format!("feature {} isn't a number", name)),
+        None => Err(format!("there's no feature {}", name)),
+    };
+    for (name, expr) in derived {
+        let invalid = |reason: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid derived feature {}: {}", name, reason),
+            )
+        };
+        if value.get(name).is_some() {
+            return Err(invalid("it's already a feature".to_owned()));
+        }
+        let val = expr.eval(&vars).map_err(invalid)?;
+        features.derived.insert(name.clone(), val);
+    }
+    Ok(())
+}
+
+/// The continuous market a spec's configuration needs, or None if it's a plain CDA or call market
+///
+/// The market is for `agents`, reporting spreads if any of them hide units and stops if any of
+/// them have one, and records their arrival order if `arrivals`.
+fn continuous_market(
+    config: &Config,
+    agents: &[Agent<'_>],
+    arrivals: bool,
+) -> io::Result<Option<Continuous>> {
+    let cda = config.cda.unwrap_or(true);
+    let num_agents = agents.len();
+    let reports = agents
+        .iter()
+        .any(|agent| agent.display().is_some() || agent.stop().is_some());
+    if config.market_maker.is_none()
+        && config.shocks.is_none()
+        && config.session.is_none()
+        && config.book_snapshots.is_none()
+        && config.arrival_order.is_none()
+        && config.closing_call.is_none()
+        && config.stopping.is_none()
+        && config.matching.is_none()
+        && !(cda && (arrivals || reports))
+    {
+        return Ok(None);
+    } else if !cda {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "a market maker, shocks, a session, book snapshots, an arrival order, a closing call, \
+             random stopping, or a matching rule require a cda",
+        ));
+    }
+    let mut market = Continuous::new();
+    if let Some(maker) = &config.market_maker {
+        let maker = MarketMaker::new(maker)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        market = market.with_maker(maker);
+    }
+    if let Some(shocks) = &config.shocks {
+        if let Some(shock) = shocks.iter().find(|s| !s.shift.is_finite()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid shock shift {}, must be finite", shock.shift),
+            ));
+        }
+        market = market.with_shocks(shocks.clone());
+    }
+    if let Some(session) = config.session {
+        if session.patience.is_nan() || session.patience <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid patience {}, must be positive", session.patience),
+            ));
+        } else if !session.wait_cost.is_finite() || session.wait_cost < 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "invalid wait cost {}, must be finite and non-negative",
+                    session.wait_cost
+                ),
+            ));
+        }
+        market = market.with_session(session);
+    }
+    if let Some(snapshots) = &config.book_snapshots {
+        if matches!(snapshots, Snapshots::Every { every: 0 }) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "book snapshots every 0 events",
+            ));
+        }
+        market = market.with_snapshots(snapshots.clone());
+    }
+    if let Some(order) = &config.arrival_order {
+        let mut seen = vec![false; num_agents];
+        for &index in order {
+            if index >= num_agents || std::mem::replace(&mut seen[index], true) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "invalid arrival order, agent {} isn't in the market or arrives twice",
+                        index
+                    ),
+                ));
+            }
+        }
+        if order.len() < num_agents {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "invalid arrival order of {} agents, the market has {}",
+                    order.len(),
+                    num_agents
+                ),
+            ));
+        }
+        market = market.with_order(order.clone());
+    }
+    if let Some(closing) = config.closing_call {
+        if !(closing.at > 0.0 && closing.at <= 1.0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid closing call at {}, must be in (0, 1]", closing.at),
+            ));
+        }
+        market = market.with_closing(closing);
+    }
+    if let Some(stopping) = config.stopping {
+        if !(stopping.probability > 0.0 && stopping.probability <= 1.0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "invalid stopping probability {}, must be in (0, 1]",
+                    stopping.probability
+                ),
+            ));
+        }
+        market = market.with_stopping(stopping);
+    }
+    if let Some(matching) = config.matching {
+        market = market.with_matching(matching);
+    }
+    if arrivals {
+        market = market.with_arrivals();
+    }
+    Ok(Some(market))
+}
+
+/// Parse a line of json, erroring on unknown keys if strict, and warning about them otherwise
+fn parse_line<T: DeserializeOwned>(line: &str, strict: bool) -> io::Result<T> {
+    let mut unknown = Vec::new();
+    let parsed = deserialize(line, |path| unknown.push(path.to_string()))?;
+    if strict && !unknown.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown keys: {}", unknown.join(", ")),
+        ));
+    }
+    for key in unknown {
+        warn!(key, "ignoring unknown key");
+    }
+    Ok(parsed)
+}
+
+/// Deserialize a line of json with serde_json, calling `ignored` with the path of every unknown key
+#[cfg(not(feature = "simd-json"))]
+fn deserialize<T: DeserializeOwned>(
+    line: &str,
+    ignored: impl FnMut(serde_ignored::Path<'_>),
+) -> io::Result<T> {
+    let mut deserializer = serde_json::Deserializer::from_str(line);
+    let parsed = serde_ignored::deserialize(&mut deserializer, ignored)?;
+    deserializer.end()?;
+    Ok(parsed)
+}
+
+/// Deserialize a line of json with simd-json, calling `ignored` with the path of every unknown key
+///
+/// simd-json parses in place, so this parses a copy of the line.
+#[cfg(feature = "simd-json")]
+fn deserialize<T: DeserializeOwned>(
+    line: &str,
+    ignored: impl FnMut(serde_ignored::Path<'_>),
+) -> io::Result<T> {
+    let invalid = |err: simd_json::Error| io::Error::new(io::ErrorKind::InvalidData, err);
+    let mut bytes = line.as_bytes().to_vec();
+    let mut deserializer = simd_json::Deserializer::from_slice(&mut bytes).map_err(invalid)?;
+    serde_ignored::deserialize(&mut deserializer, ignored).map_err(invalid)
+}
+
+/// Parse a spec line, merging equivalent strategies in each role under one canonical name
+pub fn parse_spec(line: &str, strict: bool, presets: &Presets) -> io::Result<Spec> {
+    let mut spec: Spec = parse_line(line, strict)?;
+    spec.assignment = canonical_roles(&spec, presets);
+    Ok(spec)
+}
+
+/// The assignment with every strategy written canonically, and equivalent ones merged
+///
+/// Strategies are equivalent if they resolve to the same parameters with the spec's defaults, e.g.
+/// "0.50_Standard" and "0.5" when the default style is Standard, and merged strategies take the
+/// first canonical name. Presets keep their names, and strategies that don't resolve are left for
+/// building the agents to report.
+fn canonical_roles(spec: &Spec, presets: &Presets) -> Roles {
+    let canonical = |role: &str, counts: &BTreeMap<String, Count>| {
+        let mut merged: Vec<(Vec<Strategy>, String, Count)> = Vec::new();
+        let mut unresolved = BTreeMap::new();
+        for (name, &count) in counts {
+            let parts: Option<Vec<_>> = name
+                .split('|')
+                .map(|part| resolve_strategy(spec, role, part, presets).ok())
+                .collect();
+            let Some(parts) = parts else {
+                unresolved.insert(name.clone(), count);
+                continue;
+            };
+            match merged.iter_mut().find(|(other, ..)| *other == parts) {
+                Some((_, first, total)) => {
+                    warn!(
+                        role,
+                        strategy = name,
+                        into = first.as_str(),
+                        "merging equivalent strategies"
+                    );
+                    total.0 += count.0;
+                }
+                None => {
+                    let name = name
+                        .split('|')
+                        .map(|part| match strategy::resolve(part, presets) {
+                            Ok(_) if presets.contains_key(part) => part.to_owned(),
+                            Ok(strat) => strat.to_string(),
+                            Err(_) => part.to_owned(),
+                        })
+                        .collect::<Vec<_>>()
+                        .join("|");
+                    merged.push((parts, name, count));
+                }
+            }
+        }
+        unresolved.extend(merged.into_iter().map(|(_, name, count)| (name, count)));
+        unresolved
+    };
+    Roles {
+        buyers: canonical("buyers", &spec.assignment.buyers),
+        sellers: canonical("sellers", &spec.assignment.sellers),
+        traders: canonical("traders", &spec.assignment.traders),
+        others: spec
+            .assignment
+            .others
+            .iter()
+            .map(|(role, counts)| (role.clone(), canonical(role, counts)))
+            .collect(),
+    }
+}
+
+/// Resolve a strategy name from a spec, filling in the spec's default style
+fn resolve_strategy(
+    spec: &Spec,
+    role: &str,
+    name: &str,
+    presets: &Presets,
+) -> io::Result<Strategy> {
+    let mut parsed = strategy::resolve(name, presets)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let config = &spec.configuration;
+    let (style, shading) = match spec.side(role)? {
+        Side::Buyers => (config.buyer_style, config.buyer_shading),
+        Side::Sellers => (config.seller_style, config.seller_shading),
+        Side::Traders => (config.trader_style, config.trader_shading),
+    };
+    parsed.style = parsed
+        .style
+        .or(style)
+        .or(config.style)
+        .or(Some(Style::Standard));
+    parsed.shading = parsed.shading.or(shading);
+    // Bne agents ignore their shading
+    if parsed.style == Some(Style::Bne) {
+        parsed.shading = parsed.shading.or(Some(Shading::Fixed(0.0)));
+    }
+    if parsed.shading.is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "strategy \"{}\" has no shading and {} have no default shading",
+                name, role
+            ),
+        ));
+    }
+    Ok(parsed)
+}
+
+/// The value support of a role in a spec
+fn role_support(spec: &Spec, role: &str) -> io::Result<(f64, f64)> {
+    let support = match spec.custom_role(role) {
+        Some(RoleConfig {
+            support: None,
+            side,
+        }) => return role_support(spec, side.role()),
+        Some(config) => config.support,
+        None => spec
+            .configuration
+            .support
+            .as_ref()
+            .and_then(|sup| match role {
+                "traders" => sup.traders,
+                "buyers" => sup.buyers,
+                _ => sup.sellers,
+            }),
+    };
+    match support {
+        None => Ok((0.0, 1.0)),
+        Some([low, high]) if low.is_finite() && high.is_finite() && low < high => Ok((low, high)),
+        Some([low, high]) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "invalid support [{}, {}], must be finite with low < high",
+                low, high
+            ),
+        )),
+    }
+}
+
+/// The smallest range covering the support of every role with agents, which every agent bids on
+/// the scale of
+fn market_support(spec: &Spec) -> io::Result<(f64, f64)> {
+    let mut scale: Option<(f64, f64)> = None;
+    for (role, counts) in spec.assignment.iter() {
+        if counts.values().any(|count| count.0 > 0.0) {
+            let (low, high) = role_support(spec, role)?;
+            scale = Some(scale.map_or((low, high), |(l, h)| (l.min(low), h.max(high))));
+        }
+    }
+    Ok(scale.unwrap_or((0.0, 1.0)))
+}
+
+/// Apply the spec's configuration of its role to an agent
+fn configure_agent(spec: &Spec, agent: &mut Agent<'_>) -> io::Result<()> {
+    let (low, high) = role_support(spec, agent.role())?;
+    agent.set_support(low, high);
+    let (low, high) = market_support(spec)?;
+    agent.set_scale(low, high);
+    match spec.configuration.cost.unwrap_or(0.0) {
+        cost if cost.is_finite() && cost >= 0.0 => agent.set_cost(cost / 2.0),
+        cost => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid cost {}, must be finite and nonnegative", cost),
+            ))
+        }
+    }
+    let (control, name) = if agent.buyer {
+        (spec.configuration.ceiling, "ceiling")
+    } else {
+        (spec.configuration.floor, "floor")
+    };
+    match control {
+        Some(limit) if limit.is_nan() => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} can't be NaN", name),
+            ))
+        }
+        Some(limit) => agent.set_price_control(limit),
+        None => (),
+    }
+    if let Some(values) = &spec.configuration.values {
+        values.configure(agent);
+    }
+    match spec.configuration.types {
+        Some(_) if spec.configuration.values.is_some() => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "\"types\" can't be combined with \"values\"",
+            ))
+        }
+        Some(Types { noise }) if noise.is_finite() && noise >= 0.0 => agent.set_types(noise),
+        Some(Types { noise }) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "invalid types noise {}, must be finite and nonnegative",
+                    noise
+                ),
+            ))
+        }
+        None => (),
+    }
+    match spec.configuration.noise.unwrap_or(0.0) {
+        noise if noise.is_finite() && noise >= 0.0 => agent.set_noise(noise),
+        noise => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid noise {}, must be finite and nonnegative", noise),
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// The most agents a spec may have without --max-agents
+const DEFAULT_MAX_AGENTS: u64 = 10_000_000;
+
+/// The most agents a spec may have, set by --max-agents
+static MAX_AGENTS: AtomicU64 = AtomicU64::new(DEFAULT_MAX_AGENTS);
+
+/// The number of agents a spec's assignment creates, after checking its counts
+fn count_agents(spec: &Spec) -> io::Result<f64> {
+    let mut total = 0.0;
+    for (role, map) in spec.assignment.iter() {
+        let per_player = (spec.side(role)?.agents().len() * spec.units(role)) as f64;
+        for (strat, num) in map {
+            if !(num.0.is_finite() && num.0 >= 0.0) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "invalid count {} of {} \"{}\", must be finite and nonnegative",
+                        num.0, role, strat
+                    ),
+                ));
+            }
+            total += num.0.ceil() * per_player;
+        }
+    }
+    Ok(total)
+}
+
+/// Create all of the agents in a spec's assignment
+fn build_agents<'a>(spec: &'a Spec, presets: &Presets) -> io::Result<Vec<Agent<'a>>> {
+    let mut external = Vec::new();
+    let mut bne = Vec::new();
+    if let Some(role) = spec
+        .configuration
+        .roles
+        .iter()
+        .flat_map(BTreeMap::keys)
+        .find(|role| spec.custom_role(role).is_none())
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("role \"{}\" is built in and can't be declared", role),
+        ));
+    }
+    for (role, units) in spec.configuration.units.iter().flatten() {
+        if *units == 0 || spec.side(role)? == Side::Traders {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "invalid {} units for role \"{}\", must be positive for buyers or sellers",
+                    units, role
+                ),
+            ));
+        }
+    }
+    let total = count_agents(spec)?;
+    let max = MAX_AGENTS.load(Ordering::Relaxed);
+    if total > max as f64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "spec has {} agents, more than the {} allowed by --max-agents",
+                total, max
+            ),
+        ));
+    }
+    let mut agents: Vec<Agent> = Vec::with_capacity(total as usize);
+    for (role, map) in spec.assignment.iter() {
+        let side = spec.side(role)?;
+        let sides = side.agents();
+        let custom = spec.custom_role(role).is_some();
+        let units = spec.units(role);
+        for (strat, &num) in map {
+            // a composite strategy splits its players among its parts, earlier parts taking any
+            // remainder, and the next part any fractional player, while all of them are still
+            // reported as the composite
+            let parts: Vec<_> = strat.split('|').collect();
+            let len = parts.len() as u64;
+            let (num, fraction) = (num.whole(), num.fraction());
+            for (part, name) in parts.iter().enumerate() {
+                let parsed = resolve_strategy(spec, role, name, presets)?;
+                if parsed.stop.is_some() && side != Side::Traders {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "only traders have a unit to stop, not {} \"{}\"",
+                            role, name
+                        ),
+                    ));
+                }
+                let count = num / len + u64::from((part as u64) < num % len);
+                let sampled = fraction > 0.0 && part as u64 == num % len;
+                for player in 0..count + u64::from(sampled) {
+                    for &bs in sides {
+                        for unit in 0..units {
+                            match parsed.style {
+                                Some(Style::External) => external.push(agents.len()),
+                                Some(Style::Bne) => bne.push(agents.len()),
+                                _ => (),
+                            }
+                            let mut agent = Agent::from_strategy(bs, strat, &parsed);
+                            agent.trader = side == Side::Traders;
+                            agent.set_unit(units, unit);
+                            if player == count {
+                                agent.set_presence(fraction);
+                            }
+                            if custom {
+                                agent.set_role(role);
+                            }
+                            configure_agent(spec, &mut agent)?;
+                            agents.push(agent);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(values) = &spec.configuration.values {
+        values.assign(&mut agents);
+        values.check(&agents)?;
+    }
+
+    // solved once per spec, for everyone in the market bidding in equilibrium
+    if !bne.is_empty() {
+        let config = spec.configuration.bne.unwrap_or_default();
+        config
+            .validate()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let solved = if spec.configuration.cda.unwrap_or(true) {
+            bne::solve(&agents, &Cda, &config)
+        } else {
+            bne::solve(&agents, &Call, &config)
+        };
+        for id in bne {
+            agents[id].set_bidder(Box::new(solved.clone()));
+        }
+    }
+
+    if !external.is_empty() {
+        let command = spec.configuration.external.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "External agents require an \"external\" command in the configuration",
+            )
+        })?;
+        let market = MarketInfo {
+            cda: spec.configuration.cda.unwrap_or(true),
+            buyers: agents.iter().filter(|a| a.buyer).count(),
+            sellers: agents.iter().filter(|a| !a.buyer).count(),
+        };
+        let process = Arc::new(Mutex::new(ExternalProcess::spawn(command, market)?));
+        for id in external {
+            agents[id].set_bidder(Box::new(ExternalAgent::new(process.clone(), id)));
+        }
+    }
+    Ok(agents)
+}
+
+/// Where a spec came from in the input, what its equilibrium should be if checked, and how its
+/// observations are related and extended
+#[derive(Debug, Clone, Copy)]
+struct SpecTag<'a> {
+    index: usize,
+    hash: &'a str,
+    expected: Option<Expected>,
+    derived: &'a [(String, Expr)],
+    persistent: Option<bool>,
+    types: bool,
+    weight: Option<f64>,
+    echo: Option<&'a SpecEcho>,
+    control: Option<Control>,
+}
+
+/// A stable 64 bit FNV-1a hash of a spec line in hex
+fn spec_hash(line: &str) -> String {
+    let hash = line.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// SplitMix64's output function, which maps consecutive inputs to independent looking outputs
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// The seed of an observation of a spec line, independent of when it was simulated
+fn derive_seed(seed: u64, spec: usize, obs: u64) -> u64 {
+    splitmix64(splitmix64(seed ^ splitmix64(spec as u64)) ^ obs)
+}
+
+#[derive(Serialize, Debug)]
+struct SummaryLine<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spec_index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spec_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    learning: Option<Learning>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weight: Option<f64>,
+    /// The points each value was integrated over, when the summary is exact
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exact: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stratification: Option<Stratification>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    controlled_surplus: Option<summary::Controlled>,
+    #[serde(flatten)]
+    report: summary::Report,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spec: Option<&'a SpecEcho>,
+}
+
+/// Write the integrated summary of a spec like a simulated one
+fn output_exact(
+    report: summary::Report,
+    nodes: u64,
+    mut out: &mut impl Write,
+    args: &Args,
+    spec: SpecTag<'_>,
+) -> io::Result<()> {
+    let echo = |mode| spec.echo.filter(|_| args.echo == Some(mode));
+    if let Some(echo) = echo(Echo::Header) {
+        let header = SpecHeader {
+            spec_index: spec.index,
+            spec: echo,
+        };
+        serde_json::to_writer(&mut out, &header)?;
+        writeln!(&mut out)?;
+    }
+    let line = SummaryLine {
+        spec_index: args.tag_output.then_some(spec.index),
+        spec_hash: args.tag_output.then(|| spec.hash.to_owned()),
+        learning: None,
+        weight: spec.weight,
+        exact: Some(nodes),
+        stratification: None,
+        controlled_surplus: None,
+        report,
+        spec: echo(Echo::Observation),
+    };
+    serde_json::to_writer(&mut out, &line)?;
+    writeln!(&mut out)?;
+    if args.flush {
+        out.flush()?
+    }
+    Ok(())
+}
+
+fn output_sim(
+    agents: &mut [Agent<'_>],
+    market: &impl Market,
+    mut out: &mut impl Write,
+    args: &Args,
+    spec: SpecTag<'_>,
+    burn_in: u64,
+    obs_range: Range<u64>,
+) -> io::Result<()> {
+    let mut num_obs = obs_range.end;
+    let tag_index = args.tag_output || args.checkpoint.is_some();
+    // burn in and bootstrapping use a stream beyond any observation's
+    let mut rng = match args.seed {
+        Some(seed) => StdRng::seed_from_u64(derive_seed(seed, spec.index, u64::MAX)),
+        None => StdRng::from_entropy(),
+    };
+    if spec.types {
+        // types have a stream of their own between the observations' and the streams at the end
+        let mut type_rng = match args.seed {
+            Some(seed) => StdRng::seed_from_u64(derive_seed(seed, spec.index, u64::MAX / 2)),
+            None => StdRng::from_entropy(),
+        };
+        agents.iter_mut().for_each(|a| a.draw_type(&mut type_rng));
+    }
+    for episode in 0..burn_in {
+        let _span = debug_span!("episode", index = episode).entered();
+        run_sim(agents, market, &mut rng);
+    }
+    let mut summary = args.summary.then(Summary::default);
+    let mut check = spec.expected.map(Check::new);
+    // agents that don't persist start every observation where the burn in left them
+    let initial = (spec.persistent == Some(false)).then(|| agents.to_vec());
+    let echo = |mode| spec.echo.filter(|_| args.echo == Some(mode));
+    // a resumed spec already has its header
+    if let (Some(echo), 0) = (echo(Echo::Header), obs_range.start) {
+        let header = SpecHeader {
+            spec_index: spec.index,
+            spec: echo,
+        };
+        serde_json::to_writer(&mut out, &header)?;
+        writeln!(&mut out)?;
+    }
+    let mut surpluses = Vec::new();
+    let mut strata: Option<(u64, Vec<Vec<f64>>)> = None;
+    let mut sobol = if !args.sobol {
+        None
+    } else {
+        // the scramble has its own stream, and a resumed spec continues the sequence
+        let mut scramble_rng = match args.seed {
+            Some(seed) => StdRng::seed_from_u64(derive_seed(seed, spec.index, u64::MAX - 2)),
+            None => StdRng::from_entropy(),
+        };
+        let mut sequence = Sobol::scrambled(agents.len(), &mut scramble_rng);
+        sequence
+            .by_ref()
+            .take(obs_range.start as usize)
+            .for_each(drop);
+        Some(sequence)
+    };
+    for obs in obs_range.start.. {
+        if obs == num_obs {
+            match summary.as_ref().and_then(|s| extend_obs(s, args, obs)) {
+                Some(extended) => num_obs = extended,
+                None => break,
+            }
+        }
+        let _span = debug_span!("obs", index = obs).entered();
+        if let Some(initial) = &initial {
+            agents.clone_from_slice(initial);
+        }
+        if let Some(size) = args.stratify {
+            let batch = obs / size;
+            let points = match strata {
+                Some((index, ref points)) if index == batch => points,
+                _ => {
+                    // every batch has its own stream, so a resumed batch draws the same strata
+                    let mut batch_rng = match args.seed {
+                        Some(seed) => StdRng::seed_from_u64(derive_seed(
+                            seed,
+                            spec.index,
+                            u64::MAX - 2 - batch,
+                        )),
+                        None => StdRng::from_entropy(),
+                    };
+                    let points =
+                        sampling::latin_hypercube(size as usize, agents.len(), &mut batch_rng);
+                    &strata.insert((batch, points)).1
+                }
+            };
+            for (agent, &quantile) in agents.iter_mut().zip(&points[(obs % size) as usize]) {
+                agent.stratify(quantile);
+            }
+        }
+        if let Some(point) = sobol.as_mut().and_then(Iterator::next) {
+            for (agent, &quantile) in agents.iter_mut().zip(&point) {
+                agent.stratify(quantile);
+            }
+        }
+        let seed = match args.seed {
+            Some(seed) => derive_seed(seed, spec.index, obs),
+            None => rng.gen(),
+        };
+        let mut counting = profile::Counting::new(StdRng::seed_from_u64(seed));
+        let start = Instant::now();
+        let mut features = run_sim(agents, market, &mut counting);
+        if args.profile {
+            features.meta = Some(Meta {
+                sim_micros: start.elapsed().as_micros() as u64,
+                rng_draws: counting.draws,
+                events: market.events(),
+            });
+        }
+        derive(&mut features, spec.derived)?;
+        if spec.persistent == Some(true) {
+            surpluses.push(features.surplus);
+            if obs + 1 == num_obs {
+                features.learning = Some(Learning::new(&surpluses, obs_range.start, num_obs));
+            }
+        }
+        debug!(?features, seed, "observed");
+        if args.paranoid {
+            let violations = invariants::check(agents, &features);
+            for violation in &violations {
+                error!(?violation, seed, "violated invariant");
+            }
+            if let Some(first) = violations.first() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "observation {} of spec {} violated {} invariants, first: {}",
+                        obs,
+                        spec.index,
+                        violations.len(),
+                        first
+                    ),
+                ));
+            }
+        }
+        monitor::observe(agents, &features)?;
+        if let Some(check) = &mut check {
+            check.add(features.ce_surplus, features.ce_price);
+        }
+        features.select(agents, &args.extra_features);
+        if let Some(summary) = &mut summary {
+            summary.add(&features, agents);
+            continue;
+        }
+        if args.egta_format {
+            let observation = egta::Observation::new(agents, &features, args.aggregate);
+            serde_json::to_writer(&mut out, &observation)?;
+        } else {
+            let policies: Vec<_> = agents.iter().map(Agent::policy).collect();
+            let learned = obs + 1 == num_obs && policies.iter().any(Option::is_some);
+            let observation = Observation {
+                spec_index: tag_index.then_some(spec.index),
+                obs_index: tag_index.then_some(obs),
+                seed: args.tag_output.then_some(seed),
+                spec_hash: args.tag_output.then_some(spec.hash),
+                stratification: Stratification::new(args).filter(|_| args.tag_output),
+                players: agents,
+                assignment: args.assignment.then(|| Assignment::realized(agents)),
+                features,
+                policies: learned.then_some(policies),
+                spec: echo(Echo::Observation),
+            };
+            if args.fields.is_empty() {
+                serde_json::to_writer(&mut out, &observation)?;
+            } else {
+                let value = serde_json::to_value(&observation)?;
+                serde_json::to_writer(&mut out, &fields::select(&value, &args.fields))?;
+            }
+        }
+        writeln!(&mut out)?;
+        if let Some(path) = &args.checkpoint {
+            // output must be durable before it's recorded as done
+            out.flush()?;
+            let progress = Progress {
+                spec: spec.index,
+                obs: obs + 1,
+            };
+            checkpoint::save(path, progress)?;
+        } else if args.flush {
+            out.flush()?
+        }
+    }
+
+    if let Some(check) = check {
+        check.report();
+    }
+
+    if let Some(summary) = summary {
+        let line = SummaryLine {
+            spec_index: tag_index.then_some(spec.index),
+            spec_hash: args.tag_output.then(|| spec.hash.to_owned()),
+            learning: (spec.persistent == Some(true))
+                .then(|| Learning::new(&surpluses, obs_range.start, num_obs)),
+            weight: spec.weight,
+            exact: None,
+            stratification: Stratification::new(args),
+            controlled_surplus: spec
+                .control
+                .and_then(|control| summary.controlled("surplus", "ce_surplus", control)),
+            report: summary.report(args.bootstrap, &mut rng),
+            spec: echo(Echo::Observation),
+        };
+        serde_json::to_writer(&mut out, &line)?;
+        writeln!(&mut out)?;
+        if let Some(path) = &args.checkpoint {
+            out.flush()?;
+            let progress = Progress {
+                spec: spec.index + 1,
+                obs: 0,
+            };
+            checkpoint::save(path, progress)?;
+        } else if args.flush {
+            out.flush()?
+        }
+    }
+    Ok(())
+}
+
+/// How many observations a summary needs for its largest standard error to reach --target-se,
+/// projected from how far it is, or none if it's reached or at --max-obs
+fn extend_obs(summary: &Summary, args: &Args, obs: u64) -> Option<u64> {
+    let target = args.target_se?;
+    let stderr = summary.max_stderr(args.target_feature.as_deref());
+    if stderr.is_some_and(|stderr| stderr <= target) {
+        return None;
+    } else if obs >= args.max_obs {
+        warn!(?stderr, target, obs, "didn't reach target standard error");
+        return None;
+    }
+    let projected = match stderr {
+        // standard errors shrink with the square root of the observations
+        Some(stderr) => (obs as f64 * (stderr / target).powi(2)).ceil() as u64,
+        None => obs * 2,
+    };
+    Some(projected.clamp(obs + 1, args.max_obs))
+}
+
+/// Give every trader a diminishing value schedule from the values drawn by its two sides
+///
+/// The unit a trader is endowed with is worth the higher value, so its seller only sells above it,
+/// while a second unit is only worth the lower value, which its buyer bids for.
+fn schedule_traders(agents: &mut [Agent<'_>]) {
+    for index in 0..agents.len() {
+        if agents[index].trader && !agents[index].buyer {
+            let (sell, buy) = (agents[index].value, agents[index + 1].value);
+            if sell < buy {
+                agents[index].value = buy;
+                agents[index].bid = -buy;
+                agents[index + 1].value = sell;
+                agents[index + 1].bid = sell;
+            }
+        }
+    }
+}
+
+/// Sort the values drawn by every multi-unit player's units into a diminishing value schedule
+///
+/// A buyer's first unit is worth the most, and a seller's first unit costs the least.
+fn schedule_units(agents: &mut [Agent<'_>]) {
+    let mut index = 0;
+    while index < agents.len() {
+        let lot = agents[index].lot(index);
+        index = lot.end;
+        if lot.len() > 1 {
+            let lot = &mut agents[lot];
+            let sign = lot[0].sign();
+            let mut values: Vec<_> = lot.iter().map(|a| sign * a.value).collect();
+            values.sort_unstable_by(|a, b| b.total_cmp(a));
+            for (agent, value) in lot.iter_mut().zip(values) {
+                agent.value = sign * value;
+                agent.bid = value;
+            }
+        }
+    }
+}
+
+/// Run one simulation of a market, returning its features
+pub fn run_sim(agents: &mut [Agent<'_>], market: &impl Market, rng: &mut impl Rng) -> Features {
+    if agents.iter().any(|a| a.presence().is_some()) {
+        run_sampled(agents, market, rng)
+    } else {
+        run_present(agents, market, rng)
+    }
+}
+
+/// Run one simulation with only the fractional players drawn to take part
+///
+/// Only fractional players draw whether they take part, before anything else, and the players
+/// that do are simulated as their own market.
+fn run_sampled(agents: &mut [Agent<'_>], market: &impl Market, rng: &mut impl Rng) -> Features {
+    let mut present = true;
+    for agent in agents.iter_mut() {
+        // both sides of a trader and every unit of a lot are one player
+        if agent.leads() && !(agent.trader && agent.buyer) {
+            present = agent.presence().is_none_or(|prob| rng.gen::<f64>() < prob);
+        }
+        agent.attend(present);
+    }
+    let indices: Vec<_> = (0..agents.len()).filter(|&i| agents[i].present()).collect();
+    let mut sampled: Vec<_> = indices.iter().map(|&i| agents[i].clone()).collect();
+    let features = run_present(&mut sampled, market, rng);
+    for (index, agent) in indices.into_iter().zip(sampled) {
+        agents[index] = agent;
+    }
+    features
+}
+
+/// Run one simulation of every agent
+fn run_present(agents: &mut [Agent<'_>], market: &impl Market, rng: &mut impl Rng) -> Features {
+    profile::time(Phase::Resample, || {
+        if !market.draw(agents, rng) {
+            draw_values(agents, rng)
+        }
+    });
+    run_drawn(agents, market, rng)
+}
+
+/// Resample every agent's values and the signals it observes of them
+fn draw_values(agents: &mut [Agent<'_>], rng: &mut impl Rng) {
+    agents.iter_mut().for_each(|a| a.resample(rng));
+    values::deal(agents, rng);
+    schedule_traders(agents);
+    schedule_units(agents);
+    agents.iter_mut().for_each(|a| a.observe(rng));
+}
+
+/// Run one simulation of agents whose values are already drawn
+fn run_drawn(agents: &mut [Agent<'_>], market: &impl Market, rng: &mut impl Rng) -> Features {
+    // agents with entry costs expect to trade at the equilibrium price of the true values
+    if agents.iter().any(Agent::chooses_entry) {
+        let price = profile::time(Phase::Equilibrium, || equilibrium::compute(agents)).price;
+        agents.iter_mut().for_each(|a| a.enter(price));
+    }
+
+    // set shading and trade
+    profile::time(Phase::Market, || {
+        agents.iter_mut().for_each(Agent::shade);
+        market.simulate(agents, rng);
+        agents.iter_mut().for_each(Agent::settle);
+    });
+
+    // compute max social welfare, after trading so it includes any shocks to values
+    let equi = profile::time(Phase::Equilibrium, || equilibrium::compute(agents));
+    let ce_price = equi.price;
+    let ce_surplus = equi.surplus;
+    profile::time(Phase::Learn, || agents.iter_mut().for_each(Agent::learn));
+    for (index, agent) in agents.iter().enumerate().filter(|(_, a)| a.rejected) {
+        warn!(
+            index,
+            strategy = agent.strategy(),
+            value = agent.value,
+            "rejected non-finite bid"
+        );
+    }
+
+    // compute features
+    let surplus = agents.iter().fold(0.0, |sum, a| sum + a.utility);
+    let mut im_surplus = 0.0;
+    let mut em_surplus = 0.0;
+    match ce_price {
+        Some(price) => {
+            for agent in agents.iter() {
+                if agent.traded && !agent.ce_traded {
+                    em_surplus += agent.sign() * (price - agent.net_value())
+                } else if !agent.traded && agent.ce_traded {
+                    im_surplus += agent.sign() * (agent.net_value() - price)
+                }
+            }
+        }
+        None => em_surplus = ce_surplus - surplus,
+    };
+    // a market missing a side can't trade, so how its surplus was lost is meaningless
+    let buyers = agents.iter().filter(|a| a.buyer).count();
+    let sellers = agents.len() - buyers;
+    let two_sided = buyers > 0 && sellers > 0;
+    let traded = agents.iter().any(|a| a.traded);
+    let (total, trades) = agents
+        .iter()
+        .filter(|a| a.traded)
+        .filter_map(|a| a.price)
+        .fold((0.0, 0), |(total, trades), price| {
+            (total + price, trades + 1)
+        });
+    let price_surplus =
+        (trades > 0).then(|| equilibrium::surplus_at(agents, total / trades as f64));
+
+    Features {
+        surplus,
+        ce_surplus,
+        im_surplus: two_sided.then_some(im_surplus),
+        em_surplus: two_sided.then_some(em_surplus),
+        ce_price,
+        efficiency: (ce_surplus > 0.0).then(|| surplus / ce_surplus),
+        imbalance: (sellers > 0).then(|| buyers as f64 / sellers as f64),
+        no_trade: Some(if traded { 0.0 } else { 1.0 }),
+        trade_surplus: traded.then_some(surplus),
+        price_surplus,
+        clipped: Some(agents.iter().filter(|a| a.clipped).count()),
+        rejected: Some(agents.iter().filter(|a| a.rejected).count()),
+        blocked: Some(agents.iter().filter(|a| a.blocked).count()),
+        losses: Some(agents.iter().filter(|a| a.utility < 0.0).count()),
+        participation: (!agents.is_empty())
+            .then(|| agents.iter().filter(|a| !a.absent).count() as f64 / agents.len() as f64),
+        payoffs: Quantiles::by_role(agents),
+        aggressiveness: Aggressiveness::by_strategy(agents, ce_price),
+        maker: market.maker(),
+        shocks: market.shocks(),
+        session: market.session(),
+        latency: LatencyReport::new(agents),
+        book: market.book(),
+        arrivals: market.arrivals(),
+        closing: market.closing(),
+        stopped: market.stopped(),
+        spread: market.spread(),
+        stops: market.stops(),
+        meta: None,
+        learning: None,
+        derived: BTreeMap::new(),
+    }
+}
+
+/// Mean change in the payoff of agent `index` if it were replaced by `deviation`
+///
+/// Each sample simulates the original and deviating profiles from the same agent state with the
+/// same random seed, so the difference only reflects the deviation.
+fn deviation_gain<'a>(
+    agents: &mut [Agent<'a>],
+    index: usize,
+    deviation: &Agent<'a>,
+    market: &impl Market,
+    samples: u64,
+    rng: &mut impl Rng,
+) -> f64 {
+    let original = agents.to_vec();
+    let mut gain = 0.0;
+    for _ in 0..samples {
+        let seed = rng.gen();
+        agents.clone_from_slice(&original);
+        run_sim(agents, market, &mut StdRng::seed_from_u64(seed));
+        let base = agents[index].utility;
+        agents.clone_from_slice(&original);
+        agents[index] = deviation.clone();
+        run_sim(agents, market, &mut StdRng::seed_from_u64(seed));
+        gain += (agents[index].utility - base) / samples as f64;
+    }
+    agents.clone_from_slice(&original);
+    gain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Agent, Args, Cda, Style};
+    use crate::strategy::Shading;
+    use clap::{CommandFactory, Parser};
+    use rand::distributions::{Distribution, Uniform};
+    use rand::seq::SliceRandom;
+    use std::collections::{BTreeSet, HashMap};
+    use std::io;
+
+    /// Simulate spec lines with the command line arguments after the program name, returning
+    /// every line of output
+    fn simulate(args: &[&str], specs: &str) -> io::Result<Vec<serde_json::Value>> {
+        let args = Args::parse_from(["cdasim"].iter().chain(args));
+        let mut out = Vec::new();
+        super::simulate_specs(&args, &HashMap::new(), false, specs.as_bytes(), &mut out)?;
+        let lines = serde_json::Deserializer::from_slice(&out).into_iter();
+        Ok(lines.collect::<Result<_, _>>()?)
+    }
+
+    #[test]
+    fn test_features() {
+        let styles = [
+            Style::Standard,
+            Style::Exponential,
+            Style::Shift,
+            Style::Correct,
+            Style::Roth,
+            Style::Bandit,
+        ];
+        let mut rng = rand::thread_rng();
+        let num_dist = Uniform::from(5..10);
+        let shade_dist = Uniform::from(0.0..=1.0);
+        for _ in 0..100 {
+            let mut agents: Vec<Agent> = Vec::new();
+            let num = num_dist.sample(&mut rng);
+            for buyer in [false, true] {
+                for _ in 0..num {
+                    agents.push(Agent::new(
+                        buyer,
+                        "",
+                        *styles.choose(&mut rng).unwrap(),
+                        Shading::Fixed(shade_dist.sample(&mut rng)),
+                    ));
+                }
+            }
+
+            for _ in 0..100 {
+                let features = super::run_sim(&mut agents, &Cda, &mut rng);
+                let ce_surplus_other = features.surplus
+                    + features.im_surplus.unwrap_or(0.0)
+                    + features.em_surplus.unwrap_or(0.0);
+                assert!((features.ce_surplus - ce_surplus_other).abs() < 1e-6);
+                let buyers = features.payoffs["buyers"];
+                assert!(buyers.p10 <= buyers.p50 && buyers.p50 <= buyers.p90);
+            }
+        }
+    }
+
+    #[test]
+    fn test_aggressiveness() {
+        let mut rng = rand::thread_rng();
+        let mut agents = Vec::new();
+        for buyer in [true, false] {
+            agents.push(Agent::new(
+                buyer,
+                "truth",
+                Style::Standard,
+                Shading::Fixed(0.0),
+            ));
+            agents.push(Agent::new(
+                buyer,
+                "shade",
+                Style::Standard,
+                Shading::Fixed(0.2),
+            ));
+        }
+        let features = super::run_sim(&mut agents, &Cda, &mut rng);
+        for agent in &agents {
+            let aggressiveness = features.aggressiveness[agent.role()][agent.strategy()];
+            match features.ce_price {
+                Some(price) => {
+                    let from_ce = agent.sign() * (agent.value - price) - aggressiveness.from_value;
+                    assert!((aggressiveness.from_ce.unwrap() - from_ce).abs() < 1e-9);
+                }
+                None => assert_eq!(aggressiveness.from_ce, None),
+            }
+        }
+        let buyers = &features.aggressiveness["buyers"];
+        assert!(buyers["truth"].from_value.abs() < 1e-9);
+        assert!(buyers["shade"].from_value > 0.0);
+
+        // nothing submitted by an empty market
+        let features = super::run_sim(&mut [], &Cda, &mut rng);
+        assert!(features.aggressiveness.is_empty());
+    }
+
+    #[test]
+    fn test_thin_market() {
+        let mut rng = rand::thread_rng();
+        let mut agents = vec![Agent::new(true, "", Style::Standard, Shading::Fixed(0.0)); 3];
+        let features = super::run_sim(&mut agents, &Cda, &mut rng);
+        assert_eq!(features.imbalance, None);
+        assert_eq!(features.im_surplus, None);
+        assert_eq!(features.no_trade, Some(1.0));
+        assert_eq!(features.trade_surplus, None);
+
+        agents.push(Agent::new(false, "", Style::Standard, Shading::Fixed(0.0)));
+        let features = super::run_sim(&mut agents, &super::Call, &mut rng);
+        assert_eq!(features.imbalance, Some(3.0));
+        assert!(features.im_surplus.is_some());
+        assert_eq!(
+            features.no_trade == Some(0.0),
+            features.trade_surplus.is_some()
+        );
+    }
+
+    #[test]
+    fn test_extra_features() {
+        let features = |args: &[&str], config: &str| {
+            let spec = format!(
+                r#"{{"assignment":{{"buyers":{{"0":2}},"sellers":{{"0":2}}}},"configuration":{}}}"#,
+                config
+            );
+            let obs = &simulate(&[&["--seed", "1"], args].concat(), &spec).unwrap()[0];
+            let names: Vec<_> = obs["features"]
+                .as_object()
+                .unwrap()
+                .keys()
+                .cloned()
+                .collect();
+            names
+        };
+        // without options, observations have the same features as ever
+        let baseline = [
+            "ce_price",
+            "ce_surplus",
+            "em_surplus",
+            "im_surplus",
+            "surplus",
+        ];
+        assert_eq!(features(&[], "{}"), baseline);
+        let extra = features(&["--extra-features", "thin,quantiles"], "{}");
+        assert!(["no_trade", "imbalance", "payoffs"]
+            .iter()
+            .all(|name| extra.iter().any(|e| e == name)));
+        assert!(!extra.iter().any(|e| e == "price_surplus"));
+        let costly = features(&[], r#"{"cost":0.01,"floor":0.2}"#);
+        assert!(costly.iter().any(|e| e == "efficiency"));
+        assert!(costly.iter().any(|e| e == "blocked"));
+        assert!(!costly.iter().any(|e| e == "clipped"));
+    }
+
+    #[test]
+    fn test_cli() {
+        Args::command().debug_assert()
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let path = std::env::temp_dir().join(format!("cdasim-checkpoint-{}", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        crate::checkpoint::save(&path, crate::Progress { spec: 1, obs: 1 }).unwrap();
+        let args = Args::parse_from(["cdasim", "--obs", "2", "--checkpoint", path_str]);
+        let spec = r#"{"assignment":{"buyers":{"0.2":2},"sellers":{"0.3":2}},"configuration":{}}"#;
+        let input = format!("{}\n{}\n{}\n", spec, spec, spec);
+        let mut out = Vec::new();
+        super::simulate_specs(&args, &HashMap::new(), false, input.as_bytes(), &mut out).unwrap();
+        let tags: Vec<_> = serde_json::Deserializer::from_slice(&out)
+            .into_iter::<serde_json::Value>()
+            .map(|obs| {
+                let obs = obs.unwrap();
+                (obs["spec_index"].as_u64(), obs["obs_index"].as_u64())
+            })
+            .collect();
+        assert_eq!(
+            tags,
+            [(Some(1), Some(1)), (Some(2), Some(0)), (Some(2), Some(1))]
+        );
+        assert_eq!(
+            crate::checkpoint::load(&path).unwrap(),
+            crate::Progress { spec: 2, obs: 2 }
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_repeated_specs() {
+        let args = Args::parse_from(["cdasim", "--tag-output", "--obs", "2"]);
+        let learn =
+            r#"{"assignment":{"buyers":{"1_Roth":2},"sellers":{"0.3":2}},"configuration":{}}"#;
+        let other = r#"{"assignment":{"buyers":{"0.2":1},"sellers":{"0.3":1}},"configuration":{}}"#;
+        let input = format!("{}\n{}\n{}\n{}\n", learn, learn, other, learn);
+        let mut out = Vec::new();
+        super::simulate_specs(&args, &HashMap::new(), false, input.as_bytes(), &mut out).unwrap();
+        let obs: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&out)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(obs.len(), 8);
+        for (ind, obs) in obs.iter().enumerate() {
+            assert_eq!(obs["spec_index"], ind / 2);
+            assert_eq!(obs["obs_index"], ind % 2);
+            // only the final observation of each learning spec has policies
+            assert_eq!(obs["policies"].is_array(), ind % 2 == 1 && ind / 2 != 2);
+        }
+    }
+
+    #[test]
+    fn test_seed_jobs() {
+        let learn =
+            r#"{"assignment":{"buyers":{"1_Roth":2},"sellers":{"0.3":2}},"configuration":{}}"#;
+        let other = r#"{"assignment":{"buyers":{"0.2":3},"sellers":{"0.3":2}},"configuration":{}}"#;
+        let input = format!("{}\n{}\n{}\n{}\n{}\n", learn, other, other, learn, other);
+        let run = |jobs: &str, seed: &str| {
+            let args = ["--tag-output", "--obs", "3", "--jobs", jobs, "--seed", seed];
+            simulate(&args, &input).unwrap()
+        };
+        let serial = run("1", "7");
+        assert_eq!(serial.len(), 15);
+        assert_eq!(run("3", "7"), serial);
+        assert_ne!(run("3", "8"), serial);
+        assert_ne!(super::derive_seed(7, 1, 0), super::derive_seed(7, 2, 0));
+        assert!(Args::try_parse_from(["cdasim", "--jobs", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_pipelined() {
+        // enough output to fill several chunks, compared to writing as it's simulated
+        let input = r#"{"assignment":{"buyers":{"0":4},"sellers":{"0":4}},"configuration":{}}"#;
+        let path = std::env::temp_dir().join(format!("cdasim-pipe-{}", std::process::id()));
+        let simulate = |extra: &[&str]| {
+            let mut argv = vec!["cdasim", "--tag-output", "--obs", "500", "--seed", "3"];
+            argv.extend(extra);
+            let args = Args::parse_from(argv);
+            let input = [input; 3].join("\n") + "\n{}";
+            let mut out = Vec::new();
+            let res =
+                super::simulate_specs(&args, &HashMap::new(), false, input.as_bytes(), &mut out);
+            (res.is_ok(), out)
+        };
+        let (ok, direct) = simulate(&["--checkpoint", path.to_str().unwrap()]);
+        std::fs::remove_file(&path).unwrap();
+        assert!(!ok);
+        assert!(direct.len() > 2 * super::CHUNK_BYTES);
+        for jobs in ["1", "2"] {
+            let (ok, piped) = simulate(&["--jobs", jobs]);
+            assert!(!ok);
+            assert_eq!(piped, direct);
+        }
+    }
+
+    #[test]
+    fn test_derived() {
+        let derive = |derived: &str| {
+            let spec = format!(
+                r#"{{"assignment":{{"buyers":{{"0":3}},"sellers":{{"0":3}}}},"configuration":{{"derived":{}}}}}"#,
+                derived
+            );
+            simulate(&["--obs", "3"], &spec)
+        };
+        let lines = derive(r#"{"eff":"surplus / ce_surplus","lost":"1 - (surplus / ce_surplus)"}"#)
+            .unwrap();
+        for line in &lines {
+            let features = &line["features"];
+            let ratio = |name: &str| features[name].as_f64().unwrap();
+            if let Some(eff) = features["eff"].as_f64() {
+                assert!((eff - ratio("surplus") / ratio("ce_surplus")).abs() < 1e-9);
+                let lost = features["lost"].as_f64().unwrap();
+                assert!((eff + lost - 1.0).abs() < 1e-9);
+            }
+        }
+        assert!(derive(r#"{"x":"surplus +"}"#).is_err());
+        assert!(derive(r#"{"x":"missing * 2"}"#).is_err());
+        assert!(derive(r#"{"surplus":"ce_surplus"}"#).is_err());
+    }
+
+    #[test]
+    fn test_arrivals() {
+        let observe = |config: &str| {
+            let spec = format!(
+                r#"{{"assignment":{{"buyers":{{"0.2":3}},"sellers":{{"0.1":2}},"traders":{{"0":1}}}},"configuration":{}}}"#,
+                config
+            );
+            simulate(&["--seed", "5", "--arrivals"], &spec).map(|mut lines| lines.remove(0))
+        };
+        let random = observe("{}").unwrap();
+        let arrivals = &random["features"]["arrivals"];
+        assert_eq!(arrivals.as_array().unwrap().len(), 7);
+        let fixed = observe(&format!(r#"{{"arrival_order":{}}}"#, arrivals)).unwrap();
+        assert_eq!(fixed, random);
+
+        assert!(observe(r#"{"arrival_order":[0,1,2,3,4,5,5]}"#).is_err());
+        assert!(observe(r#"{"arrival_order":[0,1,2,3,4,5]}"#).is_err());
+        assert!(observe(r#"{"arrival_order":[0,1,2,3,4,5,6],"cda":false}"#).is_err());
+        let call = observe(r#"{"cda":false}"#).unwrap();
+        assert!(call["features"].get("arrivals").is_none());
+    }
+
+    #[test]
+    fn test_closing_call() {
+        let features = |config: &str| {
+            let spec = format!(
+                r#"{{"assignment":{{"buyers":{{"0.2":4}},"sellers":{{"0.2":4}}}},"configuration":{}}}"#,
+                config
+            );
+            simulate(&["--seed", "3"], &spec).map(|lines| lines[0]["features"].clone())
+        };
+        let closing = &features(r#"{"closing_call":{"at":0.5}}"#).unwrap()["closing"];
+        let (continuous, call) = (
+            closing["continuous"].as_u64().unwrap(),
+            closing["closing"].as_u64().unwrap(),
+        );
+        assert!(continuous + call <= 4);
+        assert_eq!(closing["price"].is_null(), call == 0);
+        assert!(features("{}").unwrap().get("closing").is_none());
+
+        assert!(features(r#"{"closing_call":{"at":0}}"#).is_err());
+        assert!(features(r#"{"closing_call":{"at":1.5}}"#).is_err());
+        assert!(features(r#"{"closing_call":{"at":0.5},"cda":false}"#).is_err());
+    }
+
+    #[test]
+    fn test_stopping() {
+        let observe = |config: &str| {
+            let spec = format!(
+                r#"{{"assignment":{{"buyers":{{"1":5}},"sellers":{{"0":5}}}},"configuration":{}}}"#,
+                config
+            );
+            simulate(&["--seed", "4", "--obs", "20"], &spec)
+        };
+        for obs in observe(r#"{"stopping":{"probability":0.2}}"#).unwrap() {
+            let stopped = obs["features"]["stopped"].as_u64().unwrap();
+            assert!((1..=10).contains(&stopped));
+        }
+        assert!(observe("{}").unwrap()[0]["features"]
+            .get("stopped")
+            .is_none());
+
+        assert!(observe(r#"{"stopping":{"probability":0}}"#).is_err());
+        assert!(observe(r#"{"stopping":{"probability":1.5}}"#).is_err());
+        assert!(observe(r#"{"stopping":{"probability":0.5},"cda":false}"#).is_err());
+    }
+
+    #[test]
+    fn test_matching() {
+        let matched = |config: &str| {
+            let spec = format!(
+                r#"{{"assignment":{{"buyers":{{"1":3}},"sellers":{{"0":3}}}},"configuration":{}}}"#,
+                config
+            );
+            simulate(&["--seed", "2", "--obs", "5"], &spec)
+        };
+        // price time priority is the default
+        let default = matched("{}").unwrap();
+        assert_eq!(matched(r#"{"matching":"price_time"}"#).unwrap(), default);
+        assert_eq!(matched(r#"{"matching":"size"}"#).unwrap(), default);
+        assert!(matched(r#"{"matching":"pro_rata"}"#).is_ok());
+        assert!(matched(r#"{"matching":"random"}"#).is_err());
+        assert!(matched(r#"{"matching":"pro_rata","cda":false}"#).is_err());
+    }
+
+    #[test]
+    fn test_units() {
+        let observe = |units: &str| {
+            let spec = format!(
+                r#"{{"assignment":{{"buyers":{{"0":2}},"sellers":{{"0":4}}}},"configuration":{{"units":{}}}}}"#,
+                units
+            );
+            simulate(&["--assignment", "--obs", "10"], &spec)
+        };
+        for obs in observe(r#"{"buyers":3}"#).unwrap() {
+            let players = obs["players"].as_array().unwrap();
+            assert_eq!(players.len(), 6);
+            for buyer in &players[..2] {
+                let prices = buyer["prices"].as_array().unwrap();
+                assert_eq!(buyer["filled"], prices.len());
+                assert!(prices.len() <= 3);
+            }
+            assert!(players[2].get("filled").is_none());
+            let total: u64 = players
+                .iter()
+                .map(|p| p["filled"].as_u64().unwrap_or(0))
+                .sum();
+            assert!(total <= 4);
+
+            // values form a diminishing schedule
+            let draws = obs["assignment"]["draws"].as_array().unwrap();
+            let mut last = draws[0]["value"].as_f64().unwrap();
+            for value in draws[0]["schedule"].as_array().unwrap() {
+                assert!(value.as_f64().unwrap() <= last);
+                last = value.as_f64().unwrap();
+            }
+            assert_eq!(obs["assignment"]["counts"]["buyers"]["0"], 2);
+        }
+
+        assert!(observe(r#"{"buyers":0}"#).is_err());
+        assert!(observe(r#"{"traders":2}"#).is_err());
+    }
+
+    #[test]
+    fn test_iceberg() {
+        let features = |sellers: &str, cda: bool| {
+            let spec = format!(
+                r#"{{"assignment":{{"buyers":{{"0":6}},"sellers":{{"{}":2}}}},"configuration":{{"units":{{"sellers":3}},"cda":{}}}}}"#,
+                sellers, cda
+            );
+            simulate(&["--seed", "6"], &spec).unwrap()[0]["features"].clone()
+        };
+        let spread = &features("0_i1", true)["spread"];
+        assert!(spread["displayed"].is_number());
+        assert!(spread["total"].is_number());
+        assert!(features("0", true).get("spread").is_none());
+        assert!(features("0_i1", false).get("spread").is_none());
+    }
+
+    #[test]
+    fn test_stops() {
+        let features = |assignment: &str| {
+            let spec = format!(r#"{{"assignment":{},"configuration":{{}}}}"#, assignment);
+            simulate(&["--seed", "1"], &spec).map(|lines| lines[0]["features"].clone())
+        };
+        let stopped =
+            features(r#"{"buyers":{"0.2":4},"sellers":{"0.2":4},"traders":{"0_s0.5":4}}"#).unwrap();
+        assert!(stopped["stops"].as_u64().unwrap() <= 4);
+        let plain = features(r#"{"buyers":{"0.2":4},"sellers":{"0.2":4}}"#).unwrap();
+        assert!(plain.get("stops").is_none());
+        assert!(features(r#"{"buyers":{"0_s0.5":4},"sellers":{"0.2":4}}"#).is_err());
+    }
+
+    #[test]
+    fn test_persistent() {
+        let run = |persistent: &str, args: &[&str]| {
+            let spec = format!(
+                r#"{{"assignment":{{"buyers":{{"1_Roth":3}},"sellers":{{"0.5_Bandit":3}}}},"configuration":{{"persistent":{}}}}}"#,
+                persistent
+            );
+            let common = ["--obs", "5", "--seed", "3", "--tag-output"];
+            simulate(&[&common, args].concat(), &spec).unwrap()
+        };
+
+        let lines = run("true", &[]);
+        assert!(lines[..4]
+            .iter()
+            .all(|l| l["features"].get("learning").is_none()));
+        let surplus = |range: std::ops::Range<usize>| {
+            let total: f64 = lines[range.clone()]
+                .iter()
+                .map(|l| l["features"]["surplus"].as_f64().unwrap())
+                .sum();
+            total / range.len() as f64
+        };
+        let learning = &lines[4]["features"]["learning"];
+        assert!((learning["first_half"].as_f64().unwrap() - surplus(0..2)).abs() < 1e-9);
+        assert!((learning["second_half"].as_f64().unwrap() - surplus(2..5)).abs() < 1e-9);
+        let summary = run("true", &["--summary"]);
+        assert_eq!(&summary[0]["learning"], learning);
+        let summary = run("null", &["--summary"]);
+        assert!(summary[0].get("learning").is_none());
+    }
+
+    #[test]
+    fn test_profile() {
+        let cda = r#"{"assignment":{"buyers":{"0.2":3},"sellers":{"0.1":3}},"configuration":{}}"#;
+        let call = cda.replace("{}}", r#"{"cda":false}}"#);
+        let input = format!("{}\n{}\n", cda, call);
+        let plain = simulate(&["--seed", "4"], &input).unwrap();
+        let mut profiled = simulate(&["--seed", "4", "--profile"], &input).unwrap();
+        for line in &mut profiled {
+            let features = line["features"].as_object_mut().unwrap();
+            assert!(features.remove("sim_micros").unwrap().is_u64());
+            // every agent draws at least its value
+            assert!(features.remove("rng_draws").unwrap().as_u64().unwrap() >= 6);
+        }
+        // a cda has an arrival and a quote for every agent, and the close
+        let events = profiled[0]["features"]
+            .as_object_mut()
+            .unwrap()
+            .remove("events");
+        assert_eq!(events, Some(serde_json::json!(13)));
+        assert!(profiled[1]["features"].get("events").is_none());
+        assert_eq!(profiled, plain);
+    }
+
+    #[test]
+    fn test_summary() {
+        let args = Args::parse_from(["cdasim", "--obs", "5", "--summary", "--bootstrap", "10"]);
+        let spec = r#"{"assignment":{"buyers":{"0.2":2},"sellers":{"0.3":2}},"configuration":{}}"#;
+        let input = format!("{}\n{}\n", spec, spec);
+        let mut out = Vec::new();
+        super::simulate_specs(&args, &HashMap::new(), false, input.as_bytes(), &mut out).unwrap();
+        let lines: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&out)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["observations"], 5);
+        assert!(lines[0]["features"]["surplus"]["interval"].is_array());
+    }
+
+    #[cfg(feature = "gpu")]
+    #[test]
+    fn test_gpu() {
+        let call =
+            r#"{"assignment":{"buyers":{"0":1},"sellers":{"0":1}},"configuration":{"cda":false}}"#;
+        let cda = r#"{"assignment":{"buyers":{"0":1},"sellers":{"0":1}},"configuration":{}}"#;
+        let specs = format!("{}\n{}\n", call, cda);
+        let args = ["--obs", "20000", "--gpu", "--summary", "--seed", "1"];
+        let lines = simulate(&args, &specs).unwrap();
+        assert_eq!(lines, simulate(&args, &specs).unwrap());
+        // truthful agents have 1/6 surplus whether batched or simulated
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            let surplus = line["features"]["surplus"]["mean"].as_f64().unwrap();
+            assert!((surplus - 1.0 / 6.0).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_tag_output() {
+        let args = Args::parse_from(["cdasim", "--tag-output"]);
+        let spec = r#"{"assignment":{"buyers":{"0.2":2},"sellers":{"0.3":2}},"configuration":{}}"#;
+        let mut out = Vec::new();
+        super::simulate_specs(&args, &HashMap::new(), false, spec.as_bytes(), &mut out).unwrap();
+        let obs: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(obs["spec_index"], 0);
+        assert_eq!(obs["obs_index"], 0);
+        assert!(obs["seed"].is_u64());
+        assert_eq!(obs["spec_hash"], super::spec_hash(spec));
+        assert_eq!(super::spec_hash(""), "cbf29ce484222325");
+    }
+
+    #[test]
+    fn test_echo() {
+        let spec = r#"{"assignment":{"buyers":{"0.2":1,"0.2_Standard":1},"sellers":{"0.3":2}},"configuration":{"cda":false,"seller_style":"Shift"}}"#;
+        let args = Args::parse_from(["cdasim", "--obs", "2", "--echo", "header"]);
+        let mut out = Vec::new();
+        super::simulate_specs(&args, &HashMap::new(), false, spec.as_bytes(), &mut out).unwrap();
+        let lines: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&out)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(lines.len(), 3);
+        let header = &lines[0];
+        assert_eq!(header["spec_index"], 0);
+        assert!(header.get("players").is_none());
+        let echo = &header["spec"];
+        assert_eq!(echo["assignment"]["buyers"]["0.2"], 2);
+        assert_eq!(echo["strategies"]["sellers"]["0.3"], "0.3_Shift");
+        assert_eq!(echo["market"], "call");
+        assert_eq!(echo["configuration"]["seller_style"], "Shift");
+        assert!(lines[1..].iter().all(|obs| obs.get("spec").is_none()));
+
+        let args = Args::parse_from(["cdasim", "--obs", "2", "--echo", "observation"]);
+        let mut out = Vec::new();
+        super::simulate_specs(&args, &HashMap::new(), false, spec.as_bytes(), &mut out).unwrap();
+        let lines: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&out)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|obs| obs["spec"] == *echo));
+    }
+
+    #[test]
+    fn test_control_variate() {
+        let spec = r#"{"assignment":{"buyers":{"0.2":3},"sellers":{"0.2":3}},"configuration":{}}"#;
+        let args = ["cdasim", "--obs", "50", "--summary", "--control-variate"];
+        let mut out = Vec::new();
+        let args = Args::parse_from(args);
+        super::simulate_specs(&args, &HashMap::new(), false, spec.as_bytes(), &mut out).unwrap();
+        let summary: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let controlled = &summary["controlled_surplus"];
+        let expected = super::expected::uniform(3, 3, 0.0, 1.0).surplus;
+        assert_eq!(controlled["control"]["mean"], expected);
+        assert!(controlled["control"].get("draws").is_none());
+        assert!(controlled["coefficient"].is_f64());
+        let stderr = |stat: &serde_json::Value| stat["stderr"].as_f64().unwrap();
+        assert!(stderr(controlled) < stderr(&summary["features"]["surplus"]));
+    }
+
+    #[test]
+    fn test_target_se() {
+        let spec = r#"{"assignment":{"buyers":{"0.2":3},"sellers":{"0.2":3}},"configuration":{}}"#;
+        let summarize = |args: &[&str]| {
+            let common = ["--obs", "4", "--summary", "--seed", "2"];
+            simulate(&[&common, args].concat(), spec).unwrap().remove(0)
+        };
+        let summary = summarize(&["--target-se", "0.05", "--target-feature", "surplus"]);
+        let observations = summary["observations"].as_u64().unwrap();
+        assert!(observations > 4);
+        assert!(summary["features"]["surplus"]["stderr"].as_f64().unwrap() <= 0.05);
+
+        let summary = summarize(&["--target-se", "0.0001", "--max-obs", "20"]);
+        assert_eq!(summary["observations"], 20);
+        let summary = summarize(&["--target-se", "1000"]);
+        assert_eq!(summary["observations"], 4);
+        assert!(Args::try_parse_from(["cdasim", "--summary", "--target-se", "-1"]).is_err());
+    }
+
+    #[test]
+    fn test_exact() {
+        let specs = concat!(
+            r#"{"assignment":{"buyers":{"0":1},"sellers":{"0":1}},"configuration":{"cda":false}}"#,
+            "\n",
+            r#"{"assignment":{"buyers":{"0":1},"sellers":{"0":1}},"configuration":{}}"#,
+        );
+        let args = Args::parse_from(["cdasim", "--obs", "3", "--exact", "--tag-output"]);
+        let mut out = Vec::new();
+        super::simulate_specs(&args, &HashMap::new(), false, specs.as_bytes(), &mut out).unwrap();
+        let lines: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&out)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        // the call market is integrated, and the cda is simulated
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["spec_index"], 0);
+        assert_eq!(lines[0]["exact"], 256);
+        assert_eq!(lines[0]["features"]["surplus"]["stderr"], 0.0);
+        assert!(lines[1..].iter().all(|obs| obs["spec_index"] == 1));
+        assert!(lines[1..].iter().all(|obs| obs.get("players").is_some()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_external_failure() {
+        let spec = r#"{"assignment":{"buyers":{"0_External":2},"sellers":{"0":2}},"configuration":{"external":["sh","-c","while read line; do echo garbage; done"]}}"#;
+        let lines = simulate(&["--obs", "3"], spec).unwrap();
+        // the external agents' orders are rejected rather than the run failing
+        assert_eq!(lines.len(), 3);
+        for line in lines {
+            assert_eq!(line["players"][0]["payoff"], 0.0);
+            assert_eq!(line["players"][1]["payoff"], 0.0);
+        }
+    }
+
+    #[test]
+    fn test_strict() {
+        let line = r#"{"assignment":{"buyers":{"0":1},"sellers":{"0":1}},"configuraion":{},"configuration":{"cdaa":true}}"#;
+        let spec: super::Spec = super::parse_line(line, false).unwrap();
+        assert!(spec.configuration.cda.is_none());
+        let err = super::parse_line::<super::Spec>(line, true).unwrap_err();
+        assert!(err.to_string().contains("configuraion"), "{}", err);
+        assert!(err.to_string().contains("configuration.cdaa"), "{}", err);
+    }
+
+    #[test]
+    fn test_role_defaults() {
+        let spec: super::Spec = serde_json::from_str(
+            r#"{"assignment":{"buyers":{"Shift":1,"0.1":1},"sellers":{"0.2":1}},"configuration":{"style":"Roth","buyer_style":"Correct","buyer_shading":"U(0,0.5)"}}"#,
+        )
+        .unwrap();
+        let presets = HashMap::new();
+        let shift = super::resolve_strategy(&spec, "buyers", "Shift", &presets).unwrap();
+        assert_eq!(shift.style, Some(Style::Shift));
+        assert_eq!(shift.shading, Some(Shading::Uniform(0.0, 0.5)));
+        let buyer = super::resolve_strategy(&spec, "buyers", "0.1", &presets).unwrap();
+        assert_eq!(buyer.style, Some(Style::Correct));
+        assert_eq!(buyer.shading, Some(Shading::Fixed(0.1)));
+        let seller = super::resolve_strategy(&spec, "sellers", "0.2", &presets).unwrap();
+        assert_eq!(seller.style, Some(Style::Roth));
+        assert!(super::resolve_strategy(&spec, "sellers", "Shift", &presets).is_err());
+        // Bne agents don't need a shading
+        assert!(super::resolve_strategy(&spec, "sellers", "Bne", &presets).is_ok());
+    }
+
+    #[test]
+    fn test_composite() {
+        let spec: super::Spec = serde_json::from_str(
+            r#"{"assignment":{"buyers":{"0.3_Shift_d0.5|0.1_Standard":5},"sellers":{"0.2|0.4|0.6":2}},"configuration":{}}"#,
+        )
+        .unwrap();
+        let agents = super::build_agents(&spec, &HashMap::new()).unwrap();
+        let latencies: Vec<_> = agents[..5].iter().map(|a| a.latency().is_some()).collect();
+        assert_eq!(latencies, [true, true, true, false, false]);
+        let shadings: Vec<_> = agents[5..].iter().map(Agent::shading).collect();
+        assert_eq!(shadings, [0.2, 0.4]);
+        assert!(agents[..5]
+            .iter()
+            .all(|a| a.strategy() == "0.3_Shift_d0.5|0.1_Standard"));
+
+        let spec: super::Spec = serde_json::from_str(
+            r#"{"assignment":{"buyers":{"0.3|":1},"sellers":{"0":1}},"configuration":{}}"#,
+        )
+        .unwrap();
+        assert!(super::build_agents(&spec, &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_canonical_strategies() {
+        let line = r#"{"assignment":{"buyers":{"0.50_Standard":2,"0.5":1,"0.30_Shift":1,"0.3_Shift":1.5,"0.3":1},"sellers":{"U(0.1, 0.4)":1,"0.1|0.20":2}},"configuration":{}}"#;
+        let spec = super::parse_spec(line, false, &HashMap::new()).unwrap();
+        let buyers: Vec<_> = spec.assignment.buyers.into_iter().collect();
+        assert_eq!(
+            buyers,
+            [
+                ("0.3".to_owned(), super::Count(1.0)),
+                ("0.3_Shift".to_owned(), super::Count(2.5)),
+                ("0.5".to_owned(), super::Count(3.0)),
+            ]
+        );
+        let sellers: Vec<_> = spec.assignment.sellers.into_keys().collect();
+        assert_eq!(sellers, ["0.1|0.2", "U(0.1,0.4)"]);
+
+        // equivalence depends on the spec's default style
+        let line = r#"{"assignment":{"buyers":{"0.3":1,"0.3_Shift":1}},"configuration":{"buyer_style":"Shift"}}"#;
+        let spec = super::parse_spec(line, false, &HashMap::new()).unwrap();
+        let buyers: Vec<_> = spec.assignment.buyers.into_iter().collect();
+        assert_eq!(buyers, [("0.3".to_owned(), super::Count(2.0))]);
+
+        // strategies that don't resolve are left as they are
+        let line = r#"{"assignment":{"buyers":{"nonsense":1}},"configuration":{}}"#;
+        let spec = super::parse_spec(line, false, &HashMap::new()).unwrap();
+        assert!(spec.assignment.buyers.contains_key("nonsense"));
+        assert!(super::build_agents(&spec, &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_max_agents() {
+        let spec: super::Spec = serde_json::from_str(
+            r#"{"assignment":{"buyers":{"0":2},"sellers":{"0":1},"traders":{"0":1.5}},"configuration":{"units":{"buyers":3}}}"#,
+        )
+        .unwrap();
+        assert_eq!(super::count_agents(&spec).unwrap(), 11.0);
+        let agents = super::build_agents(&spec, &HashMap::new()).unwrap();
+        assert_eq!(agents.len(), 11);
+
+        // rejected before anything is allocated
+        let spec: super::Spec =
+            serde_json::from_str(r#"{"assignment":{"buyers":{"0.5":1e12}},"configuration":{}}"#)
+                .unwrap();
+        let err = super::build_agents(&spec, &HashMap::new()).unwrap_err();
+        assert!(err.to_string().contains("--max-agents"));
+    }
+
+    #[test]
+    fn test_fractional_counts() {
+        let spec: super::Spec = serde_json::from_str(
+            r#"{"assignment":{"buyers":{"0.1|0.2":2.5},"sellers":{"0":2},"traders":{"0":0.5}},"configuration":{}}"#,
+        )
+        .unwrap();
+        let agents = super::build_agents(&spec, &HashMap::new()).unwrap();
+        let presence: Vec<_> = agents.iter().map(Agent::presence).collect();
+        assert_eq!(presence[..3], [None, Some(0.5), None]);
+        assert_eq!(agents[1].shading(), 0.1);
+        assert_eq!(presence[5..], [Some(0.5), Some(0.5)]);
+
+        let args = Args::parse_from(["cdasim", "--obs", "200", "--seed", "7"]);
+        let mut out = Vec::new();
+        let line = r#"{"assignment":{"buyers":{"0":1.5},"sellers":{"0":2},"traders":{"0":0.5}},"configuration":{}}"#;
+        super::simulate_specs(&args, &HashMap::new(), false, line.as_bytes(), &mut out).unwrap();
+        let mut sizes = BTreeSet::new();
+        for obs in serde_json::Deserializer::from_slice(&out).into_iter::<serde_json::Value>() {
+            let players = obs.unwrap()["players"].as_array().unwrap().clone();
+            let count = |role| players.iter().filter(|p| p["role"] == role).count();
+            assert_eq!(count("sellers"), 2);
+            sizes.insert((count("buyers"), count("traders")));
+        }
+        // each fractional player takes part independently
+        assert_eq!(sizes.len(), 4);
+
+        let spec: super::Spec =
+            serde_json::from_str(r#"{"assignment":{"buyers":{"0":-0.5}},"configuration":{}}"#)
+                .unwrap();
+        assert!(super::build_agents(&spec, &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_custom_roles() {
+        let args = Args::parse_from(["cdasim", "--obs", "2", "--summary"]);
+        let spec = r#"{"assignment":{"background":{"0.1":3},"hft":{"0.2":2},"sellers":{"0":2}},"configuration":{"roles":{"background":{"side":"buyers"},"hft":{"side":"traders","support":[2,3]}}}}"#;
+        let mut out = Vec::new();
+        super::simulate_specs(&args, &HashMap::new(), false, spec.as_bytes(), &mut out).unwrap();
+        let summary: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let payoffs = summary["payoffs"].as_object().unwrap();
+        let roles: Vec<_> = payoffs.keys().map(String::as_str).collect();
+        assert_eq!(roles, ["background", "hft", "sellers"]);
+
+        let spec: super::Spec = serde_json::from_str(spec).unwrap();
+        let agents = super::build_agents(&spec, &HashMap::new()).unwrap();
+        assert_eq!(agents.len(), 9);
+        // sellers, then the other roles in order, where each hft is a seller and a buyer
+        let sides: Vec<_> = agents.iter().map(|a| (a.role(), a.buyer)).collect();
+        assert_eq!(sides[2..5], [("background", true); 3]);
+        assert_eq!(sides[5..7], [("hft", false), ("hft", true)]);
+        assert!(agents[5].trader);
+        assert_eq!(agents[5].support(), (2.0, 3.0));
+        assert_eq!(agents[2].support(), (0.0, 1.0));
+
+        for invalid in [
+            r#"{"assignment":{"hft":{"0":1}},"configuration":{}}"#,
+            r#"{"assignment":{"buyers":{"0":1}},"configuration":{"roles":{"buyers":{"side":"sellers"}}}}"#,
+        ] {
+            let spec: super::Spec = serde_json::from_str(invalid).unwrap();
+            assert!(super::build_agents(&spec, &HashMap::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_market_support() {
+        let spec: super::Spec = serde_json::from_str(
+            r#"{"assignment":{"buyers":{"0":2},"sellers":{"0":2}},"configuration":{"cda":false,"support":{"buyers":[0,3],"sellers":[-2,-1],"traders":[5,6]}}}"#,
+        )
+        .unwrap();
+        // traders aren't in the market, so they don't widen it
+        assert_eq!(super::market_support(&spec).unwrap(), (-2.0, 3.0));
+        // every buyer values more than every seller's disposal cost, so truthful agents all trade
+        let mut agents = super::build_agents(&spec, &HashMap::new()).unwrap();
+        let mut rng = rand::thread_rng();
+        let features = super::run_sim(&mut agents, &super::Call, &mut rng);
+        assert!((features.efficiency.unwrap() - 1.0).abs() < 1e-9);
+        assert!(agents.iter().all(|a| a.traded));
+    }
+
+    #[test]
+    fn test_assignment() {
+        let args = Args::parse_from(["cdasim", "--assignment"]);
+        let spec = r#"{"assignment":{"buyers":{"U(0,0.5)":2},"sellers":{"0.3":1},"traders":{"0":1}},"configuration":{}}"#;
+        let mut out = Vec::new();
+        super::simulate_specs(&args, &HashMap::new(), false, spec.as_bytes(), &mut out).unwrap();
+        let obs: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let assignment = &obs["assignment"];
+        assert_eq!(assignment["counts"]["buyers"]["U(0,0.5)"], 2);
+        assert_eq!(assignment["counts"]["traders"]["0"], 1);
+        let draws = assignment["draws"].as_array().unwrap();
+        assert_eq!(draws.len(), 4);
+        assert!((0.0..=0.5).contains(&draws[0]["shading"].as_f64().unwrap()));
+        assert_eq!(draws[2]["shading"], 0.3);
+        assert!(draws[2].get("second_value").is_none());
+        assert!(draws[3]["value"].as_f64() >= draws[3]["second_value"].as_f64());
+    }
+
+    #[test]
+    fn test_stratify() {
+        let args = ["cdasim", "--obs", "12", "--stratify", "8", "--seed", "3"];
+        let args = Args::parse_from(args.into_iter().chain(["--assignment", "--tag-output"]));
+        let spec = r#"{"assignment":{"buyers":{"0":2},"sellers":{"0":1}},"configuration":{}}"#;
+        let mut out = Vec::new();
+        super::simulate_specs(&args, &HashMap::new(), false, spec.as_bytes(), &mut out).unwrap();
+        let lines: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&out)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(lines[0]["stratification"]["scheme"], "latin_hypercube");
+        assert_eq!(lines[0]["stratification"]["batch"], 8);
+        // every agent's values in the first batch fall once in each eighth of their support
+        for player in 0..3 {
+            let mut strata: Vec<_> = lines[..8]
+                .iter()
+                .map(|obs| {
+                    let value = obs["assignment"]["draws"][player]["value"]
+                        .as_f64()
+                        .unwrap();
+                    (value * 8.0) as usize
+                })
+                .collect();
+            strata.sort_unstable();
+            assert_eq!(strata, (0..8).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_sobol() {
+        let args = [
+            "--obs",
+            "16",
+            "--sobol",
+            "--seed",
+            "3",
+            "--assignment",
+            "--tag-output",
+        ];
+        let spec = r#"{"assignment":{"buyers":{"0":2},"sellers":{"0":1}},"configuration":{}}"#;
+        let lines = simulate(&args, spec).unwrap();
+        assert_eq!(lines[0]["stratification"]["scheme"], "sobol");
+        assert!(lines[0]["stratification"].get("batch").is_none());
+        // the first 15 points and the shifted origin fill every sixteenth once
+        for player in 0..3 {
+            let mut strata: Vec<_> = lines[..15]
+                .iter()
+                .map(|obs| {
+                    let value = obs["assignment"]["draws"][player]["value"]
+                        .as_f64()
+                        .unwrap();
+                    (value * 16.0) as usize
+                })
+                .collect();
+            strata.sort_unstable();
+            strata.dedup();
+            assert_eq!(strata.len(), 15);
+        }
+        assert_eq!(simulate(&args, spec).unwrap(), lines);
+
+        // markets larger than Joe and Kuo's table of directions are still stratified
+        let spec = r#"{"assignment":{"buyers":{"0":9},"sellers":{"0":8}},"configuration":{}}"#;
+        let lines = simulate(&args, spec).unwrap();
+        let mut strata: Vec<_> = lines[..15]
+            .iter()
+            .map(|obs| (obs["assignment"]["draws"][16]["value"].as_f64().unwrap() * 16.0) as usize)
+            .collect();
+        strata.sort_unstable();
+        strata.dedup();
+        assert_eq!(strata.len(), 15);
+    }
+
+    #[test]
+    fn test_types() {
+        let values = |config: &str| {
+            let spec = format!(
+                r#"{{"assignment":{{"buyers":{{"0":2}},"sellers":{{"0":2}}}},"configuration":{}}}"#,
+                config
+            );
+            let specs = format!("{}\n{}", spec, spec);
+            let lines = simulate(&["--obs", "3", "--seed", "5", "--assignment"], &specs)?;
+            let values: Vec<Vec<f64>> = lines
+                .iter()
+                .map(|obs| {
+                    let draws = obs["assignment"]["draws"].as_array().unwrap();
+                    draws.iter().map(|d| d["value"].as_f64().unwrap()).collect()
+                })
+                .collect();
+            Ok::<_, io::Error>(values)
+        };
+        // without noise, every observation of a spec line has the same values
+        let fixed = values(r#"{"types":{"noise":0}}"#).unwrap();
+        assert_eq!(fixed.len(), 6);
+        assert!(fixed[..3].iter().all(|values| *values == fixed[0]));
+        assert!(fixed[3..].iter().all(|values| *values == fixed[3]));
+        assert_ne!(fixed[0], fixed[3]);
+        let noisy = values(r#"{"types":{"noise":0.01}}"#).unwrap();
+        assert_ne!(noisy[0], noisy[1]);
+        for (first, second) in noisy[0].iter().zip(&noisy[1]) {
+            assert!((first - second).abs() < 0.2);
+        }
+        assert!(values(r#"{"types":{"noise":-1}}"#).is_err());
+        assert!(values(r#"{"types":{"noise":0},"values":{"buyers":[0.5,0.6]}}"#).is_err());
+    }
+
+    #[test]
+    fn test_define() {
+        let args = Args::parse_from(["cdasim", "--define", "N=3", "--define", "S=0.2"]);
+        let spec =
+            r#"{"assignment":{"buyers":{"${S}":${N}},"sellers":{"0":${M:-2}}},"configuration":{}}"#;
+        let mut out = Vec::new();
+        super::simulate_specs(&args, &HashMap::new(), false, spec.as_bytes(), &mut out).unwrap();
+        let obs: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let players = obs["players"].as_array().unwrap();
+        assert_eq!(players.len(), 5);
+        assert_eq!(players[0]["strategy"], "0.2");
+
+        let spec = r#"{"assignment":{"buyers":{"0":${UNDEFINED_CDASIM_VAR}}},"configuration":{}}"#;
+        let mut out = Vec::new();
+        let result =
+            super::simulate_specs(&args, &HashMap::new(), false, spec.as_bytes(), &mut out);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_spec_obs() {
+        let args = Args::parse_from(["cdasim", "--obs", "4", "--summary"]);
+        let specs = [
+            r#"{"assignment":{"buyers":{"0":1},"sellers":{"0":1}},"configuration":{}}"#,
+            r#"{"assignment":{"buyers":{"0":1},"sellers":{"0":1}},"configuration":{"obs":6}}"#,
+            r#"{"assignment":{"buyers":{"0":1},"sellers":{"0":1}},"configuration":{"weight":2.6}}"#,
+        ]
+        .join("\n");
+        let mut out = Vec::new();
+        super::simulate_specs(&args, &HashMap::new(), false, specs.as_bytes(), &mut out).unwrap();
+        let summaries: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&out)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let observations: Vec<_> = summaries.iter().map(|s| &s["observations"]).collect();
+        assert_eq!(observations, [4, 6, 10]);
+        assert!(summaries[0].get("weight").is_none());
+        assert_eq!(summaries[2]["weight"], 2.6);
+
+        let spec = r#"{"assignment":{"buyers":{"0":1}},"configuration":{"weight":-1}}"#;
+        let res = super::simulate_specs(&args, &HashMap::new(), false, spec.as_bytes(), &mut out);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_traders() {
+        let args = Args::parse_from(["cdasim", "--obs", "20"]);
+        let spec = r#"{"assignment":{"buyers":{"0":1},"sellers":{"0":1},"traders":{"0.1":2}},"configuration":{}}"#;
+        let mut out = Vec::new();
+        super::simulate_specs(&args, &HashMap::new(), false, spec.as_bytes(), &mut out).unwrap();
+        for line in out.split(|&b| b == b'\n').filter(|l| !l.is_empty()) {
+            let obs: serde_json::Value = serde_json::from_slice(line).unwrap();
+            let players = obs["players"].as_array().unwrap();
+            assert_eq!(players.len(), 4);
+            assert_eq!(players[2]["role"], "traders");
+            let payoffs: f64 = players.iter().map(|p| p["payoff"].as_f64().unwrap()).sum();
+            let surplus = obs["features"]["surplus"].as_f64().unwrap();
+            assert!((payoffs - surplus).abs() < 1e-9);
+            assert!(surplus <= obs["features"]["ce_surplus"].as_f64().unwrap() + 1e-9);
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use serde::{Deserialize, Serialize};
+
+/// How fast the market maker's price estimate moves toward observed trade prices
+const LEARNING_RATE: f64 = 0.1;
+
+/// Configuration of a market maker
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct MakerConfig {
+    /// Distance between the bid and ask
+    spread: Option<f64>,
+    /// The largest long or short position the maker will take
+    limit: Option<u64>,
+    /// The maker's initial estimate of the equilibrium price
+    price: Option<f64>,
+    /// The trade price at or below which the maker sells a long position and stops buying
+    stop: Option<f64>,
+}
+
+/// A dealer that quotes a bid and an ask around its estimate of the equilibrium price
+///
+/// The maker trades one unit at a time with any agent that crosses its quotes, and its inventory,
+/// cash, and estimate carry over between simulations. Its estimate is an exponential moving average
+/// of the market's trade prices, and it stops quoting a side once its position reaches its limit.
+/// With a stop, once the market trades at or below it while the maker is long, the maker sells its
+/// position into the book's bids and stops bidding for the rest of the simulation.
+#[derive(Debug, Clone)]
+pub struct MarketMaker {
+    spread: f64,
+    limit: i64,
+    stop: Option<f64>,
+    stopped: bool,
+    estimate: f64,
+    inventory: i64,
+    cash: f64,
+    trades: u64,
+    path: Vec<i64>,
+}
+
+/// The state of a market maker after a simulation
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct MakerReport {
+    pub inventory: i64,
+    pub cash: f64,
+    /// Cash plus inventory marked at the current estimate
+    pub pnl: f64,
+    pub estimate: f64,
+    /// Trades in the last simulation
+    pub trades: u64,
+    /// Inventory after every trade in the last simulation
+    pub path: Vec<i64>,
+}
+
+impl MarketMaker {
+    pub fn new(config: &MakerConfig) -> Result<Self, String> {
+        let spread = config.spread.unwrap_or(0.1);
+        let estimate = config.price.unwrap_or(0.5);
+        if !spread.is_finite() || spread < 0.0 {
+            Err(format!("invalid market maker spread {}", spread))
+        } else if !estimate.is_finite() {
+            Err(format!("invalid market maker price {}", estimate))
+        } else if config.stop.is_some_and(|stop| !stop.is_finite()) {
+            Err(format!(
+                "invalid market maker stop {}",
+                config.stop.unwrap()
+            ))
+        } else {
+            Ok(MarketMaker {
+                spread,
+                limit: config.limit.unwrap_or(10).min(i64::MAX as u64) as i64,
+                stop: config.stop,
+                stopped: false,
+                estimate,
+                inventory: 0,
+                cash: 0.0,
+                trades: 0,
+                path: Vec::new(),
+            })
+        }
+    }
+
+    /// Start a new simulation
+    pub fn start(&mut self) {
+        self.trades = 0;
+        self.path.clear();
+        self.stopped = false;
+    }
+
+    /// The price the maker buys at, if it's buying
+    pub fn bid(&self) -> Option<f64> {
+        (!self.stopped && self.inventory < self.limit).then(|| self.estimate - self.spread / 2.0)
+    }
+
+    /// If the maker's stop is triggered by a trade at `price`, after which it liquidates
+    pub fn triggers(&self, price: f64) -> bool {
+        !self.stopped && self.inventory > 0 && self.stop.is_some_and(|stop| price <= stop)
+    }
+
+    pub fn trigger(&mut self) {
+        self.stopped = true;
+    }
+
+    pub fn stop(&self) -> Option<f64> {
+        self.stop
+    }
+
+    /// If the maker has a long position to liquidate
+    pub fn long(&self) -> bool {
+        self.inventory > 0
+    }
+
+    /// The price the maker sells at, if it's selling
+    pub fn ask(&self) -> Option<f64> {
+        (-self.inventory < self.limit).then(|| self.estimate + self.spread / 2.0)
+    }
+
+    pub fn buy(&mut self, price: f64) {
+        self.trade(1, price);
+    }
+
+    pub fn sell(&mut self, price: f64) {
+        self.trade(-1, price);
+    }
+
+    fn trade(&mut self, units: i64, price: f64) {
+        self.inventory += units;
+        self.cash -= units as f64 * price;
+        self.trades += 1;
+        self.path.push(self.inventory);
+    }
+
+    /// Update the price estimate with a trade in the market
+    pub fn observe(&mut self, price: f64) {
+        self.estimate += LEARNING_RATE * (price - self.estimate);
+    }
+
+    pub fn report(&self) -> MakerReport {
+        MakerReport {
+            inventory: self.inventory,
+            cash: self.cash,
+            pnl: self.cash + self.inventory as f64 * self.estimate,
+            estimate: self.estimate,
+            trades: self.trades,
+            path: self.path.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MakerConfig, MarketMaker};
+    use crate::market::{Continuous, Market};
+    use crate::strategy::Shading;
+    use crate::{Agent, Style};
+
+    #[test]
+    fn test_limits() {
+        let config = MakerConfig {
+            spread: Some(0.2),
+            limit: Some(1),
+            price: None,
+            stop: None,
+        };
+        let mut maker = MarketMaker::new(&config).unwrap();
+        assert_eq!(maker.bid(), Some(0.4));
+        maker.buy(0.4);
+        assert_eq!(maker.bid(), None);
+        assert_eq!(maker.ask(), Some(0.6));
+        let report = maker.report();
+        assert_eq!(report.path, [1]);
+        assert!((report.pnl - 0.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_maker_market() {
+        let market =
+            Continuous::new().with_maker(MarketMaker::new(&MakerConfig::default()).unwrap());
+        // a lone eager buyer can only trade with the maker
+        let mut agents = [Agent::new(true, "", Style::Standard, Shading::Fixed(0.0))];
+        agents[0].value = 0.9;
+        agents[0].shade();
+        let price = market.simulate(&mut agents, &mut rand::thread_rng());
+        assert_eq!(price, Some(0.55));
+        assert!(agents[0].traded);
+        let report = market.maker().unwrap();
+        assert_eq!(report.inventory, -1);
+        assert_eq!(report.path, [-1]);
+    }
+
+    #[test]
+    fn test_stop() {
+        let config = MakerConfig {
+            spread: Some(0.2),
+            limit: None,
+            price: None,
+            stop: Some(0.45),
+        };
+        let market = Continuous::new()
+            .with_maker(MarketMaker::new(&config).unwrap())
+            .with_order(vec![0, 1]);
+        let mut agents = [(true, 0.35), (false, 0.3)].map(|(buyer, value)| {
+            let mut agent = Agent::new(buyer, "", Style::Standard, Shading::Fixed(0.0));
+            agent.value = value;
+            agent.shade();
+            agent
+        });
+        // the maker buys from the seller below its stop, and sells to the standing bid
+        market.simulate(&mut agents, &mut rand::thread_rng());
+        assert_eq!(agents[1].price, Some(0.4));
+        assert_eq!(agents[0].price, Some(0.35));
+        let report = market.maker().unwrap();
+        assert_eq!(report.path, [1, 0]);
+        assert_eq!(market.stops(), Some(1));
+    }
+}
@@ -1,117 +1,763 @@
 use rand::seq::SliceRandom;
-use std::cmp::Ordering;
+use rand::Rng;
+use std::cell::RefCell;
+use std::cmp::Reverse;
 use std::collections::BinaryHeap;
+use std::mem;
 
+use crate::bidding::MarketState;
+use crate::context::{self, SimContext};
+use crate::engine::{self, Action, Engine, Fill, Level, Matching, Order};
+use crate::maker::{MakerReport, MarketMaker};
 use crate::Agent;
+use serde::{Deserialize, Serialize};
 
-impl<'a> Ord for Agent<'a> {
-    fn cmp(&self, other: &Agent<'a>) -> Ordering {
-        self.partial_cmp(other).expect("got nan bids")
+/// Replace buys and sells with the orders of every agent that has one
+fn orders(agents: &[Agent<'_>], buys: &mut Vec<Order>, sells: &mut Vec<Order>) {
+    buys.clear();
+    sells.clear();
+    for (index, agent) in agents.iter().enumerate().filter(|(_, a)| a.has_order()) {
+        let book = if agent.buyer { &mut *buys } else { &mut *sells };
+        book.push(Order::new(agent.bid, index));
     }
 }
 
-impl<'a> PartialOrd for Agent<'a> {
-    fn partial_cmp(&self, other: &Agent<'a>) -> Option<Ordering> {
-        self.bid.partial_cmp(&other.bid)
+/// Clear buys and sells at once at the price between the last matched pair, returning it and the
+/// number of trades
+pub fn call(
+    agents: &mut [Agent<'_>],
+    buys: &mut [Order],
+    sells: &mut [Order],
+) -> Option<(f64, usize)> {
+    buys.sort_unstable_by(|a, b| a.cmp(b).reverse());
+    sells.sort_unstable_by(|a, b| a.cmp(b).reverse());
+    let matched = buys
+        .iter()
+        .zip(sells.iter())
+        .take_while(|(b, s)| -s.bid <= b.bid)
+        .count();
+    if matched > 0 {
+        let price = (buys[matched - 1].bid - sells[matched - 1].bid) / 2.0;
+        for (buy, sell) in buys.iter().zip(sells.iter()).take(matched) {
+            engine::trade(agents, buy.index, sell.index, price);
+        }
+        Some((price, matched))
+    } else {
+        None
     }
 }
 
-impl<'a> PartialEq for Agent<'a> {
-    fn eq(&self, other: &Agent<'a>) -> bool {
-        self.bid == other.bid
+pub trait Market {
+    fn simulate(&self, agents: &mut [Agent<'_>], rng: &mut impl Rng) -> Option<f64>;
+
+    /// Draw the agents' values for the next simulation, returning false to have them resampled
+    /// as usual instead
+    fn draw(&self, _agents: &mut [Agent<'_>], _rng: &mut impl Rng) -> bool {
+        false
+    }
+
+    /// The state of the market's market maker, if it has one
+    fn maker(&self) -> Option<MakerReport> {
+        None
+    }
+
+    /// What happened around each value shock in the last simulation
+    fn shocks(&self) -> Vec<ShockReport> {
+        Vec::new()
+    }
+
+    /// How long agents waited in the last simulation, if it was a timed session
+    fn session(&self) -> Option<SessionReport> {
+        None
+    }
+
+    /// Snapshots of the book during the last simulation
+    fn book(&self) -> Vec<BookSnapshot> {
+        Vec::new()
+    }
+
+    /// The order agents arrived in during the last simulation, if it was recorded
+    fn arrivals(&self) -> Option<Vec<usize>> {
+        None
+    }
+
+    /// The number of events in the last simulation, if the market counts them
+    fn events(&self) -> Option<usize> {
+        None
+    }
+
+    /// Volume traded continuously and in the closing call of the last simulation, if it has one
+    fn closing(&self) -> Option<ClosingReport> {
+        None
+    }
+
+    /// The number of agents that arrived before the last simulation closed, if it stops randomly
+    fn stopped(&self) -> Option<usize> {
+        None
+    }
+
+    /// The mean spreads of the last simulation's book, if anyone hides units of their order
+    fn spread(&self) -> Option<SpreadReport> {
+        None
+    }
+
+    /// The number of stop orders that triggered in the last simulation, if anyone has a stop
+    fn stops(&self) -> Option<usize> {
+        None
     }
 }
 
-impl<'a> Eq for Agent<'a> {}
+/// Public news that shifts the values of every agent yet to arrive in a CDA
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Shock {
+    /// The number of agents that arrive before the shock
+    pub at: usize,
+    /// The amount added to the remaining agents' values
+    pub shift: f64,
+}
 
-pub trait Market {
-    fn simulate(&self, agents: &mut [Agent<'_>]) -> Option<f64>;
+/// A trading session of unit length that agents arrive in uniformly at random
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Session {
+    /// How long an order stands in the book before its agent leaves
+    #[serde(default = "infinite")]
+    pub patience: f64,
+    /// The cost an agent pays per unit of time its order stands in the book
+    #[serde(default)]
+    pub wait_cost: f64,
 }
 
-pub struct Cda;
+fn infinite() -> f64 {
+    f64::INFINITY
+}
 
-impl Market for Cda {
-    fn simulate<'a>(&self, agents: &mut [Agent<'a>]) -> Option<f64> {
-        let mut buys = BinaryHeap::<&'a mut Agent<'a>>::new();
-        let mut sells = BinaryHeap::<&'a mut Agent<'a>>::new();
+/// How long agents waited in the last session
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SessionReport {
+    /// The number of agents that left at their deadline without trading
+    pub expired: usize,
+    /// The mean time agents with orders waited before trading or leaving
+    pub waiting: Option<f64>,
+}
+
+/// When to snapshot the order book of a CDA, either after a list of event counts or every so
+/// many events
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Snapshots {
+    At(Vec<usize>),
+    Every { every: usize },
+}
 
-        // Random order
-        agents.shuffle(&mut rand::thread_rng());
+impl Snapshots {
+    fn due(&self, events: usize) -> bool {
+        match self {
+            Snapshots::At(at) => at.contains(&events),
+            Snapshots::Every { every } => events.is_multiple_of(*every),
+        }
+    }
+}
 
-        // Bookkeeping
-        let mut avg_price = 0.0;
-        let mut num_trans = 0;
+/// The standing orders in the book after a number of events
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct BookSnapshot {
+    pub event: usize,
+    pub time: f64,
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+}
 
-        {
-            // Block to control scope of closure
-            let mut trans = |buy: &mut Agent, sell: &mut Agent, price: f64| {
-                buy.transact(price);
-                sell.transact(price);
-                num_trans += 1;
-                avg_price += (price - avg_price) / num_trans as f64;
-            };
+/// A call auction that clears a CDA's book when it closes, after continuous trading stops
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ClosingCall {
+    /// When continuous trading stops
+    pub at: f64,
+}
+
+/// A CDA that closes after each arrival with a probability, so it closes after a geometrically
+/// distributed number of arrivals
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Stopping {
+    pub probability: f64,
+}
+
+/// The mean spread between the best ask and the best bid after every event where there were both,
+/// as it's shown in the book and including the hidden units of iceberg orders
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SpreadReport {
+    pub displayed: Option<f64>,
+    pub total: Option<f64>,
+}
+
+/// How many units traded continuously and in the closing call
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ClosingReport {
+    pub continuous: usize,
+    pub closing: usize,
+    /// The price of the closing call, if anything traded in it
+    pub price: Option<f64>,
+}
+
+/// A shock and the trade prices on either side of it
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ShockReport {
+    pub at: usize,
+    pub shift: f64,
+    /// The price of the last trade before the shock
+    pub last_price: Option<f64>,
+    /// The price of the first trade after the shock
+    pub next_price: Option<f64>,
+}
+
+/// One event of a traced CDA, and the book after it
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Step {
+    pub time: f64,
+    pub action: Action,
+    /// The signed bid of an order that reached the market, None if it was dropped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bid: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fill: Option<Fill>,
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+}
+
+/// What a continuous market recorded during its last simulation
+#[derive(Debug, Default)]
+struct Records {
+    shocks: Vec<ShockReport>,
+    session: Option<SessionReport>,
+    books: Vec<BookSnapshot>,
+    /// Every event, if the market is traced
+    trace: Option<Vec<Step>>,
+    /// The agents in the order they arrived, if they're recorded
+    arrivals: Option<Vec<usize>>,
+    /// The number of events
+    events: usize,
+    closing: Option<ClosingReport>,
+    /// The number of agents that arrived before a randomly stopped market closed
+    stopped: Option<usize>,
+    /// The spreads of the book, if anyone hides units
+    spread: Option<SpreadReport>,
+    /// The number of stops that triggered, if anyone has one
+    stops: Option<usize>,
+}
+
+/// How a continuous market runs apart from its market maker
+#[derive(Debug, Default)]
+struct Rules {
+    shocks: Vec<Shock>,
+    session: Option<Session>,
+    snapshots: Option<Snapshots>,
+    /// The order agents arrive in instead of a random one
+    order: Option<Vec<usize>>,
+    closing: Option<ClosingCall>,
+    stopping: Option<Stopping>,
+    matching: Matching,
+}
+
+/// Run a continuous double auction, optionally with a market maker quoting both sides
+///
+/// Agents arrive in a random order, spread evenly over a unit of time, and quote as they arrive,
+/// multi-unit players quoting every unit at once, see [`Agent::lot`],
+/// see [`Engine`], though their orders only reach the market after their latency, and are dropped
+/// if that's after the market closes at the end of the unit of time. Agents that requote cancel
+/// their standing orders after a number of further orders reach the market, and quote an improved
+/// one. Shocks, sorted by when they arrive, shift the values of agents that haven't
+/// arrived yet, who rebid, while standing orders stay as they are. In a session, agents arrive at
+/// uniformly random times in the same order, orders leave the book at their agent's deadline, and
+/// agents pay for the time they wait in the book. A fixed arrival order replaces the random one
+/// after it's drawn, so every other random draw is the same as without it. With a closing call,
+/// continuous trading stops at its time, orders that reach the market later rest in the book
+/// without trading, no one requotes, and a call auction clears the book when the market closes.
+/// With random stopping, the number of arrivals before the market closes is drawn after the
+/// arrival times, and the market closes when the next agent would arrive, if anyone's left.
+/// Orders at the same price trade in the order of the matching rule, and pro rata matching draws
+/// a random priority for every order that reaches the market, weighted by its player's units.
+/// A player that displays only some of its units quotes those when it arrives, and another unit
+/// reaches the market as soon as one of them trades, behind any orders at its price. Trades can
+/// trigger the stops of traders and the market maker, whose sales can trigger further stops.
+fn continuous(
+    agents: &mut [Agent<'_>],
+    rng: &mut impl Rng,
+    maker: Option<&mut MarketMaker>,
+    rules: &Rules,
+    records: &mut Records,
+    ctx: &mut SimContext,
+) -> Option<f64> {
+    let stops = maker.as_ref().is_some_and(|maker| maker.stop().is_some())
+        || agents.iter().any(|agent| agent.stop().is_some());
+    let mut engine = Engine::reusing(mem::take(&mut ctx.engine), agents.len(), maker);
+    let session = rules.session.as_ref();
+    let snapshots = rules.snapshots.as_ref();
+
+    // Random arrival order, leaving the order of agents untouched
+    let order = &mut ctx.arrivals;
+    order.clear();
+    order.extend(0..agents.len());
+    order.shuffle(rng);
+    if let Some(fixed) = &rules.order {
+        order.clone_from(fixed);
+    }
+    if let Some(arrivals) = &mut records.arrivals {
+        arrivals.clone_from(order);
+    }
+    // a multi-unit player's lot arrives with its first unit
+    order.retain(|&index| agents[index].leads());
+    let times = &mut ctx.times;
+    times.clear();
+    let patience = match session {
+        Some(session) => {
+            times.extend((0..order.len()).map(|_| rng.gen::<f64>()));
+            times.sort_by(f64::total_cmp);
+            session.patience
+        }
+        None => {
+            let len = order.len() as f64;
+            times.extend((0..order.len()).map(|arrival| arrival as f64 / len));
+            f64::INFINITY
+        }
+    };
+    records.stopped = rules.stopping.map(|stopping| {
+        let draw: f64 = rng.gen();
+        let arrivals = ((1.0 - draw).ln() / (1.0 - stopping.probability).ln()).floor() + 1.0;
+        (arrivals as usize).min(order.len())
+    });
+    // the market closes before anyone arriving at the same time
+    let close = records.stopped.and_then(|num| times.get(num)).copied();
+    engine.schedule(close.unwrap_or(1.0), Action::Clear);
+    for (&index, &time) in order.iter().zip(times.iter()) {
+        engine.schedule(time, Action::Arrive(index));
+    }
+    if let Some(closing) = rules.closing {
+        engine.schedule(closing.at, Action::Close);
+    }
 
-            for agent in agents.iter_mut() {
-                if agent.buyer {
-                    if sells.peek().map(|s| -s.bid <= agent.bid).unwrap_or(false) {
-                        let seller = sells.pop().unwrap();
-                        let price = -seller.bid;
-                        trans(agent, seller, price);
-                    } else {
-                        buys.push(agent);
+    // Bookkeeping
+    let mut avg_price = 0.0;
+    let mut num_trans = 0;
+    let mut last_price = None;
+    let mut arrived = 0;
+    let mut expired = 0;
+    let mut closed = false;
+    let mut halted = false;
+    let mut quotes = 0;
+    let mut requotes = BinaryHeap::new();
+    let hidden = &mut ctx.hidden;
+    hidden.clear();
+    hidden.resize(agents.len(), false);
+    let icebergs = agents.iter().any(|agent| agent.display().is_some());
+    let (mut displayed, mut total) = (Vec::new(), Vec::new());
+    let mut pending = rules.shocks.iter().peekable();
+    let reports = &mut records.shocks;
+    reports.clear();
+    let mut events = 0;
+    records.books.clear();
+    if let Some(trace) = &mut records.trace {
+        trace.clear();
+    }
+
+    while let Some(event) = engine.next_event() {
+        let (mut bid, mut fill) = (None, None);
+        match event.action {
+            Action::Arrive(index) => {
+                while let Some(shock) = pending.next_if(|shock| shock.at <= arrived) {
+                    for &later in &order[arrived..] {
+                        for unit in agents[later].lot(later) {
+                            agents[unit].shift(shock.shift);
+                        }
+                    }
+                    reports.push(ShockReport {
+                        at: shock.at,
+                        shift: shock.shift,
+                        last_price,
+                        next_price: None,
+                    });
+                }
+                arrived += 1;
+                if agents[index].watches() {
+                    let (best_bid, best_ask) = engine.best(agents);
+                    let state = MarketState {
+                        best_bid,
+                        best_ask,
+                        last_price,
+                    };
+                    for unit in agents[index].lot(index) {
+                        agents[unit].watch(state);
                     }
-                } else if buys.peek().map(|b| -agent.bid <= b.bid).unwrap_or(false) {
-                    let buyer = buys.pop().unwrap();
-                    let price = buyer.bid;
-                    trans(buyer, agent, price);
+                }
+                let lot = agents[index].lot(index);
+                if lot.clone().any(|unit| agents[unit].has_order()) {
+                    let delay = agents[index].send(rng);
+                    let mut shown = agents[index].display().unwrap_or(usize::MAX);
+                    for unit in lot {
+                        if !agents[unit].has_order() {
+                            continue;
+                        } else if shown == 0 {
+                            hidden[unit] = true;
+                            continue;
+                        }
+                        shown -= 1;
+                        agents[unit].delay = delay;
+                        engine.schedule(event.time + delay, Action::Quote(unit));
+                    }
+                }
+            }
+            Action::Quote(index) => {
+                // orders that reach the market after it closes never enter the book
+                if closed || !agents[index].has_order() {
+                    if let Some(trace) = &mut records.trace {
+                        trace.push(Step {
+                            time: event.time,
+                            action: event.action,
+                            bid: None,
+                            fill: None,
+                            bids: Vec::new(),
+                            asks: Vec::new(),
+                        });
+                    }
+                    continue;
+                }
+                quotes += 1;
+                bid = Some(agents[index].bid);
+                while let Some(&Reverse((at, due))) = requotes.peek() {
+                    if at > quotes {
+                        break;
+                    }
+                    requotes.pop();
+                    engine.schedule(event.time, Action::Requote(due));
+                }
+                let size = agents[index].units() as f64;
+                match rules.matching {
+                    Matching::PriceTime => (),
+                    Matching::ProRata => {
+                        engine.prioritize(index, rng.gen::<f64>().powf(1.0 / size))
+                    }
+                    Matching::Size => engine.prioritize(index, size),
+                }
+                fill = if halted {
+                    engine.rest(agents, index);
+                    None
                 } else {
-                    sells.push(agent);
+                    engine.quote(agents, index)
+                };
+                if fill.is_none() {
+                    if let Some(events) = agents[index].requote_after() {
+                        requotes.push(Reverse((quotes + events, index)));
+                    }
+                    if patience.is_finite() {
+                        engine.schedule(event.time + patience, Action::Cancel(index));
+                    }
+                }
+            }
+            Action::Liquidate => fill = engine.liquidate(agents),
+            Action::Cancel(index) => {
+                // orders still standing for agents that haven't withdrawn left at their deadline
+                if engine.cancel(index) && agents[index].has_order() {
+                    expired += 1;
+                }
+            }
+            Action::Requote(index) => {
+                if !halted && engine.standing(index) && agents[index].improve() {
+                    engine.cancel(index);
+                    let delay = agents[index].send(rng);
+                    engine.schedule(event.time + delay, Action::Quote(index));
+                }
+            }
+            Action::Close => halted = true,
+            Action::Clear => {
+                closed = true;
+                if rules.closing.is_some() {
+                    let (mut buys, mut sells) = engine.resting(agents);
+                    let (price, closing) = call(agents, &mut buys, &mut sells).unzip();
+                    let closing = closing.unwrap_or(0);
+                    records.closing = Some(ClosingReport {
+                        continuous: num_trans,
+                        closing,
+                        price,
+                    });
+                    if let Some(price) = price {
+                        num_trans += closing;
+                        avg_price += (price - avg_price) * closing as f64 / num_trans as f64;
+                    }
                 }
+                engine.clear();
             }
         }
+        if let Some(Fill {
+            price,
+            counterparty,
+        }) = fill
+        {
+            // icebergs reveal another unit as soon as a shown one trades
+            let parties = [event.action.agent(), counterparty];
+            for party in parties.into_iter().flatten() {
+                let lot = agents[party].lot(party);
+                if let Some(unit) = lot.into_iter().find(|&unit| hidden[unit]) {
+                    hidden[unit] = false;
+                    engine.schedule(event.time, Action::Quote(unit));
+                }
+            }
+            last_price = Some(price);
+            for report in reports.iter_mut().filter(|r| r.next_price.is_none()) {
+                report.next_price = Some(price);
+            }
+            num_trans += 1;
+            avg_price += (price - avg_price) / num_trans as f64;
+        }
+        events += 1;
+        if icebergs {
+            let (buys, sells) = engine.resting(agents);
+            let best = |orders: &[Order]| orders.iter().map(|o| o.bid).reduce(f64::max);
+            let (bid, ask) = (best(&buys), best(&sells));
+            if let Some((bid, ask)) = bid.zip(ask) {
+                displayed.push(-ask - bid);
+            }
+            let secret = |buyer: bool| {
+                (0..agents.len())
+                    .filter(|&unit| hidden[unit] && agents[unit].buyer == buyer)
+                    .filter(|&unit| agents[unit].has_order())
+                    .map(|unit| agents[unit].bid)
+                    .reduce(f64::max)
+            };
+            let bid = bid.into_iter().chain(secret(true)).reduce(f64::max);
+            let ask = ask.into_iter().chain(secret(false)).reduce(f64::max);
+            if let Some((bid, ask)) = bid.zip(ask) {
+                total.push(-ask - bid);
+            }
+        }
+        if snapshots.is_some_and(|when| when.due(events)) {
+            let (bids, asks) = engine.levels(agents);
+            records.books.push(BookSnapshot {
+                event: events,
+                time: event.time,
+                bids,
+                asks,
+            });
+        }
+        if let Some(trace) = &mut records.trace {
+            let (bids, asks) = engine.levels(agents);
+            trace.push(Step {
+                time: event.time,
+                action: event.action,
+                bid,
+                fill,
+                bids,
+                asks,
+            });
+        }
+    }
+
+    // shocks after everyone has arrived don't affect anyone
+    reports.extend(pending.map(|shock| ShockReport {
+        at: shock.at,
+        shift: shock.shift,
+        last_price,
+        next_price: None,
+    }));
 
-        if num_trans > 0 {
-            Some(avg_price)
-        } else {
-            None
+    if let Some(session) = session {
+        let mut waits = Vec::new();
+        for (index, agent) in agents.iter_mut().enumerate() {
+            if let (Some(entry), Some(exit)) = (engine.entry(index), engine.exit(index)) {
+                let waited = exit - entry;
+                agent.waiting = session.wait_cost * waited;
+                waits.push(waited);
+            }
         }
+        records.session = Some(SessionReport {
+            expired,
+            waiting: (!waits.is_empty()).then(|| crate::stats::mean(&waits)),
+        });
+    }
+
+    records.events = events;
+    records.stops = stops.then(|| engine.stops());
+    ctx.engine = engine.into_buffers();
+    let mean = |spreads: &[f64]| (!spreads.is_empty()).then(|| crate::stats::mean(spreads));
+    records.spread = icebergs.then(|| SpreadReport {
+        displayed: mean(&displayed),
+        total: mean(&total),
+    });
+    if num_trans > 0 {
+        Some(avg_price)
+    } else {
+        None
+    }
+}
+
+pub struct Cda;
+
+impl Market for Cda {
+    fn simulate(&self, agents: &mut [Agent<'_>], rng: &mut impl Rng) -> Option<f64> {
+        let (rules, mut records) = (Rules::default(), Records::default());
+        context::with(|ctx| continuous(agents, rng, None, &rules, &mut records, ctx))
+    }
+}
+
+/// A continuous double auction with a market maker that keeps its state across simulations,
+/// scheduled value shocks, a timed session, snapshots of its book, a fixed arrival order, or
+/// records of every event or of the arrival order
+#[derive(Default)]
+pub struct Continuous {
+    maker: Option<RefCell<MarketMaker>>,
+    rules: Rules,
+    records: RefCell<Records>,
+}
+
+impl Continuous {
+    pub fn new() -> Self {
+        Continuous::default()
+    }
+
+    pub fn with_maker(mut self, maker: MarketMaker) -> Self {
+        self.maker = Some(RefCell::new(maker));
+        self
+    }
+
+    pub fn with_shocks(mut self, mut shocks: Vec<Shock>) -> Self {
+        shocks.sort_by_key(|shock| shock.at);
+        self.rules.shocks = shocks;
+        self
+    }
+
+    pub fn with_session(mut self, session: Session) -> Self {
+        self.rules.session = Some(session);
+        self
+    }
+
+    pub fn with_snapshots(mut self, snapshots: Snapshots) -> Self {
+        self.rules.snapshots = Some(snapshots);
+        self
+    }
+
+    pub fn with_trace(self) -> Self {
+        self.records.borrow_mut().trace = Some(Vec::new());
+        self
+    }
+
+    /// End with a closing call auction
+    pub fn with_closing(mut self, closing: ClosingCall) -> Self {
+        self.rules.closing = Some(closing);
+        self
+    }
+
+    /// Close after a random number of arrivals
+    pub fn with_stopping(mut self, stopping: Stopping) -> Self {
+        self.rules.stopping = Some(stopping);
+        self
+    }
+
+    pub fn with_matching(mut self, matching: Matching) -> Self {
+        self.rules.matching = matching;
+        self
+    }
+
+    /// Make agents arrive in this order, a permutation of their indices
+    pub fn with_order(mut self, order: Vec<usize>) -> Self {
+        self.rules.order = Some(order);
+        self
+    }
+
+    pub fn with_arrivals(self) -> Self {
+        self.records.borrow_mut().arrivals = Some(Vec::new());
+        self
+    }
+
+    /// Every event of the last simulation, if the market is traced
+    pub fn trace(&self) -> Vec<Step> {
+        self.records.borrow().trace.clone().unwrap_or_default()
+    }
+}
+
+impl Market for Continuous {
+    fn simulate(&self, agents: &mut [Agent<'_>], rng: &mut impl Rng) -> Option<f64> {
+        let mut maker = self.maker.as_ref().map(RefCell::borrow_mut);
+        if let Some(maker) = &mut maker {
+            maker.start();
+        }
+        let mut records = self.records.borrow_mut();
+        context::with(|ctx| {
+            continuous(
+                agents,
+                rng,
+                maker.as_deref_mut(),
+                &self.rules,
+                &mut records,
+                ctx,
+            )
+        })
+    }
+
+    fn maker(&self) -> Option<MakerReport> {
+        self.maker.as_ref().map(|maker| maker.borrow().report())
+    }
+
+    fn shocks(&self) -> Vec<ShockReport> {
+        self.records.borrow().shocks.clone()
+    }
+
+    fn session(&self) -> Option<SessionReport> {
+        self.records.borrow().session.clone()
+    }
+
+    fn book(&self) -> Vec<BookSnapshot> {
+        self.records.borrow().books.clone()
+    }
+
+    fn arrivals(&self) -> Option<Vec<usize>> {
+        self.records.borrow().arrivals.clone()
+    }
+
+    fn events(&self) -> Option<usize> {
+        Some(self.records.borrow().events)
+    }
+
+    fn closing(&self) -> Option<ClosingReport> {
+        self.records.borrow().closing.clone()
+    }
+
+    fn stopped(&self) -> Option<usize> {
+        self.records.borrow().stopped
+    }
+
+    fn spread(&self) -> Option<SpreadReport> {
+        self.records.borrow().spread.clone()
+    }
+
+    fn stops(&self) -> Option<usize> {
+        self.records.borrow().stops
     }
 }
 
 pub struct Call;
 
 impl Market for Call {
-    fn simulate<'a>(&self, agents: &mut [Agent<'a>]) -> Option<f64> {
-        let mut buys = Vec::<&'a mut Agent<'a>>::new();
-        let mut sells = Vec::<&'a mut Agent<'a>>::new();
-        agents
-            .iter_mut()
-            .for_each(|a| if a.buyer { &mut buys } else { &mut sells }.push(a));
-        buys.sort_unstable_by(|a, b| a.cmp(b).reverse());
-        sells.sort_unstable_by(|a, b| a.cmp(b).reverse());
-        let matched = buys
-            .iter()
-            .zip(sells.iter())
-            .take_while(|(b, s)| -s.bid <= b.bid)
-            .count();
-        if matched > 0 {
-            let price = (buys[matched - 1].bid - sells[matched - 1].bid) / 2.0;
-            buys[..matched].iter_mut().for_each(|b| b.transact(price));
-            sells[..matched].iter_mut().for_each(|b| b.transact(price));
-            Some(price)
-        } else {
-            None
-        }
+    fn simulate(&self, agents: &mut [Agent<'_>], _: &mut impl Rng) -> Option<f64> {
+        context::with(|ctx| {
+            let (buys, sells) = &mut ctx.orders;
+            orders(agents, buys, sells);
+            call(agents, buys, sells).map(|(price, _)| price)
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Call, Market};
+    use super::{Call, Cda, ClosingCall, Continuous, Market, Session, Shock, Snapshots, Stopping};
+    use crate::engine::{Action, Matching};
+    use crate::strategy::Shading;
     use crate::{Agent, Style};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
 
     fn truthful(buyer: bool, value: f64) -> Agent<'static> {
-        let mut agent = Agent::new(buyer, "", Style::Correct, 0.0);
+        let mut agent = Agent::new(buyer, "", Style::Correct, Shading::Fixed(0.0));
         agent.value = value;
         agent.shade();
         agent
@@ -125,7 +771,7 @@ mod tests {
             truthful(true, 0.3),
             truthful(false, 0.0),
         ];
-        let price = Call.simulate(&mut agents);
+        let price = Call.simulate(&mut agents, &mut rand::thread_rng());
         let [one, two, three, four] = agents;
 
         assert_eq!(price, Some(0.5)); // NOTE This probably isn't portable
@@ -134,4 +780,416 @@ mod tests {
         assert!(!three.traded);
         assert!(four.traded);
     }
+
+    #[test]
+    fn test_simple_cda() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            let mut agents = [
+                truthful(true, 1.0),
+                truthful(false, 0.7),
+                truthful(true, 0.3),
+                truthful(false, 0.0),
+            ];
+            agents[2].rejected = true;
+            let price = Cda.simulate(&mut agents, &mut rng).unwrap();
+            assert!(agents[0].traded);
+            assert!(!agents[2].traded);
+            assert_eq!(agents[1].traded, !agents[3].traded);
+            assert!([0.0, 0.7, 1.0].contains(&price));
+        }
+    }
+
+    #[test]
+    fn test_trader_withdraws() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            // a trader who values its unit at 0.6 and another at 0.4
+            let mut agents = [
+                truthful(false, 0.6),
+                truthful(true, 0.4),
+                truthful(true, 0.8),
+                truthful(false, 0.2),
+            ];
+            agents[0].trader = true;
+            agents[1].trader = true;
+            Cda.simulate(&mut agents, &mut rng);
+            // the trader only ever trades one unit
+            assert!(!(agents[0].traded && agents[1].traded));
+        }
+    }
+
+    #[test]
+    fn test_shock() {
+        let mut rng = rand::thread_rng();
+        let shock = Shock { at: 2, shift: 0.25 };
+        let market = Continuous::new().with_shocks(vec![shock]);
+        let mut agents = [truthful(true, 0.5), truthful(false, 0.5)];
+        market.simulate(&mut agents, &mut rng);
+        // the shock comes after everyone has arrived
+        assert_eq!(agents.map(|a| a.value), [0.5, 0.5]);
+        let shocks = market.shocks();
+        assert_eq!(shocks.len(), 1);
+        assert_eq!(shocks[0].last_price, Some(0.5));
+        assert_eq!(shocks[0].next_price, None);
+
+        let market = Continuous::new().with_shocks(vec![Shock { at: 1, shift: 0.25 }]);
+        let mut agents = [truthful(true, 0.5), truthful(false, 0.5)];
+        market.simulate(&mut agents, &mut rng);
+        // whoever arrives second is shifted, and truthfully outbids or underasks the other
+        assert_eq!(agents.iter().filter(|a| a.value == 0.75).count(), 1);
+        assert_eq!(agents[0].traded, agents[1].value == 0.5);
+    }
+
+    #[test]
+    fn test_latency() {
+        let mut rng = rand::thread_rng();
+        let mut slow = Agent::from_strategy(true, "", &"0_Correct_d2".parse().unwrap());
+        slow.value = 0.8;
+        slow.shade();
+        let mut agents = [slow, truthful(false, 0.2)];
+        // the buyer's order reaches the market after it closes
+        assert_eq!(Cda.simulate(&mut agents, &mut rng), None);
+        assert_eq!(agents[0].delay, 2.0);
+        assert!(agents.iter().all(|a| !a.traded));
+    }
+
+    #[test]
+    fn test_requote() {
+        let mut rng = rand::thread_rng();
+        let mut traded = false;
+        for _ in 0..50 {
+            let mut buyer = Agent::from_strategy(true, "", &"0.5_r0.1".parse().unwrap());
+            buyer.value = 0.8;
+            buyer.shade();
+            let mut agents = [buyer, truthful(false, 0.45)];
+            let price = Cda.simulate(&mut agents, &mut rng);
+            // the buyer only requotes if the seller's order arrives after it's standing
+            if let Some(price) = price {
+                assert_eq!(price, 0.45);
+                assert!((agents[0].bid - 0.5).abs() < 1e-9);
+                traded = true;
+            } else {
+                assert_eq!(agents[0].bid, 0.4);
+            }
+        }
+        assert!(traded);
+    }
+
+    #[test]
+    fn test_snapshots() {
+        let mut rng = rand::thread_rng();
+        let market = Continuous::new().with_snapshots(Snapshots::Every { every: 1 });
+        let mut agents = [truthful(true, 0.2), truthful(false, 0.8)];
+        market.simulate(&mut agents, &mut rng);
+        // two arrivals, two quotes, and the close
+        let book = market.book();
+        assert_eq!(book.len(), 5);
+        assert_eq!(book[3].bids[0].price, 0.2);
+        assert_eq!(book[3].asks[0].price, 0.8);
+        assert!(book[4].bids.is_empty() && book[4].asks.is_empty());
+
+        let market = Continuous::new().with_snapshots(Snapshots::At(vec![0, 4, 9]));
+        market.simulate(&mut agents, &mut rng);
+        assert_eq!(
+            market.book().iter().map(|b| b.event).collect::<Vec<_>>(),
+            [4]
+        );
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut rng = rand::thread_rng();
+        let market = Continuous::new().with_trace();
+        let mut agents = [truthful(true, 0.8), truthful(false, 0.2)];
+        market.simulate(&mut agents, &mut rng);
+        let trace = market.trace();
+        assert_eq!(trace.len(), 5);
+        let quotes: Vec<_> = trace
+            .iter()
+            .filter(|step| matches!(step.action, Action::Quote(_)))
+            .collect();
+        // the first order stands and the second trades with it
+        assert_eq!(quotes[0].fill, None);
+        assert_eq!(quotes[0].bids.len() + quotes[0].asks.len(), 1);
+        let fill = quotes[1].fill.unwrap();
+        assert!(
+            matches!(quotes[0].action, Action::Quote(index) if fill.counterparty == Some(index))
+        );
+        assert!(Continuous::new().trace().is_empty());
+
+        // tracing doesn't change what a cda draws
+        let mut traced = agents.clone();
+        Cda.simulate(&mut agents, &mut StdRng::seed_from_u64(1));
+        market.simulate(&mut traced, &mut StdRng::seed_from_u64(1));
+        for (agent, traced) in agents.iter().zip(&traced) {
+            assert_eq!(agent.price, traced.price);
+        }
+    }
+
+    #[test]
+    fn test_arrival_order() {
+        let agents = [
+            truthful(true, 0.8),
+            truthful(false, 0.2),
+            truthful(false, 0.5),
+        ];
+        // the buyer trades with whichever seller arrives first at its ask
+        for (order, price) in [(vec![1, 0, 2], 0.2), (vec![2, 0, 1], 0.5)] {
+            let market = Continuous::new().with_order(order.clone()).with_arrivals();
+            let mut fixed = agents.clone();
+            market.simulate(&mut fixed, &mut rand::thread_rng());
+            assert_eq!(fixed[0].price, Some(price));
+            assert_eq!(market.arrivals(), Some(order));
+        }
+
+        // recording the arrivals doesn't change what a cda draws
+        let market = Continuous::new().with_arrivals();
+        let (mut plain, mut recorded) = (agents.clone(), agents.clone());
+        Cda.simulate(&mut plain, &mut StdRng::seed_from_u64(2));
+        market.simulate(&mut recorded, &mut StdRng::seed_from_u64(2));
+        let mut arrivals = market.arrivals().unwrap();
+        assert_eq!(plain[0].price, recorded[0].price);
+        arrivals.sort_unstable();
+        assert_eq!(arrivals, [0, 1, 2]);
+        assert_eq!(Cda.arrivals(), None);
+    }
+
+    #[test]
+    fn test_closing_call() {
+        let agents = [
+            truthful(true, 0.8),
+            truthful(false, 0.2),
+            truthful(false, 0.5),
+            truthful(true, 0.7),
+        ];
+        let order = vec![1, 0, 2, 3];
+        let closing = ClosingCall { at: 0.6 };
+        let market = Continuous::new()
+            .with_order(order.clone())
+            .with_closing(closing);
+        let mut closed = agents.clone();
+        let price = market.simulate(&mut closed, &mut rand::thread_rng());
+        // the last buyer crosses the resting ask after trading stops, so they trade in the call
+        let report = market.closing().unwrap();
+        assert_eq!(report.continuous, 1);
+        assert_eq!(report.closing, 1);
+        assert_eq!(report.price, Some(0.6));
+        assert_eq!(closed[0].price, Some(0.2));
+        assert_eq!(closed[3].price, Some(0.6));
+        assert!((price.unwrap() - 0.4).abs() < 1e-9);
+
+        // without it the last buyer trades continuously at the resting ask
+        let market = Continuous::new().with_order(order);
+        let mut open = agents.clone();
+        market.simulate(&mut open, &mut rand::thread_rng());
+        assert_eq!(open[3].price, Some(0.5));
+        assert_eq!(market.closing(), None);
+
+        // closing at the end leaves nothing crossed for the call, so everyone trades the same
+        let market = Continuous::new().with_closing(ClosingCall { at: 1.0 });
+        let mut late = agents.clone();
+        let price = market.simulate(&mut late, &mut StdRng::seed_from_u64(3));
+        assert_eq!(market.closing().unwrap().closing, 0);
+        let mut plain = agents.clone();
+        assert_eq!(
+            Cda.simulate(&mut plain, &mut StdRng::seed_from_u64(3)),
+            price
+        );
+        for (late, plain) in late.iter().zip(&plain) {
+            assert_eq!(late.price, plain.price);
+        }
+    }
+
+    #[test]
+    fn test_stopping() {
+        let agents = [truthful(true, 0.8), truthful(false, 0.2)];
+        // the market always closes after the first arrival, so no one trades
+        let market = Continuous::new().with_stopping(Stopping { probability: 1.0 });
+        let mut stopped = agents.clone();
+        assert_eq!(market.simulate(&mut stopped, &mut rand::thread_rng()), None);
+        assert_eq!(market.stopped(), Some(1));
+
+        // with a tiny probability everyone almost surely arrives and trades
+        let market = Continuous::new().with_stopping(Stopping { probability: 1e-9 });
+        let mut open = agents.clone();
+        assert!(market
+            .simulate(&mut open, &mut rand::thread_rng())
+            .is_some());
+        assert_eq!(market.stopped(), Some(2));
+        assert_eq!(Cda.stopped(), None);
+
+        // buyers and sellers alternate at most so many trades before the close
+        let agents: Vec<_> = (0..10)
+            .map(|i| truthful(i < 5, (i < 5) as u8 as f64))
+            .collect();
+        let market = Continuous::new().with_stopping(Stopping { probability: 0.2 });
+        let mut rng = StdRng::seed_from_u64(4);
+        for _ in 0..20 {
+            let mut agents = agents.clone();
+            market.simulate(&mut agents, &mut rng);
+            let stopped = market.stopped().unwrap();
+            assert!((1..=10).contains(&stopped));
+            let trades = agents.iter().filter(|a| a.price.is_some()).count() / 2;
+            assert!(trades <= stopped / 2);
+        }
+    }
+
+    #[test]
+    fn test_matching() {
+        let agents = [
+            truthful(true, 0.8),
+            truthful(false, 0.3),
+            truthful(false, 0.3),
+        ];
+        // the seller that reached the book first trades
+        for (order, first) in [(vec![1, 2, 0], 1), (vec![2, 1, 0], 2)] {
+            let market = Continuous::new().with_order(order);
+            let mut matched = agents.clone();
+            market.simulate(&mut matched, &mut rand::thread_rng());
+            assert_eq!(matched[first].price, Some(0.3));
+            assert_eq!(matched[3 - first].price, None);
+        }
+
+        // pro rata sometimes lets the later one trade
+        let market = Continuous::new()
+            .with_order(vec![1, 2, 0])
+            .with_matching(Matching::ProRata);
+        let mut rng = StdRng::seed_from_u64(0);
+        let later = (0..100)
+            .filter(|_| {
+                let mut matched = agents.clone();
+                market.simulate(&mut matched, &mut rng);
+                matched[2].price.is_some()
+            })
+            .count();
+        assert!(later > 20 && later < 80, "{}", later);
+    }
+
+    #[test]
+    fn test_partial_fill() {
+        let mut agents = [
+            truthful(false, 0.2),
+            truthful(false, 0.6),
+            truthful(true, 0.9),
+            truthful(true, 0.7),
+            truthful(true, 0.5),
+        ];
+        for (unit, agent) in agents[2..].iter_mut().enumerate() {
+            agent.set_unit(3, unit);
+        }
+        // the buyer's order fills two units against both sellers, and the last rests
+        let market = Continuous::new().with_order(vec![0, 1, 2, 3, 4]);
+        let price = market.simulate(&mut agents, &mut rand::thread_rng());
+        assert_eq!(agents[2].price, Some(0.2));
+        assert_eq!(agents[3].price, Some(0.6));
+        assert_eq!(agents[4].price, None);
+        assert_eq!(price, Some(0.4));
+    }
+
+    #[test]
+    fn test_iceberg() {
+        let strat = "0_Correct_i1".parse().unwrap();
+        let mut agents = vec![truthful(true, 0.1)];
+        for (unit, value) in [0.2, 0.3, 0.4].into_iter().enumerate() {
+            let mut agent = Agent::from_strategy(false, "", &strat);
+            agent.value = value;
+            agent.shade();
+            agent.set_unit(3, unit);
+            agents.push(agent);
+        }
+        agents.extend([truthful(true, 0.9), truthful(true, 0.8)]);
+        // the seller shows one unit at a time, revealing the next after each trade
+        let market = Continuous::new()
+            .with_order(vec![0, 1, 2, 3, 4, 5])
+            .with_trace();
+        market.simulate(&mut agents, &mut rand::thread_rng());
+        assert_eq!(agents[1].price, Some(0.2));
+        assert_eq!(agents[2].price, Some(0.3));
+        assert_eq!(agents[3].price, None);
+        let trace = market.trace();
+        let quoted = &trace
+            .iter()
+            .find(|step| step.action == Action::Quote(1))
+            .unwrap()
+            .asks;
+        assert_eq!(quoted.len(), 1);
+        assert_eq!(quoted[0].depth, 1);
+
+        // the hidden units still count for the total spread after the shown ones trade
+        let spread = market.spread().unwrap();
+        assert!(spread.displayed.unwrap() > 0.0);
+        assert!(spread.total.unwrap() > spread.displayed.unwrap());
+        assert_eq!(Cda.spread(), None);
+    }
+
+    #[test]
+    fn test_stop_cascade() {
+        let trader = |strat: &str, value: f64| {
+            let strat = strat.parse().unwrap();
+            let mut sides = [false, true].map(|buyer| Agent::from_strategy(buyer, "", &strat));
+            for (side, value) in sides.iter_mut().zip([value, 0.0]) {
+                side.trader = true;
+                side.value = value;
+                side.shade();
+            }
+            sides
+        };
+        let market = |first: &str, second: &str| {
+            let mut agents = [trader(first, 0.6), trader(second, 0.7)].concat();
+            agents.extend([
+                truthful(true, 0.45),
+                truthful(true, 0.4),
+                truthful(true, 0.3),
+                truthful(false, 0.1),
+            ]);
+            let market = Continuous::new().with_order(vec![4, 5, 6, 0, 1, 2, 3, 7]);
+            market.simulate(&mut agents, &mut rand::thread_rng());
+            (agents, market.stops())
+        };
+
+        // the last seller's trade stops the first trader, whose sale stops the second
+        let (agents, stops) = market("0_Correct_s0.45", "0_Correct_s0.4");
+        assert_eq!(agents[7].price, Some(0.45));
+        assert_eq!(agents[0].price, Some(0.4));
+        assert_eq!(agents[2].price, Some(0.3));
+        assert_eq!(stops, Some(2));
+
+        // a lower stop isn't reached, so nothing cascades
+        let (agents, stops) = market("0_Correct_s0.3", "0_Correct");
+        assert_eq!(agents[0].price, None);
+        assert_eq!(agents[2].price, None);
+        assert_eq!(stops, Some(0));
+        assert_eq!(Cda.stops(), None);
+    }
+
+    #[test]
+    fn test_session() {
+        let mut rng = rand::thread_rng();
+        let session = Session {
+            patience: f64::INFINITY,
+            wait_cost: 1.0,
+        };
+        let market = Continuous::new().with_session(session);
+        let mut agents = [truthful(true, 0.2), truthful(false, 0.8)];
+        market.simulate(&mut agents, &mut rng);
+        agents.iter_mut().for_each(Agent::settle);
+        // neither trades, so both wait until the end of the session
+        let report = market.session().unwrap();
+        assert_eq!(report.expired, 0);
+        let waiting = report.waiting.unwrap();
+        assert!(waiting > 0.0 && waiting < 1.0);
+        let paid: f64 = agents.iter().map(|a| a.utility).sum();
+        assert!((paid + 2.0 * waiting).abs() < 1e-9);
+
+        let market = Continuous::new().with_session(Session {
+            patience: 1e-12,
+            wait_cost: 0.0,
+        });
+        let mut agents = [truthful(true, 0.8), truthful(false, 0.2)];
+        market.simulate(&mut agents, &mut rng);
+        // the first to arrive leaves before the second arrives
+        assert!(agents.iter().all(|a| !a.traded));
+        assert_eq!(market.session().unwrap().expired, 2);
+    }
 }
@@ -0,0 +1,133 @@
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+/// A whole file mapped read only into memory
+///
+/// The file mustn't change while it's mapped. Systems without mmap read the file instead.
+pub struct Mmap {
+    #[cfg(unix)]
+    ptr: *mut libc::c_void,
+    #[cfg(unix)]
+    len: usize,
+    #[cfg(not(unix))]
+    data: Vec<u8>,
+}
+
+impl Mmap {
+    #[cfg(unix)]
+    pub fn open(path: &Path) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        // empty maps are invalid
+        if len == 0 {
+            return Ok(Mmap {
+                ptr: std::ptr::null_mut(),
+                len,
+            });
+        }
+        // SAFETY: the map is private and read only, and outlives the file descriptor on its own
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the range was just mapped, and advice doesn't change its contents
+        unsafe { libc::madvise(ptr, len, libc::MADV_SEQUENTIAL) };
+        Ok(Mmap { ptr, len })
+    }
+
+    #[cfg(not(unix))]
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Mmap {
+            data: std::fs::read(path)?,
+        })
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    #[cfg(unix)]
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            &[]
+        } else {
+            // SAFETY: the map is len readable bytes until it's dropped
+            unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            // SAFETY: the map is only unmapped once, and no slice of it outlives it
+            unsafe { libc::munmap(self.ptr, self.len) };
+        }
+    }
+}
+
+/// The lines of some bytes without their line endings, like [`io::BufRead::lines`]
+pub fn lines(data: &[u8]) -> impl Iterator<Item = io::Result<String>> + '_ {
+    let mut start = 0;
+    let ends = memchr::memchr_iter(b'\n', data).chain(
+        // the last line needn't end in a newline
+        (data.last().is_some_and(|&last| last != b'\n')).then_some(data.len()),
+    );
+    ends.map(move |end| {
+        let mut line = &data[start..end];
+        start = end + 1;
+        if let Some(rest) = line.strip_suffix(b"\r") {
+            line = rest;
+        }
+        std::str::from_utf8(line)
+            .map(str::to_owned)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mmap;
+    use std::io::BufRead;
+
+    #[test]
+    fn test_lines() {
+        for data in [&b"a\nbc\r\n\nd"[..], b"a\nbc\n\nd\n", b"", b"\n", b"x"] {
+            let lines: Vec<_> = super::lines(data).map(Result::unwrap).collect();
+            let expected: Vec<_> = data.lines().map(Result::unwrap).collect();
+            assert_eq!(lines, expected);
+        }
+        assert!(super::lines(b"\xff\n").next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_mmap() {
+        let path = std::env::temp_dir().join(format!("cdasim-mmap-{}", std::process::id()));
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+        let map = Mmap::open(&path).unwrap();
+        assert_eq!(&map[..], b"first\nsecond\n");
+        drop(map);
+        std::fs::write(&path, "").unwrap();
+        assert!(Mmap::open(&path).unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{Agent, Features};
+use serde::Serialize;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Running aggregates of every observation simulated so far
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub observations: u64,
+    /// Mean efficiency of observations where it's defined
+    pub efficiency: Option<f64>,
+    pub surplus: f64,
+    /// Mean number of agents that traded
+    pub traded: f64,
+    /// Fraction of observations where no one traded
+    pub no_trade: f64,
+    pub seconds: f64,
+}
+
+#[derive(Debug)]
+struct Monitor {
+    every: u64,
+    path: Option<PathBuf>,
+    start: Instant,
+    observations: u64,
+    efficient: u64,
+    efficiency: f64,
+    surplus: f64,
+    traded: f64,
+    no_trade: f64,
+}
+
+impl Monitor {
+    fn stats(&self) -> Stats {
+        let count = self.observations.max(1) as f64;
+        Stats {
+            observations: self.observations,
+            efficiency: (self.efficient > 0).then(|| self.efficiency / self.efficient as f64),
+            surplus: self.surplus / count,
+            traded: self.traded / count,
+            no_trade: self.no_trade / count,
+            seconds: self.start.elapsed().as_secs_f64(),
+        }
+    }
+}
+
+static MONITOR: Mutex<Option<Monitor>> = Mutex::new(None);
+
+/// Start aggregating observations, reporting every `every` of them, and also replacing the file at
+/// `path` with the aggregates as json if given
+pub fn enable(every: u64, path: Option<PathBuf>) {
+    *MONITOR.lock().unwrap() = Some(Monitor {
+        every,
+        path,
+        start: Instant::now(),
+        observations: 0,
+        efficient: 0,
+        efficiency: 0.0,
+        surplus: 0.0,
+        traded: 0.0,
+        no_trade: 0.0,
+    });
+}
+
+/// Add an observation to the aggregates if monitoring is enabled, reporting them if it's time
+pub fn observe(agents: &[Agent<'_>], features: &Features) -> io::Result<()> {
+    let mut monitor = MONITOR.lock().unwrap();
+    let Some(monitor) = monitor.as_mut() else {
+        return Ok(());
+    };
+    monitor.observations += 1;
+    if let Some(efficiency) = features.efficiency {
+        monitor.efficient += 1;
+        monitor.efficiency += efficiency;
+    }
+    monitor.surplus += features.surplus;
+    let traded = agents.iter().filter(|a| a.traded).count();
+    monitor.traded += traded as f64;
+    if traded == 0 {
+        monitor.no_trade += 1.0;
+    }
+    if !monitor.observations.is_multiple_of(monitor.every) {
+        return Ok(());
+    }
+    let stats = monitor.stats();
+    report(&mut io::stderr(), &stats)?;
+    if let Some(path) = &monitor.path {
+        // replaced atomically so it can be read at any time
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, serde_json::to_string(&stats)?)?;
+        std::fs::rename(&tmp, path)?;
+    }
+    Ok(())
+}
+
+/// Write one line of aggregates
+fn report(out: &mut impl Write, stats: &Stats) -> io::Result<()> {
+    let efficiency = stats
+        .efficiency
+        .map_or_else(|| "-".to_owned(), |e| format!("{:.4}", e));
+    writeln!(
+        out,
+        "{} observations in {:.1}s: efficiency {}, surplus {:.4}, traded {:.2}, no trade {:.4}",
+        stats.observations, stats.seconds, efficiency, stats.surplus, stats.traded, stats.no_trade
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Stats;
+
+    #[test]
+    fn test_report() {
+        let stats = Stats {
+            observations: 10,
+            efficiency: None,
+            surplus: 0.5,
+            traded: 2.0,
+            no_trade: 0.1,
+            seconds: 1.5,
+        };
+        let mut out = Vec::new();
+        super::report(&mut out, &stats).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "10 observations in 1.5s: efficiency -, surplus 0.5000, traded 2.00, no trade 0.1000\n"
+        );
+    }
+}
@@ -0,0 +1,401 @@
+use crate::agent::{Agent, Style};
+use crate::market::{Call, Cda, Market};
+use crate::stats;
+use crate::strategy::{Presets, Shading};
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
+use std::f64::consts::PI;
+use std::io::{self, BufRead, Write};
+
+#[derive(Parser, Debug)]
+/// Search for the best response shading of one agent against each input spec
+///
+/// Takes the same spec lines as the default command on stdin. The spec's assignment is the fixed
+/// profile of opponents, and one additional agent in --role searches over its shading. Each line
+/// of output is the best shading found, its estimated payoff, and every evaluated point.
+pub struct OptimizeArgs {
+    /// Role of the optimizing agent
+    #[clap(long, value_enum, default_value_t = Role::Buyers)]
+    role: Role,
+
+    /// Style of the optimizing agent, defaulting to the spec's style
+    #[clap(long, value_parser)]
+    style: Option<Style>,
+
+    /// How to search over shadings
+    #[clap(long, value_enum, default_value_t = Search::Grid)]
+    search: Search,
+
+    /// Smallest shading to consider
+    #[clap(long, value_parser, default_value_t = 0.0)]
+    low: f64,
+
+    /// Largest shading to consider
+    #[clap(long, value_parser, default_value_t = 1.0)]
+    high: f64,
+
+    /// Number of grid points, golden-section iterations, or shadings chosen by Bayesian
+    /// optimization
+    #[clap(long, value_parser, default_value_t = 11)]
+    points: u64,
+
+    /// Number of simulations used to estimate the payoff of each shading
+    #[clap(long, value_parser, default_value_t = 1000)]
+    samples: u64,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Buyers,
+    Sellers,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Search {
+    /// Evaluate evenly spaced shadings
+    Grid,
+    /// Golden-section search, assuming the payoff is unimodal in shading
+    Golden,
+    /// Bayesian optimization, which models the payoff with a Gaussian process and simulates the
+    /// shading with the greatest expected improvement next
+    Bayes,
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+struct Point {
+    shading: f64,
+    payoff: f64,
+}
+
+#[derive(Serialize, Debug)]
+struct BestResponse {
+    shading: f64,
+    payoff: f64,
+    curve: Vec<Point>,
+}
+
+pub fn optimize(
+    args: &OptimizeArgs,
+    presets: &Presets,
+    strict: bool,
+    ihandle: impl BufRead,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    if !args.low.is_finite() || !args.high.is_finite() || args.low > args.high || args.points == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "optimize needs finite low <= high and at least one point",
+        ));
+    }
+    for (index, line) in ihandle.lines().enumerate() {
+        let _span = tracing::info_span!("spec", line = index).entered();
+        let spec = crate::parse_spec(&line?, strict, presets)?;
+        let buyer = args.role == Role::Buyers;
+        let role_style = if buyer {
+            spec.configuration.buyer_style
+        } else {
+            spec.configuration.seller_style
+        };
+        let style = args
+            .style
+            .or(role_style)
+            .or(spec.configuration.style)
+            .unwrap_or(Style::Standard);
+        if matches!(style, Style::External | Style::Bne) {
+            // Bne agents ignore the shading being optimized
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("optimize doesn't support a {:?} deviator", style),
+            ));
+        }
+        let mut deviator = Agent::new(buyer, "", style, Shading::Fixed(0.0));
+        crate::configure_agent(&spec, &mut deviator)?;
+        let mut agents = vec![deviator];
+        agents.extend(crate::build_agents(&spec, presets)?);
+        let best = if spec.configuration.cda.unwrap_or(true) {
+            best_response(args, &mut agents, &Cda)
+        } else {
+            best_response(args, &mut agents, &Call)
+        };
+        serde_json::to_writer(&mut *out, &best)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Find the best shading for the first agent
+fn best_response(
+    args: &OptimizeArgs,
+    agents: &mut [Agent<'_>],
+    market: &impl Market,
+) -> BestResponse {
+    let deviator = agents[0].clone();
+    let mut curve = Vec::new();
+    let mut rng = rand::thread_rng();
+    let mut evaluate = |shading: f64| {
+        agents[0] = deviator.clone();
+        agents[0].set_shading(Shading::Fixed(shading));
+        let mut payoff = 0.0;
+        for _ in 0..args.samples {
+            crate::run_sim(agents, market, &mut rng);
+            payoff += agents[0].utility / args.samples as f64;
+        }
+        curve.push(Point { shading, payoff });
+        payoff
+    };
+
+    match args.search {
+        Search::Grid => {
+            let step = (args.high - args.low) / (args.points.max(2) - 1) as f64;
+            for ind in 0..args.points {
+                evaluate(args.low + step * ind as f64);
+            }
+        }
+        Search::Golden => golden_section(args.low, args.high, args.points, evaluate),
+        Search::Bayes => bayes(args.low, args.high, args.points, evaluate),
+    }
+
+    curve.sort_by(|a, b| a.shading.total_cmp(&b.shading));
+    let best = curve
+        .iter()
+        .copied()
+        .reduce(|best, point| {
+            if point.payoff > best.payoff {
+                point
+            } else {
+                best
+            }
+        })
+        .expect("no points evaluated");
+    BestResponse {
+        shading: best.shading,
+        payoff: best.payoff,
+        curve,
+    }
+}
+
+/// Narrow [low, high] toward the maximum of `func` with `iters` golden-section steps
+fn golden_section(mut low: f64, mut high: f64, iters: u64, mut func: impl FnMut(f64) -> f64) {
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    let mut left = high - ratio * (high - low);
+    let mut right = low + ratio * (high - low);
+    let mut left_val = func(left);
+    let mut right_val = func(right);
+    for _ in 1..iters {
+        if left_val >= right_val {
+            high = right;
+            right = left;
+            right_val = left_val;
+            left = high - ratio * (high - low);
+            left_val = func(left);
+        } else {
+            low = left;
+            left = right;
+            left_val = right_val;
+            right = low + ratio * (high - low);
+            right_val = func(right);
+        }
+    }
+}
+
+/// Candidate points per unit of range that expected improvement is maximized over
+const BAYES_CANDIDATES: usize = 200;
+
+/// Length scale of the Gaussian process kernel as a fraction of the range
+const BAYES_LENGTH: f64 = 0.15;
+
+/// Variance of the noise of each evaluation relative to the variance of the payoffs, since they're
+/// estimated from simulations
+const BAYES_NOISE: f64 = 0.01;
+
+/// Evaluate `func` `iters` times, first at the ends and middle of [low, high], and then where a
+/// Gaussian process fit to the evaluations so far expects the most improvement over the best
+fn bayes(low: f64, high: f64, iters: u64, mut func: impl FnMut(f64) -> f64) {
+    let mut xs = Vec::new();
+    let mut ys = Vec::new();
+    for x in [low, high, (low + high) / 2.0]
+        .into_iter()
+        .take(iters as usize)
+    {
+        xs.push(x);
+        ys.push(func(x));
+    }
+    let length = BAYES_LENGTH * (high - low);
+    for _ in xs.len() as u64..iters {
+        // standardize payoffs so the kernel's unit variance fits them
+        let mean = stats::mean(&ys);
+        let scale = stats::variance(&ys).sqrt();
+        let scale = if scale > 0.0 { scale } else { 1.0 };
+        let targets: Vec<_> = ys.iter().map(|y| (y - mean) / scale).collect();
+        let best = targets.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+        let kernel = |a: f64, b: f64| (-(a - b) * (a - b) / (2.0 * length * length)).exp();
+        let mut cov: Vec<Vec<f64>> = xs
+            .iter()
+            .map(|&a| xs.iter().map(|&b| kernel(a, b)).collect())
+            .collect();
+        for (ind, row) in cov.iter_mut().enumerate() {
+            row[ind] += BAYES_NOISE;
+        }
+        let chol = cholesky(&cov);
+        let weights = solve_transposed(&chol, &solve_lower(&chol, &targets));
+
+        let next = (0..=BAYES_CANDIDATES)
+            .map(|ind| low + (high - low) * ind as f64 / BAYES_CANDIDATES as f64)
+            .map(|x| {
+                let cross: Vec<_> = xs.iter().map(|&point| kernel(x, point)).collect();
+                let pred: f64 = cross.iter().zip(&weights).map(|(k, w)| k * w).sum();
+                let proj = solve_lower(&chol, &cross);
+                let var = 1.0 - proj.iter().map(|p| p * p).sum::<f64>();
+                (x, expected_improvement(pred - best, var.max(0.0).sqrt()))
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(x, _)| x)
+            .expect("there's always a candidate");
+        xs.push(next);
+        ys.push(func(next));
+    }
+}
+
+/// Expected improvement of a normal prediction `gain` above the best with deviation `sigma`
+fn expected_improvement(gain: f64, sigma: f64) -> f64 {
+    if sigma <= 0.0 {
+        return gain.max(0.0);
+    }
+    let z = gain / sigma;
+    let density = (-z * z / 2.0).exp() / (2.0 * PI).sqrt();
+    gain * stats::normal_cdf(z) + sigma * density
+}
+
+/// Lower triangular Cholesky factor of a positive definite matrix
+fn cholesky(mat: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    let size = mat.len();
+    let mut chol = vec![vec![0.0; size]; size];
+    for row in 0..size {
+        for col in 0..=row {
+            let dot: f64 = (0..col).map(|ind| chol[row][ind] * chol[col][ind]).sum();
+            chol[row][col] = if row == col {
+                (mat[row][row] - dot).max(f64::MIN_POSITIVE).sqrt()
+            } else {
+                (mat[row][col] - dot) / chol[col][col]
+            };
+        }
+    }
+    chol
+}
+
+/// Solve `chol x = rhs` for lower triangular `chol`
+fn solve_lower(chol: &[Vec<f64>], rhs: &[f64]) -> Vec<f64> {
+    let mut sol = Vec::with_capacity(rhs.len());
+    for (row, val) in chol.iter().zip(rhs) {
+        let dot: f64 = row.iter().zip(&sol).map(|(a, b)| a * b).sum();
+        sol.push((val - dot) / row[sol.len()]);
+    }
+    sol
+}
+
+/// Solve `chol' x = rhs` for lower triangular `chol`
+fn solve_transposed(chol: &[Vec<f64>], rhs: &[f64]) -> Vec<f64> {
+    let size = rhs.len();
+    let mut sol = vec![0.0; size];
+    for row in (0..size).rev() {
+        let dot: f64 = (row + 1..size).map(|ind| chol[ind][row] * sol[ind]).sum();
+        sol[row] = (rhs[row] - dot) / chol[row][row];
+    }
+    sol
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OptimizeArgs, Role, Search};
+    use crate::agent::Style;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_golden_section() {
+        let mut points = Vec::new();
+        super::golden_section(0.0, 1.0, 30, |x| {
+            points.push(x);
+            -(x - 0.3) * (x - 0.3)
+        });
+        assert_eq!(points.len(), 31);
+        assert!((points.last().unwrap() - 0.3).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_bayes() {
+        let mut points = Vec::new();
+        super::bayes(0.0, 1.0, 15, |x| {
+            points.push(x);
+            -(x - 0.3) * (x - 0.3)
+        });
+        assert_eq!(points.len(), 15);
+        let best = points
+            .iter()
+            .copied()
+            .min_by(|a, b| (a - 0.3).abs().total_cmp(&(b - 0.3).abs()))
+            .unwrap();
+        assert!((best - 0.3).abs() < 0.02);
+        assert!(points.iter().all(|x| (0.0..=1.0).contains(x)));
+    }
+
+    #[test]
+    fn test_cholesky() {
+        let mat = vec![vec![4.0, 2.0], vec![2.0, 3.0]];
+        let chol = super::cholesky(&mat);
+        let sol = super::solve_transposed(&chol, &super::solve_lower(&chol, &[2.0, 1.0]));
+        // [4 2; 2 3] [0.5 0] = [2 1]
+        assert!((sol[0] - 0.5).abs() < 1e-12 && sol[1].abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_optimize() {
+        for search in [Search::Grid, Search::Golden, Search::Bayes] {
+            let args = OptimizeArgs {
+                role: Role::Sellers,
+                style: None,
+                search,
+                low: 0.0,
+                high: 1.0,
+                points: 5,
+                samples: 10,
+            };
+            let input = r#"{"assignment":{"buyers":{"0.2":3},"sellers":{"0.3":2}},"configuration":{"cda":false}}"#;
+            let mut out = Vec::new();
+            super::optimize(&args, &HashMap::new(), false, input.as_bytes(), &mut out).unwrap();
+            let best: serde_json::Value = serde_json::from_slice(&out).unwrap();
+            let curve = best["curve"].as_array().unwrap();
+            assert!(!curve.is_empty());
+            let max = curve
+                .iter()
+                .map(|p| p["payoff"].as_f64().unwrap())
+                .fold(f64::NEG_INFINITY, f64::max);
+            assert_eq!(best["payoff"].as_f64().unwrap(), max);
+        }
+    }
+
+    #[test]
+    fn test_unsupported_style() {
+        for style in [Style::External, Style::Bne] {
+            let args = OptimizeArgs {
+                role: Role::Sellers,
+                style: Some(style),
+                search: Search::Grid,
+                low: 0.0,
+                high: 1.0,
+                points: 2,
+                samples: 1,
+            };
+            let input = r#"{"assignment":{"buyers":{"0":1},"sellers":{"0":1}},"configuration":{}}"#;
+            let err = super::optimize(
+                &args,
+                &HashMap::new(),
+                false,
+                input.as_bytes(),
+                &mut Vec::new(),
+            )
+            .unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use crate::strategy::Presets;
+use crate::values::Values;
+use crate::{Agent, Args, Call, Cda, Spec, SpecEcho};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::Serialize;
+use std::io::{self, Write};
+use std::time::Instant;
+
+/// How many throwaway observations of each spec are timed to estimate its runtime
+const PROBE_OBS: u64 = 3;
+
+/// What simulating a spec line would do
+#[derive(Serialize, Debug)]
+struct Plan {
+    spec_index: usize,
+    spec_hash: String,
+    /// The number of agents, counting both sides of traders and every unit of a lot
+    agents: usize,
+    /// Simulations run before the observations that are discarded
+    episodes: u64,
+    observations: u64,
+    /// The most observations --target-se may extend it to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_observations: Option<u64>,
+    /// The single threaded seconds to simulate the spec, unless it has external agents, before any
+    /// observations --target-se adds
+    estimated_seconds: Option<f64>,
+    spec: SpecEcho,
+}
+
+/// Why a spec line can't be simulated
+#[derive(Serialize, Debug)]
+struct Invalid {
+    spec_index: usize,
+    spec_hash: String,
+    error: String,
+}
+
+/// Write the plan of every spec line, or why it's invalid, erroring at the end if any were
+pub fn dry_run(
+    args: &Args,
+    presets: &Presets,
+    strict: bool,
+    lines: impl Iterator<Item = io::Result<String>>,
+    mut out: &mut impl Write,
+) -> io::Result<()> {
+    let default_values = args
+        .values
+        .as_deref()
+        .map(|path| Values::load(path, true))
+        .transpose()?;
+    let (mut specs, mut invalid) = (0, 0);
+    for (spec_index, line) in lines.enumerate() {
+        let line = line?;
+        specs += 1;
+        match plan(
+            args,
+            presets,
+            strict,
+            default_values.as_ref(),
+            spec_index,
+            &line,
+        ) {
+            Ok(plan) => serde_json::to_writer(&mut out, &plan)?,
+            Err(err) => {
+                invalid += 1;
+                let invalid = Invalid {
+                    spec_index,
+                    spec_hash: crate::spec_hash(&line),
+                    error: err.to_string(),
+                };
+                serde_json::to_writer(&mut out, &invalid)?;
+            }
+        }
+        writeln!(&mut out)?;
+    }
+    if invalid > 0 {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} of {} specs are invalid", invalid, specs),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn plan(
+    args: &Args,
+    presets: &Presets,
+    strict: bool,
+    default_values: Option<&Values>,
+    spec_index: usize,
+    line: &str,
+) -> io::Result<Plan> {
+    let mut spec = crate::parse_spec(line, strict, presets)?;
+    if spec.configuration.values.is_none() {
+        spec.configuration.values = default_values.cloned();
+    }
+    let mut agents = crate::build_agents(&spec, presets)?;
+    crate::parse_derived(&spec.configuration)?;
+    let market = crate::market_name(&spec.configuration, &agents)?;
+    let (episodes, observations) = crate::spec_obs(&spec.configuration, args)?;
+    // external agents would start their command
+    let estimated_seconds = match spec.configuration.external {
+        Some(_) => None,
+        None => Some(probe(&spec, &mut agents)? * (episodes + observations) as f64),
+    };
+    Ok(Plan {
+        spec_index,
+        spec_hash: crate::spec_hash(line),
+        agents: agents.len(),
+        episodes,
+        observations,
+        max_observations: args.target_se.map(|_| args.max_obs.max(observations)),
+        estimated_seconds,
+        spec: SpecEcho::new(line, &spec, market, presets)?,
+    })
+}
+
+/// The seconds one observation of a spec takes
+fn probe(spec: &Spec, agents: &mut [Agent<'_>]) -> io::Result<f64> {
+    let mut rng = StdRng::seed_from_u64(0);
+    let start = Instant::now();
+    for _ in 0..PROBE_OBS {
+        match crate::continuous_market(&spec.configuration, agents, false)? {
+            Some(market) => crate::run_sim(agents, &market, &mut rng),
+            None if spec.configuration.cda.unwrap_or(true) => {
+                crate::run_sim(agents, &Cda, &mut rng)
+            }
+            None => crate::run_sim(agents, &Call, &mut rng),
+        };
+    }
+    Ok(start.elapsed().as_secs_f64() / PROBE_OBS as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Args;
+    use clap::Parser;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_dry_run() {
+        let args = Args::parse_from(["cdasim", "--obs", "7", "--dry-run"]);
+        let specs = concat!(
+            r#"{"assignment":{"buyers":{"0.1":2,"0.10":1},"traders":{"0":1}},"configuration":{"cda":false,"episodes":2}}"#,
+            "\n",
+            r#"{"assignment":{"buyers":{"x":1}},"configuration":{}}"#,
+            "\n",
+            r#"{"assignment":{"sellers":{"0":1}},"configuration":{"episodes":2,"burn_in":false}}"#,
+        );
+        let mut out = Vec::new();
+        let lines = specs.lines().map(|line| Ok(line.to_owned()));
+        let res = super::dry_run(&args, &HashMap::new(), false, lines, &mut out);
+        assert!(res.is_err());
+        let plans: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&out)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(plans.len(), 3);
+        assert_eq!(plans[0]["agents"], 5);
+        assert_eq!(plans[0]["episodes"], 2);
+        assert_eq!(plans[0]["observations"], 7);
+        assert!(plans[0]["estimated_seconds"].as_f64().unwrap() >= 0.0);
+        assert_eq!(plans[0]["spec"]["market"], "call");
+        assert_eq!(plans[0]["spec"]["assignment"]["buyers"]["0.1"], 3);
+        assert_eq!(plans[1]["spec_index"], 1);
+        assert!(plans[1]["error"].is_string());
+        assert_eq!(plans[2]["episodes"], 0);
+        assert_eq!(plans[2]["observations"], 9);
+        assert_eq!(plans[2]["spec"]["market"], "cda");
+    }
+}
@@ -0,0 +1,236 @@
+use crate::engine::Action;
+use crate::market::Step;
+use crate::strategy::Presets;
+use crate::{Agent, Features};
+use clap::Parser;
+use plotters::coord::types::RangedCoordf64;
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+
+/// Size of each panel in pixels
+const WIDTH: u32 = 500;
+const HEIGHT: u32 = 400;
+
+/// Space around each panel for labels
+const MARGIN: u32 = 30;
+
+#[derive(Parser, Debug)]
+/// Plot one observation of a spec as an svg
+///
+/// Replays the spec at --line with --seed like inspect, and writes an svg with two panels: the
+/// demand and supply curves of agents' net values with the band of competitive equilibrium prices
+/// shaded and the "ce_price", the midpoint of the marginal pair, dashed, and the price of every
+/// trade over the unit of time of a CDA, or the clearing price of a call market.
+pub struct PlotArgs {
+    /// Seed of the observation to plot
+    #[clap(long, value_parser)]
+    seed: u64,
+
+    /// Index of the spec line to plot
+    #[clap(long, value_parser, default_value_t = 0)]
+    line: usize,
+}
+
+pub fn plot(
+    args: &PlotArgs,
+    presets: &Presets,
+    strict: bool,
+    ihandle: impl BufRead,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    crate::inspect::replay(
+        args.line,
+        args.seed,
+        presets,
+        strict,
+        ihandle,
+        |agents, features, trace| out.write_all(svg(agents, features, trace)?.as_bytes()),
+    )
+}
+
+/// Net values of one side, from most to least willing to trade
+fn curve(agents: &[Agent<'_>], buyer: bool) -> Vec<f64> {
+    let mut values: Vec<_> = agents
+        .iter()
+        .filter(|a| a.buyer == buyer)
+        .map(Agent::net_value)
+        .collect();
+    values.sort_unstable_by(f64::total_cmp);
+    if buyer {
+        values.reverse();
+    }
+    values
+}
+
+/// The points of a step function that's `values[i]` over [i, i + 1]
+fn steps(values: &[f64]) -> Vec<(f64, f64)> {
+    values
+        .iter()
+        .enumerate()
+        .flat_map(|(i, &value)| [(i as f64, value), (i as f64 + 1.0, value)])
+        .collect()
+}
+
+/// The range of prices that clear the market with the most trades, if anyone can trade
+fn band(demand: &[f64], supply: &[f64]) -> Option<(f64, f64)> {
+    let trades = demand
+        .iter()
+        .zip(supply)
+        .take_while(|(buy, sell)| buy >= sell)
+        .count();
+    let last = trades.checked_sub(1)?;
+    let low = supply[last].max(demand.get(trades).copied().unwrap_or(f64::NEG_INFINITY));
+    let high = demand[last].min(supply.get(trades).copied().unwrap_or(f64::INFINITY));
+    Some((low, high))
+}
+
+/// Render an observation as an svg
+pub fn svg(
+    agents: &[Agent<'_>],
+    features: &Features,
+    trace: Option<&[Step]>,
+) -> io::Result<String> {
+    let demand = curve(agents, true);
+    let supply = curve(agents, false);
+    let prices: Vec<(f64, f64)> = match trace {
+        Some(trace) => trace
+            .iter()
+            .filter(|step| matches!(step.action, Action::Quote(_)))
+            .filter_map(|step| step.fill.map(|fill| (step.time, fill.price)))
+            .collect(),
+        // a call market trades everyone at one price at the end
+        None => agents
+            .iter()
+            .find_map(|a| a.price)
+            .map(|price| vec![(0.0, price), (1.0, price)])
+            .unwrap_or_default(),
+    };
+    let all = demand
+        .iter()
+        .chain(&supply)
+        .chain(prices.iter().map(|(_, price)| price));
+    let (low, high) = all.fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), &v| {
+        (low.min(v), high.max(v))
+    });
+    let (low, high) = if low < high {
+        (low, high)
+    } else if low.is_finite() {
+        (low - 0.5, low + 0.5)
+    } else {
+        (0.0, 1.0)
+    };
+    let pad = (high - low) * 0.05;
+    let ys = low - pad..high + pad;
+    let band = band(&demand, &supply);
+    let units = demand.len().max(supply.len()).max(1) as f64;
+    let title = if trace.is_some() {
+        "trade prices"
+    } else {
+        "clearing price"
+    };
+
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, (2 * WIDTH, HEIGHT)).into_drawing_area();
+        root.fill(&WHITE).map_err(io::Error::other)?;
+        let (left, right) = root.split_horizontally(WIDTH);
+
+        let mut curves = chart(&left, "demand and supply", "units", 0.0..units, ys.clone())?;
+        equilibrium(&mut curves, 0.0..units, band, features.ce_price)?;
+        for (values, color) in [(&demand, BLUE), (&supply, RED)] {
+            curves
+                .draw_series(LineSeries::new(steps(values), color.stroke_width(2)))
+                .map_err(io::Error::other)?;
+        }
+
+        let mut path = chart(&right, title, "time", 0.0..1.0, ys)?;
+        equilibrium(&mut path, 0.0..1.0, band, features.ce_price)?;
+        path.draw_series(LineSeries::new(prices.iter().copied(), BLACK))
+            .map_err(io::Error::other)?;
+        path.draw_series(
+            prices
+                .iter()
+                .map(|&point| Circle::new(point, 3, BLACK.filled())),
+        )
+        .map_err(io::Error::other)?;
+        root.present().map_err(io::Error::other)?;
+    }
+    Ok(svg)
+}
+
+type Chart<'a, 'b> = ChartContext<'a, SVGBackend<'b>, Cartesian2d<RangedCoordf64, RangedCoordf64>>;
+
+/// A panel with a title, labeled axes, and the range of each
+fn chart<'a, 'b>(
+    area: &'a DrawingArea<SVGBackend<'b>, Shift>,
+    title: &str,
+    xlabel: &str,
+    xs: Range<f64>,
+    ys: Range<f64>,
+) -> io::Result<Chart<'a, 'b>> {
+    let mut chart = ChartBuilder::on(area)
+        .caption(title, ("sans-serif", 16))
+        .margin(MARGIN)
+        .x_label_area_size(MARGIN)
+        .y_label_area_size(MARGIN)
+        .build_cartesian_2d(xs, ys)
+        .map_err(io::Error::other)?;
+    chart
+        .configure_mesh()
+        .disable_mesh()
+        .x_desc(xlabel)
+        .x_labels(5)
+        .y_labels(5)
+        .draw()
+        .map_err(io::Error::other)?;
+    Ok(chart)
+}
+
+/// The band of equilibrium prices and the equilibrium price across a panel
+fn equilibrium(
+    chart: &mut Chart<'_, '_>,
+    xs: Range<f64>,
+    band: Option<(f64, f64)>,
+    price: Option<f64>,
+) -> io::Result<()> {
+    if let Some((low, high)) = band {
+        let shade = Rectangle::new([(xs.start, low), (xs.end, high)], BLACK.mix(0.1).filled());
+        chart.draw_series([shade]).map_err(io::Error::other)?;
+    }
+    if let Some(price) = price {
+        let line = DashedLineSeries::new([(xs.start, price), (xs.end, price)], 4, 4, BLACK.into());
+        chart.draw_series(line).map_err(io::Error::other)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PlotArgs;
+    use clap::Parser;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_band() {
+        // two buyers above two sellers, where the third seller is above the second buyer
+        let band = super::band(&[0.9, 0.6, 0.1], &[0.2, 0.4, 0.7]);
+        assert_eq!(band, Some((0.4, 0.6)));
+        assert_eq!(super::band(&[0.9, 0.6], &[0.2, 0.4, 0.5]), Some((0.4, 0.5)));
+        assert_eq!(super::band(&[0.1], &[0.2]), None);
+    }
+
+    #[test]
+    fn test_plot() {
+        let spec = r#"{"assignment":{"buyers":{"0.2":3},"sellers":{"0.1":4}},"configuration":{}}"#;
+        for spec in [spec.to_owned(), spec.replace("{}}", r#"{"cda":false}}"#)] {
+            let args = PlotArgs::parse_from(["plot", "--seed", "4"]);
+            let mut out = Vec::new();
+            super::plot(&args, &HashMap::new(), false, spec.as_bytes(), &mut out).unwrap();
+            let svg = String::from_utf8(out).unwrap();
+            assert!(svg.starts_with("<svg") && svg.ends_with("</svg>\n"));
+            assert!(svg.contains("demand and supply"));
+        }
+    }
+}
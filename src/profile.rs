@@ -0,0 +1,119 @@
+use rand::RngCore;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
+use tracing::trace_span;
+
+/// The parts of simulating a spec that are timed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Parse,
+    Build,
+    Resample,
+    Equilibrium,
+    Market,
+    Learn,
+}
+
+const PHASES: [Phase; 6] = [
+    Phase::Parse,
+    Phase::Build,
+    Phase::Resample,
+    Phase::Equilibrium,
+    Phase::Market,
+    Phase::Learn,
+];
+
+impl Phase {
+    fn name(self) -> &'static str {
+        match self {
+            Phase::Parse => "parse",
+            Phase::Build => "build",
+            Phase::Resample => "resample",
+            Phase::Equilibrium => "equilibrium",
+            Phase::Market => "market",
+            Phase::Learn => "learn",
+        }
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static NANOS: [AtomicU64; PHASES.len()] = [const { AtomicU64::new(0) }; PHASES.len()];
+static CALLS: [AtomicU64; PHASES.len()] = [const { AtomicU64::new(0) }; PHASES.len()];
+
+/// Start accumulating the time spent in each phase
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Run one phase of a simulation in its own span, timing it if profiling is enabled
+pub fn time<T>(phase: Phase, func: impl FnOnce() -> T) -> T {
+    let _span = trace_span!("phase", name = phase.name()).entered();
+    if ENABLED.load(Ordering::Relaxed) {
+        let start = Instant::now();
+        let res = func();
+        let nanos = start.elapsed().as_nanos() as u64;
+        NANOS[phase as usize].fetch_add(nanos, Ordering::Relaxed);
+        CALLS[phase as usize].fetch_add(1, Ordering::Relaxed);
+        res
+    } else {
+        func()
+    }
+}
+
+/// A random number generator that counts how many times it's drawn from
+pub struct Counting<R> {
+    rng: R,
+    pub draws: u64,
+}
+
+impl<R> Counting<R> {
+    pub fn new(rng: R) -> Self {
+        Counting { rng, draws: 0 }
+    }
+}
+
+impl<R: RngCore> RngCore for Counting<R> {
+    fn next_u32(&mut self) -> u32 {
+        self.draws += 1;
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.draws += 1;
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.draws += 1;
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.draws += 1;
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+/// Write the total and mean time of each phase, and its share of the total
+pub fn report(out: &mut impl Write) -> io::Result<()> {
+    let total: u64 = NANOS.iter().map(|n| n.load(Ordering::Relaxed)).sum();
+    writeln!(
+        out,
+        "{:<12} {:>12} {:>8} {:>12}",
+        "phase", "total (s)", "share", "mean (us)"
+    )?;
+    for phase in PHASES {
+        let nanos = NANOS[phase as usize].load(Ordering::Relaxed);
+        let calls = CALLS[phase as usize].load(Ordering::Relaxed).max(1);
+        writeln!(
+            out,
+            "{:<12} {:>12.3} {:>7.1}% {:>12.3}",
+            phase.name(),
+            nanos as f64 * 1e-9,
+            100.0 * nanos as f64 / total.max(1) as f64,
+            nanos as f64 * 1e-3 / calls as f64,
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use crate::market::{Call, Cda, Market};
+use crate::strategy::{Presets, Shading};
+use crate::Agent;
+use clap::Parser;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+
+#[derive(Parser, Debug)]
+/// Estimate the gain each strategy would get from bidding truthfully
+///
+/// Takes the same spec lines as the default command on stdin. For every strategy in each role, one
+/// agent playing it switches to a shading of 0, and the original and deviating profiles are
+/// simulated with the same random draws. Each line of output has a "regret" map from role to
+/// strategy to the mean payoff gain of that deviation, so positive values indicate the strategy
+/// would rather bid truthfully.
+pub struct RegretArgs {
+    /// Number of paired simulations per strategy
+    #[clap(long, value_parser, default_value_t = 1000)]
+    samples: u64,
+}
+
+/// The regrets of each role's strategies
+type Regrets<'a> = BTreeMap<&'a str, BTreeMap<&'a str, f64>>;
+
+#[derive(Serialize, Debug)]
+struct Analysis<'a> {
+    regret: Regrets<'a>,
+}
+
+pub fn regret(
+    args: &RegretArgs,
+    presets: &Presets,
+    strict: bool,
+    ihandle: impl BufRead,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    for (index, line) in ihandle.lines().enumerate() {
+        let _span = tracing::info_span!("spec", line = index).entered();
+        let spec = crate::parse_spec(&line?, strict, presets)?;
+        let mut agents = crate::build_agents(&spec, presets)?;
+        let regret = if spec.configuration.cda.unwrap_or(true) {
+            truthful_regrets(&mut agents, &Cda, args.samples)
+        } else {
+            truthful_regrets(&mut agents, &Call, args.samples)
+        };
+        serde_json::to_writer(&mut *out, &Analysis { regret })?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn truthful_regrets<'a>(
+    agents: &mut [Agent<'a>],
+    market: &impl Market,
+    samples: u64,
+) -> Regrets<'a> {
+    let mut rng = rand::thread_rng();
+    let mut regrets = Regrets::default();
+    for index in 0..agents.len() {
+        let agent = &agents[index];
+        // a trader's sides aren't separate players to deviate
+        if agent.trader {
+            continue;
+        }
+        let role = regrets.entry(agent.role()).or_default();
+        if role.contains_key(agent.strategy()) {
+            continue;
+        }
+        let mut truthful = agent.clone();
+        truthful.set_shading(Shading::Fixed(0.0));
+        let gain = crate::deviation_gain(agents, index, &truthful, market, samples, &mut rng);
+        role.insert(truthful.strategy(), gain);
+    }
+    regrets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RegretArgs;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_truthful_regret() {
+        let input = r#"{"assignment":{"buyers":{"0":3,"0.5":1},"sellers":{"0_Shift":3}},"configuration":{}}"#;
+        let mut out = Vec::new();
+        super::regret(
+            &RegretArgs { samples: 20 },
+            &HashMap::new(),
+            false,
+            input.as_bytes(),
+            &mut out,
+        )
+        .unwrap();
+        let analysis: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let regret = &analysis["regret"];
+        // truthful strategies are paired with identical simulations
+        assert_eq!(regret["buyers"]["0"].as_f64().unwrap(), 0.0);
+        assert_eq!(regret["sellers"]["0_Shift"].as_f64().unwrap(), 0.0);
+        assert!(regret["buyers"]["0.5"].is_f64());
+    }
+
+    #[test]
+    fn test_custom_roles() {
+        let input = r#"{"assignment":{"buyers":{"0.2":2},"background":{"0.2":2,"0.5":1}},"configuration":{"roles":{"background":{"side":"sellers"}}}}"#;
+        let mut out = Vec::new();
+        super::regret(
+            &RegretArgs { samples: 20 },
+            &HashMap::new(),
+            false,
+            input.as_bytes(),
+            &mut out,
+        )
+        .unwrap();
+        let analysis: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let regret = analysis["regret"].as_object().unwrap();
+        // every role's strategies are kept apart, even with the same names
+        assert_eq!(regret.keys().collect::<Vec<_>>(), ["background", "buyers"]);
+        assert_eq!(regret["buyers"].as_object().unwrap().len(), 1);
+        assert_eq!(regret["background"].as_object().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,277 @@
+use crate::market::{Call, Cda};
+use crate::strategy::Presets;
+use crate::{stream, Agent, Config, Extra, Features};
+use clap::{Parser, ValueEnum};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use tracing::warn;
+
+#[derive(Parser, Debug)]
+/// Clear recorded observations again under another market
+///
+/// Reads spec lines on stdin, and observations of them output with --tag-output from
+/// --observations. Each observation is simulated again from its "seed" under its spec's market and
+/// under --market, so both clear the same values, shadings, and arrival order, and the comparison
+/// has no sampling noise. Every observation produces a line with its "spec_index", "obs_index" and
+/// "seed", the "original" and "replayed" features, the "difference" of every numeric feature, and
+/// the difference of every player's payoff in "payoffs", all replayed minus original. Like
+/// inspect, agents start fresh, so observations of learning agents or a market maker, or after a
+/// burn in, aren't reproduced, which is warned about when the original surplus doesn't match the
+/// observation's.
+pub struct ReplayArgs {
+    /// Market to clear the observations with
+    #[clap(long, value_enum)]
+    market: Mechanism,
+
+    /// Observations output with --tag-output, optionally compressed
+    #[clap(long, value_parser)]
+    observations: PathBuf,
+
+    /// The --extra-features the observations were output with
+    #[clap(long, value_enum, value_delimiter = ',')]
+    extra_features: Vec<Extra>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mechanism {
+    /// A call market that clears every order at once
+    Call,
+    /// A continuous double auction, with any market maker, shocks, session, or arrival order of
+    /// the spec
+    Cda,
+}
+
+#[derive(Deserialize, Debug)]
+struct RecordedFeatures {
+    surplus: f64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Recorded {
+    spec_index: usize,
+    obs_index: Option<u64>,
+    seed: u64,
+    features: Option<RecordedFeatures>,
+}
+
+#[derive(Serialize, Debug)]
+struct Replayed {
+    spec_index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    obs_index: Option<u64>,
+    seed: u64,
+    original: Features,
+    replayed: Features,
+    difference: BTreeMap<String, f64>,
+    payoffs: Vec<f64>,
+}
+
+pub fn replay(
+    args: &ReplayArgs,
+    presets: &Presets,
+    strict: bool,
+    ihandle: impl BufRead,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let specs = ihandle
+        .lines()
+        .map(|line| crate::parse_spec(&line?, strict, presets))
+        .collect::<io::Result<Vec<_>>>()?;
+    let input = stream::decompress(BufReader::new(File::open(&args.observations)?))?;
+    for line in input.lines() {
+        let recorded: Recorded = serde_json::from_str(&line?)?;
+        let spec = specs.get(recorded.spec_index).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("there's no spec line {}", recorded.spec_index),
+            )
+        })?;
+        let derived = crate::parse_derived(&spec.configuration)?;
+        let template = crate::build_agents(spec, presets)?;
+
+        let mut agents = template.clone();
+        let cda = spec.configuration.cda.unwrap_or(true);
+        let mut original = simulate(&mut agents, &spec.configuration, cda, recorded.seed)?;
+        crate::derive(&mut original, &derived)?;
+        original.select(&agents, &args.extra_features);
+        if let Some(features) = &recorded.features {
+            if features.surplus != original.surplus {
+                warn!(
+                    spec_index = recorded.spec_index,
+                    obs_index = recorded.obs_index,
+                    "observation isn't reproduced by its seed"
+                );
+            }
+        }
+        let before: Vec<_> = crate::players(&agents).map(|p| p.payoff).collect();
+
+        agents.clone_from(&template);
+        let target = args.market == Mechanism::Cda;
+        let mut replayed = simulate(&mut agents, &spec.configuration, target, recorded.seed)?;
+        crate::derive(&mut replayed, &derived)?;
+        replayed.select(&agents, &args.extra_features);
+        let payoffs = crate::players(&agents)
+            .zip(before)
+            .map(|(player, before)| player.payoff - before)
+            .collect();
+
+        let line = Replayed {
+            spec_index: recorded.spec_index,
+            obs_index: recorded.obs_index,
+            seed: recorded.seed,
+            difference: difference(&original, &replayed)?,
+            original,
+            replayed,
+            payoffs,
+        };
+        serde_json::to_writer(&mut *out, &line)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Simulate one observation from its seed in a CDA or a call market
+fn simulate(
+    agents: &mut [Agent<'_>],
+    config: &Config,
+    cda: bool,
+    seed: u64,
+) -> io::Result<Features> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    if !cda {
+        return Ok(crate::run_sim(agents, &Call, &mut rng));
+    }
+    let config = Config {
+        cda: Some(true),
+        ..config.clone()
+    };
+    Ok(match crate::continuous_market(&config, agents, false)? {
+        Some(market) => crate::run_sim(agents, &market, &mut rng),
+        None => crate::run_sim(agents, &Cda, &mut rng),
+    })
+}
+
+/// The difference of every feature that's a number in both observations
+fn difference(original: &Features, replayed: &Features) -> io::Result<BTreeMap<String, f64>> {
+    let (original, replayed) = (
+        serde_json::to_value(original)?,
+        serde_json::to_value(replayed)?,
+    );
+    let (Value::Object(original), Value::Object(replayed)) = (original, replayed) else {
+        unreachable!("features are a struct");
+    };
+    Ok(original
+        .into_iter()
+        .filter_map(|(name, before)| {
+            let after = replayed.get(&name)?.as_f64()?;
+            Some((name, after - before.as_f64()?))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReplayArgs;
+    use clap::Parser;
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_replay() {
+        let spec = r#"{"assignment":{"buyers":{"0.2":3},"sellers":{"0.1":3}},"configuration":{}}"#;
+        let args = crate::Args::parse_from(["cdasim", "--obs", "3", "--tag-output"]);
+        let mut recorded = Vec::new();
+        crate::simulate_specs(
+            &args,
+            &HashMap::new(),
+            false,
+            spec.as_bytes(),
+            &mut recorded,
+        )
+        .unwrap();
+        let path = env::temp_dir().join(format!("cdasim-replay-{}.json", std::process::id()));
+        fs::write(&path, &recorded).unwrap();
+
+        let replay = |market: &str| {
+            let args = ReplayArgs::parse_from([
+                "replay",
+                "--market",
+                market,
+                "--observations",
+                path.to_str().unwrap(),
+            ]);
+            let mut out = Vec::new();
+            super::replay(&args, &HashMap::new(), false, spec.as_bytes(), &mut out).unwrap();
+            serde_json::Deserializer::from_slice(&out)
+                .into_iter::<serde_json::Value>()
+                .map(Result::unwrap)
+                .collect::<Vec<_>>()
+        };
+
+        // replaying under the same market reproduces every observation exactly
+        let recorded: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&recorded)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        let same = replay("cda");
+        assert_eq!(same.len(), 3);
+        for (line, obs) in same.iter().zip(&recorded) {
+            assert_eq!(line["original"], obs["features"]);
+            assert_eq!(line["replayed"], obs["features"]);
+            assert_eq!(line["difference"]["surplus"], 0.0);
+        }
+
+        // both markets clear the same draws, so their competitive equilibria match
+        for line in replay("call") {
+            assert_eq!(line["difference"]["ce_surplus"], 0.0);
+            assert_eq!(line["payoffs"].as_array().unwrap().len(), 6);
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_replay_fresh_learners() {
+        // every observation of learners that aren't persistent starts fresh, so it's reproduced
+        // from its seed alone
+        let spec = r#"{"assignment":{"buyers":{"1_Roth":3},"sellers":{"0.5_Bandit":3}},"configuration":{"persistent":false}}"#;
+        let args = crate::Args::parse_from(["cdasim", "--obs", "5", "--seed", "3", "--tag-output"]);
+        let mut recorded = Vec::new();
+        crate::simulate_specs(
+            &args,
+            &HashMap::new(),
+            false,
+            spec.as_bytes(),
+            &mut recorded,
+        )
+        .unwrap();
+        let path = env::temp_dir().join(format!("cdasim-persistent-{}", std::process::id()));
+        fs::write(&path, &recorded).unwrap();
+        let args = ReplayArgs::parse_from([
+            "replay",
+            "--market",
+            "cda",
+            "--observations",
+            path.to_str().unwrap(),
+        ]);
+        let mut out = Vec::new();
+        super::replay(&args, &HashMap::new(), false, spec.as_bytes(), &mut out).unwrap();
+        fs::remove_file(path).unwrap();
+        let recorded = serde_json::Deserializer::from_slice(&recorded).into_iter();
+        let replayed = serde_json::Deserializer::from_slice(&out).into_iter();
+        let pairs: Vec<(serde_json::Value, serde_json::Value)> = recorded
+            .zip(replayed)
+            .map(|(line, replayed)| (line.unwrap(), replayed.unwrap()))
+            .collect();
+        assert_eq!(pairs.len(), 5);
+        for (line, replayed) in pairs {
+            assert_eq!(replayed["original"], line["features"]);
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+/// Degree, interior coefficients, and initial direction numbers of the primitive polynomials
+/// that generate the first Sobol dimensions after the first, from Joe and Kuo's table
+const POLYNOMIALS: [(u32, u32, &[u32]); 15] = [
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+    (3, 2, &[1, 1, 1]),
+    (4, 1, &[1, 1, 3, 3]),
+    (4, 4, &[1, 3, 5, 13]),
+    (5, 2, &[1, 1, 5, 5, 17]),
+    (5, 4, &[1, 1, 5, 5, 5]),
+    (5, 7, &[1, 1, 7, 11, 19]),
+    (5, 11, &[1, 1, 5, 1, 1]),
+    (5, 13, &[1, 1, 1, 3, 11]),
+    (5, 14, &[1, 3, 5, 5, 31]),
+    (6, 1, &[1, 3, 3, 9, 7, 49]),
+    (6, 13, &[1, 1, 1, 15, 21, 21]),
+    (6, 16, &[1, 3, 1, 13, 27, 49]),
+];
+
+/// Bits of precision of each coordinate
+const BITS: usize = 32;
+
+/// Whether `poly`, of `degree` with its leading and constant terms, is primitive over GF(2)
+///
+/// It is if x has order 2^degree - 1 modulo it, i.e. x to that power is one, but not x to that
+/// power divided by any of its prime factors.
+fn primitive(poly: u64, degree: u32) -> bool {
+    let mulmod = |mut a: u64, mut b: u64| {
+        let mut prod = 0;
+        while b != 0 {
+            if b & 1 == 1 {
+                prod ^= a;
+            }
+            b >>= 1;
+            a <<= 1;
+            if a >> degree & 1 == 1 {
+                a ^= poly;
+            }
+        }
+        prod
+    };
+    let powmod = |mut exp: u64| {
+        // x, which is reduced when the polynomial is x + 1
+        let (mut base, mut pow) = (mulmod(1, 2), 1);
+        while exp != 0 {
+            if exp & 1 == 1 {
+                pow = mulmod(pow, base);
+            }
+            base = mulmod(base, base);
+            exp >>= 1;
+        }
+        pow
+    };
+    let order = (1u64 << degree) - 1;
+    if powmod(order) != 1 {
+        return false;
+    }
+    let mut rest = order;
+    let mut factor = 2;
+    while rest > 1 {
+        if factor * factor > rest {
+            factor = rest;
+        }
+        if rest.is_multiple_of(factor) {
+            if powmod(order / factor) == 1 {
+                return false;
+            }
+            while rest.is_multiple_of(factor) {
+                rest /= factor;
+            }
+        }
+        factor += 1;
+    }
+    true
+}
+
+/// Degree and interior coefficients of every primitive polynomial in Joe and Kuo's order, by
+/// degree and then by coefficients
+fn primitives() -> impl Iterator<Item = (u32, u32)> {
+    (1..BITS as u32).flat_map(|degree| {
+        (0..1 << (degree - 1))
+            .filter(move |&coeffs| primitive(1 << degree | (coeffs as u64) << 1 | 1, degree))
+            .map(move |coeffs| (degree, coeffs))
+    })
+}
+
+/// A Sobol low-discrepancy sequence in the unit hypercube
+///
+/// Points are generated in Gray code order, and the first point, the origin, is skipped, so the
+/// first 2^k - 1 points and the origin fill every dyadic box of volume 2^-k exactly once.
+#[derive(Debug, Clone)]
+pub struct Sobol {
+    directions: Vec<[u32; BITS]>,
+    state: Vec<u32>,
+    /// Bits flipped in every coordinate of every point
+    shift: Vec<u32>,
+    index: u32,
+}
+
+impl Sobol {
+    /// A sequence of points with `dims` coordinates
+    ///
+    /// Dimensions past Joe and Kuo's table use the following primitive polynomials with odd
+    /// initial direction numbers drawn from a fixed stream, so every coordinate is still a
+    /// stratified sequence, but pairs of them are less evenly spread.
+    pub fn new(dims: usize) -> Self {
+        let table = POLYNOMIALS
+            .iter()
+            .map(|&(degree, coeffs, initial)| (degree, coeffs, initial.to_vec()));
+        let extra = primitives()
+            .skip(POLYNOMIALS.len())
+            .zip(POLYNOMIALS.len() as u64..)
+            .map(|((degree, coeffs), dim)| {
+                let initial = (0..degree)
+                    .map(|bit| {
+                        let draw = crate::splitmix64(dim << 32 | bit as u64) as u32;
+                        // odd and less than 2^(bit + 1)
+                        (draw & ((1 << bit) - 1)) << 1 | 1
+                    })
+                    .collect();
+                (degree, coeffs, initial)
+            });
+        let mut directions = Vec::with_capacity(dims);
+        if dims > 0 {
+            // the first dimension is the van der Corput sequence
+            directions.push(std::array::from_fn(|bit| 1 << (BITS - 1 - bit)));
+        }
+        for (degree, coeffs, initial) in table.chain(extra).take(dims.saturating_sub(1)) {
+            let degree = degree as usize;
+            let mut dirs = [0; BITS];
+            for (bit, &init) in initial.iter().enumerate() {
+                dirs[bit] = init << (BITS - 1 - bit);
+            }
+            for bit in degree..BITS {
+                let mut dir = dirs[bit - degree] ^ (dirs[bit - degree] >> degree);
+                for term in 1..degree {
+                    if (coeffs >> (degree - 1 - term)) & 1 == 1 {
+                        dir ^= dirs[bit - term];
+                    }
+                }
+                dirs[bit] = dir;
+            }
+            directions.push(dirs);
+        }
+        Sobol {
+            directions,
+            state: vec![0; dims],
+            shift: vec![0; dims],
+            index: 0,
+        }
+    }
+
+    /// A sequence with a random digital shift, which keeps how evenly it fills the hypercube but
+    /// makes every point uniform, so means over it are unbiased
+    pub fn scrambled(dims: usize, rng: &mut impl Rng) -> Self {
+        let mut sobol = Sobol::new(dims);
+        sobol.shift.iter_mut().for_each(|shift| *shift = rng.gen());
+        sobol
+    }
+}
+
+impl Iterator for Sobol {
+    type Item = Vec<f64>;
+
+    fn next(&mut self) -> Option<Vec<f64>> {
+        // the direction to flip is the lowest zero bit of the previous index
+        let bit = self.index.trailing_ones() as usize;
+        if bit >= BITS {
+            return None;
+        }
+        self.index += 1;
+        for (state, dirs) in self.state.iter_mut().zip(&self.directions) {
+            *state ^= dirs[bit];
+        }
+        Some(
+            self.state
+                .iter()
+                .zip(&self.shift)
+                .map(|(&state, &shift)| (state ^ shift) as f64 / (1u64 << BITS) as f64)
+                .collect(),
+        )
+    }
+}
+
+/// A Latin hypercube of `num` points with `dims` coordinates
+///
+/// Each coordinate's range is divided into `num` equal strata, and every stratum of every
+/// coordinate holds exactly one point, placed uniformly within it.
+pub fn latin_hypercube(num: usize, dims: usize, rng: &mut impl Rng) -> Vec<Vec<f64>> {
+    let mut points = vec![vec![0.0; dims]; num];
+    let mut strata: Vec<_> = (0..num).collect();
+    for dim in 0..dims {
+        strata.shuffle(rng);
+        for (point, &stratum) in points.iter_mut().zip(&strata) {
+            point[dim] = (stratum as f64 + rng.gen::<f64>()) / num as f64;
+        }
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Sobol, POLYNOMIALS};
+
+    #[test]
+    fn test_primitives() {
+        let table: Vec<_> = POLYNOMIALS
+            .iter()
+            .map(|&(deg, coeffs, _)| (deg, coeffs))
+            .collect();
+        let found: Vec<_> = super::primitives().take(table.len()).collect();
+        assert_eq!(found, table);
+        // the number of primitive polynomials of each degree is phi(2^d - 1) / d
+        let degrees: Vec<_> = super::primitives()
+            .take_while(|&(deg, _)| deg <= 8)
+            .collect();
+        for (degree, num) in [(6, 6), (7, 18), (8, 16)] {
+            assert_eq!(
+                degrees.iter().filter(|&&(deg, _)| deg == degree).count(),
+                num
+            );
+        }
+    }
+
+    #[test]
+    fn test_sobol_points() {
+        let points: Vec<_> = Sobol::new(2).take(7).collect();
+        let expected = [
+            [0.5, 0.5],
+            [0.75, 0.25],
+            [0.25, 0.75],
+            [0.375, 0.375],
+            [0.875, 0.875],
+            [0.625, 0.125],
+            [0.125, 0.625],
+        ];
+        for (point, exp) in points.iter().zip(expected) {
+            assert_eq!(point[..], exp);
+        }
+    }
+
+    #[test]
+    fn test_sobol_stratified() {
+        // with the origin, the first 2^k points fill every dyadic interval of each coordinate
+        let num = 64;
+        let dims = 40;
+        let mut counts = vec![vec![0; num]; dims];
+        for dim in &mut counts {
+            dim[0] += 1;
+        }
+        for point in Sobol::new(dims).take(num - 1) {
+            assert!(point.iter().all(|coord| (0.0..1.0).contains(coord)));
+            for (dim, coord) in counts.iter_mut().zip(point) {
+                dim[(coord * num as f64) as usize] += 1;
+            }
+        }
+        assert!(counts.iter().flatten().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn test_sobol_scrambled() {
+        // a digital shift moves every point but still leaves at most one in each dyadic interval
+        let num = 32;
+        let mut rng = rand::thread_rng();
+        let mut counts = vec![vec![0; num]; 4];
+        for point in Sobol::scrambled(4, &mut rng).take(num - 1) {
+            for (dim, coord) in counts.iter_mut().zip(point) {
+                dim[(coord * num as f64) as usize] += 1;
+            }
+        }
+        assert!(counts.iter().flatten().all(|&count| count <= 1));
+        let first = Sobol::scrambled(4, &mut rng).next().unwrap();
+        assert_ne!(first, Sobol::new(4).next().unwrap());
+    }
+
+    #[test]
+    fn test_latin_hypercube() {
+        let mut rng = rand::thread_rng();
+        let num = 20;
+        let points = super::latin_hypercube(num, 3, &mut rng);
+        assert_eq!(points.len(), num);
+        for dim in 0..3 {
+            let mut strata: Vec<_> = points
+                .iter()
+                .map(|point| (point[dim] * num as f64) as usize)
+                .collect();
+            strata.sort_unstable();
+            assert_eq!(strata, (0..num).collect::<Vec<_>>());
+        }
+    }
+}
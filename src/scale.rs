@@ -0,0 +1,137 @@
+use crate::market::{Call, Cda, Market};
+use crate::strategy::Presets;
+use crate::summary::{self, Stat};
+use crate::{Agent, Spec};
+use clap::Parser;
+use serde::Serialize;
+use std::io::{self, BufRead, Write};
+
+#[derive(Parser, Debug)]
+/// Study how a market converges as its population grows
+///
+/// Takes the same spec lines as the default command on stdin, and simulates each with every count
+/// of its assignment multiplied by each of --factors. Each line of output has a "scale" list with
+/// the "factor", the number of "players", and the mean and standard error of the "efficiency" and
+/// of the "price_deviation", the mean absolute difference between trade prices and the
+/// competitive equilibrium price, over the observations where they're defined.
+pub struct ScaleArgs {
+    /// Multiples of the population to simulate
+    #[clap(long, value_parser, value_delimiter = ',', default_values_t = [1.0, 2.0, 4.0, 8.0])]
+    factors: Vec<f64>,
+
+    /// Number of simulations at each multiple
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 1000)]
+    samples: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct Size {
+    factor: f64,
+    players: usize,
+    efficiency: Option<Stat>,
+    price_deviation: Option<Stat>,
+}
+
+#[derive(Serialize, Debug)]
+struct Analysis {
+    scale: Vec<Size>,
+}
+
+pub fn scale(
+    args: &ScaleArgs,
+    presets: &Presets,
+    strict: bool,
+    ihandle: impl BufRead,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    if let Some(factor) = args.factors.iter().find(|f| !(f.is_finite() && **f > 0.0)) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid factor {}, must be finite and positive", factor),
+        ));
+    }
+    for (index, line) in ihandle.lines().enumerate() {
+        let _span = tracing::info_span!("spec", line = index).entered();
+        let base = crate::parse_spec(&line?, strict, presets)?;
+        let mut scale = Vec::with_capacity(args.factors.len());
+        for &factor in &args.factors {
+            let spec = Spec {
+                assignment: base.assignment.scaled(factor),
+                configuration: base.configuration.clone(),
+            };
+            let mut agents = crate::build_agents(&spec, presets)?;
+            let players = crate::players(&agents).count();
+            let (efficiency, price_deviation) =
+                match crate::continuous_market(&spec.configuration, &agents, false)? {
+                    Some(market) => simulate(&mut agents, &market, args.samples),
+                    None if spec.configuration.cda.unwrap_or(true) => {
+                        simulate(&mut agents, &Cda, args.samples)
+                    }
+                    None => simulate(&mut agents, &Call, args.samples),
+                };
+            scale.push(Size {
+                factor,
+                players,
+                efficiency,
+                price_deviation,
+            });
+        }
+        serde_json::to_writer(&mut *out, &Analysis { scale })?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// The efficiency and price deviation of a market's simulations
+fn simulate(
+    agents: &mut [Agent<'_>],
+    market: &impl Market,
+    samples: u64,
+) -> (Option<Stat>, Option<Stat>) {
+    let mut rng = rand::thread_rng();
+    let mut efficiencies = Vec::new();
+    let mut deviations = Vec::new();
+    for _ in 0..samples {
+        let features = crate::run_sim(agents, market, &mut rng);
+        efficiencies.extend(features.efficiency);
+        let prices: Vec<_> = agents.iter().filter_map(|a| a.price).collect();
+        if let (Some(ce_price), false) = (features.ce_price, prices.is_empty()) {
+            let total: f64 = prices.iter().map(|price| (price - ce_price).abs()).sum();
+            deviations.push(total / prices.len() as f64);
+        }
+    }
+    let mut stat =
+        |samples: &[f64]| (!samples.is_empty()).then(|| summary::stat(samples, None, &mut rng));
+    (stat(&efficiencies), stat(&deviations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScaleArgs;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_scale() {
+        let args = ScaleArgs {
+            factors: vec![1.0, 4.0],
+            samples: 50,
+        };
+        let input = r#"{"assignment":{"buyers":{"0":2},"sellers":{"0.2":1.5}},"configuration":{"cda":false}}"#;
+        let mut out = Vec::new();
+        super::scale(&args, &HashMap::new(), false, input.as_bytes(), &mut out).unwrap();
+        let analysis: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let scale = analysis["scale"].as_array().unwrap();
+        let players: Vec<_> = scale.iter().map(|size| size["players"].clone()).collect();
+        assert_eq!(players, [4, 14]);
+        for size in scale {
+            assert!(size["efficiency"]["mean"].as_f64().unwrap() <= 1.0 + 1e-9);
+            assert!(size["price_deviation"]["mean"].as_f64().unwrap() >= 0.0);
+        }
+
+        let args = ScaleArgs {
+            factors: vec![0.0],
+            samples: 1,
+        };
+        assert!(super::scale(&args, &HashMap::new(), false, input.as_bytes(), &mut out).is_err());
+    }
+}
@@ -0,0 +1,268 @@
+use crate::market::{Call, Cda, Market};
+use crate::strategy::{Presets, Shading};
+use crate::summary::{Report, Summary};
+use crate::{Agent, Spec};
+use clap::Parser;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use serde_json::Value;
+use std::io::{self, BufRead, Write};
+
+#[derive(Parser, Debug)]
+/// Trace how features change as one parameter of each spec varies
+///
+/// Takes the same spec lines as the default command on stdin. For every value in --range, the
+/// parameter is set in the spec and it's simulated --samples times, reusing the same random seeds
+/// at every value so the curve isn't obscured by noise. Each line of output has a "sensitivity"
+/// list with the "value" of the parameter and a summary of the simulations at it, like that of
+/// --summary, whose standard errors give confidence intervals.
+pub struct SensitivityArgs {
+    /// The parameter to vary
+    ///
+    /// "shading" sets the shading of every agent, and anything else is a configuration key, with
+    /// dots separating the keys of nested objects, e.g. "cost" or "market_maker.spread".
+    #[clap(long, value_parser)]
+    param: String,
+
+    /// The values of the parameter as "start:stop:step", including stop
+    #[clap(long, value_parser = parse_range)]
+    range: Range,
+
+    /// Number of simulations at each value
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 1000)]
+    samples: u64,
+
+    /// Add 95% percentile bootstrap confidence intervals with this many resamples
+    #[clap(long, value_parser)]
+    bootstrap: Option<u64>,
+}
+
+/// Evenly spaced parameter values
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Range {
+    start: f64,
+    stop: f64,
+    step: f64,
+}
+
+impl Range {
+    fn values(&self) -> impl Iterator<Item = f64> {
+        let Range { start, stop, step } = *self;
+        // tolerate rounding so a stop a whole number of steps away is included
+        let num = ((stop - start) / step + 1e-9).floor() as u64 + 1;
+        (0..num).map(move |ind| start + ind as f64 * step)
+    }
+}
+
+fn parse_range(text: &str) -> Result<Range, String> {
+    let parts: Vec<_> = text.split(':').collect();
+    let [start, stop, step] = parts[..] else {
+        return Err(format!("range must be start:stop:step, but got {}", text));
+    };
+    let parse = |part: &str| {
+        part.parse::<f64>()
+            .ok()
+            .filter(|num| num.is_finite())
+            .ok_or_else(|| format!("{} isn't a finite number", part))
+    };
+    let range = Range {
+        start: parse(start)?,
+        stop: parse(stop)?,
+        step: parse(step)?,
+    };
+    if range.step <= 0.0 || range.start > range.stop {
+        Err(format!(
+            "range must have a positive step and start at or below stop, but got {}",
+            text
+        ))
+    } else {
+        Ok(range)
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct Point {
+    value: f64,
+    #[serde(flatten)]
+    report: Report,
+}
+
+#[derive(Serialize, Debug)]
+struct Analysis {
+    sensitivity: Vec<Point>,
+}
+
+pub fn sensitivity(
+    args: &SensitivityArgs,
+    presets: &Presets,
+    strict: bool,
+    ihandle: impl BufRead,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let mut rng = rand::thread_rng();
+    for (index, line) in ihandle.lines().enumerate() {
+        let _span = tracing::info_span!("spec", line = index).entered();
+        let base: Value = crate::parse_line(&line?, false)?;
+        let seeds: Vec<u64> = (0..args.samples).map(|_| rng.gen()).collect();
+        let mut sensitivity = Vec::new();
+        for value in args.range.values() {
+            let spec = with_param(&base, &args.param, value, strict, presets)?;
+            let mut agents = crate::build_agents(&spec, presets)?;
+            if args.param == "shading" {
+                agents
+                    .iter_mut()
+                    .for_each(|a| a.set_shading(Shading::Fixed(value)));
+            }
+            let summary = match crate::continuous_market(&spec.configuration, &agents, false)? {
+                Some(market) => simulate(&mut agents, &market, &seeds),
+                None if spec.configuration.cda.unwrap_or(true) => {
+                    simulate(&mut agents, &Cda, &seeds)
+                }
+                None => simulate(&mut agents, &Call, &seeds),
+            };
+            let report = summary.report(args.bootstrap, &mut rng);
+            sensitivity.push(Point { value, report });
+        }
+        serde_json::to_writer(&mut *out, &Analysis { sensitivity })?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// The spec with a configuration parameter set, which shading isn't
+fn with_param(
+    base: &Value,
+    param: &str,
+    value: f64,
+    strict: bool,
+    presets: &Presets,
+) -> io::Result<Spec> {
+    let mut spec = base.clone();
+    if param != "shading" {
+        set_param(&mut spec, param, value)?;
+    }
+    crate::parse_spec(&spec.to_string(), strict, presets)
+}
+
+/// Set a configuration key of a spec, with dots separating the keys of nested objects
+pub fn set_param(spec: &mut Value, param: &str, value: f64) -> io::Result<()> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("can't set {} in a spec's configuration", param),
+        )
+    };
+    let mut target = spec
+        .as_object_mut()
+        .ok_or_else(invalid)?
+        .entry("configuration")
+        .or_insert_with(|| Value::Object(Default::default()));
+    for key in param.split('.') {
+        target = target
+            .as_object_mut()
+            .ok_or_else(invalid)?
+            .entry(key)
+            .or_insert(Value::Null);
+    }
+    // integer parameters, like counts, don't deserialize from floats
+    *target = if value.fract() == 0.0 && value.abs() < u32::MAX as f64 {
+        Value::from(value as i64)
+    } else {
+        Value::from(value)
+    };
+    Ok(())
+}
+
+fn simulate(agents: &mut [Agent<'_>], market: &impl Market, seeds: &[u64]) -> Summary {
+    let mut summary = Summary::default();
+    for &seed in seeds {
+        let features = crate::run_sim(agents, market, &mut StdRng::seed_from_u64(seed));
+        summary.add(&features, agents);
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Range, SensitivityArgs};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse_range() {
+        let range = super::parse_range("0:1:0.25").unwrap();
+        let values: Vec<_> = range.values().collect();
+        assert_eq!(values, [0.0, 0.25, 0.5, 0.75, 1.0]);
+        let range = super::parse_range("0:1:0.1").unwrap();
+        assert_eq!(range.values().count(), 11);
+        assert!(super::parse_range("0:1").is_err());
+        assert!(super::parse_range("1:0:0.1").is_err());
+        assert!(super::parse_range("0:1:0").is_err());
+        assert!(super::parse_range("0:inf:1").is_err());
+    }
+
+    fn sensitivity(input: &str, param: &str, range: Range) -> serde_json::Value {
+        let args = SensitivityArgs {
+            param: param.to_owned(),
+            range,
+            samples: 50,
+            bootstrap: None,
+        };
+        let mut out = Vec::new();
+        super::sensitivity(&args, &HashMap::new(), true, input.as_bytes(), &mut out).unwrap();
+        serde_json::from_slice(&out).unwrap()
+    }
+
+    #[test]
+    fn test_shading_sensitivity() {
+        let input =
+            r#"{"assignment":{"buyers":{"0":3},"sellers":{"0":3}},"configuration":{"cda":false}}"#;
+        let range = Range {
+            start: 0.0,
+            stop: 1.0,
+            step: 0.5,
+        };
+        let analysis = sensitivity(input, "shading", range);
+        let curve = analysis["sensitivity"].as_array().unwrap();
+        assert_eq!(curve.len(), 3);
+        let surplus: Vec<_> = curve
+            .iter()
+            .map(|point| point["features"]["surplus"]["mean"].as_f64().unwrap())
+            .collect();
+        // a call market with truthful bids is efficient, and full shading never trades
+        assert!(surplus[0] >= surplus[1]);
+        assert_eq!(surplus[2], 0.0);
+        assert!(curve[0]["features"]["surplus"]["stderr"].is_f64());
+    }
+
+    #[test]
+    fn test_config_sensitivity() {
+        let input =
+            r#"{"assignment":{"buyers":{"0":3},"sellers":{"0":3}},"configuration":{"cda":false}}"#;
+        let range = Range {
+            start: 0.0,
+            stop: 0.2,
+            step: 0.1,
+        };
+        let analysis = sensitivity(input, "cost", range);
+        let surplus: Vec<_> = analysis["sensitivity"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|point| point["features"]["surplus"]["mean"].as_f64().unwrap())
+            .collect();
+        // the same draws with a higher cost can't produce more surplus
+        assert!(surplus.windows(2).all(|pair| pair[0] >= pair[1]));
+
+        let args = SensitivityArgs {
+            param: "nonsense".to_owned(),
+            range,
+            samples: 1,
+            bootstrap: None,
+        };
+        let mut out = Vec::new();
+        assert!(
+            super::sensitivity(&args, &HashMap::new(), true, input.as_bytes(), &mut out).is_err()
+        );
+    }
+}
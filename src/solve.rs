@@ -0,0 +1,272 @@
+use crate::agent::{Agent, Style};
+use crate::market::{Call, Cda, Market};
+use crate::strategy::{Shading, Strategy};
+use clap::{Parser, ValueEnum};
+use rand::distributions::{Distribution, WeightedIndex};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+#[derive(Parser, Debug)]
+/// Approximate a role-symmetric equilibrium over a grid of shadings
+pub struct SolveArgs {
+    /// Number of buyers
+    #[clap(long, value_parser, default_value_t = 5)]
+    buyers: usize,
+
+    /// Number of sellers
+    #[clap(long, value_parser, default_value_t = 5)]
+    sellers: usize,
+
+    /// Comma separated shadings that agents in both roles choose between
+    #[clap(long, value_parser, value_delimiter = ',', default_values_t = [0.0, 0.2, 0.4, 0.6, 0.8, 1.0])]
+    grid: Vec<f64>,
+
+    /// Style of every agent
+    #[clap(long, value_parser, default_value = "Standard")]
+    style: Style,
+
+    /// Use a call market instead of a CDA
+    #[clap(long, value_parser)]
+    call: bool,
+
+    /// Number of simulations used to estimate each deviation payoff
+    #[clap(long, value_parser, default_value_t = 100)]
+    samples: u64,
+
+    /// Number of iterations of the dynamics
+    #[clap(long, value_parser, default_value_t = 100)]
+    iters: u64,
+
+    /// Dynamics used to find the equilibrium
+    #[clap(long, value_enum, default_value_t = Method::Replicator)]
+    method: Method,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    /// Discrete replicator dynamics
+    Replicator,
+    /// Fictitious play against the running average of best responses
+    Fictitious,
+}
+
+#[derive(Serialize, Debug)]
+struct Solution<'a> {
+    buyers: BTreeMap<&'a str, f64>,
+    sellers: BTreeMap<&'a str, f64>,
+    regret: f64,
+}
+
+/// The index of each role into role-indexed arrays, and whether that role buys
+const ROLES: [(usize, bool); 2] = [(0, true), (1, false)];
+
+pub fn solve(args: &SolveArgs, out: &mut impl Write) -> io::Result<()> {
+    if args.buyers == 0 || args.sellers == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "solve needs at least one buyer and one seller",
+        ));
+    } else if args.grid.is_empty() || args.grid.iter().any(|s| !s.is_finite()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "solve needs a non-empty grid of finite shadings",
+        ));
+    } else if matches!(args.style, Style::External | Style::Bne) {
+        // Bne agents ignore their shading, and are only solved for the markets of specs
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("solve doesn't support {:?} agents", args.style),
+        ));
+    }
+    if args.call {
+        solve_market(args, &Call, out)
+    } else {
+        solve_market(args, &Cda, out)
+    }
+}
+
+fn solve_market(args: &SolveArgs, market: &impl Market, out: &mut impl Write) -> io::Result<()> {
+    let labels: Vec<String> = args
+        .grid
+        .iter()
+        .map(|&shading| {
+            Strategy {
+                style: Some(args.style),
+                ..Strategy::new(Shading::Fixed(shading))
+            }
+            .to_string()
+        })
+        .collect();
+    let uniform = vec![1.0 / labels.len() as f64; labels.len()];
+    let mut mixtures = [uniform.clone(), uniform];
+
+    for iter in 0..args.iters {
+        let payoffs = deviation_payoffs(args, &labels, &mixtures, market);
+        for (role, _) in ROLES {
+            match args.method {
+                Method::Replicator => replicate(&mut mixtures[role], &payoffs[role]),
+                Method::Fictitious => fictitious(&mut mixtures[role], &payoffs[role], iter),
+            }
+        }
+    }
+
+    let payoffs = deviation_payoffs(args, &labels, &mixtures, market);
+    let regret = ROLES
+        .iter()
+        .map(|&(role, _)| role_regret(&mixtures[role], &payoffs[role]))
+        .fold(0.0, f64::max);
+    let [buyers, sellers] =
+        mixtures.map(|mix| labels.iter().map(String::as_str).zip(mix).collect());
+    serde_json::to_writer(
+        &mut *out,
+        &Solution {
+            buyers,
+            sellers,
+            regret,
+        },
+    )?;
+    writeln!(out)
+}
+
+/// Estimate the payoff of every grid strategy in every role against the role mixtures
+fn deviation_payoffs(
+    args: &SolveArgs,
+    labels: &[String],
+    mixtures: &[Vec<f64>; 2],
+    market: &impl Market,
+) -> [Vec<f64>; 2] {
+    let mut rng = rand::thread_rng();
+    let dists = [
+        WeightedIndex::new(&mixtures[0]).expect("invalid buyer mixture"),
+        WeightedIndex::new(&mixtures[1]).expect("invalid seller mixture"),
+    ];
+    let counts = [args.buyers, args.sellers];
+    let new_agent = |buyer: bool, strat: usize| {
+        Agent::new(
+            buyer,
+            &labels[strat],
+            args.style,
+            Shading::Fixed(args.grid[strat]),
+        )
+    };
+
+    let mut payoffs = [vec![0.0; labels.len()], vec![0.0; labels.len()]];
+    for (role, buyer) in ROLES {
+        for (strat, payoff) in payoffs[role].iter_mut().enumerate() {
+            for _ in 0..args.samples {
+                // the deviator is always the first agent
+                let mut agents = vec![new_agent(buyer, strat)];
+                for (other, other_buyer) in ROLES {
+                    let num = counts[other] - usize::from(other == role);
+                    for _ in 0..num {
+                        agents.push(new_agent(other_buyer, dists[other].sample(&mut rng)));
+                    }
+                }
+                crate::run_sim(&mut agents, market, &mut rng);
+                *payoff += agents[0].utility / args.samples as f64;
+            }
+        }
+    }
+    payoffs
+}
+
+/// One step of discrete replicator dynamics with payoffs shifted to be positive
+pub fn replicate(mixture: &mut [f64], payoffs: &[f64]) {
+    let offset = payoffs.iter().copied().fold(0.0, f64::min);
+    mixture
+        .iter_mut()
+        .zip(payoffs)
+        .for_each(|(prob, pay)| *prob *= pay - offset + 1e-6);
+    let total: f64 = mixture.iter().sum();
+    mixture.iter_mut().for_each(|prob| *prob /= total);
+}
+
+/// One step of fictitious play, moving the mixture toward the best response
+fn fictitious(mixture: &mut [f64], payoffs: &[f64], iter: u64) {
+    let best = payoffs.iter().enumerate().fold(
+        0,
+        |best, (ind, pay)| if *pay > payoffs[best] { ind } else { best },
+    );
+    let step = 1.0 / (iter + 2) as f64;
+    for (ind, prob) in mixture.iter_mut().enumerate() {
+        let target = if ind == best { 1.0 } else { 0.0 };
+        *prob += (target - *prob) * step;
+    }
+}
+
+/// The gain from the best deviation over playing the mixture
+fn role_regret(mixture: &[f64], payoffs: &[f64]) -> f64 {
+    let expected: f64 = mixture.iter().zip(payoffs).map(|(p, u)| p * u).sum();
+    let best = payoffs.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    best - expected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Method, SolveArgs};
+    use crate::agent::Style;
+
+    #[test]
+    fn test_dynamics() {
+        let payoffs = [0.1, 0.3, 0.2];
+        let mut mixture = vec![1.0 / 3.0; 3];
+        super::replicate(&mut mixture, &payoffs);
+        assert!((mixture.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!(mixture[1] > mixture[2] && mixture[2] > mixture[0]);
+
+        let mut mixture = vec![1.0 / 3.0; 3];
+        super::fictitious(&mut mixture, &payoffs, 0);
+        assert!((mixture.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!((mixture[1] - 2.0 / 3.0).abs() < 1e-9);
+
+        assert!((super::role_regret(&[0.5, 0.5, 0.0], &payoffs) - 0.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_solve() {
+        for method in [Method::Replicator, Method::Fictitious] {
+            let args = SolveArgs {
+                buyers: 2,
+                sellers: 3,
+                grid: vec![0.0, 0.5],
+                style: Style::Standard,
+                call: false,
+                samples: 5,
+                iters: 5,
+                method,
+            };
+            let mut out = Vec::new();
+            super::solve(&args, &mut out).unwrap();
+            let sol: serde_json::Value = serde_json::from_slice(&out).unwrap();
+            for role in ["buyers", "sellers"] {
+                let total: f64 = sol[role]
+                    .as_object()
+                    .unwrap()
+                    .values()
+                    .map(|p| p.as_f64().unwrap())
+                    .sum();
+                assert!((total - 1.0).abs() < 1e-9);
+            }
+            assert!(sol["regret"].as_f64().unwrap() >= 0.0);
+        }
+    }
+
+    #[test]
+    fn test_unsupported_style() {
+        for style in [Style::External, Style::Bne] {
+            let args = SolveArgs {
+                buyers: 1,
+                sellers: 1,
+                grid: vec![0.0],
+                style,
+                call: false,
+                samples: 1,
+                iters: 1,
+                method: Method::Replicator,
+            };
+            let err = super::solve(&args, &mut Vec::new()).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+}
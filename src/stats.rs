@@ -0,0 +1,484 @@
+use rand::Rng;
+use serde::Serialize;
+use std::f64::consts::{PI, SQRT_2};
+
+pub fn mean(samples: &[f64]) -> f64 {
+    samples.iter().sum::<f64>() / samples.len() as f64
+}
+
+/// Unbiased sample variance
+pub fn variance(samples: &[f64]) -> f64 {
+    let mean = mean(samples);
+    samples.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / (samples.len() as f64 - 1.0)
+}
+
+/// Pearson correlation of paired samples, if neither is constant
+pub fn correlation(first: &[f64], second: &[f64]) -> Option<f64> {
+    let (mx, my) = (mean(first), mean(second));
+    let (mut cov, mut vx, mut vy) = (0.0, 0.0, 0.0);
+    for (x, y) in first.iter().zip(second) {
+        cov += (x - mx) * (y - my);
+        vx += (x - mx) * (x - mx);
+        vy += (y - my) * (y - my);
+    }
+    (vx > 0.0 && vy > 0.0).then(|| cov / (vx * vy).sqrt())
+}
+
+/// Result of a two-sided hypothesis test
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Test {
+    pub statistic: f64,
+    pub p: f64,
+}
+
+/// Welch's unequal variances t-test of whether two samples have the same mean
+pub fn welch(first: &[f64], second: &[f64]) -> Test {
+    let (n1, n2) = (first.len() as f64, second.len() as f64);
+    let (v1, v2) = (variance(first) / n1, variance(second) / n2);
+    let statistic = (mean(second) - mean(first)) / (v1 + v2).sqrt();
+    let dof = (v1 + v2).powi(2) / (v1 * v1 / (n1 - 1.0) + v2 * v2 / (n2 - 1.0));
+    let p = inc_beta(dof / 2.0, 0.5, dof / (dof + statistic * statistic));
+    Test { statistic, p }
+}
+
+/// Mann-Whitney U test of whether one sample tends to be larger, using a normal approximation
+///
+/// The statistic is the U of the second sample, so it's large when the second sample is larger.
+pub fn mann_whitney(first: &[f64], second: &[f64]) -> Test {
+    let (n1, n2) = (first.len() as f64, second.len() as f64);
+    let mut all: Vec<(f64, bool)> = first
+        .iter()
+        .map(|&x| (x, false))
+        .chain(second.iter().map(|&x| (x, true)))
+        .collect();
+    all.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+
+    // average ranks over ties, accumulating the tie correction
+    let mut rank_sum = 0.0;
+    let mut ties = 0.0;
+    let mut start = 0;
+    while start < all.len() {
+        let end = start
+            + all[start..]
+                .iter()
+                .take_while(|x| x.0 == all[start].0)
+                .count();
+        let rank = (start + end + 1) as f64 / 2.0;
+        rank_sum += rank * all[start..end].iter().filter(|x| x.1).count() as f64;
+        let count = (end - start) as f64;
+        ties += count * count * count - count;
+        start = end;
+    }
+
+    let statistic = rank_sum - n2 * (n2 + 1.0) / 2.0;
+    let center = n1 * n2 / 2.0;
+    let total = n1 + n2;
+    let sigma = (n1 * n2 / 12.0 * (total + 1.0 - ties / (total * (total - 1.0)))).sqrt();
+    let diff = (statistic - center).abs() - 0.5;
+    let p = if sigma > 0.0 {
+        (2.0 * (1.0 - normal_cdf(diff.max(0.0) / sigma))).min(1.0)
+    } else {
+        1.0
+    };
+    Test { statistic, p }
+}
+
+/// Radius of an asymptotic confidence sequence for a mean, which covers it at every count at once
+///
+/// This is the normal mixture boundary of Waudby-Smith et al.'s time-uniform central limit
+/// theory, tuned to be tightest at `tuned` samples, so a test can check it after every sample and
+/// still err with probability at most `alpha`.
+pub fn confidence_sequence(variance: f64, count: f64, alpha: f64, tuned: f64) -> f64 {
+    let log = -2.0 * alpha.ln();
+    let mixture = (log + (log + 1.0).ln()) / tuned;
+    let scale = count * mixture + 1.0;
+    (variance * 2.0 * scale / (count * count * mixture) * (scale.sqrt() / alpha).ln()).sqrt()
+}
+
+/// Percentile bootstrap confidence interval of the difference in means, second minus first
+pub fn bootstrap_diff(
+    first: &[f64],
+    second: &[f64],
+    resamples: u64,
+    confidence: f64,
+    rng: &mut impl Rng,
+) -> [f64; 2] {
+    let mut diffs: Vec<f64> = (0..resamples)
+        .map(|_| resample_mean(second, rng) - resample_mean(first, rng))
+        .collect();
+    percentile_interval(&mut diffs, confidence)
+}
+
+/// Percentile bootstrap confidence interval of the mean
+pub fn bootstrap_mean(
+    samples: &[f64],
+    resamples: u64,
+    confidence: f64,
+    rng: &mut impl Rng,
+) -> [f64; 2] {
+    let mut means: Vec<f64> = (0..resamples)
+        .map(|_| resample_mean(samples, rng))
+        .collect();
+    percentile_interval(&mut means, confidence)
+}
+
+fn resample_mean(samples: &[f64], rng: &mut impl Rng) -> f64 {
+    (0..samples.len())
+        .map(|_| samples[rng.gen_range(0..samples.len())])
+        .sum::<f64>()
+        / samples.len() as f64
+}
+
+fn percentile_interval(stats: &mut [f64], confidence: f64) -> [f64; 2] {
+    stats.sort_unstable_by(f64::total_cmp);
+    let alpha = (1.0 - confidence) / 2.0;
+    [quantile(stats, alpha), quantile(stats, 1.0 - alpha)]
+}
+
+/// Linearly interpolated quantile of sorted samples
+pub fn quantile(sorted: &[f64], prob: f64) -> f64 {
+    let pos = prob * (sorted.len() - 1) as f64;
+    let low = pos.floor() as usize;
+    let high = pos.ceil() as usize;
+    sorted[low] + (sorted[high] - sorted[low]) * (pos - low as f64)
+}
+
+/// A streaming estimate of one quantile with the P² algorithm, in constant memory
+///
+/// Five markers track the minimum, maximum, target quantile, and the quantiles halfway to it,
+/// and their heights are adjusted with piecewise parabolic interpolation as samples arrive. The
+/// estimate is exact for five samples or fewer.
+#[derive(Debug, Clone)]
+pub struct P2 {
+    prob: f64,
+    count: usize,
+    heights: [f64; 5],
+    positions: [f64; 5],
+    desired: [f64; 5],
+    increments: [f64; 5],
+}
+
+impl P2 {
+    pub fn new(prob: f64) -> Self {
+        P2 {
+            prob,
+            count: 0,
+            heights: [0.0; 5],
+            positions: [0.0, 1.0, 2.0, 3.0, 4.0],
+            desired: [0.0, 2.0 * prob, 4.0 * prob, 2.0 + 2.0 * prob, 4.0],
+            increments: [0.0, prob / 2.0, prob, (1.0 + prob) / 2.0, 1.0],
+        }
+    }
+
+    pub fn add(&mut self, sample: f64) {
+        if self.count < 5 {
+            self.heights[self.count] = sample;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_unstable_by(f64::total_cmp);
+            }
+            return;
+        }
+        self.count += 1;
+        let heights = &mut self.heights;
+        let cell = if sample < heights[0] {
+            heights[0] = sample;
+            0
+        } else if sample >= heights[4] {
+            heights[4] = sample;
+            3
+        } else {
+            (0..4).rfind(|&i| heights[i] <= sample).unwrap()
+        };
+        self.positions[cell + 1..]
+            .iter_mut()
+            .for_each(|pos| *pos += 1.0);
+        for (desired, inc) in self.desired.iter_mut().zip(self.increments) {
+            *desired += inc;
+        }
+        let pos = &mut self.positions;
+        for i in 1..4 {
+            let diff = self.desired[i] - pos[i];
+            if (diff >= 1.0 && pos[i + 1] - pos[i] > 1.0)
+                || (diff <= -1.0 && pos[i - 1] - pos[i] < -1.0)
+            {
+                let step = diff.signum();
+                let parabolic = heights[i]
+                    + step / (pos[i + 1] - pos[i - 1])
+                        * ((pos[i] - pos[i - 1] + step) * (heights[i + 1] - heights[i])
+                            / (pos[i + 1] - pos[i])
+                            + (pos[i + 1] - pos[i] - step) * (heights[i] - heights[i - 1])
+                                / (pos[i] - pos[i - 1]));
+                heights[i] = if heights[i - 1] < parabolic && parabolic < heights[i + 1] {
+                    parabolic
+                } else {
+                    let other = if step > 0.0 { i + 1 } else { i - 1 };
+                    heights[i] + step * (heights[other] - heights[i]) / (pos[other] - pos[i])
+                };
+                pos[i] += step;
+            }
+        }
+    }
+
+    /// The current estimate, or None without any samples
+    pub fn quantile(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            count if count < 5 => {
+                let mut sorted = self.heights[..count].to_vec();
+                sorted.sort_unstable_by(f64::total_cmp);
+                Some(quantile(&sorted, self.prob))
+            }
+            _ => Some(self.heights[2]),
+        }
+    }
+}
+
+/// Draw from a standard normal with the Box-Muller transform
+pub fn standard_normal(rng: &mut impl Rng) -> f64 {
+    let radius = (-2.0 * (1.0 - rng.gen::<f64>()).ln()).sqrt();
+    radius * (2.0 * PI * rng.gen::<f64>()).cos()
+}
+
+pub fn normal_cdf(x: f64) -> f64 {
+    0.5 * erfc(-x / SQRT_2)
+}
+
+/// Complementary error function with fractional error below 1.2e-7
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = [
+        -1.26551223,
+        1.00002368,
+        0.37409196,
+        0.09678418,
+        -0.18628806,
+        0.27886807,
+        -1.13520398,
+        1.48851587,
+        -0.82215223,
+        0.17087277,
+    ]
+    .iter()
+    .rev()
+    .fold(0.0, |acc, coef| acc * t + coef);
+    let ans = t * (-z * z + poly).exp();
+    if x >= 0.0 {
+        ans
+    } else {
+        2.0 - ans
+    }
+}
+
+/// Natural log of the gamma function using the Lanczos approximation
+pub fn ln_gamma(x: f64) -> f64 {
+    const COEFS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let series = COEFS
+        .iter()
+        .enumerate()
+        .fold(1.000000000190015, |acc, (ind, coef)| {
+            acc + coef / (x + 1.0 + ind as f64)
+        });
+    -tmp + (2.0 * PI).sqrt().ln() + (series / x).ln()
+}
+
+/// The regularized incomplete beta function I_x(a, b)
+pub fn inc_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    } else if x >= 1.0 {
+        return 1.0;
+    }
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+/// Continued fraction for the incomplete beta function by the modified Lentz method
+fn beta_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    if d.abs() < TINY {
+        d = TINY;
+    }
+    d = 1.0 / d;
+    let mut frac = d;
+    for m in 1..300 {
+        let m = m as f64;
+        for num in [
+            m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m)),
+            -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0)),
+        ] {
+            d = 1.0 + num * d;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = 1.0 + num / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1.0 / d;
+            frac *= d * c;
+        }
+        if (d * c - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+    frac
+}
+
+/// Ordinary least squares coefficients of targets on rows of regressors, if they're identified
+///
+/// Solves the normal equations by Gaussian elimination with partial pivoting, which is accurate
+/// enough for the handful of well scaled regressors it's used with.
+pub fn least_squares(rows: &[Vec<f64>], targets: &[f64]) -> Option<Vec<f64>> {
+    let dims = rows.first()?.len();
+    // the augmented normal equations [X'X | X'y]
+    let mut system = vec![vec![0.0; dims + 1]; dims];
+    for (row, target) in rows.iter().zip(targets) {
+        for (eq, &left) in system.iter_mut().zip(row) {
+            for (coef, &right) in eq.iter_mut().zip(row) {
+                *coef += left * right;
+            }
+            eq[dims] += left * target;
+        }
+    }
+    let scale = system
+        .iter()
+        .enumerate()
+        .fold(0.0, |max: f64, (ind, eq)| max.max(eq[ind].abs()));
+    for col in 0..dims {
+        let pivot =
+            (col..dims).max_by(|&a, &b| system[a][col].abs().total_cmp(&system[b][col].abs()))?;
+        if system[pivot][col].abs() <= scale * 1e-12 {
+            return None;
+        }
+        system.swap(col, pivot);
+        let pivot = system[col].clone();
+        for eq in system.iter_mut().skip(col + 1) {
+            let factor = eq[col] / pivot[col];
+            for (coef, &sub) in eq.iter_mut().zip(&pivot).skip(col) {
+                *coef -= factor * sub;
+            }
+        }
+    }
+    let mut coefs = vec![0.0; dims];
+    for row in (0..dims).rev() {
+        let known: f64 = (row + 1..dims)
+            .map(|col| system[row][col] * coefs[col])
+            .sum();
+        coefs[row] = (system[row][dims] - known) / system[row][row];
+    }
+    Some(coefs)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_least_squares() {
+        // y = 1 + 2x - x^2 exactly
+        let xs = [0.0, 0.5, 1.0, 1.5, 2.0];
+        let rows: Vec<_> = xs.iter().map(|&x| vec![1.0, x, x * x]).collect();
+        let ys: Vec<_> = xs.iter().map(|x| 1.0 + 2.0 * x - x * x).collect();
+        let coefs = super::least_squares(&rows, &ys).unwrap();
+        for (coef, exp) in coefs.iter().zip([1.0, 2.0, -1.0]) {
+            assert!((coef - exp).abs() < 1e-9);
+        }
+        // a constant regressor can't be told apart from the intercept
+        let rows = vec![vec![1.0, 2.0]; 3];
+        assert_eq!(super::least_squares(&rows, &[1.0, 2.0, 3.0]), None);
+        assert_eq!(super::least_squares(&[], &[]), None);
+    }
+
+    #[test]
+    fn test_distributions() {
+        assert!((super::normal_cdf(1.959964) - 0.975).abs() < 1e-6);
+        assert!((super::normal_cdf(0.0) - 0.5).abs() < 1e-7);
+        assert!((super::inc_beta(1.0, 1.0, 0.3) - 0.3).abs() < 1e-9);
+        assert!((super::inc_beta(2.0, 3.0, 0.4) - 0.5248).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_correlation() {
+        let first = [1.0, 2.0, 3.0, 4.0];
+        assert!((super::correlation(&first, &[2.0, 4.0, 6.0, 8.0]).unwrap() - 1.0).abs() < 1e-12);
+        assert!((super::correlation(&first, &[4.0, 3.0, 2.0, 1.0]).unwrap() + 1.0).abs() < 1e-12);
+        assert_eq!(super::correlation(&first, &[1.0; 4]), None);
+    }
+
+    #[test]
+    fn test_confidence_sequence() {
+        let radius = |count| super::confidence_sequence(1.0, count, 0.05, 100.0);
+        // wider than a fixed sample interval, for being valid at every count
+        assert!((radius(100.0) - 0.3035).abs() < 1e-4);
+        assert!(radius(100.0) > 1.96 / 10.0);
+        assert!(radius(1000.0) < radius(100.0));
+        assert_eq!(super::confidence_sequence(0.0, 10.0, 0.05, 100.0), 0.0);
+    }
+
+    #[test]
+    fn test_welch() {
+        let first = [
+            27.5, 21.0, 19.0, 23.6, 17.0, 17.9, 16.9, 20.1, 21.9, 22.6, 23.1, 19.6,
+        ];
+        let second = [
+            27.1, 22.0, 20.8, 23.4, 23.4, 23.5, 25.8, 22.0, 24.8, 20.2, 21.9, 22.1,
+        ];
+        let test = super::welch(&first, &second);
+        assert!((test.statistic - 2.08958).abs() < 1e-4);
+        assert!((test.p - 0.050388).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_mann_whitney() {
+        let low: Vec<f64> = (0..20).map(f64::from).collect();
+        let high: Vec<f64> = (10..30).map(f64::from).collect();
+        let test = super::mann_whitney(&low, &high);
+        assert_eq!(test.statistic, 350.0);
+        assert!(test.p < 0.01);
+        assert!(super::mann_whitney(&low, &low).p > 0.99);
+    }
+
+    #[test]
+    fn test_p2() {
+        let mut sketch = super::P2::new(0.9);
+        assert_eq!(sketch.quantile(), None);
+        sketch.add(3.0);
+        sketch.add(1.0);
+        assert_eq!(sketch.quantile(), Some(2.8));
+        // a shuffled uniform grid, whose 90th percentile is 0.9
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut samples: Vec<f64> = (0..=10000).map(|i| i as f64 / 10000.0).collect();
+        samples.shuffle(&mut rng);
+        let mut sketch = super::P2::new(0.9);
+        samples.iter().for_each(|&sample| sketch.add(sample));
+        assert!((sketch.quantile().unwrap() - 0.9).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_bootstrap() {
+        let mut rng = rand::thread_rng();
+        let samples: Vec<f64> = (0..100).map(f64::from).collect();
+        let [low, high] = super::bootstrap_mean(&samples, 1000, 0.95, &mut rng);
+        assert!(low < 49.5 && 49.5 < high);
+        let [low, high] = super::bootstrap_diff(&samples, &samples, 1000, 0.95, &mut rng);
+        assert!(low < 0.0 && 0.0 < high);
+    }
+}
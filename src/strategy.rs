@@ -0,0 +1,596 @@
+use crate::agent::Style;
+use rand::Rng;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The amount of shading of a strategy
+///
+/// Shading is either a fixed float, or `U(<low>,<high>)` for a shading drawn uniformly by each
+/// agent every observation.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "ShadingRepr")]
+pub enum Shading {
+    Fixed(f64),
+    Uniform(f64, f64),
+}
+
+impl Shading {
+    /// The shading at quantile `prob` of this distribution
+    pub fn quantile(&self, prob: f64) -> f64 {
+        match *self {
+            Shading::Fixed(shading) => shading,
+            Shading::Uniform(low, high) => low + (high - low) * prob,
+        }
+    }
+
+    /// Draw a concrete shading
+    ///
+    /// This always consumes exactly one draw from `rng` so that paired simulations stay aligned
+    /// when an agent's shading changes.
+    pub fn sample(&self, rng: &mut impl Rng) -> f64 {
+        self.quantile(rng.gen())
+    }
+}
+
+impl fmt::Display for Shading {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shading::Fixed(shading) => write!(f, "{}", shading),
+            Shading::Uniform(low, high) => write!(f, "U({},{})", low, high),
+        }
+    }
+}
+
+impl FromStr for Shading {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let shading = match string.strip_prefix("U(").and_then(|s| s.strip_suffix(')')) {
+            Some(bounds) => {
+                let (low, high) = bounds
+                    .split_once(',')
+                    .ok_or_else(|| format!("uniform shading \"{}\" needs two bounds", string))?;
+                let parse = |bound: &str| {
+                    bound.trim().parse::<f64>().map_err(|_| {
+                        format!("invalid bound \"{}\" in shading \"{}\"", bound, string)
+                    })
+                };
+                Shading::Uniform(parse(low)?, parse(high)?)
+            }
+            None => Shading::Fixed(
+                string
+                    .parse()
+                    .map_err(|_| format!("invalid shading \"{}\"", string))?,
+            ),
+        };
+        shading.validate()
+    }
+}
+
+impl Shading {
+    fn validate(self) -> Result<Self, String> {
+        match self {
+            Shading::Fixed(shading) if !shading.is_finite() => {
+                Err(format!("shading \"{}\" must be finite", self))
+            }
+            Shading::Uniform(low, high)
+                if !(low.is_finite() && high.is_finite() && low <= high) =>
+            {
+                Err(format!(
+                    "uniform shading \"{}\" must have finite ordered bounds",
+                    self
+                ))
+            }
+            _ => Ok(self),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ShadingRepr {
+    Value(f64),
+    Spec(String),
+}
+
+impl TryFrom<ShadingRepr> for Shading {
+    type Error = String;
+
+    fn try_from(repr: ShadingRepr) -> Result<Self, Self::Error> {
+        match repr {
+            ShadingRepr::Value(shading) => Shading::Fixed(shading).validate(),
+            ShadingRepr::Spec(string) => string.parse(),
+        }
+    }
+}
+
+/// How long an agent's orders take to reach a continuous market after it decides to quote
+///
+/// Latency is either a fixed nonnegative float, or `E(<mean>)` for a latency drawn exponentially
+/// for every order. Latency is measured in units of the trading period.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "ShadingRepr")]
+pub enum Latency {
+    Fixed(f64),
+    Exponential(f64),
+}
+
+impl Latency {
+    /// Draw a concrete latency, which only consumes randomness if it's exponential
+    pub fn sample(&self, rng: &mut impl Rng) -> f64 {
+        match *self {
+            Latency::Fixed(latency) => latency,
+            Latency::Exponential(mean) => -mean * (1.0 - rng.gen::<f64>()).ln(),
+        }
+    }
+}
+
+impl fmt::Display for Latency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Latency::Fixed(latency) => write!(f, "{}", latency),
+            Latency::Exponential(mean) => write!(f, "E({})", mean),
+        }
+    }
+}
+
+impl FromStr for Latency {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let parse = |value: &str| {
+            value
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite() && *value >= 0.0)
+                .ok_or_else(|| format!("latency \"{}\" must be finite and nonnegative", string))
+        };
+        match string.strip_prefix("E(").and_then(|s| s.strip_suffix(')')) {
+            Some(mean) => Ok(Latency::Exponential(parse(mean)?)),
+            None => Ok(Latency::Fixed(parse(string)?)),
+        }
+    }
+}
+
+impl TryFrom<ShadingRepr> for Latency {
+    type Error = String;
+
+    fn try_from(repr: ShadingRepr) -> Result<Self, Self::Error> {
+        match repr {
+            ShadingRepr::Value(latency) => latency.to_string().parse(),
+            ShadingRepr::Spec(string) => string.parse(),
+        }
+    }
+}
+
+/// A parsed strategy string
+///
+/// Strategies have the grammar `<shading>[_<style>][_<key><value>]...`, where `<shading>` is a
+/// [Shading], `<style>` is any [Style] name, and the remaining underscore separated parameters are
+/// identified by a single leading key character. The shading may be omitted if the strategy starts
+/// with its style, e.g. `Shift_l0.2`, in which case it's filled in with its role's default:
+///
+/// - `e<epsilon>`: exploration probability of an epsilon-greedy Bandit, which uses UCB otherwise
+/// - `l<min_price>`: the lowest price the agent will bid or ask
+/// - `h<max_price>`: the highest price the agent will bid or ask
+/// - `c<entry_cost>`: the cost the agent pays to submit an order, deciding whether to enter
+/// - `d<latency>`: the [Latency] of the agent's orders in a continuous market
+/// - `r<delta>`: in a continuous market, cancel an untraded standing order and improve it by
+///   `delta`, up to the agent's value
+/// - `k<events>`: how many orders reach the market before a requote, 1 by default
+/// - `s<stop>`: in a continuous market, a trader sells its unit with a market order once a trade
+///   is at or below `stop`
+/// - `i<display>`: in a continuous market, show only this many units of a multi-unit player's
+///   order in the book at once, revealing a hidden unit whenever a shown one trades
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Strategy {
+    pub shading: Option<Shading>,
+    pub style: Option<Style>,
+    pub epsilon: Option<f64>,
+    pub min_price: Option<f64>,
+    pub max_price: Option<f64>,
+    pub entry_cost: Option<f64>,
+    pub latency: Option<Latency>,
+    pub requote: Option<f64>,
+    pub requote_after: Option<u64>,
+    pub display: Option<u64>,
+    pub stop: Option<f64>,
+}
+
+impl Strategy {
+    /// A strategy with only a shading
+    pub fn new(shading: Shading) -> Self {
+        Strategy {
+            shading: Some(shading),
+            style: None,
+            epsilon: None,
+            min_price: None,
+            max_price: None,
+            entry_cost: None,
+            latency: None,
+            requote: None,
+            requote_after: None,
+            display: None,
+            stop: None,
+        }
+    }
+
+    fn validate(self) -> Result<Self, String> {
+        let invalid = |msg: &str| Err(format!("{} in strategy \"{}\"", msg, self));
+        if self
+            .epsilon
+            .map(|eps| !(0.0..=1.0).contains(&eps))
+            .unwrap_or(false)
+        {
+            return invalid("epsilon must be in [0, 1]");
+        }
+        if [self.min_price, self.max_price]
+            .iter()
+            .flatten()
+            .any(|price| !price.is_finite())
+        {
+            return invalid("price bounds must be finite");
+        }
+        if self
+            .entry_cost
+            .is_some_and(|cost| !(cost.is_finite() && cost >= 0.0))
+        {
+            return invalid("entry cost must be finite and nonnegative");
+        }
+        if self
+            .requote
+            .is_some_and(|delta| !(delta.is_finite() && delta > 0.0))
+        {
+            return invalid("requote delta must be finite and positive");
+        }
+        match (self.requote, self.requote_after) {
+            (None, Some(_)) => return invalid("requote events need a requote delta"),
+            (_, Some(0)) => return invalid("requote events must be positive"),
+            _ => (),
+        }
+        if self.stop.is_some_and(|stop| !stop.is_finite()) {
+            return invalid("stop must be finite");
+        }
+        if self.display == Some(0) {
+            return invalid("display quantity must be positive");
+        }
+        match (self.min_price, self.max_price) {
+            (Some(min), Some(max)) if min > max => invalid("min price is above max price"),
+            _ => Ok(self),
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.shading, self.style) {
+            (Some(shading), Some(style)) => write!(f, "{}_{:?}", shading, style)?,
+            (Some(shading), None) => write!(f, "{}", shading)?,
+            (None, Some(style)) => write!(f, "{:?}", style)?,
+            (None, None) => (),
+        }
+        if let Some(eps) = self.epsilon {
+            write!(f, "_e{}", eps)?;
+        }
+        if let Some(min) = self.min_price {
+            write!(f, "_l{}", min)?;
+        }
+        if let Some(max) = self.max_price {
+            write!(f, "_h{}", max)?;
+        }
+        if let Some(cost) = self.entry_cost {
+            write!(f, "_c{}", cost)?;
+        }
+        if let Some(latency) = self.latency {
+            write!(f, "_d{}", latency)?;
+        }
+        if let Some(delta) = self.requote {
+            write!(f, "_r{}", delta)?;
+        }
+        if let Some(events) = self.requote_after {
+            write!(f, "_k{}", events)?;
+        }
+        if let Some(display) = self.display {
+            write!(f, "_i{}", display)?;
+        }
+        if let Some(stop) = self.stop {
+            write!(f, "_s{}", stop)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let mut tokens = string.split('_').peekable();
+        let first = tokens.next().unwrap();
+        let mut strat = match first.parse::<Style>() {
+            // the style starts strategies without a shading
+            Ok(style) => Strategy {
+                shading: None,
+                style: Some(style),
+                ..Strategy::new(Shading::Fixed(0.0))
+            },
+            Err(_) => Strategy::new(
+                first
+                    .parse()
+                    .map_err(|err| format!("{} in strategy \"{}\"", err, string))?,
+            ),
+        };
+        for token in tokens {
+            if token.is_empty() {
+                return Err(format!("empty parameter in strategy \"{}\"", string));
+            } else if token.starts_with(|c: char| c.is_ascii_uppercase()) {
+                if strat.style.is_some() {
+                    return Err(format!("style specified twice in strategy \"{}\"", string));
+                }
+                strat.style = Some(token.parse()?);
+            } else if let Some(latency) = token.strip_prefix('d') {
+                if strat.latency.is_some() {
+                    return Err(format!(
+                        "parameter \"d\" specified twice in strategy \"{}\"",
+                        string
+                    ));
+                }
+                strat.latency = Some(
+                    latency
+                        .parse()
+                        .map_err(|err| format!("{} in strategy \"{}\"", err, string))?,
+                );
+            } else if let Some((key, slot)) = token
+                .strip_prefix('k')
+                .map(|_| ('k', &mut strat.requote_after))
+                .or_else(|| token.strip_prefix('i').map(|_| ('i', &mut strat.display)))
+            {
+                if slot.is_some() {
+                    return Err(format!(
+                        "parameter \"{}\" specified twice in strategy \"{}\"",
+                        key, string
+                    ));
+                }
+                let value = &token[1..];
+                *slot = Some(value.parse().map_err(|_| {
+                    format!(
+                        "invalid value \"{}\" for parameter \"{}\" in strategy \"{}\"",
+                        value, key, string
+                    )
+                })?);
+            } else {
+                let key = token.chars().next().unwrap();
+                let value = &token[key.len_utf8()..];
+                let slot = match key {
+                    'e' => &mut strat.epsilon,
+                    'l' => &mut strat.min_price,
+                    'h' => &mut strat.max_price,
+                    'c' => &mut strat.entry_cost,
+                    'r' => &mut strat.requote,
+                    's' => &mut strat.stop,
+                    _ => {
+                        return Err(format!(
+                            "unknown parameter \"{}\" in strategy \"{}\"",
+                            token, string
+                        ))
+                    }
+                };
+                if slot.is_some() {
+                    return Err(format!(
+                        "parameter \"{}\" specified twice in strategy \"{}\"",
+                        key, string
+                    ));
+                }
+                *slot = Some(value.parse().map_err(|_| {
+                    format!(
+                        "invalid value \"{}\" for parameter \"{}\" in strategy \"{}\"",
+                        value, key, string
+                    )
+                })?);
+            }
+        }
+        strat.validate()
+    }
+}
+
+/// Named strategies that spec assignments can reference instead of a strategy string
+pub type Presets = HashMap<String, Strategy>;
+
+/// Load presets from a file, parsed as json if it has a `.json` extension and toml otherwise
+pub fn load_presets(path: &Path) -> io::Result<Presets> {
+    let contents = fs::read_to_string(path)?;
+    let presets: Presets = if path.extension().map(|ext| ext == "json").unwrap_or(false) {
+        serde_json::from_str(&contents)?
+    } else {
+        toml::from_str(&contents).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+    };
+    presets
+        .into_iter()
+        .map(|(name, strat)| Ok((name, strat.validate()?)))
+        .collect::<Result<_, String>>()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// The most strategy names each thread remembers the parse of
+const PARSED_NAMES: usize = 4096;
+
+thread_local! {
+    /// Strategy strings already parsed, since batches of specs repeat the same few names
+    static PARSED: RefCell<HashMap<String, Result<Strategy, String>>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Resolve a strategy name, preferring a preset over parsing it as a strategy string
+pub fn resolve(name: &str, presets: &Presets) -> Result<Strategy, String> {
+    if let Some(strat) = presets.get(name) {
+        return Ok(*strat);
+    }
+    PARSED.with(|parsed| {
+        let mut parsed = parsed.borrow_mut();
+        if let Some(res) = parsed.get(name) {
+            return res.clone();
+        }
+        let res: Result<Strategy, String> = name.parse();
+        // forget everything rather than grow without bound on specs that never repeat
+        if parsed.len() >= PARSED_NAMES {
+            parsed.clear();
+        }
+        parsed.insert(name.to_owned(), res.clone());
+        res
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Presets, Shading, Strategy};
+    use crate::agent::Style;
+
+    #[test]
+    fn test_parse() {
+        let strat: Strategy = "0.5".parse().unwrap();
+        assert_eq!(strat.shading, Some(Shading::Fixed(0.5)));
+        assert_eq!(strat.style, None);
+
+        let strat: Strategy = "1_Shift".parse().unwrap();
+        assert_eq!(strat.shading, Some(Shading::Fixed(1.0)));
+        assert_eq!(strat.style, Some(Style::Shift));
+
+        let strat: Strategy = "U(0.1, 0.4)_Standard".parse().unwrap();
+        assert_eq!(strat.shading, Some(Shading::Uniform(0.1, 0.4)));
+        assert_eq!(strat.style, Some(Style::Standard));
+
+        let strat: Strategy = "1_Bandit_e0.05".parse().unwrap();
+        assert_eq!(strat.style, Some(Style::Bandit));
+        assert_eq!(strat.epsilon, Some(0.05));
+
+        let strat: Strategy = "Roth_h0.8".parse().unwrap();
+        assert_eq!(strat.shading, None);
+        assert_eq!(strat.style, Some(Style::Roth));
+
+        let strat: Strategy = "0.1_h0.8_l0.2".parse().unwrap();
+        assert_eq!(strat.min_price, Some(0.2));
+        assert_eq!(strat.max_price, Some(0.8));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for string in [
+            "0.5",
+            "0.25_Standard",
+            "1_Correct",
+            "0.1_Exponential",
+            "U(0.1,0.4)_Shift",
+            "0.5_Bandit_e0.1",
+            "0.5_l0.1_h0.9",
+            "0.2_c0.05",
+            "Shift_l0.1",
+            "0.3_d0.1",
+            "0.3_dE(0.05)",
+            "0.3_r0.05_k3",
+            "0.4_i2",
+            "0.2_s0.3",
+        ] {
+            let strat: Strategy = string.parse().unwrap();
+            let copy: Strategy = strat.to_string().parse().unwrap();
+            assert_eq!(copy, strat);
+            assert_eq!(strat.to_string(), string);
+        }
+    }
+
+    #[test]
+    fn test_shading_sample() {
+        let mut rng = rand::thread_rng();
+        let shading = Shading::Uniform(0.1, 0.4);
+        for _ in 0..100 {
+            let sample = shading.sample(&mut rng);
+            assert!((0.1..=0.4).contains(&sample));
+        }
+        assert_eq!(Shading::Fixed(0.3).sample(&mut rng), 0.3);
+    }
+
+    #[test]
+    fn test_errors() {
+        for string in [
+            "",
+            "abc",
+            "inf",
+            "0.5_",
+            "0.5_Unknown",
+            "0.5_Shift_Correct",
+            "0.5_z3",
+            "0.5_e0.1_e0.2",
+            "0.5_e2",
+            "0.5_ex",
+            "0.5_l0.6_h0.4",
+            "0.5_hinf",
+            "0.5_c-1",
+            "0.5_d-0.1",
+            "0.5_dE(x)",
+            "0.5_d0.1_d0.2",
+            "0.5_r0",
+            "0.5_k2",
+            "0.5_r0.1_k0",
+            "0.5_r0.1_k1.5",
+            "0.5_i0",
+            "0.5_sinf",
+            "0.5_i1_i2",
+            "U(0.1)",
+            "U(0.4,0.1)",
+            "U(0.1,x)",
+        ] {
+            assert!(string.parse::<Strategy>().is_err(), "{}", string);
+        }
+    }
+
+    #[test]
+    fn test_presets() {
+        let presets: Presets = toml::from_str(
+            r#"
+            shift_fast = { style = "Shift", shading = 0.3 }
+            plain = { shading = 0.1 }
+            spread = { shading = "U(0.1,0.2)" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            super::resolve("shift_fast", &presets).unwrap(),
+            Strategy {
+                style: Some(Style::Shift),
+                ..Strategy::new(Shading::Fixed(0.3))
+            }
+        );
+        assert_eq!(super::resolve("plain", &presets).unwrap().style, None);
+        assert_eq!(
+            super::resolve("spread", &presets).unwrap().shading,
+            Some(Shading::Uniform(0.1, 0.2))
+        );
+        assert_eq!(
+            super::resolve("0.2", &presets).unwrap().shading,
+            Some(Shading::Fixed(0.2))
+        );
+        assert!(super::resolve("missing", &presets).is_err());
+        assert!(toml::from_str::<Presets>("bad = { shading = 0.1, beta = 2 }").is_err());
+    }
+
+    #[test]
+    fn test_resolve_repeated() {
+        // remembered parses give the same answer, and don't hide presets of the same name
+        let presets: Presets = toml::from_str(r#""0.4" = { shading = 0.1 }"#).unwrap();
+        for _ in 0..2 {
+            let strat = super::resolve("0.4_Shift", &Presets::new()).unwrap();
+            assert_eq!(strat.style, Some(Style::Shift));
+            assert!(super::resolve("0.4_Nonsense", &Presets::new()).is_err());
+            let plain = super::resolve("0.4", &Presets::new()).unwrap();
+            assert_eq!(plain.shading, Some(Shading::Fixed(0.4)));
+            let preset = super::resolve("0.4", &presets).unwrap();
+            assert_eq!(preset.shading, Some(Shading::Fixed(0.1)));
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use clap::ValueEnum;
+use flate2::bufread::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Whether bytes start with a gzip or zstd header
+pub fn compressed(head: &[u8]) -> bool {
+    head.starts_with(GZIP_MAGIC) || head.starts_with(ZSTD_MAGIC)
+}
+
+/// Wrap input in a decoder if it starts with a gzip or zstd header
+pub fn decompress<'a>(mut input: impl BufRead + 'a) -> io::Result<Box<dyn BufRead + 'a>> {
+    let head = input.fill_buf()?;
+    Ok(if head.starts_with(GZIP_MAGIC) {
+        Box::new(BufReader::new(MultiGzDecoder::new(input)))
+    } else if head.starts_with(ZSTD_MAGIC) {
+        Box::new(BufReader::new(zstd::Decoder::with_buffer(input)?))
+    } else {
+        Box::new(input)
+    })
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+/// Output that's optionally compressed, which must be finished to write the end of the stream
+pub enum Output<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Output<W> {
+    pub fn new(out: W, compression: Compression) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::None => Output::Plain(out),
+            Compression::Gzip => Output::Gzip(GzEncoder::new(out, flate2::Compression::default())),
+            Compression::Zstd => Output::Zstd(zstd::Encoder::new(out, 0)?),
+        })
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Output::Plain(out) => Ok(out),
+            Output::Gzip(enc) => enc.finish(),
+            Output::Zstd(enc) => enc.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Output<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(out) => out.write(buf),
+            Output::Gzip(enc) => enc.write(buf),
+            Output::Zstd(enc) => enc.write(buf),
+        }
+    }
+
+    /// Flushing compressed output ends the current block so everything so far can be decoded
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(out) => out.flush(),
+            Output::Gzip(enc) => enc.flush(),
+            Output::Zstd(enc) => enc.flush(),
+        }
+    }
+}
+
+/// Output split into files of at most a number of lines each, each compressed on its own
+///
+/// Files are named by replacing "{}" in a pattern with the index of the file, padded to five
+/// digits so they sort in order, and every file ends with a whole line.
+pub struct Sharded {
+    pattern: String,
+    size: Option<u64>,
+    compression: Compression,
+    index: u64,
+    lines: u64,
+    current: Option<Output<BufWriter<File>>>,
+}
+
+impl Sharded {
+    pub fn new(pattern: &str, size: Option<u64>, compression: Compression) -> io::Result<Self> {
+        if size.is_some() && !pattern.contains("{}") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "sharded output \"{}\" must contain {{}} for the shard",
+                    pattern
+                ),
+            ));
+        }
+        Ok(Sharded {
+            pattern: pattern.to_owned(),
+            size,
+            compression,
+            index: 0,
+            lines: 0,
+            current: None,
+        })
+    }
+
+    /// The name of the file with this index
+    pub fn path(&self, index: u64) -> String {
+        self.pattern.replace("{}", &format!("{:05}", index))
+    }
+
+    /// The file being written, which is created on the first write after the last one filled
+    fn current(&mut self) -> io::Result<&mut Output<BufWriter<File>>> {
+        if self.current.is_none() {
+            let file = BufWriter::new(File::create(self.path(self.index))?);
+            self.current = Some(Output::new(file, self.compression)?);
+        }
+        Ok(self.current.as_mut().unwrap())
+    }
+
+    /// Finish the last file, creating an empty first one if nothing was written
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.index == 0 {
+            self.current()?;
+        }
+        match self.current.take() {
+            Some(out) => out.finish()?.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for Sharded {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(size) = self.size else {
+            return self.current()?.write(buf);
+        };
+        // never write past the end of the current file's last line
+        let len = match buf.iter().position(|&byte| byte == b'\n') {
+            Some(pos) => pos + 1,
+            None => buf.len(),
+        };
+        let written = self.current()?.write(&buf[..len])?;
+        if written == len && buf[len - 1] == b'\n' {
+            self.lines += 1;
+            if self.lines == size {
+                self.current.take().unwrap().finish()?.flush()?;
+                self.index += 1;
+                self.lines = 0;
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some(out) => out.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Compression, Output, Sharded};
+    use std::io::{BufRead, Write};
+
+    #[test]
+    fn test_round_trip() {
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            let mut out = Output::new(Vec::new(), compression).unwrap();
+            writeln!(out, "first").unwrap();
+            writeln!(out, "second").unwrap();
+            let bytes = out.finish().unwrap();
+            let lines: Vec<String> = super::decompress(&bytes[..])
+                .unwrap()
+                .lines()
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(lines, ["first", "second"]);
+        }
+    }
+
+    #[test]
+    fn test_sharded() {
+        let dir = std::env::temp_dir().join(format!("cdasim-shards-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pattern = dir.join("shard-{}.jsonl").to_str().unwrap().to_owned();
+        let mut out = Sharded::new(&pattern, Some(2), Compression::Gzip).unwrap();
+        write!(out, "0\n1\n2").unwrap();
+        writeln!(out, "3").unwrap();
+        writeln!(out, "4").unwrap();
+        let paths: Vec<_> = (0..2).map(|index| out.path(index)).collect();
+        out.finish().unwrap();
+        let lines: Vec<Vec<String>> = paths
+            .iter()
+            .map(|path| {
+                let bytes = std::fs::read(path).unwrap();
+                let input = super::decompress(&bytes[..]).unwrap();
+                input.lines().map(Result::unwrap).collect()
+            })
+            .collect();
+        assert_eq!(lines, [["0", "1"], ["23", "4"]]);
+        assert!(paths[0].ends_with("shard-00000.jsonl"));
+        assert!(!dir.join("shard-00002.jsonl").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(Sharded::new("out.jsonl", Some(2), Compression::None).is_err());
+    }
+}
@@ -0,0 +1,314 @@
+use crate::expected::Control;
+use crate::stats;
+use crate::{Agent, Features};
+use rand::Rng;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Confidence level of bootstrap intervals
+const CONFIDENCE: f64 = 0.95;
+
+/// Samples of the features and strategy payoffs of a spec's observations
+#[derive(Debug, Default)]
+pub struct Summary {
+    observations: u64,
+    features: BTreeMap<String, Vec<f64>>,
+    payoffs: BTreeMap<String, BTreeMap<String, Vec<f64>>>,
+}
+
+/// Aggregate statistics of one feature or payoff
+#[derive(Serialize, Clone, Debug)]
+pub struct Stat {
+    mean: f64,
+    stderr: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interval: Option<[f64; 2]>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Report {
+    observations: u64,
+    features: BTreeMap<String, Stat>,
+    /// Role to strategy to payoff
+    payoffs: BTreeMap<String, BTreeMap<String, Stat>>,
+}
+
+/// A feature's mean adjusted by its regression on a control variate with a known mean
+#[derive(Serialize, Clone, Debug)]
+pub struct Controlled {
+    control: Control,
+    /// How much the feature changes with the control
+    coefficient: f64,
+    #[serde(flatten)]
+    stat: Stat,
+}
+
+/// Means of the features and strategy payoffs of equally weighted points, which have no variance
+#[derive(Debug, Default)]
+pub struct Means {
+    points: u64,
+    features: BTreeMap<String, (f64, u64)>,
+    payoffs: BTreeMap<String, BTreeMap<String, (f64, u64)>>,
+}
+
+/// Every numeric feature of an observation, and the mean payoff of every strategy's players
+fn samples(
+    features: &Features,
+    agents: &[Agent<'_>],
+    mut feature: impl FnMut(&str, f64),
+    mut payoff: impl FnMut(&str, &str, f64),
+) {
+    let value = serde_json::to_value(features).expect("features are always serializable");
+    for (name, val) in value.as_object().expect("features are a struct") {
+        // features like the price are missing when no one trades
+        if let Some(num) = val.as_f64() {
+            feature(name, num);
+        }
+    }
+
+    let mut totals: BTreeMap<(&str, &str), (f64, usize)> = BTreeMap::new();
+    for player in crate::players(agents) {
+        let total = totals.entry((player.role, player.strategy)).or_default();
+        total.0 += player.payoff;
+        total.1 += 1;
+    }
+    for ((role, strat), (total, count)) in totals {
+        payoff(role, strat, total / count as f64);
+    }
+}
+
+impl Summary {
+    /// Add an observation, where a strategy's payoff is the mean of the players playing it
+    pub fn add(&mut self, features: &Features, agents: &[Agent<'_>]) {
+        self.observations += 1;
+        samples(
+            features,
+            agents,
+            |name, num| self.features.entry(name.to_owned()).or_default().push(num),
+            |role, strat, payoff| {
+                self.payoffs
+                    .entry(role.to_owned())
+                    .or_default()
+                    .entry(strat.to_owned())
+                    .or_default()
+                    .push(payoff)
+            },
+        );
+    }
+
+    /// The mean of a feature less its coefficient on another times how far that one's mean is from
+    /// its known mean, if both were observed together more than once
+    ///
+    /// The standard error is of the residuals, plus the error in an estimated known mean.
+    pub fn controlled(&self, feature: &str, control: &str, known: Control) -> Option<Controlled> {
+        let samples = self.features.get(feature)?;
+        let controls = self.features.get(control)?;
+        if samples.len() != controls.len() || samples.len() < 2 {
+            return None;
+        }
+        let count = samples.len() as f64;
+        let (mean, control_mean) = (stats::mean(samples), stats::mean(controls));
+        let covariance = samples
+            .iter()
+            .zip(controls)
+            .map(|(sample, control)| (sample - mean) * (control - control_mean))
+            .sum::<f64>()
+            / (count - 1.0);
+        let variance = stats::variance(controls);
+        let coefficient = if variance > 0.0 {
+            covariance / variance
+        } else {
+            0.0
+        };
+        let residuals: Vec<_> = samples
+            .iter()
+            .zip(controls)
+            .map(|(sample, control)| sample - coefficient * control)
+            .collect();
+        let known_error = coefficient * known.stderr.unwrap_or(0.0);
+        let stat = Stat {
+            mean: mean - coefficient * (control_mean - known.mean),
+            stderr: Some((stats::variance(&residuals) / count + known_error * known_error).sqrt()),
+            interval: None,
+        };
+        Some(Controlled {
+            control: known,
+            coefficient,
+            stat,
+        })
+    }
+
+    /// The largest standard error of a feature, or of every strategy's payoff without one, if
+    /// every one has more than one sample
+    pub fn max_stderr(&self, feature: Option<&str>) -> Option<f64> {
+        match feature {
+            Some(feature) => stderr(self.features.get(feature)?),
+            None => {
+                let stderrs: Option<Vec<_>> = self
+                    .payoffs
+                    .values()
+                    .flat_map(BTreeMap::values)
+                    .map(|samples| stderr(samples))
+                    .collect();
+                stderrs?.into_iter().reduce(f64::max)
+            }
+        }
+    }
+
+    /// Summarize every statistic, with bootstrap intervals if a number of resamples is given
+    pub fn report(&self, bootstrap: Option<u64>, rng: &mut impl Rng) -> Report {
+        let mut summarize = |samples: &BTreeMap<String, Vec<f64>>| {
+            samples
+                .iter()
+                .map(|(name, samples)| (name.clone(), stat(samples, bootstrap, rng)))
+                .collect()
+        };
+        Report {
+            observations: self.observations,
+            features: summarize(&self.features),
+            payoffs: self
+                .payoffs
+                .iter()
+                .map(|(role, strats)| (role.clone(), summarize(strats)))
+                .collect(),
+        }
+    }
+}
+
+impl Means {
+    /// Add a point, where a strategy's payoff is the mean of the players playing it
+    pub fn add(&mut self, features: &Features, agents: &[Agent<'_>]) {
+        self.points += 1;
+        let add = |total: &mut (f64, u64), num| {
+            total.0 += num;
+            total.1 += 1;
+        };
+        samples(
+            features,
+            agents,
+            |name, num| add(self.features.entry(name.to_owned()).or_default(), num),
+            |role, strat, payoff| {
+                let strats = self.payoffs.entry(role.to_owned()).or_default();
+                add(strats.entry(strat.to_owned()).or_default(), payoff)
+            },
+        );
+    }
+
+    /// The mean of every feature and payoff over the points where it's defined
+    pub fn report(&self) -> Report {
+        let summarize = |totals: &BTreeMap<String, (f64, u64)>| {
+            totals
+                .iter()
+                .map(|(name, &(total, count))| {
+                    let stat = Stat {
+                        mean: total / count as f64,
+                        stderr: Some(0.0),
+                        interval: None,
+                    };
+                    (name.clone(), stat)
+                })
+                .collect()
+        };
+        Report {
+            observations: self.points,
+            features: summarize(&self.features),
+            payoffs: self
+                .payoffs
+                .iter()
+                .map(|(role, strats)| (role.clone(), summarize(strats)))
+                .collect(),
+        }
+    }
+}
+
+/// The statistics of one set of samples
+pub fn stat(samples: &[f64], bootstrap: Option<u64>, rng: &mut impl Rng) -> Stat {
+    Stat {
+        mean: stats::mean(samples),
+        stderr: stderr(samples),
+        interval: bootstrap
+            .filter(|&resamples| resamples > 0)
+            .map(|resamples| stats::bootstrap_mean(samples, resamples, CONFIDENCE, rng)),
+    }
+}
+
+/// The standard error of the mean of samples, if there's more than one
+fn stderr(samples: &[f64]) -> Option<f64> {
+    (samples.len() > 1).then(|| (stats::variance(samples) / samples.len() as f64).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Summary;
+    use crate::expected::Control;
+    use crate::strategy::Shading;
+    use crate::{Agent, Cda, Style};
+
+    #[test]
+    fn test_summary() {
+        let mut rng = rand::thread_rng();
+        let mut agents: Vec<_> = [true, true, false]
+            .into_iter()
+            .map(|buyer| Agent::new(buyer, "0.1", Style::Standard, Shading::Fixed(0.1)))
+            .collect();
+        let mut summary = Summary::default();
+        for _ in 0..10 {
+            let features = crate::run_sim(&mut agents, &Cda, &mut rng);
+            summary.add(&features, &agents);
+        }
+        let report = serde_json::to_value(summary.report(Some(100), &mut rng)).unwrap();
+        assert_eq!(report["observations"], 10);
+        let surplus = &report["features"]["surplus"];
+        let [low, high] = [0, 1].map(|ind| surplus["interval"][ind].as_f64().unwrap());
+        assert!(low <= surplus["mean"].as_f64().unwrap());
+        assert!(surplus["mean"].as_f64().unwrap() <= high);
+        assert!(surplus["stderr"].is_f64());
+        assert!(report["payoffs"]["buyers"]["0.1"].is_object());
+        assert!(report["payoffs"]["sellers"]["0.1"].is_object());
+    }
+
+    #[test]
+    fn test_controlled() {
+        let mut summary = Summary::default();
+        for (surplus, control) in [(1.1, 1.0), (2.0, 2.0), (2.9, 3.0), (4.0, 4.0)] {
+            summary
+                .features
+                .entry("surplus".to_owned())
+                .or_default()
+                .push(surplus);
+            summary
+                .features
+                .entry("ce".to_owned())
+                .or_default()
+                .push(control);
+        }
+        let known = Control {
+            mean: 2.0,
+            stderr: None,
+            draws: None,
+        };
+        let controlled = summary.controlled("surplus", "ce", known).unwrap();
+        // the surplus nearly tracks the control, whose mean is half too high
+        assert!((controlled.coefficient - 0.96).abs() < 1e-9);
+        assert!((controlled.stat.mean - (2.5 - 0.96 * 0.5)).abs() < 1e-9);
+        let stderr = controlled.stat.stderr.unwrap();
+        assert!(stderr < crate::stats::variance(&summary.features["surplus"]).sqrt() / 2.0 / 10.0);
+        assert!(summary.controlled("surplus", "missing", known).is_none());
+    }
+
+    #[test]
+    fn test_max_stderr() {
+        let mut summary = Summary::default();
+        let payoffs = summary.payoffs.entry("buyers".to_owned()).or_default();
+        payoffs.insert("a".to_owned(), vec![1.0, 3.0]);
+        payoffs.insert("b".to_owned(), vec![1.0, 1.0]);
+        assert_eq!(summary.max_stderr(None), Some(1.0));
+        assert_eq!(summary.max_stderr(Some("surplus")), None);
+        summary
+            .payoffs
+            .entry("sellers".to_owned())
+            .or_default()
+            .insert("c".to_owned(), vec![2.0]);
+        assert_eq!(summary.max_stderr(None), None);
+    }
+}
@@ -0,0 +1,177 @@
+use crate::sampling::{self, Sobol};
+use crate::Spec;
+use clap::{Parser, ValueEnum};
+use rand::Rng;
+use serde_json::Value;
+use std::io::{self, BufRead, Write};
+
+#[derive(Parser, Debug)]
+/// Sample configurations of each spec for a sweep over several parameters
+///
+/// Takes spec lines on stdin, and for each writes --samples copies with every --param set to a
+/// point in its range, spreading the points over the space of parameters instead of taking a full
+/// grid of them. The output is spec lines for the default command, whose "spec_index" of each
+/// observation identifies its sample.
+pub struct SweepArgs {
+    /// A configuration key and its range as "key=low:high", with dots separating the keys of
+    /// nested objects, e.g. "cost=0:0.2" or "market_maker.spread=0:0.1"
+    #[clap(long = "param", value_parser = parse_param, required = true)]
+    params: Vec<Param>,
+
+    /// How to choose the points
+    #[clap(long, value_enum, default_value_t = Sampler::Sobol)]
+    sampler: Sampler,
+
+    /// Number of points to sample for each spec
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 1000)]
+    samples: u64,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sampler {
+    /// A Sobol sequence, the same for every spec
+    Sobol,
+    /// A random Latin hypercube, which stratifies every parameter on its own
+    Latin,
+    /// Independent uniform points
+    Random,
+}
+
+/// A configuration key varied over a range
+#[derive(Debug, Clone, PartialEq)]
+struct Param {
+    key: String,
+    low: f64,
+    high: f64,
+}
+
+fn parse_param(text: &str) -> Result<Param, String> {
+    let invalid = || format!("param must be key=low:high, but got {}", text);
+    let (key, range) = text.split_once('=').ok_or_else(invalid)?;
+    let (low, high) = range.split_once(':').ok_or_else(invalid)?;
+    let parse = |num: &str| {
+        num.parse::<f64>()
+            .ok()
+            .filter(|num| num.is_finite())
+            .ok_or_else(|| format!("{} isn't a finite number", num))
+    };
+    let (low, high) = (parse(low)?, parse(high)?);
+    if key.is_empty() || low > high {
+        Err(invalid())
+    } else {
+        Ok(Param {
+            key: key.to_owned(),
+            low,
+            high,
+        })
+    }
+}
+
+/// Points in the unit hypercube with a coordinate for each parameter
+fn points(args: &SweepArgs) -> Vec<Vec<f64>> {
+    let dims = args.params.len();
+    let num = args.samples as usize;
+    let mut rng = rand::thread_rng();
+    match args.sampler {
+        Sampler::Sobol => Sobol::new(dims).take(num).collect(),
+        Sampler::Latin => sampling::latin_hypercube(num, dims, &mut rng),
+        Sampler::Random => (0..num)
+            .map(|_| (0..dims).map(|_| rng.gen()).collect())
+            .collect(),
+    }
+}
+
+pub fn sweep(
+    args: &SweepArgs,
+    strict: bool,
+    ihandle: impl BufRead,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    for (index, line) in ihandle.lines().enumerate() {
+        let _span = tracing::info_span!("spec", line = index).entered();
+        let base: Value = crate::parse_line(&line?, false)?;
+        for point in points(args) {
+            let mut spec = base.clone();
+            for (param, coord) in args.params.iter().zip(point) {
+                let value = param.low + coord * (param.high - param.low);
+                crate::sensitivity::set_param(&mut spec, &param.key, value)?;
+            }
+            let line = spec.to_string();
+            // catch keys that aren't in the configuration before anything is simulated
+            crate::parse_line::<Spec>(&line, strict)?;
+            writeln!(out, "{}", line)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Param, Sampler, SweepArgs};
+
+    fn sweep(sampler: Sampler, samples: u64) -> Vec<serde_json::Value> {
+        let input = r#"{"assignment":{"buyers":{"0":2},"sellers":{"0":2}},"configuration":{"market_maker":{"price":0.5}}}"#;
+        let args = SweepArgs {
+            params: vec![
+                super::parse_param("cost=0:0.2").unwrap(),
+                super::parse_param("market_maker.spread=0.1:0.3").unwrap(),
+            ],
+            sampler,
+            samples,
+        };
+        let mut out = Vec::new();
+        super::sweep(&args, true, input.as_bytes(), &mut out).unwrap();
+        serde_json::Deserializer::from_slice(&out)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_param() {
+        let param = super::parse_param("a.b=-1:2.5").unwrap();
+        let expected = Param {
+            key: "a.b".to_owned(),
+            low: -1.0,
+            high: 2.5,
+        };
+        assert_eq!(param, expected);
+        assert!(super::parse_param("cost").is_err());
+        assert!(super::parse_param("cost=1").is_err());
+        assert!(super::parse_param("cost=1:0").is_err());
+        assert!(super::parse_param("=0:1").is_err());
+        assert!(super::parse_param("cost=0:nan").is_err());
+    }
+
+    #[test]
+    fn test_sweep() {
+        for sampler in [Sampler::Sobol, Sampler::Latin, Sampler::Random] {
+            let specs = sweep(sampler, 8);
+            assert_eq!(specs.len(), 8);
+            for spec in &specs {
+                let config = &spec["configuration"];
+                let cost = config["cost"].as_f64().unwrap();
+                assert!((0.0..=0.2).contains(&cost));
+                let maker = &config["market_maker"];
+                let spread = maker["spread"].as_f64().unwrap();
+                assert!((0.1..=0.3).contains(&spread));
+                // other keys are left alone
+                assert_eq!(maker["price"], 0.5);
+            }
+        }
+        let specs = sweep(Sampler::Sobol, 1);
+        assert_eq!(specs[0]["configuration"]["cost"], 0.1);
+    }
+
+    #[test]
+    fn test_sweep_unknown_key() {
+        let input = r#"{"assignment":{"buyers":{"0":2}},"configuration":{}}"#;
+        let args = SweepArgs {
+            params: vec![super::parse_param("nonsense=0:1").unwrap()],
+            sampler: Sampler::Latin,
+            samples: 2,
+        };
+        let mut out = Vec::new();
+        assert!(super::sweep(&args, true, input.as_bytes(), &mut out).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::env;
+use std::io;
+
+/// Values of the variables spec text can use as `${NAME}`, from --define or else the environment
+#[derive(Debug, Default)]
+pub struct Template {
+    defines: HashMap<String, String>,
+}
+
+/// If a variable name is letters, digits, and underscores, not starting with a digit
+fn valid_name(name: &str) -> bool {
+    !name.starts_with(|chr: char| chr.is_ascii_digit())
+        && !name.is_empty()
+        && name
+            .chars()
+            .all(|chr| chr.is_ascii_alphanumeric() || chr == '_')
+}
+
+/// Parse a --define of "KEY=VALUE"
+pub fn parse_define(text: &str) -> Result<(String, String), String> {
+    match text.split_once('=') {
+        Some((name, value)) if valid_name(name) => Ok((name.to_owned(), value.to_owned())),
+        _ => Err(format!("define must be KEY=VALUE, but got {}", text)),
+    }
+}
+
+impl Template {
+    pub fn new(defines: &[(String, String)]) -> Self {
+        Template {
+            defines: defines.iter().cloned().collect(),
+        }
+    }
+
+    /// Substitute every variable in spec text
+    ///
+    /// `${NAME}` is the variable's value, `${NAME:-default}` is the default if it's undefined, and
+    /// `$${` is a literal `${`. Values are inserted as is, so they can be numbers or parts of
+    /// strings. Text without a "$" is returned without copying it.
+    pub fn apply<'a>(&self, text: &'a str) -> io::Result<Cow<'a, str>> {
+        if !text.contains('$') {
+            return Ok(Cow::Borrowed(text));
+        }
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut result = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(ind) = rest.find('$') {
+            result.push_str(&rest[..ind]);
+            let after = &rest[ind..];
+            if let Some(tail) = after.strip_prefix("$${") {
+                result.push_str("${");
+                rest = tail;
+            } else if let Some(tail) = after.strip_prefix("${") {
+                let end = tail
+                    .find('}')
+                    .ok_or_else(|| invalid(format!("unterminated variable in {}", text)))?;
+                let (name, default) = match tail[..end].split_once(":-") {
+                    Some((name, default)) => (name, Some(default)),
+                    None => (&tail[..end], None),
+                };
+                if !valid_name(name) {
+                    return Err(invalid(format!("invalid variable name \"{}\"", name)));
+                }
+                let value = match (self.defines.get(name), default) {
+                    (Some(value), _) => value.clone(),
+                    (None, default) => env::var(name)
+                        .ok()
+                        .or_else(|| default.map(str::to_owned))
+                        .ok_or_else(|| {
+                        invalid(format!(
+                            "variable {} isn't defined with --define or the environment",
+                            name
+                        ))
+                    })?,
+                };
+                result.push_str(&value);
+                rest = &tail[end + 1..];
+            } else {
+                result.push('$');
+                rest = &after[1..];
+            }
+        }
+        result.push_str(rest);
+        Ok(Cow::Owned(result))
+    }
+
+    /// Substitute every variable in an owned line, reusing it if it has none
+    pub fn apply_line(&self, line: String) -> io::Result<String> {
+        let substituted = match self.apply(&line)? {
+            Cow::Owned(result) => Some(result),
+            Cow::Borrowed(_) => None,
+        };
+        Ok(substituted.unwrap_or(line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Template;
+
+    #[test]
+    fn test_apply() {
+        let defines = [
+            ("N".to_owned(), "3".to_owned()),
+            ("S".to_owned(), "0.2".to_owned()),
+        ];
+        let template = Template::new(&defines);
+        let spec = r#"{"buyers":{"${S}_Shift":${N}},"cost":${COST:-0},"note":"$$5 $${N}"}"#;
+        assert_eq!(
+            template.apply(spec).unwrap(),
+            r#"{"buyers":{"0.2_Shift":3},"cost":0,"note":"$$5 ${N}"}"#
+        );
+        assert!(matches!(
+            template.apply("plain"),
+            Ok(std::borrow::Cow::Borrowed(_))
+        ));
+        std::env::set_var("CDASIM_TEMPLATE_TEST", "env");
+        assert_eq!(template.apply("${CDASIM_TEMPLATE_TEST}").unwrap(), "env");
+        for invalid in ["${UNDEFINED_CDASIM_VAR}", "${N", "${1x}"] {
+            assert!(template.apply(invalid).is_err(), "{}", invalid);
+        }
+
+        assert_eq!(
+            super::parse_define("A_1=x=y").unwrap(),
+            ("A_1".to_owned(), "x=y".to_owned())
+        );
+        assert!(super::parse_define("novalue").is_err());
+        assert!(super::parse_define("=1").is_err());
+    }
+}
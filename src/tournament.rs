@@ -0,0 +1,382 @@
+use crate::export;
+use crate::market::{Call, Cda, Market};
+use crate::strategy::Presets;
+use crate::{Agent, Config, Roles, Spec};
+use clap::{Parser, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+
+#[derive(Parser, Debug)]
+/// Play every matchup between a set of strategies
+///
+/// Takes lines of json on stdin like {"strategies": [strat...], "buyers": 5, "sellers": 5,
+/// "configuration": {...}}, where the configuration is the same as the default command's, and
+/// "buyers" and "sellers" are the number of agents in each role. By default every matchup has all
+/// buyers play one strategy and all sellers another, and each line of output is the payoff matrix
+/// with the "strategies", the mean buyer payoff of every pair as "buyers", indexed by the buyers'
+/// strategy then the sellers', and the mean seller payoff as "sellers". With --profiles, every
+/// profile of the strategies within each role is played instead, and the output has a "profiles"
+/// list with the "buyers" and "sellers" counts of each, and the mean "payoffs" of every strategy in
+/// each role.
+pub struct TournamentArgs {
+    /// Number of simulations per matchup
+    #[clap(long, value_parser, default_value_t = 1000)]
+    samples: u64,
+
+    /// Play every profile of the strategies in each role instead of only uniform ones
+    #[clap(long, value_parser)]
+    profiles: bool,
+
+    /// How to write the results
+    #[clap(long, value_enum, default_value_t = Format::Json)]
+    format: Format,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// A line of json per input line
+    Json,
+    /// Csv rows, with the header buyer_strategy,seller_strategy,buyer_payoff,seller_payoff for the
+    /// payoff matrix, and profile,role,strategy,count,payoff for profiles
+    Csv,
+    /// A Gambit normal form game of the payoff matrix per input line, with buyers as the row player
+    Nfg,
+    /// A line of gameanalysis game json per input line, requires --profiles
+    Gameanalysis,
+}
+
+#[derive(Deserialize, Debug)]
+struct Tournament {
+    strategies: Vec<String>,
+    #[serde(default = "default_players")]
+    buyers: u64,
+    #[serde(default = "default_players")]
+    sellers: u64,
+    configuration: Config,
+}
+
+fn default_players() -> u64 {
+    5
+}
+
+#[derive(Serialize, Debug)]
+pub struct Matrix<'a> {
+    pub strategies: &'a [String],
+    pub buyers: Vec<Vec<f64>>,
+    pub sellers: Vec<Vec<f64>>,
+}
+
+/// One profile and the mean payoff of each strategy in each role
+#[derive(Serialize, Debug)]
+pub struct Profile {
+    pub buyers: BTreeMap<String, u64>,
+    pub sellers: BTreeMap<String, u64>,
+    pub payoffs: BTreeMap<String, BTreeMap<String, f64>>,
+}
+
+#[derive(Serialize, Debug)]
+struct Profiles {
+    profiles: Vec<Profile>,
+}
+
+pub fn tournament(
+    args: &TournamentArgs,
+    presets: &Presets,
+    strict: bool,
+    ihandle: impl BufRead,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    match (args.format, args.profiles) {
+        (Format::Nfg, true) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "nfg output is only for the payoff matrix, not --profiles",
+            ))
+        }
+        (Format::Gameanalysis, false) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "gameanalysis output requires --profiles",
+            ))
+        }
+        _ => (),
+    }
+    if args.format == Format::Csv {
+        if args.profiles {
+            writeln!(out, "profile,role,strategy,count,payoff")?;
+        } else {
+            writeln!(
+                out,
+                "buyer_strategy,seller_strategy,buyer_payoff,seller_payoff"
+            )?;
+        }
+    }
+    for (index, line) in ihandle.lines().enumerate() {
+        let _span = tracing::info_span!("spec", line = index).entered();
+        let tourn: Tournament = crate::parse_line(&line?, strict)?;
+        if tourn.strategies.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "a tournament needs at least one strategy",
+            ));
+        }
+        if args.profiles {
+            let profiles = play_profiles(&tourn, presets, args.samples)?;
+            if args.format == Format::Gameanalysis {
+                export::gameanalysis(
+                    &tourn.strategies,
+                    tourn.buyers,
+                    tourn.sellers,
+                    &profiles,
+                    out,
+                )?;
+            } else if args.format == Format::Csv {
+                for (num, profile) in profiles.iter().enumerate() {
+                    for (role, counts) in
+                        [("buyers", &profile.buyers), ("sellers", &profile.sellers)]
+                    {
+                        for (strat, count) in counts {
+                            let payoff = profile.payoffs[role][strat];
+                            writeln!(out, "{},{},{},{},{}", num, role, strat, count, payoff)?;
+                        }
+                    }
+                }
+            } else {
+                serde_json::to_writer(&mut *out, &Profiles { profiles })?;
+                writeln!(out)?;
+            }
+        } else {
+            let matrix = play_matrix(&tourn, presets, args.samples)?;
+            if args.format == Format::Nfg {
+                export::nfg(&matrix, out)?;
+            } else if args.format == Format::Csv {
+                for (row, buyer) in tourn.strategies.iter().enumerate() {
+                    for (col, seller) in tourn.strategies.iter().enumerate() {
+                        writeln!(
+                            out,
+                            "{},{},{},{}",
+                            buyer, seller, matrix.buyers[row][col], matrix.sellers[row][col]
+                        )?;
+                    }
+                }
+            } else {
+                serde_json::to_writer(&mut *out, &matrix)?;
+                writeln!(out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Play every pair of a buyer strategy and a seller strategy
+fn play_matrix<'a>(
+    tourn: &'a Tournament,
+    presets: &Presets,
+    samples: u64,
+) -> io::Result<Matrix<'a>> {
+    let num = tourn.strategies.len();
+    let mut buyers = vec![vec![0.0; num]; num];
+    let mut sellers = vec![vec![0.0; num]; num];
+    for (row, buyer) in tourn.strategies.iter().enumerate() {
+        for (col, seller) in tourn.strategies.iter().enumerate() {
+            let counts = |strat: &str, count| BTreeMap::from([(strat.to_owned(), count)]);
+            let profile = play(
+                tourn,
+                counts(buyer, tourn.buyers),
+                counts(seller, tourn.sellers),
+                presets,
+                samples,
+            )?;
+            buyers[row][col] = profile.payoffs["buyers"].get(buyer).copied().unwrap_or(0.0);
+            sellers[row][col] = profile.payoffs["sellers"]
+                .get(seller)
+                .copied()
+                .unwrap_or(0.0);
+        }
+    }
+    Ok(Matrix {
+        strategies: &tourn.strategies,
+        buyers,
+        sellers,
+    })
+}
+
+/// Play every profile of the strategies within each role
+fn play_profiles(tourn: &Tournament, presets: &Presets, samples: u64) -> io::Result<Vec<Profile>> {
+    let buyer_counts = compositions(tourn.buyers, tourn.strategies.len());
+    let seller_counts = compositions(tourn.sellers, tourn.strategies.len());
+    let named = |counts: &[u64]| -> BTreeMap<String, u64> {
+        tourn
+            .strategies
+            .iter()
+            .zip(counts)
+            .filter(|(_, &count)| count > 0)
+            .map(|(strat, &count)| (strat.clone(), count))
+            .collect()
+    };
+    let mut profiles = Vec::new();
+    for buyers in &buyer_counts {
+        for sellers in &seller_counts {
+            profiles.push(play(
+                tourn,
+                named(buyers),
+                named(sellers),
+                presets,
+                samples,
+            )?);
+        }
+    }
+    Ok(profiles)
+}
+
+/// Simulate one profile, averaging each strategy's payoff over its players and the samples
+fn play(
+    tourn: &Tournament,
+    buyers: BTreeMap<String, u64>,
+    sellers: BTreeMap<String, u64>,
+    presets: &Presets,
+    samples: u64,
+) -> io::Result<Profile> {
+    let counts = |counts: &BTreeMap<String, u64>| {
+        counts
+            .iter()
+            .map(|(strat, &count)| (strat.clone(), count.into()))
+            .collect()
+    };
+    let spec = Spec {
+        assignment: Roles {
+            buyers: counts(&buyers),
+            sellers: counts(&sellers),
+            ..Roles::default()
+        },
+        configuration: tourn.configuration.clone(),
+    };
+    let mut agents = crate::build_agents(&spec, presets)?;
+    let payoffs = if spec.configuration.cda.unwrap_or(true) {
+        mean_payoffs(&mut agents, &Cda, samples)
+    } else {
+        mean_payoffs(&mut agents, &Call, samples)
+    };
+    Ok(Profile {
+        buyers,
+        sellers,
+        payoffs,
+    })
+}
+
+fn mean_payoffs(
+    agents: &mut [Agent<'_>],
+    market: &impl Market,
+    samples: u64,
+) -> BTreeMap<String, BTreeMap<String, f64>> {
+    let mut rng = rand::thread_rng();
+    let mut totals: BTreeMap<(&str, &str), (f64, u64)> = BTreeMap::new();
+    for _ in 0..samples {
+        crate::run_sim(agents, market, &mut rng);
+        for agent in agents.iter() {
+            let total = totals.entry((agent.role(), agent.strategy())).or_default();
+            total.0 += agent.utility;
+            total.1 += 1;
+        }
+    }
+    let mut payoffs: BTreeMap<_, BTreeMap<_, _>> = BTreeMap::from([
+        ("buyers".to_owned(), BTreeMap::new()),
+        ("sellers".to_owned(), BTreeMap::new()),
+    ]);
+    for ((role, strat), (total, count)) in totals {
+        payoffs
+            .entry(role.to_owned())
+            .or_default()
+            .insert(strat.to_owned(), total / count as f64);
+    }
+    payoffs
+}
+
+/// Every way to split `total` players among `parts` strategies
+fn compositions(total: u64, parts: usize) -> Vec<Vec<u64>> {
+    if parts == 1 {
+        return vec![vec![total]];
+    }
+    let mut all = Vec::new();
+    for first in (0..=total).rev() {
+        for mut rest in compositions(total - first, parts - 1) {
+            rest.insert(0, first);
+            all.push(rest);
+        }
+    }
+    all
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Format, TournamentArgs};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_compositions() {
+        let comps = super::compositions(2, 3);
+        assert_eq!(comps.len(), 6);
+        assert!(comps.iter().all(|comp| comp.iter().sum::<u64>() == 2));
+        assert_eq!(comps[0], [2, 0, 0]);
+    }
+
+    #[test]
+    fn test_tournament() {
+        let input =
+            r#"{"strategies":["0","0.5"],"buyers":2,"sellers":2,"configuration":{"cda":false}}"#;
+        for (profiles, format, lines) in [
+            (false, Format::Json, 1),
+            (false, Format::Csv, 5),
+            (false, Format::Nfg, 8),
+            (true, Format::Json, 1),
+            (true, Format::Csv, 25),
+            (true, Format::Gameanalysis, 1),
+        ] {
+            let args = TournamentArgs {
+                samples: 10,
+                profiles,
+                format,
+            };
+            let mut out = Vec::new();
+            super::tournament(&args, &HashMap::new(), false, input.as_bytes(), &mut out).unwrap();
+            let out = String::from_utf8(out).unwrap();
+            assert_eq!(out.lines().count(), lines, "{}", out);
+            match (profiles, format) {
+                (false, Format::Json) => {
+                    let matrix: serde_json::Value = serde_json::from_str(&out).unwrap();
+                    assert_eq!(matrix["buyers"].as_array().unwrap().len(), 2);
+                    assert!(matrix["sellers"][1][0].is_f64());
+                }
+                (true, Format::Json) => {
+                    let profiles: serde_json::Value = serde_json::from_str(&out).unwrap();
+                    assert_eq!(profiles["profiles"].as_array().unwrap().len(), 9);
+                }
+                (true, Format::Gameanalysis) => {
+                    let game: serde_json::Value = serde_json::from_str(&out).unwrap();
+                    assert_eq!(game["roles"][0]["count"], 2);
+                    assert_eq!(game["profiles"].as_array().unwrap().len(), 9);
+                }
+                (false, Format::Nfg) => assert!(out.starts_with("NFG 1 R")),
+                _ => (),
+            }
+        }
+    }
+
+    #[test]
+    fn test_mismatched_format() {
+        let input = r#"{"strategies":["0"],"configuration":{}}"#;
+        for (profiles, format) in [(true, Format::Nfg), (false, Format::Gameanalysis)] {
+            let args = TournamentArgs {
+                samples: 1,
+                profiles,
+                format,
+            };
+            let mut out = Vec::new();
+            assert!(
+                super::tournament(&args, &HashMap::new(), false, input.as_bytes(), &mut out)
+                    .is_err()
+            );
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use crate::engine::{Action, Level};
+use crate::market::Step;
+use crate::Agent;
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::crossterm::{cursor, execute};
+use ratatui::layout::{Alignment, Constraint, Layout};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, Paragraph, Row, Table};
+use ratatui::{Frame, Terminal};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Number of price levels of each side of the ladder
+const DEPTH: usize = 8;
+
+/// Number of recent trades on the tape
+const TAPE: usize = 8;
+
+/// How long to wait for a key while nothing is due to change on screen
+const IDLE: Duration = Duration::from_millis(250);
+
+/// A trade on the tape
+struct Print {
+    time: f64,
+    price: f64,
+    buyer: Option<usize>,
+    seller: Option<usize>,
+}
+
+/// What's shown of the events so far, and how they're played
+struct View {
+    speed: f64,
+    paused: bool,
+    tape: Vec<Print>,
+    surplus: f64,
+    done: bool,
+}
+
+/// The pause between events at `speed` events per second
+fn pause(speed: f64) -> io::Result<Duration> {
+    Duration::try_from_secs_f64(1.0 / speed).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid speed {}, must be positive", speed),
+        )
+    })
+}
+
+/// Animate a traced CDA in the terminal, drawing the book ladder, the trade tape and the
+/// cumulative surplus after every event, at `speed` events per second
+///
+/// The arrow keys or + and - double or halve the speed, space pauses, and q or escape quit. The
+/// last event stays on screen until quitting. Fails without drawing anything if the pause between
+/// events, the inverse of `speed`, isn't a duration, like for zero, negative, or vanishingly small
+/// speeds.
+pub fn play(
+    agents: &[Agent<'_>],
+    trace: &[Step],
+    speed: f64,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    pause(speed)?;
+    terminal::enable_raw_mode()?;
+    execute!(out, EnterAlternateScreen, cursor::Hide)?;
+    let res = Terminal::new(CrosstermBackend::new(&mut *out))
+        .and_then(|mut term| animate(&mut term, agents, trace, speed, read_key));
+    // restore the terminal even if drawing failed
+    execute!(out, LeaveAlternateScreen, cursor::Show)?;
+    terminal::disable_raw_mode()?;
+    res
+}
+
+/// The next key pressed within `wait`, treating ctrl-c as escape since raw mode swallows it
+fn read_key(wait: Duration) -> io::Result<Option<KeyCode>> {
+    if !event::poll(wait)? {
+        return Ok(None);
+    }
+    match event::read()? {
+        Event::Key(key) if key.kind == KeyEventKind::Press => {
+            let interrupt =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            Ok(Some(if interrupt { KeyCode::Esc } else { key.code }))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Play the events of `trace` on `term`, taking key presses from `input`, which waits at most
+/// its argument for one
+fn animate<B: Backend>(
+    term: &mut Terminal<B>,
+    agents: &[Agent<'_>],
+    trace: &[Step],
+    speed: f64,
+    mut input: impl FnMut(Duration) -> io::Result<Option<KeyCode>>,
+) -> io::Result<()> {
+    if trace.is_empty() {
+        return Ok(());
+    }
+    let mut view = View {
+        speed,
+        paused: false,
+        tape: Vec::new(),
+        surplus: 0.0,
+        done: false,
+    };
+    let mut num = 0;
+    let mut due = Instant::now();
+    loop {
+        if !view.done && !view.paused && Instant::now() >= due {
+            view.record(agents, &trace[num]);
+            view.done = num + 1 == trace.len();
+            term.draw(|frame| view.draw(frame, agents, trace, num))?;
+            if !view.done {
+                num += 1;
+                due = Instant::now() + pause(view.speed)?;
+            }
+        }
+        let wait = if view.done || view.paused {
+            IDLE
+        } else {
+            due.saturating_duration_since(Instant::now())
+        };
+        match input(wait)? {
+            Some(KeyCode::Char('q') | KeyCode::Esc) => return Ok(()),
+            Some(KeyCode::Char(' ')) => view.paused = !view.paused,
+            Some(KeyCode::Char('+') | KeyCode::Up | KeyCode::Right) => view.adjust(2.0),
+            Some(KeyCode::Char('-') | KeyCode::Down | KeyCode::Left) => view.adjust(0.5),
+            _ => continue,
+        }
+        // the next event is due a full pause at the new speed, or after unpausing
+        due = Instant::now() + pause(view.speed)?;
+        if let Some(shown) = num.checked_sub(usize::from(!view.done)) {
+            term.draw(|frame| view.draw(frame, agents, trace, shown))?;
+        }
+    }
+}
+
+impl View {
+    /// Multiply the speed by `factor`, unless the pause at the new speed isn't a duration
+    fn adjust(&mut self, factor: f64) {
+        if pause(self.speed * factor).is_ok() {
+            self.speed *= factor;
+        }
+    }
+
+    /// Add a step's trade to the tape and surplus
+    fn record(&mut self, agents: &[Agent<'_>], step: &Step) {
+        let (Action::Quote(index), Some(fill)) = (step.action, step.fill) else {
+            return;
+        };
+        let (buyer, seller) = match fill.counterparty {
+            Some(other) if agents[index].buyer => (Some(index), Some(other)),
+            Some(other) => (Some(other), Some(index)),
+            None if agents[index].buyer => (Some(index), None),
+            None => (None, Some(index)),
+        };
+        // the market maker's side of a trade isn't part of the agents' surplus
+        let gain = |side: Option<usize>| {
+            side.map_or(0.0, |i| {
+                agents[i].sign() * (agents[i].net_value() - fill.price)
+            })
+        };
+        self.surplus += gain(buyer) + gain(seller);
+        self.tape.push(Print {
+            time: step.time,
+            price: fill.price,
+            buyer,
+            seller,
+        });
+    }
+
+    /// Draw the screen after event `num`
+    fn draw(&self, frame: &mut Frame<'_>, agents: &[Agent<'_>], trace: &[Step], num: usize) {
+        let step = &trace[num];
+        let [status, event, body, help] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let state = if self.done {
+            "done"
+        } else if self.paused {
+            "paused"
+        } else {
+            ""
+        };
+        let status_line = format!(
+            "time {:.4}   event {}/{}   surplus {:.4}   {} events/s {}",
+            step.time,
+            num + 1,
+            trace.len(),
+            self.surplus,
+            self.speed,
+            state
+        );
+        frame.render_widget(Paragraph::new(status_line), status);
+        frame.render_widget(Paragraph::new(crate::inspect::event(agents, step)), event);
+
+        let [ladder, tape] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(body);
+        let level = |level: &Level| format!("{:.4} x{}", level.price, level.depth);
+        // asks from the highest shown down to the best, then bids from the best down
+        let asks = step
+            .asks
+            .iter()
+            .take(DEPTH)
+            .rev()
+            .map(|ask| Row::new([Line::default(), Line::from(level(ask))]));
+        let bids = step.bids.iter().take(DEPTH).map(|bid| {
+            Row::new([
+                Line::from(level(bid)).alignment(Alignment::Right),
+                Line::default(),
+            ])
+        });
+        let halves = [Constraint::Percentage(50), Constraint::Percentage(50)];
+        let header = Row::new([
+            Line::from("bids").alignment(Alignment::Right),
+            "asks".into(),
+        ]);
+        let table = Table::new(asks.chain(bids), halves)
+            .header(header)
+            .block(Block::bordered().title("book"));
+        frame.render_widget(table, ladder);
+
+        let party =
+            |side: Option<usize>| side.map_or_else(|| "maker".to_owned(), |i| i.to_string());
+        let prints = self.tape.iter().rev().take(TAPE).map(|print| {
+            format!(
+                "{:>8.4} {:.4}  buyer {} seller {}",
+                print.time,
+                print.price,
+                party(print.buyer),
+                party(print.seller)
+            )
+        });
+        frame.render_widget(
+            List::new(prints).block(Block::bordered().title("tape")),
+            tape,
+        );
+        frame.render_widget(Paragraph::new("+/- speed   space pause   q quit"), help);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::market::{Continuous, Market};
+    use crate::strategy::Shading;
+    use crate::{Agent, Style};
+    use ratatui::backend::TestBackend;
+    use ratatui::crossterm::event::KeyCode;
+    use ratatui::Terminal;
+
+    #[test]
+    fn test_play() {
+        let mut agents: Vec<_> = [(true, 0.8), (false, 0.2)]
+            .into_iter()
+            .map(|(buyer, value)| {
+                let mut agent = Agent::new(buyer, "", Style::Correct, Shading::Fixed(0.0));
+                agent.value = value;
+                agent.shade();
+                agent
+            })
+            .collect();
+        let market = Continuous::new().with_trace();
+        market.simulate(&mut agents, &mut rand::thread_rng());
+        let mut term = Terminal::new(TestBackend::new(80, 20)).unwrap();
+        // quit once the animation idles at the last event
+        let quit = |wait| Ok((wait == super::IDLE).then_some(KeyCode::Char('q')));
+        super::animate(&mut term, &agents, &market.trace(), 1e6, quit).unwrap();
+        let screen: String = term
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.starts_with("time 1.0000   event 5/5   surplus 0.6000"));
+        assert!(screen.contains("done"));
+        assert!(screen.contains("buyer 0 seller 1"));
+
+        // a pause too long to represent is an error rather than a panic
+        for speed in [1e-320, 0.0, -1.0, f64::NAN] {
+            let mut out = Vec::new();
+            assert!(super::play(&agents, &market.trace(), speed, &mut out).is_err());
+            assert!(out.is_empty());
+        }
+    }
+}
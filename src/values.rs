@@ -0,0 +1,271 @@
+use rand::seq::index;
+use rand::Rng;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::Agent;
+
+/// Empirical values that agents draw from instead of their uniform support
+///
+/// Values are either loaded from a csv file where every line is `<role>,<value>`, e.g.
+/// `buyers,0.75`, skipping a `role,value` header and lines starting with `#`, or fixed, with a
+/// list of every agent's value in each role.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "ValuesRepr")]
+pub struct Values {
+    pools: HashMap<String, Arc<[f64]>>,
+    replace: bool,
+    fixed: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FixedValues {
+    buyers: Option<Vec<f64>>,
+    sellers: Option<Vec<f64>>,
+    traders: Option<Vec<f64>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ValuesRepr {
+    Path(PathBuf),
+    Config {
+        path: PathBuf,
+        #[serde(default = "default_replace")]
+        replace: bool,
+    },
+    Fixed(FixedValues),
+}
+
+fn default_replace() -> bool {
+    true
+}
+
+impl TryFrom<ValuesRepr> for Values {
+    type Error = String;
+
+    fn try_from(repr: ValuesRepr) -> Result<Self, Self::Error> {
+        let (path, replace) = match repr {
+            ValuesRepr::Path(path) => (path, true),
+            ValuesRepr::Config { path, replace } => (path, replace),
+            ValuesRepr::Fixed(fixed) => return Values::fixed(fixed),
+        };
+        Values::load(&path, replace).map_err(|err| err.to_string())
+    }
+}
+
+impl Values {
+    pub fn load(path: &Path, replace: bool) -> io::Result<Self> {
+        let invalid = |line: usize, msg: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: {}", path.display(), line + 1, msg),
+            )
+        };
+        let contents = fs::read_to_string(path)?;
+        let mut pools: HashMap<String, Vec<f64>> = HashMap::new();
+        for (num, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || (num == 0 && line == "role,value") {
+                continue;
+            }
+            let (role, value) = line.split_once(',').ok_or_else(|| {
+                invalid(num, format!("expected <role>,<value> but got \"{}\"", line))
+            })?;
+            let role = role.trim();
+            if !["buyers", "sellers", "traders"].contains(&role) {
+                return Err(invalid(num, format!("unknown role \"{}\"", role)));
+            }
+            let value: f64 = value
+                .trim()
+                .parse()
+                .ok()
+                .filter(|value: &f64| value.is_finite())
+                .ok_or_else(|| invalid(num, format!("invalid value \"{}\"", value.trim())))?;
+            pools.entry(role.to_owned()).or_default().push(value);
+        }
+        Ok(Values {
+            pools: pools
+                .into_iter()
+                .map(|(role, values)| (role, values.into()))
+                .collect(),
+            replace,
+            fixed: false,
+        })
+    }
+
+    fn fixed(fixed: FixedValues) -> Result<Self, String> {
+        let roles = [
+            ("buyers", fixed.buyers),
+            ("sellers", fixed.sellers),
+            ("traders", fixed.traders),
+        ];
+        let mut pools = HashMap::new();
+        for (role, values) in roles {
+            if let Some(values) = values {
+                if let Some(value) = values.iter().find(|v| !v.is_finite()) {
+                    return Err(format!("invalid {} value {}", role, value));
+                }
+                pools.insert(role.to_owned(), values.into());
+            }
+        }
+        Ok(Values {
+            pools,
+            replace: true,
+            fixed: true,
+        })
+    }
+
+    /// Have an agent draw from its role's values, if there are any
+    ///
+    /// Fixed values depend on an agent's place in its role, so they're given by [`Values::assign`].
+    pub fn configure(&self, agent: &mut Agent<'_>) {
+        if let Some(pool) = self.pools.get(agent.role()).filter(|_| !self.fixed) {
+            agent.set_pool(pool.clone(), self.replace);
+        }
+    }
+
+    /// Give every agent in a role with fixed values its own, in order
+    pub fn assign(&self, agents: &mut [Agent<'_>]) {
+        if !self.fixed {
+            return;
+        }
+        let mut next: HashMap<&str, usize> = HashMap::new();
+        for agent in agents {
+            if let Some(pool) = self.pools.get(agent.role()) {
+                let place = next.entry(agent.role()).or_default();
+                if let Some(&value) = pool.get(*place) {
+                    agent.set_pool(Arc::new([value]), true);
+                }
+                *place += 1;
+            }
+        }
+    }
+
+    /// Check there are enough values to draw every agent's without replacement, or exactly one
+    /// for every agent if they're fixed
+    pub fn check(&self, agents: &[Agent<'_>]) -> io::Result<()> {
+        if self.replace && !self.fixed {
+            return Ok(());
+        }
+        for (role, pool) in &self.pools {
+            let count = agents.iter().filter(|a| a.role() == role).count();
+            if self.fixed && count != pool.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} fixed values for {} {}, must be one for each",
+                        pool.len(),
+                        count,
+                        role
+                    ),
+                ));
+            } else if count > pool.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} {} can't draw without replacement from {} values",
+                        count,
+                        role,
+                        pool.len()
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Redraw the values of agents that draw without replacement, so no two in a role share a value
+///
+/// Agents draw their values with replacement when they resample, so if there are more agents than
+/// values, only as many as there are values are redrawn.
+pub fn deal(agents: &mut [Agent<'_>], rng: &mut impl Rng) {
+    let mut roles: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (index, agent) in agents.iter().enumerate() {
+        if agent.pool().is_some() && !agent.replaces() {
+            roles.entry(agent.role()).or_default().push(index);
+        }
+    }
+    for indices in roles.into_values() {
+        let pool = agents[indices[0]].pool().unwrap().clone();
+        let count = indices.len().min(pool.len());
+        for (draw, index) in index::sample(rng, pool.len(), count)
+            .into_iter()
+            .zip(indices)
+        {
+            agents[index].value = pool[draw];
+            agents[index].bid = agents[index].sign() * pool[draw];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Values;
+    use crate::strategy::Shading;
+    use crate::{Agent, Style};
+    use std::fs;
+
+    #[test]
+    fn test_values() {
+        let path = std::env::temp_dir().join(format!("cdasim-values-{}.csv", std::process::id()));
+        fs::write(
+            &path,
+            "role,value\nbuyers,0.9\n# comment\nbuyers,0.7\nsellers,0.2\n",
+        )
+        .unwrap();
+        let values = Values::load(&path, false).unwrap();
+        fs::write(&path, "buyers,x\n").unwrap();
+        assert!(Values::load(&path, true).is_err());
+        fs::remove_file(&path).unwrap();
+
+        let mut rng = rand::thread_rng();
+        let mut agents: Vec<_> = [true, true, false]
+            .into_iter()
+            .map(|buyer| Agent::new(buyer, "", Style::Standard, Shading::Fixed(0.0)))
+            .collect();
+        agents.iter_mut().for_each(|a| values.configure(a));
+        values.check(&agents).unwrap();
+        for _ in 0..20 {
+            agents.iter_mut().for_each(|a| a.resample(&mut rng));
+            super::deal(&mut agents, &mut rng);
+            let mut buyers = [agents[0].value, agents[1].value];
+            buyers.sort_by(f64::total_cmp);
+            assert_eq!(buyers, [0.7, 0.9]);
+            assert_eq!(agents[2].value, 0.2);
+        }
+        agents.push(Agent::new(true, "", Style::Standard, Shading::Fixed(0.0)));
+        values.configure(&mut agents[3]);
+        assert!(values.check(&agents).is_err());
+    }
+
+    #[test]
+    fn test_fixed() {
+        let values: Values =
+            serde_json::from_str(r#"{"buyers":[0.9,0.4],"sellers":[0.1]}"#).unwrap();
+        let mut rng = rand::thread_rng();
+        let mut agents: Vec<_> = [true, false, true]
+            .into_iter()
+            .map(|buyer| Agent::new(buyer, "", Style::Standard, Shading::Fixed(0.0)))
+            .collect();
+        agents.iter_mut().for_each(|a| values.configure(a));
+        values.assign(&mut agents);
+        values.check(&agents).unwrap();
+        for _ in 0..5 {
+            agents.iter_mut().for_each(|a| a.resample(&mut rng));
+            super::deal(&mut agents, &mut rng);
+            assert_eq!(
+                agents.iter().map(|a| a.value).collect::<Vec<_>>(),
+                [0.9, 0.1, 0.4]
+            );
+        }
+        assert!(values.check(&agents[..2]).is_err());
+        assert!(serde_json::from_str::<Values>(r#"{"buyer":[0.5]}"#).is_err());
+    }
+}